@@ -15,6 +15,7 @@ use postgres::types::FromSql;
 use postgres::rows::Rows;
 use crypto::digest::Digest;
 use crypto::sha2::Sha256;
+use ogg::vorbis::{front_cover, is_picture_field};
 
 
 #[derive(Debug)]
@@ -60,6 +61,17 @@ pub fn create_album(
         ", &[&album.0, key, val]));
         println!("cp1 - {}", key);
     }
+    let album_artists = match ac.metadata.get("ALBUMARTIST") {
+        Some(names) => artist_credits(Some(names), ac.metadata.get("ALBUMARTISTSORT")),
+        None => artist_credits(ac.metadata.get("ARTIST"), ac.metadata.get("ARTISTSORT")),
+    };
+    for (position, &(ref name, ref sort_name)) in album_artists.iter().enumerate() {
+        let artist_id = try!(find_or_create_artist(&trans, name, sort_name));
+        try!(trans.execute("
+            INSERT INTO album_artist (album_id, artist_id, position)
+            VALUES ($1, $2, $3)
+        ", &[&album.0, &artist_id, &(position as i32)]));
+    }
     println!("cp1");
 
     let mut song_ids = Vec::new();
@@ -80,6 +92,17 @@ pub fn create_album(
             ", &[&dbsong.0, key, val]));
             println!("cp2.0 - {}", key);
         }
+        let song_artists = match song.metadata.get("ARTIST") {
+            Some(names) => artist_credits(Some(names), song.metadata.get("ARTISTSORT")),
+            None => artist_credits(ac.metadata.get("ARTIST"), ac.metadata.get("ARTISTSORT")),
+        };
+        for (position, &(ref name, ref sort_name)) in song_artists.iter().enumerate() {
+            let artist_id = try!(find_or_create_artist(&trans, name, sort_name));
+            try!(trans.execute("
+                INSERT INTO song_artist (song_id, artist_id, position)
+                VALUES ($1, $2, $3)
+            ", &[&dbsong.0, &artist_id, &(position as i32)]));
+        }
 
         song_ids.push(dbsong);
         println!("cp2 - {}", song_ids.len());
@@ -91,6 +114,67 @@ pub fn create_album(
     Ok(AlbumId(0))
 }
 
+/// Splits `ARTIST` style values on `;`, pairing each name with the
+/// `ARTISTSORT` entry at the same position.  Same rules as the server.
+fn artist_credits(names: Option<&String>, sort_names: Option<&String>) -> Vec<(String, Option<String>)> {
+    fn split(value: Option<&String>) -> Vec<String> {
+        value.map(|v| {
+            v.split(';')
+                .map(|part| part.trim())
+                .filter(|part| part.len() > 0)
+                .map(|part| part.chars().take(256).collect())
+                .collect()
+        }).unwrap_or_else(Vec::new)
+    }
+
+    let sort_names = split(sort_names);
+    let mut out: Vec<(String, Option<String>)> = Vec::new();
+    for (idx, name) in split(names).into_iter().enumerate() {
+        if out.iter().any(|c| c.0.to_lowercase() == name.to_lowercase()) {
+            continue;
+        }
+        out.push((name, sort_names.get(idx).cloned()));
+    }
+    out
+}
+
+fn find_or_create_artist(
+    trans: &postgres::transaction::Transaction,
+    name: &String,
+    sort_name: &Option<String>
+) -> Result<i64, Box<::std::error::Error>> {
+    let rows = try!(trans.query("
+        SELECT artist_id FROM artist_alias WHERE lower(name) = lower($1)
+    ", &[name]));
+    if let Some(row) = rows.iter().next() {
+        return Ok(row.get(0));
+    }
+
+    let rows = try!(trans.query("
+        INSERT INTO artist (name, sort_name)
+        VALUES ($1, $2)
+        ON CONFLICT ((lower(name)))
+        DO UPDATE SET sort_name = COALESCE(artist.sort_name, EXCLUDED.sort_name)
+        RETURNING id
+    ", &[name, sort_name]));
+    extract_single2(rows)
+}
+
+/// Vorbis field names are case-insensitive and may repeat, e.g. one
+/// `ARTIST` per performer; repeated values are joined with `;`.
+fn comment_map(comments: Vec<(String, String)>) -> HashMap<String, String> {
+    let mut out: HashMap<String, String> = HashMap::new();
+    for (key, value) in comments.into_iter() {
+        let key = key.to_uppercase();
+        let joined = match out.get(&key) {
+            Some(existing) => format!("{}; {}", existing, value),
+            None => value,
+        };
+        out.insert(key, joined);
+    }
+    out
+}
+
 #[derive(Serialize, Debug)]
 pub struct SongCreate {
     pub blob: String,
@@ -179,6 +263,7 @@ fn main() {
     }
 
     let mut blobs = HashMap::new();
+    let mut pictures = Vec::new();
     let mut track_num = 1;
     for file in files.iter() {
         let mut buf = Vec::new();
@@ -206,6 +291,10 @@ fn main() {
         let sample_rate = id_header.audio_sample_rate;
         let comments = ogg::vorbis::VorbisPacket::find_comments(&mut page_iter).unwrap();
         let comments = comments.comments().unwrap().comments;
+        pictures.extend(ogg::vorbis::pictures(&comments));
+        let comments = comments.into_iter()
+            .filter(|&(ref key, _)| !is_picture_field(key))
+            .collect();
 
         let mut granule_pos_max = 0;
         for page in track.pages() {
@@ -218,11 +307,18 @@ fn main() {
             blob: blob_hash,
             track_no: idx as i16,
             length_ms: (1000 * granule_pos_max / sample_rate as u64) as i32,
-            metadata: comments.into_iter().collect(),
+            metadata: comment_map(comments),
         });        
     }
 
-    let album = album_from_songs(songs);
+    let mut album = album_from_songs(songs);
+    if let Some(picture) = front_cover(pictures) {
+        let mut hasher = Sha256::new();
+        hasher.input(&picture.data);
+        let art_hash = hasher.result_str();
+        album.art_blob = Some(art_hash.clone());
+        blobs.insert(art_hash, picture.data);
+    }
     println!("{}", serde_json::to_string_pretty(&album).unwrap());
 
     for (key, blob) in blobs.iter() {
@@ -0,0 +1,19 @@
+CREATE TABLE "metadata_change" (
+    id          bigserial,
+    -- NULL once the editing account has been removed
+    account_id  uuid REFERENCES account (id),
+    song_id     bigint REFERENCES song (id),
+    album_id    bigint REFERENCES album (id),
+    field_name  character varying(32) NOT NULL,
+    -- NULL when the field was absent before/after the change
+    old_value   character varying(256),
+    new_value   character varying(256),
+    reverts     bigint REFERENCES metadata_change (id),
+    changed_at  timestamp without time zone NOT NULL DEFAULT NOW(),
+
+    PRIMARY KEY (id),
+    CONSTRAINT metadata_change_one_target CHECK ((song_id IS NULL) <> (album_id IS NULL))
+);
+
+CREATE INDEX metadata_change_song_idx ON metadata_change (song_id, id);
+CREATE INDEX metadata_change_album_idx ON metadata_change (album_id, id);
@@ -0,0 +1,4 @@
+ALTER TABLE "account_song_metadata"
+    DROP CONSTRAINT account_song_metadata_account_song_uniq,
+    DROP CONSTRAINT account_song_metadata_account_fk,
+    ALTER COLUMN score DROP DEFAULT;
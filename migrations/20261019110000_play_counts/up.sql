@@ -0,0 +1,6 @@
+ALTER TABLE "account_song_metadata"
+    ALTER COLUMN score SET DEFAULT 0,
+    ADD CONSTRAINT account_song_metadata_account_fk
+        FOREIGN KEY (account_id) REFERENCES account (id),
+    ADD CONSTRAINT account_song_metadata_account_song_uniq
+        UNIQUE (account_id, song_id);
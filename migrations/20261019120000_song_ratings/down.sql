@@ -0,0 +1,9 @@
+ALTER TABLE "account_song_metadata"
+    DROP CONSTRAINT account_song_metadata_score_range;
+
+UPDATE "account_song_metadata" SET score = 0;
+
+ALTER TABLE "account_song_metadata"
+    ALTER COLUMN score SET NOT NULL,
+    ALTER COLUMN score SET DEFAULT 0,
+    ADD CONSTRAINT account_song_metadata_score_check CHECK (ABS(score) <= 0);
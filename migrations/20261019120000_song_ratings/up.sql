@@ -0,0 +1,10 @@
+-- ratings are 1 to 5 stars, NULL while the song is unrated
+ALTER TABLE "account_song_metadata"
+    DROP CONSTRAINT account_song_metadata_score_check,
+    ALTER COLUMN score DROP NOT NULL,
+    ALTER COLUMN score DROP DEFAULT;
+
+UPDATE "account_song_metadata" SET score = NULL WHERE score = 0;
+
+ALTER TABLE "account_song_metadata"
+    ADD CONSTRAINT account_song_metadata_score_range CHECK (score BETWEEN 1 AND 5);
@@ -0,0 +1,2 @@
+DROP TABLE "playlist_entry";
+DROP TABLE "playlist";
@@ -0,0 +1,26 @@
+CREATE TABLE "playlist" (
+    id          bigserial,
+    account_id  uuid NOT NULL REFERENCES account (id),
+    name        character varying(256) NOT NULL,
+    description text NOT NULL DEFAULT '',
+    created_at  timestamp without time zone NOT NULL DEFAULT NOW(),
+    updated_at  timestamp without time zone NOT NULL DEFAULT NOW(),
+
+    PRIMARY KEY (id)
+);
+
+CREATE INDEX playlist_account_idx ON playlist (account_id);
+
+-- a song may appear several times in a playlist, so entries have their own id.
+-- positions are contiguous and start at 0.
+CREATE TABLE "playlist_entry" (
+    id          bigserial,
+    playlist_id bigint NOT NULL REFERENCES playlist (id) ON DELETE CASCADE,
+    song_id     bigint NOT NULL REFERENCES song (id),
+    position    int NOT NULL,
+
+    PRIMARY KEY (id),
+    -- deferred so that entries can be shifted with a single UPDATE
+    CONSTRAINT playlist_entry_position_uniq UNIQUE (playlist_id, position)
+        DEFERRABLE INITIALLY DEFERRED
+);
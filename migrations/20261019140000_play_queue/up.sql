@@ -0,0 +1,12 @@
+-- one queue per account, shared by all of its devices.  version is bumped on
+-- every write so that clients can detect concurrent edits.
+CREATE TABLE "play_queue" (
+    account_id    uuid NOT NULL REFERENCES account (id),
+    song_ids      bigint[] NOT NULL DEFAULT '{}',
+    current_index int NOT NULL DEFAULT 0,
+    position_ms   bigint NOT NULL DEFAULT 0,
+    version       bigint NOT NULL,
+    updated_at    timestamp without time zone NOT NULL DEFAULT NOW(),
+
+    PRIMARY KEY (account_id)
+);
@@ -0,0 +1,14 @@
+-- every play reported by a client, counted or not.  rows are only ever
+-- inserted, or deleted once they fall out of the retention window.
+CREATE TABLE "listen_history" (
+    id          bigserial,
+    account_id  uuid NOT NULL REFERENCES account (id),
+    song_id     bigint NOT NULL REFERENCES song (id),
+    started_at  timestamp without time zone NOT NULL,
+    duration_ms int NOT NULL,
+    client_id   character varying(64),
+
+    PRIMARY KEY (id)
+);
+
+CREATE INDEX listen_history_account_started_idx ON listen_history (account_id, started_at DESC, id DESC);
@@ -0,0 +1,13 @@
+-- rules are kept as submitted and compiled whenever the playlist is played
+CREATE TABLE "smart_playlist" (
+    id          bigserial,
+    account_id  uuid NOT NULL REFERENCES account (id),
+    name        character varying(256) NOT NULL,
+    rules       jsonb NOT NULL,
+    created_at  timestamp without time zone NOT NULL DEFAULT NOW(),
+    updated_at  timestamp without time zone NOT NULL DEFAULT NOW(),
+
+    PRIMARY KEY (id)
+);
+
+CREATE INDEX smart_playlist_account_idx ON smart_playlist (account_id);
@@ -0,0 +1,4 @@
+DROP TABLE "album_artist";
+DROP TABLE "song_artist";
+DROP TABLE "artist_alias";
+DROP TABLE "artist";
@@ -0,0 +1,98 @@
+CREATE TABLE "artist" (
+    id          bigserial,
+    name        character varying(256) NOT NULL,
+    sort_name   character varying(256),
+
+    PRIMARY KEY (id)
+);
+
+-- spelling variants differing only in case are the same artist
+CREATE UNIQUE INDEX artist_name_uniq ON artist (lower(name));
+
+-- names of artists that were merged into another one, so that importing the
+-- old spelling again links to the surviving artist.
+CREATE TABLE "artist_alias" (
+    name        character varying(256) NOT NULL,
+    artist_id   bigint NOT NULL REFERENCES artist (id) ON DELETE CASCADE,
+
+    PRIMARY KEY (name)
+);
+
+CREATE UNIQUE INDEX artist_alias_name_uniq ON artist_alias (lower(name));
+
+-- position keeps the order the artists were credited in
+CREATE TABLE "song_artist" (
+    song_id     bigint NOT NULL REFERENCES song (id) ON DELETE CASCADE,
+    artist_id   bigint NOT NULL REFERENCES artist (id),
+    position    int NOT NULL,
+
+    PRIMARY KEY (song_id, artist_id)
+);
+
+CREATE INDEX song_artist_artist_idx ON song_artist (artist_id);
+
+CREATE TABLE "album_artist" (
+    album_id    bigint NOT NULL REFERENCES album (id) ON DELETE CASCADE,
+    artist_id   bigint NOT NULL REFERENCES artist (id),
+    position    int NOT NULL,
+
+    PRIMARY KEY (album_id, artist_id)
+);
+
+CREATE INDEX album_artist_artist_idx ON album_artist (artist_id);
+
+-- backfill from the existing metadata, the same way the server links artists
+-- for new albums: ARTIST values are split on ';', a song without its own
+-- ARTIST uses its album's, and an album uses ALBUMARTIST before ARTIST.
+-- sort names are not backfilled.
+CREATE TEMPORARY TABLE artist_credit_import AS
+    SELECT 'song'::text AS kind, credit.owner_id, trim(credit.name) AS name, credit.position
+    FROM (
+        SELECT s.id AS owner_id, split.name, split.position
+        FROM song AS s
+        CROSS JOIN LATERAL (
+            SELECT COALESCE(
+                (SELECT sm.value FROM song_metadata AS sm
+                    WHERE sm.song_id = s.id AND sm.field_name = 'ARTIST'),
+                (SELECT am.value FROM album_metadata AS am
+                    WHERE am.album_id = s.album_id AND am.field_name = 'ARTIST')
+            ) AS value
+        ) AS v
+        CROSS JOIN LATERAL regexp_split_to_table(v.value, ';') WITH ORDINALITY AS split(name, position)
+    ) AS credit
+    UNION ALL
+    SELECT 'album'::text, credit.owner_id, trim(credit.name), credit.position
+    FROM (
+        SELECT a.id AS owner_id, split.name, split.position
+        FROM album AS a
+        CROSS JOIN LATERAL (
+            SELECT COALESCE(
+                (SELECT am.value FROM album_metadata AS am
+                    WHERE am.album_id = a.id AND am.field_name = 'ALBUMARTIST'),
+                (SELECT am.value FROM album_metadata AS am
+                    WHERE am.album_id = a.id AND am.field_name = 'ARTIST')
+            ) AS value
+        ) AS v
+        CROSS JOIN LATERAL regexp_split_to_table(v.value, ';') WITH ORDINALITY AS split(name, position)
+    ) AS credit;
+
+DELETE FROM artist_credit_import WHERE name = '';
+
+INSERT INTO artist (name)
+SELECT DISTINCT ON (lower(name)) name FROM artist_credit_import ORDER BY lower(name), name;
+
+INSERT INTO song_artist (song_id, artist_id, position)
+SELECT DISTINCT ON (c.owner_id, ar.id) c.owner_id, ar.id, c.position - 1
+FROM artist_credit_import AS c
+JOIN artist AS ar ON lower(ar.name) = lower(c.name)
+WHERE c.kind = 'song'
+ORDER BY c.owner_id, ar.id, c.position;
+
+INSERT INTO album_artist (album_id, artist_id, position)
+SELECT DISTINCT ON (c.owner_id, ar.id) c.owner_id, ar.id, c.position - 1
+FROM artist_credit_import AS c
+JOIN artist AS ar ON lower(ar.name) = lower(c.name)
+WHERE c.kind = 'album'
+ORDER BY c.owner_id, ar.id, c.position;
+
+DROP TABLE artist_credit_import;
@@ -0,0 +1,3 @@
+DROP INDEX song_audio_digest_idx;
+DROP INDEX song_blob_idx;
+ALTER TABLE "song" DROP COLUMN audio_digest;
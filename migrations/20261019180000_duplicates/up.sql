@@ -0,0 +1,7 @@
+-- hex sha256 of the Vorbis audio pages, see media::audio_digest.  NULL for
+-- songs imported before it was recorded until the duplicate report fills
+-- it in.
+ALTER TABLE "song" ADD COLUMN audio_digest character varying(64);
+
+CREATE INDEX song_blob_idx ON song (blob);
+CREATE INDEX song_audio_digest_idx ON song (audio_digest);
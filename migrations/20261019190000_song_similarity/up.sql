@@ -0,0 +1,11 @@
+-- rebuilt wholesale by the server's background refresh, see
+-- database::similarity for how scores are computed.
+CREATE TABLE "song_similarity" (
+    song_id         bigint NOT NULL REFERENCES song (id) ON DELETE CASCADE,
+    similar_song_id bigint NOT NULL REFERENCES song (id) ON DELETE CASCADE,
+    score           double precision NOT NULL,
+
+    PRIMARY KEY (song_id, similar_song_id)
+);
+
+CREATE INDEX song_similarity_score_idx ON song_similarity (song_id, score DESC);
@@ -0,0 +1,9 @@
+DROP TRIGGER song_metadata_catalog_change ON song_metadata;
+DROP TRIGGER album_metadata_catalog_change ON album_metadata;
+DROP TRIGGER song_catalog_change ON song;
+DROP TRIGGER album_catalog_change ON album;
+DROP FUNCTION catalog_metadata_change();
+DROP FUNCTION catalog_row_change();
+DROP FUNCTION record_catalog_change(text, bigint, boolean);
+DROP TABLE "catalog_change";
+DROP SEQUENCE catalog_version_seq;
@@ -0,0 +1,87 @@
+-- One row per song or album with the catalog version at which it last
+-- changed.  Deleted songs and albums keep their row, marked deleted, so that
+-- clients syncing from an older version learn about the deletion.
+CREATE SEQUENCE catalog_version_seq;
+
+CREATE TABLE "catalog_change" (
+    version     bigint NOT NULL DEFAULT nextval('catalog_version_seq'),
+    kind        character varying(8) NOT NULL CHECK (kind IN ('song', 'album')),
+    target_id   bigint NOT NULL,
+    deleted     boolean NOT NULL DEFAULT FALSE,
+
+    PRIMARY KEY (kind, target_id)
+);
+
+CREATE INDEX catalog_change_version_idx ON catalog_change (version);
+
+CREATE FUNCTION record_catalog_change(change_kind text, change_target bigint, change_deleted boolean)
+RETURNS void AS $$
+BEGIN
+    -- Writers take turns so that versions become visible in the order they
+    -- were handed out, otherwise a client could sync past a version that
+    -- was still uncommitted.  Readers aren't blocked.
+    LOCK TABLE catalog_change IN EXCLUSIVE MODE;
+    INSERT INTO catalog_change (kind, target_id, deleted)
+    VALUES (change_kind, change_target, change_deleted)
+    ON CONFLICT (kind, target_id) DO UPDATE
+        SET version = EXCLUDED.version, deleted = EXCLUDED.deleted;
+END;
+$$ LANGUAGE plpgsql;
+
+-- for song and album, TG_ARGV[0] is the kind
+CREATE FUNCTION catalog_row_change() RETURNS trigger AS $$
+BEGIN
+    IF TG_OP = 'DELETE' THEN
+        PERFORM record_catalog_change(TG_ARGV[0], OLD.id, TRUE);
+        RETURN OLD;
+    END IF;
+    PERFORM record_catalog_change(TG_ARGV[0], NEW.id, FALSE);
+    RETURN NEW;
+END;
+$$ LANGUAGE plpgsql;
+
+-- for song_metadata and album_metadata, TG_ARGV[0] is the kind and the
+-- owner is in the <kind>_id column
+CREATE FUNCTION catalog_metadata_change() RETURNS trigger AS $$
+DECLARE
+    changed jsonb;
+    target bigint;
+    owner_exists boolean;
+BEGIN
+    IF TG_OP = 'DELETE' THEN
+        changed := to_jsonb(OLD);
+    ELSE
+        changed := to_jsonb(NEW);
+    END IF;
+    target := (changed ->> (TG_ARGV[0] || '_id'))::bigint;
+    -- metadata going along with its owner mustn't undo the tombstone
+    EXECUTE format('SELECT EXISTS (SELECT 1 FROM %I WHERE id = $1)', TG_ARGV[0])
+        INTO owner_exists USING target;
+    IF owner_exists THEN
+        PERFORM record_catalog_change(TG_ARGV[0], target, FALSE);
+    END IF;
+    RETURN NULL;
+END;
+$$ LANGUAGE plpgsql;
+
+CREATE TRIGGER album_catalog_change
+    AFTER INSERT OR DELETE OR UPDATE OF art_blob ON album
+    FOR EACH ROW EXECUTE PROCEDURE catalog_row_change('album');
+
+CREATE TRIGGER song_catalog_change
+    AFTER INSERT OR DELETE OR UPDATE OF blob, album_id, track_no, length_ms ON song
+    FOR EACH ROW EXECUTE PROCEDURE catalog_row_change('song');
+
+CREATE TRIGGER album_metadata_catalog_change
+    AFTER INSERT OR UPDATE OR DELETE ON album_metadata
+    FOR EACH ROW EXECUTE PROCEDURE catalog_metadata_change('album');
+
+CREATE TRIGGER song_metadata_catalog_change
+    AFTER INSERT OR UPDATE OR DELETE ON song_metadata
+    FOR EACH ROW EXECUTE PROCEDURE catalog_metadata_change('song');
+
+-- everything already in the catalog starts out at a version of its own
+INSERT INTO catalog_change (kind, target_id)
+SELECT 'album', id FROM album ORDER BY id;
+INSERT INTO catalog_change (kind, target_id)
+SELECT 'song', id FROM song ORDER BY id;
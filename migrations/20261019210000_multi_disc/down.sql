@@ -0,0 +1,9 @@
+DROP TRIGGER song_catalog_change ON song;
+CREATE TRIGGER song_catalog_change
+    AFTER INSERT OR DELETE OR UPDATE OF blob, album_id, track_no, length_ms ON song
+    FOR EACH ROW EXECUTE PROCEDURE catalog_row_change('song');
+
+-- fails if any album has the same track number on two discs
+ALTER TABLE "song" DROP CONSTRAINT song_album_disc_track_uniq;
+ALTER TABLE "song" ADD CONSTRAINT song_album_track_uniq UNIQUE (album_id, track_no);
+ALTER TABLE "song" DROP COLUMN disc_no;
@@ -0,0 +1,24 @@
+-- Multi-disc albums repeat track numbers, so positions are per disc.  The
+-- number comes from DISCNUMBER, on the song or failing that its album.
+ALTER TABLE "song" ADD COLUMN disc_no smallint NOT NULL DEFAULT 1 CHECK (disc_no > 0);
+
+UPDATE song SET disc_no = d.disc_no
+FROM (
+    SELECT s.id, substring(coalesce(sm.value, am.value) FROM '^\s*(\d{1,4})')::smallint AS disc_no
+    FROM song AS s
+    LEFT JOIN song_metadata AS sm ON sm.song_id = s.id AND sm.field_name = 'DISCNUMBER'
+    LEFT JOIN album_metadata AS am ON am.album_id = s.album_id AND am.field_name = 'DISCNUMBER'
+) AS d
+WHERE d.id = song.id AND d.disc_no > 0;
+
+ALTER TABLE "song" DROP CONSTRAINT song_album_track_uniq;
+ALTER TABLE "song" ADD CONSTRAINT song_album_disc_track_uniq UNIQUE (album_id, disc_no, track_no);
+
+DROP TRIGGER song_catalog_change ON song;
+CREATE TRIGGER song_catalog_change
+    AFTER INSERT OR DELETE OR UPDATE OF blob, album_id, disc_no, track_no, length_ms ON song
+    FOR EACH ROW EXECUTE PROCEDURE catalog_row_change('song');
+
+-- songs and albums gained typed fields, so synced clients fetch everything
+-- once more
+UPDATE catalog_change SET version = nextval('catalog_version_seq') WHERE NOT deleted;
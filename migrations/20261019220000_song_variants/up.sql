@@ -0,0 +1,21 @@
+-- Every encoding of a song's audio, e.g. a lossless master alongside a lossy
+-- copy.  song.blob stays the encoding the song was imported with and is
+-- recorded here as well for songs imported from now on; older songs get
+-- theirs the first time another variant is added.
+CREATE TABLE "song_variant" (
+    id          bigserial PRIMARY KEY,
+    song_id     bigint NOT NULL REFERENCES song (id) ON DELETE CASCADE,
+    blob        character varying(64) NOT NULL,
+    codec       character varying(32) NOT NULL,
+    -- bits per second, nominal if the encoder said so, else the average
+    bitrate     integer NOT NULL CHECK (bitrate > 0),
+    channels    smallint NOT NULL CHECK (channels > 0),
+    sample_rate integer NOT NULL CHECK (sample_rate > 0),
+    size        bigint NOT NULL CHECK (size >= 0),
+
+    CONSTRAINT song_variant_song_blob_uniq UNIQUE (song_id, blob)
+);
+
+CREATE TRIGGER song_variant_catalog_change
+    AFTER INSERT OR UPDATE OR DELETE ON song_variant
+    FOR EACH ROW EXECUTE PROCEDURE catalog_metadata_change('song');
@@ -0,0 +1,15 @@
+-- song_metadata.value only takes 256 characters, and the timestamps of
+-- synced lyrics have nowhere to go; refuse to cut lyrics short rather than
+-- lose them silently.
+DO $$
+BEGIN
+    IF EXISTS (SELECT 1 FROM song_lyrics WHERE length(text) > 256) THEN
+        RAISE EXCEPTION 'song_lyrics holds lyrics over 256 characters, which song_metadata can''t take; export and delete them first';
+    END IF;
+END
+$$;
+
+INSERT INTO song_metadata (song_id, field_name, value)
+SELECT song_id, 'LYRICS', text FROM song_lyrics;
+
+DROP TABLE "song_lyrics";
@@ -0,0 +1,19 @@
+-- lines holds the time-synced lines as [{"time_ms": ..., "text": ...}] and
+-- is empty for plain lyrics; text is the lyrics without timestamps either way
+CREATE TABLE "song_lyrics" (
+    song_id     bigint NOT NULL REFERENCES song (id) ON DELETE CASCADE,
+    text        text NOT NULL,
+    lines       jsonb NOT NULL DEFAULT '[]',
+    updated_at  timestamp without time zone NOT NULL DEFAULT NOW(),
+
+    PRIMARY KEY (song_id)
+);
+
+-- lyrics imported so far ended up among the song's metadata
+INSERT INTO song_lyrics (song_id, text)
+SELECT DISTINCT ON (sm.song_id) sm.song_id, trim(sm.value)
+FROM song_metadata AS sm
+WHERE sm.field_name IN ('LYRICS', 'UNSYNCEDLYRICS') AND trim(sm.value) <> ''
+ORDER BY sm.song_id, sm.field_name;
+
+DELETE FROM song_metadata WHERE field_name IN ('LYRICS', 'UNSYNCEDLYRICS');
@@ -0,0 +1,14 @@
+DROP TRIGGER album_catalog_change ON album;
+CREATE TRIGGER album_catalog_change
+    AFTER INSERT OR DELETE OR UPDATE OF art_blob ON album
+    FOR EACH ROW EXECUTE PROCEDURE catalog_row_change('album');
+
+DROP TRIGGER song_catalog_change ON song;
+CREATE TRIGGER song_catalog_change
+    AFTER INSERT OR DELETE OR UPDATE OF blob, album_id, disc_no, track_no, length_ms ON song
+    FOR EACH ROW EXECUTE PROCEDURE catalog_row_change('song');
+
+ALTER TABLE "album" DROP COLUMN album_peak;
+ALTER TABLE "album" DROP COLUMN album_gain;
+ALTER TABLE "song" DROP COLUMN track_peak;
+ALTER TABLE "song" DROP COLUMN track_gain;
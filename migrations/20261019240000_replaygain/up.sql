@@ -0,0 +1,68 @@
+-- ReplayGain as typed values: gains in dB, peaks as a fraction of full
+-- scale.  NULL until read from the tags at import or measured by
+-- replaygain-scan.
+ALTER TABLE "song" ADD COLUMN track_gain double precision;
+ALTER TABLE "song" ADD COLUMN track_peak double precision CHECK (track_peak >= 0);
+ALTER TABLE "album" ADD COLUMN album_gain double precision;
+ALTER TABLE "album" ADD COLUMN album_peak double precision CHECK (album_peak >= 0);
+
+DROP TRIGGER album_catalog_change ON album;
+CREATE TRIGGER album_catalog_change
+    AFTER INSERT OR DELETE OR UPDATE OF art_blob, album_gain, album_peak ON album
+    FOR EACH ROW EXECUTE PROCEDURE catalog_row_change('album');
+
+DROP TRIGGER song_catalog_change ON song;
+CREATE TRIGGER song_catalog_change
+    AFTER INSERT OR DELETE OR UPDATE OF blob, album_id, disc_no, track_no, length_ms, track_gain, track_peak ON song
+    FOR EACH ROW EXECUTE PROCEDURE catalog_row_change('song');
+
+-- move the tags imported so far out of the metadata, e.g. `-6.48 dB`
+CREATE FUNCTION pg_temp.replaygain_gain(raw text) RETURNS double precision AS $$
+    SELECT substring(raw FROM '^\s*([+-]?\d+(?:\.\d+)?)')::double precision
+$$ LANGUAGE sql IMMUTABLE;
+
+CREATE FUNCTION pg_temp.replaygain_peak(raw text) RETURNS double precision AS $$
+    SELECT substring(raw FROM '^\s*\+?(\d+(?:\.\d+)?)')::double precision
+$$ LANGUAGE sql IMMUTABLE;
+
+UPDATE song SET
+    track_gain = (
+        SELECT pg_temp.replaygain_gain(sm.value) FROM song_metadata AS sm
+        WHERE sm.song_id = song.id AND sm.field_name = 'REPLAYGAIN_TRACK_GAIN'
+    ),
+    track_peak = (
+        SELECT pg_temp.replaygain_peak(sm.value) FROM song_metadata AS sm
+        WHERE sm.song_id = song.id AND sm.field_name = 'REPLAYGAIN_TRACK_PEAK'
+    );
+
+-- the album tags end up on the album unless its songs disagreed
+UPDATE album SET
+    album_gain = coalesce(
+        (
+            SELECT pg_temp.replaygain_gain(am.value) FROM album_metadata AS am
+            WHERE am.album_id = album.id AND am.field_name = 'REPLAYGAIN_ALBUM_GAIN'
+        ),
+        (
+            SELECT pg_temp.replaygain_gain(sm.value) FROM song_metadata AS sm
+            JOIN song AS s ON s.id = sm.song_id
+            WHERE s.album_id = album.id AND sm.field_name = 'REPLAYGAIN_ALBUM_GAIN'
+            ORDER BY s.disc_no, s.track_no LIMIT 1
+        )
+    ),
+    album_peak = coalesce(
+        (
+            SELECT pg_temp.replaygain_peak(am.value) FROM album_metadata AS am
+            WHERE am.album_id = album.id AND am.field_name = 'REPLAYGAIN_ALBUM_PEAK'
+        ),
+        (
+            SELECT max(pg_temp.replaygain_peak(sm.value)) FROM song_metadata AS sm
+            JOIN song AS s ON s.id = sm.song_id
+            WHERE s.album_id = album.id AND sm.field_name = 'REPLAYGAIN_ALBUM_PEAK'
+        )
+    );
+
+DELETE FROM song_metadata WHERE field_name LIKE 'REPLAYGAIN\_%';
+DELETE FROM album_metadata WHERE field_name LIKE 'REPLAYGAIN\_%';
+
+-- songs and albums gained the typed fields
+UPDATE catalog_change SET version = nextval('catalog_version_seq') WHERE NOT deleted;
@@ -0,0 +1 @@
+ALTER TABLE "listen_history" DROP COLUMN position_ms;
@@ -0,0 +1,3 @@
+-- how far playback got, as reported with the play.  listens recorded
+-- before it was kept have 0.
+ALTER TABLE "listen_history" ADD COLUMN position_ms int NOT NULL DEFAULT 0 CHECK (position_ms >= 0);
@@ -0,0 +1 @@
+DROP INDEX listen_history_started_idx;
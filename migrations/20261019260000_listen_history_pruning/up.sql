@@ -0,0 +1,2 @@
+-- retention pruning deletes by age across all accounts
+CREATE INDEX listen_history_started_idx ON listen_history (started_at);
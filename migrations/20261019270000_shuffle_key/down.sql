@@ -0,0 +1,3 @@
+DROP FUNCTION shuffle_key(bigint, bigint);
+DROP FUNCTION u64_value(bit(64));
+DROP FUNCTION u64_bits(numeric);
@@ -0,0 +1,23 @@
+-- database::shuffle_key, so that a seeded shuffle puts songs in the same
+-- order whichever driver runs it.  bigint arithmetic can't wrap around,
+-- so the multiplications are done on numeric and reduced mod 2^64.
+CREATE FUNCTION u64_bits(n numeric) RETURNS bit(64) AS $$
+    SELECT (CASE WHEN r >= 9223372036854775808 THEN r - 18446744073709551616 ELSE r END)::bigint::bit(64)
+    FROM (SELECT mod(n, 18446744073709551616) AS r) AS wrapped
+$$ LANGUAGE sql IMMUTABLE STRICT;
+
+CREATE FUNCTION u64_value(b bit(64)) RETURNS numeric AS $$
+    SELECT CASE WHEN b::bigint < 0 THEN b::bigint + 18446744073709551616 ELSE b::bigint END
+$$ LANGUAGE sql IMMUTABLE STRICT;
+
+CREATE FUNCTION shuffle_key(seed bigint, song_id bigint) RETURNS bigint AS $$
+DECLARE
+    z bit(64);
+BEGIN
+    -- splitmix64 finalizer
+    z := u64_bits(u64_value((seed << 32)::bit(64) # song_id::bit(64)) + 11400714819323198485);
+    z := u64_bits(u64_value(z # (z >> 30)) * 13787848793156543929);
+    z := u64_bits(u64_value(z # (z >> 27)) * 10723151780598845931);
+    RETURN (z # (z >> 31))::bigint;
+END
+$$ LANGUAGE plpgsql IMMUTABLE STRICT;
@@ -0,0 +1,10 @@
+-- the sequence of a radio session as drawn when it started, so that paging
+-- through it isn't upset by the plays and ratings it leads to.
+CREATE TABLE "radio_session" (
+    account_id uuid NOT NULL REFERENCES account (id),
+    seed       bigint NOT NULL,
+    started_at timestamp without time zone NOT NULL,
+    song_ids   bigint[] NOT NULL,
+
+    PRIMARY KEY (account_id, seed, started_at)
+);
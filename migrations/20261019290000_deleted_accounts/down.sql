@@ -0,0 +1,6 @@
+ALTER TABLE "account_song_metadata"
+    DROP CONSTRAINT account_song_metadata_account_fk,
+    ADD CONSTRAINT account_song_metadata_account_fk
+        FOREIGN KEY (account_id) REFERENCES account (id);
+
+DROP TABLE "deleted_account";
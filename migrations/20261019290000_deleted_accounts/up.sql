@@ -0,0 +1,14 @@
+-- access tokens can't be revoked, so the ids of deleted accounts are kept
+-- for them to be turned away.
+CREATE TABLE "deleted_account" (
+    id         uuid NOT NULL,
+    deleted_at timestamp without time zone NOT NULL DEFAULT NOW(),
+
+    PRIMARY KEY (id)
+);
+
+-- plays and ratings must not outlive their account
+ALTER TABLE "account_song_metadata"
+    DROP CONSTRAINT account_song_metadata_account_fk,
+    ADD CONSTRAINT account_song_metadata_account_fk
+        FOREIGN KEY (account_id) REFERENCES account (id) ON DELETE CASCADE;
@@ -0,0 +1,25 @@
+DROP TRIGGER account_song_metadata_stats_version ON account_song_metadata;
+DROP FUNCTION bump_stats_version();
+ALTER TABLE "account" DROP COLUMN stats_version;
+
+DROP TRIGGER catalog_change_number ON catalog_change;
+DROP FUNCTION number_catalog_changes();
+
+CREATE OR REPLACE FUNCTION record_catalog_change(change_kind text, change_target bigint, change_deleted boolean)
+RETURNS void AS $$
+BEGIN
+    -- Writers take turns so that versions become visible in the order they
+    -- were handed out, otherwise a client could sync past a version that
+    -- was still uncommitted.  Readers aren't blocked.
+    LOCK TABLE catalog_change IN EXCLUSIVE MODE;
+    INSERT INTO catalog_change (kind, target_id, deleted)
+    VALUES (change_kind, change_target, change_deleted)
+    ON CONFLICT (kind, target_id) DO UPDATE
+        SET version = EXCLUDED.version, deleted = EXCLUDED.deleted;
+END;
+$$ LANGUAGE plpgsql;
+
+DROP INDEX catalog_change_pending_idx;
+ALTER TABLE "catalog_change"
+    ALTER COLUMN version SET DEFAULT nextval('catalog_version_seq'),
+    ALTER COLUMN version SET NOT NULL;
@@ -0,0 +1,61 @@
+-- Versions used to be handed out under a table lock held until commit, which
+-- made every catalog write wait for every other.  Now changes are recorded
+-- without a version and numbered at commit, under a lock held only for the
+-- commit itself, so that versions still become visible in order.
+ALTER TABLE "catalog_change"
+    ALTER COLUMN version DROP NOT NULL,
+    ALTER COLUMN version DROP DEFAULT;
+
+CREATE INDEX catalog_change_pending_idx ON catalog_change (kind, target_id) WHERE version IS NULL;
+
+CREATE OR REPLACE FUNCTION record_catalog_change(change_kind text, change_target bigint, change_deleted boolean)
+RETURNS void AS $$
+BEGIN
+    INSERT INTO catalog_change (kind, target_id, version, deleted)
+    VALUES (change_kind, change_target, NULL, change_deleted)
+    ON CONFLICT (kind, target_id) DO UPDATE
+        SET version = NULL, deleted = EXCLUDED.deleted;
+END;
+$$ LANGUAGE plpgsql;
+
+-- the first call in a transaction numbers all of its changes, later calls
+-- find nothing left to do
+CREATE FUNCTION number_catalog_changes() RETURNS trigger AS $$
+BEGIN
+    PERFORM pg_advisory_xact_lock(hashtext('catalog_change'));
+    UPDATE catalog_change SET version = nextval('catalog_version_seq')
+    WHERE version IS NULL;
+    RETURN NULL;
+END;
+$$ LANGUAGE plpgsql;
+
+CREATE CONSTRAINT TRIGGER catalog_change_number
+    AFTER INSERT OR UPDATE ON catalog_change
+    DEFERRABLE INITIALLY DEFERRED
+    FOR EACH ROW
+    WHEN (NEW.version IS NULL)
+    EXECUTE PROCEDURE number_catalog_changes();
+
+-- bumped along with any change to the account's play counts and ratings,
+-- so that song listings can tell whether those changed without reading
+-- them all
+ALTER TABLE "account" ADD COLUMN stats_version bigint NOT NULL DEFAULT 0;
+
+CREATE FUNCTION bump_stats_version() RETURNS trigger AS $$
+DECLARE
+    changed jsonb;
+BEGIN
+    IF TG_OP = 'DELETE' THEN
+        changed := to_jsonb(OLD);
+    ELSE
+        changed := to_jsonb(NEW);
+    END IF;
+    UPDATE account SET stats_version = stats_version + 1
+    WHERE id = (changed ->> 'account_id')::uuid;
+    RETURN NULL;
+END;
+$$ LANGUAGE plpgsql;
+
+CREATE TRIGGER account_song_metadata_stats_version
+    AFTER INSERT OR UPDATE OR DELETE ON account_song_metadata
+    FOR EACH ROW EXECUTE PROCEDURE bump_stats_version();
@@ -0,0 +1,117 @@
+//! The standard, padded base64 alphabet of RFC 4648, as used by
+//! `METADATA_BLOCK_PICTURE` comments.
+
+const ALPHABET: &'static [u8] =
+    b"ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz0123456789+/";
+
+const PAD: u8 = b'=';
+
+pub fn encode(data: &[u8]) -> String {
+    let mut out = String::with_capacity((data.len() + 2) / 3 * 4);
+    for chunk in data.chunks(3) {
+        let b0 = chunk[0] as u32;
+        let b1 = chunk.get(1).map(|&b| b as u32).unwrap_or(0);
+        let b2 = chunk.get(2).map(|&b| b as u32).unwrap_or(0);
+        let group = (b0 << 16) | (b1 << 8) | b2;
+
+        out.push(ALPHABET[(group >> 18) as usize & 0x3F] as char);
+        out.push(ALPHABET[(group >> 12) as usize & 0x3F] as char);
+        if chunk.len() > 1 {
+            out.push(ALPHABET[(group >> 6) as usize & 0x3F] as char);
+        } else {
+            out.push(PAD as char);
+        }
+        if chunk.len() > 2 {
+            out.push(ALPHABET[group as usize & 0x3F] as char);
+        } else {
+            out.push(PAD as char);
+        }
+    }
+    out
+}
+
+/// Whitespace is skipped, as some taggers wrap long values.  Padding is
+/// optional but nothing may follow it.
+pub fn decode(text: &str) -> Option<Vec<u8>> {
+    let mut out = Vec::with_capacity(text.len() / 4 * 3);
+    let mut group: u32 = 0;
+    let mut group_len = 0;
+    let mut padding = 0;
+
+    for &c in text.as_bytes().iter() {
+        let value = match c {
+            b'A'...b'Z' => c - b'A',
+            b'a'...b'z' => c - b'a' + 26,
+            b'0'...b'9' => c - b'0' + 52,
+            b'+' => 62,
+            b'/' => 63,
+            PAD => {
+                padding += 1;
+                continue;
+            }
+            b' ' | b'\t' | b'\r' | b'\n' => continue,
+            _ => return None,
+        };
+        if padding > 0 {
+            return None;
+        }
+        group = (group << 6) | value as u32;
+        group_len += 1;
+        if group_len == 4 {
+            out.push((group >> 16) as u8);
+            out.push((group >> 8) as u8);
+            out.push(group as u8);
+            group = 0;
+            group_len = 0;
+        }
+    }
+
+    match group_len {
+        0 => (),
+        // a single character can't make up a byte
+        1 => return None,
+        2 => out.push((group >> 4) as u8),
+        3 => {
+            out.push((group >> 10) as u8);
+            out.push((group >> 2) as u8);
+        }
+        _ => unreachable!(),
+    }
+    if 2 < padding || (padding > 0 && (group_len + padding) % 4 != 0) {
+        return None;
+    }
+    Some(out)
+}
+
+#[cfg(test)]
+mod test {
+    use super::{encode, decode};
+
+    #[test]
+    fn test_encode() {
+        assert_eq!(encode(b""), "");
+        assert_eq!(encode(b"f"), "Zg==");
+        assert_eq!(encode(b"fo"), "Zm8=");
+        assert_eq!(encode(b"foo"), "Zm9v");
+        assert_eq!(encode(b"foobar"), "Zm9vYmFy");
+        assert_eq!(encode(&[0xFB, 0xFF]), "+/8=");
+    }
+
+    #[test]
+    fn test_decode() {
+        assert_eq!(decode("").unwrap(), b"");
+        assert_eq!(decode("Zg==").unwrap(), b"f");
+        assert_eq!(decode("Zm8=").unwrap(), b"fo");
+        assert_eq!(decode("Zm8").unwrap(), b"fo");
+        assert_eq!(decode("Zm9v\nYmFy").unwrap(), b"foobar");
+        assert_eq!(decode("+/8=").unwrap(), &[0xFB, 0xFF]);
+    }
+
+    #[test]
+    fn test_decode_invalid() {
+        assert!(decode("Z").is_none());
+        assert!(decode("Zm9v!").is_none());
+        assert!(decode("Zg==Zm9v").is_none());
+        assert!(decode("Zm8==").is_none());
+    }
+}
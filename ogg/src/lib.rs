@@ -15,6 +15,7 @@ mod reader;
 mod slice;
 pub mod vorbis;
 mod crc;
+mod base64;
 
 use slice::Slice;
 
@@ -5,8 +5,9 @@ use std::ops;
 use std::str;
 use std::convert;
 use std::borrow::{Borrow, BorrowMut, ToOwned};
-use byteorder::{ByteOrder, LittleEndian, ReadBytesExt};
+use byteorder::{ByteOrder, BigEndian, LittleEndian, ReadBytesExt};
 
+use ::base64;
 use ::reader;
 use ::reader::Reader;
 use ::slice::Slice;
@@ -360,11 +361,166 @@ fn split_comment(buffer: &str) -> Result<(&str, &str), VorbisPacketCheckError>{
     }
 }
 
+/// The comment embedding pictures, see `Picture`.
+pub const METADATA_BLOCK_PICTURE: &'static str = "METADATA_BLOCK_PICTURE";
+
+/// The picture type of a front cover.
+pub const PICTURE_TYPE_FRONT_COVER: u32 = 3;
+
+#[derive(Debug)]
+pub enum PictureError {
+    BadBase64,
+    Truncated,
+    Invalid(&'static str),
+}
+
+impl convert::From<reader::Error> for PictureError {
+    fn from(e: reader::Error) -> PictureError {
+        match e {
+            reader::Error::Truncated => PictureError::Truncated,
+        }
+    }
+}
+
+impl convert::From<str::Utf8Error> for PictureError {
+    fn from(_e: str::Utf8Error) -> PictureError {
+        PictureError::Invalid("invalid utf8 in picture")
+    }
+}
+
+/// A picture embedded in a `METADATA_BLOCK_PICTURE` comment, which holds a
+/// FLAC picture block in base64.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Picture {
+    /// ID3v2 APIC picture type, e.g. `PICTURE_TYPE_FRONT_COVER`
+    pub picture_type: u32,
+    pub mime_type: String,
+    pub description: String,
+    pub width: u32,
+    pub height: u32,
+    /// bits per pixel
+    pub depth: u32,
+    /// number of colors of an indexed picture, 0 otherwise
+    pub colors: u32,
+    pub data: Vec<u8>,
+}
+
+impl Picture {
+    pub fn from_comment(value: &str) -> Result<Picture, PictureError> {
+        let buf = try!(base64::decode(value).ok_or(PictureError::BadBase64));
+        Picture::parse(&buf)
+    }
+
+    /// Reads a FLAC picture block, which is big-endian unlike everything
+    /// else in Vorbis.
+    pub fn parse(buf: &[u8]) -> Result<Picture, PictureError> {
+        let mut reader = Reader::<BigEndian>::new(buf);
+
+        let picture_type = try!(reader.read_u32());
+        let mime_len = try!(reader.read_u32());
+        let mime_buf = try!(reader.read_buffer(mime_len as usize));
+        if !mime_buf.iter().all(|&b| 0x20 <= b && b <= 0x7E) {
+            return Err(PictureError::Invalid("MIME type is not printable ASCII"));
+        }
+        let mime_type = try!(str::from_utf8(mime_buf)).to_string();
+        let description_len = try!(reader.read_u32());
+        let description_buf = try!(reader.read_buffer(description_len as usize));
+        let description = try!(str::from_utf8(description_buf)).to_string();
+
+        let width = try!(reader.read_u32());
+        let height = try!(reader.read_u32());
+        let depth = try!(reader.read_u32());
+        let colors = try!(reader.read_u32());
+        let data_len = try!(reader.read_u32());
+        let data = try!(reader.read_buffer(data_len as usize)).to_vec();
+
+        Ok(Picture {
+            picture_type: picture_type,
+            mime_type: mime_type,
+            description: description,
+            width: width,
+            height: height,
+            depth: depth,
+            colors: colors,
+            data: data,
+        })
+    }
+
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut buf = Vec::with_capacity(32 + self.mime_type.len() + self.description.len() + self.data.len());
+        write_picture_u32(&mut buf, self.picture_type);
+        write_picture_u32(&mut buf, self.mime_type.as_bytes().len() as u32);
+        buf.extend(self.mime_type.as_bytes());
+        write_picture_u32(&mut buf, self.description.as_bytes().len() as u32);
+        buf.extend(self.description.as_bytes());
+        write_picture_u32(&mut buf, self.width);
+        write_picture_u32(&mut buf, self.height);
+        write_picture_u32(&mut buf, self.depth);
+        write_picture_u32(&mut buf, self.colors);
+        write_picture_u32(&mut buf, self.data.len() as u32);
+        buf.extend(&self.data[..]);
+        buf
+    }
+
+    /// The value of a `METADATA_BLOCK_PICTURE` comment.
+    pub fn to_comment(&self) -> String {
+        base64::encode(&self.to_bytes())
+    }
+}
+
+fn write_picture_u32(buf: &mut Vec<u8>, val: u32) {
+    let mut bytes = [0; 4];
+    BigEndian::write_u32(&mut bytes[..], val);
+    buf.extend(&bytes[..]);
+}
+
+impl Comments {
+    /// Every picture in `METADATA_BLOCK_PICTURE` comments, skipping the
+    /// ones that don't parse.
+    pub fn pictures(&self) -> Vec<Picture> {
+        pictures(&self.comments)
+    }
+}
+
+pub fn pictures(comments: &[(String, String)]) -> Vec<Picture> {
+    comments.iter()
+        .filter(|&&(ref key, _)| key.to_uppercase() == METADATA_BLOCK_PICTURE)
+        .filter_map(|&(_, ref value)| Picture::from_comment(value).ok())
+        .collect()
+}
+
+/// The picture to use as cover art: the first front cover, or else the
+/// first picture of any type.  Pictures given as a link (MIME type `-->`)
+/// are skipped.
+pub fn front_cover(pictures: Vec<Picture>) -> Option<Picture> {
+    let pictures: Vec<Picture> = pictures.into_iter()
+        .filter(|p| p.mime_type != "-->" && p.data.len() > 0)
+        .collect();
+    let front_cover = pictures.iter()
+        .position(|p| p.picture_type == PICTURE_TYPE_FRONT_COVER);
+    pictures.into_iter().nth(front_cover.unwrap_or(0))
+}
+
+/// Comments that embed pictures.  `COVERART` and `COVERARTMIME` are the
+/// older, unofficial way of doing what `METADATA_BLOCK_PICTURE` does.
+const PICTURE_COMMENTS: &'static [&'static str] = &[
+    METADATA_BLOCK_PICTURE,
+    "COVERART",
+    "COVERARTMIME",
+];
+
+/// Whether a comment embeds a picture; field names are case-insensitive.
+pub fn is_picture_field(field: &str) -> bool {
+    let field = field.to_uppercase();
+    PICTURE_COMMENTS.iter().any(|name| field == *name)
+}
+
 
 #[cfg(test)]
 mod test {
     use {OggTrack};
-    use super::{VorbisPacketBuf, VorbisPacket, Comments};
+    use super::{VorbisPacketBuf, VorbisPacket, Comments, Picture, PictureError, front_cover, is_picture_field};
+    use super::PICTURE_TYPE_FRONT_COVER;
 
     #[test]
     fn test_parse_identification_header() {
@@ -564,4 +720,71 @@ mod test {
     fn test_parse_malformed_comment_header_truncated_comments() {
         VorbisPacket::new(COMMENT_HEADER_TRUNCATED_COMMENTS).err().unwrap();
     }
+
+    static PICTURE_BLOCK: &'static [u8] = &[
+        0x00, 0x00, 0x00, 0x03,  // picture type = front cover
+        0x00, 0x00, 0x00, 0x09,  // MIME type length = 9
+        b'i', b'm', b'a', b'g', b'e', b'/', b'p', b'n', b'g',
+        0x00, 0x00, 0x00, 0x05,  // description length = 5
+        b'c', b'o', b'v', b'e', b'r',
+        0x00, 0x00, 0x01, 0xF4,  // width = 500
+        0x00, 0x00, 0x01, 0xF4,  // height = 500
+        0x00, 0x00, 0x00, 0x18,  // depth = 24
+        0x00, 0x00, 0x00, 0x00,  // colors
+        0x00, 0x00, 0x00, 0x04,  // data length = 4
+        0x89, b'P', b'N', b'G',
+    ];
+
+    #[test]
+    fn test_parse_picture() {
+        let picture = Picture::parse(PICTURE_BLOCK).unwrap();
+        assert_eq!(picture.picture_type, PICTURE_TYPE_FRONT_COVER);
+        assert_eq!(picture.mime_type, "image/png");
+        assert_eq!(picture.description, "cover");
+        assert_eq!(picture.width, 500);
+        assert_eq!(picture.height, 500);
+        assert_eq!(picture.depth, 24);
+        assert_eq!(picture.colors, 0);
+        assert_eq!(picture.data, b"\x89PNG");
+        assert_eq!(picture.to_bytes(), PICTURE_BLOCK);
+    }
+
+    #[test]
+    fn test_picture_comment_roundtrip() {
+        let picture = Picture::parse(PICTURE_BLOCK).unwrap();
+        let comments = Comments {
+            vendor: "test".to_string(),
+            comments: vec![
+                ("TITLE".to_string(), "Hydrate".to_string()),
+                ("metadata_block_picture".to_string(), picture.to_comment()),
+            ],
+        };
+        assert_eq!(comments.pictures(), vec![picture]);
+    }
+
+    #[test]
+    fn test_front_cover() {
+        let front = Picture::parse(PICTURE_BLOCK).unwrap();
+        let back = Picture { picture_type: 4, ..front.clone() };
+        let link = Picture { mime_type: "-->".to_string(), data: b"cover.png".to_vec(), ..front.clone() };
+        assert_eq!(front_cover(vec![back.clone(), front.clone()]), Some(front.clone()));
+        assert_eq!(front_cover(vec![link.clone(), back.clone()]), Some(back.clone()));
+        assert_eq!(front_cover(vec![link]), None);
+        assert_eq!(front_cover(Vec::new()), None);
+        assert!(is_picture_field("metadata_block_picture"));
+        assert!(is_picture_field("COVERART"));
+        assert!(!is_picture_field("TITLE"));
+    }
+
+    #[test]
+    fn test_parse_malformed_picture() {
+        match Picture::parse(&PICTURE_BLOCK[..PICTURE_BLOCK.len() - 1]) {
+            Err(PictureError::Truncated) => (),
+            other => panic!("expected a truncated picture, got {:?}", other),
+        }
+        match Picture::from_comment("not base64!") {
+            Err(PictureError::BadBase64) => (),
+            other => panic!("expected bad base64, got {:?}", other),
+        }
+    }
 }
@@ -0,0 +1,160 @@
+//! Measures the loudness of songs imported without ReplayGain tags, then
+//! fills in the album gain of every album whose songs all have one.
+//!
+//!     replaygain-scan postgres://user@localhost/music /srv/music/blobs
+//!
+//! The second argument is the `blob_base` of the blob store.  Loudness is
+//! measured with ffmpeg's `ebur128` filter, so ffmpeg has to be on the
+//! PATH.  Only missing values are filled in, so the scan can be stopped and
+//! run again at any time.
+
+use std::path::{Path, PathBuf};
+use std::process::Command;
+
+extern crate postgres;
+
+use postgres::{Connection, TlsMode};
+
+/// ReplayGain 2.0 aims for -18 LUFS.
+const REFERENCE_LOUDNESS: f64 = -18.0;
+
+/// The album's loudness is the length-weighted energy average of its
+/// tracks', with each track's loudness recovered from its gain.  This is
+/// close to but not quite what measuring the whole album at once gives, as
+/// the gating of quiet passages is done per track.
+const ALBUM_GAIN_UPDATE: &'static str = "
+    UPDATE album SET
+        album_gain = coalesce(album.album_gain, t.gain),
+        album_peak = coalesce(album.album_peak, t.peak)
+    FROM (
+        SELECT
+            s.album_id,
+            round((-10 * log(
+                sum(s.length_ms * power(10, -s.track_gain / 10)) / sum(s.length_ms)
+            ))::numeric, 2)::double precision AS gain,
+            max(s.track_peak) AS peak
+        FROM song AS s
+        GROUP BY s.album_id
+        HAVING bool_and(s.track_gain IS NOT NULL AND s.track_peak IS NOT NULL)
+           AND sum(s.length_ms) > 0
+    ) AS t
+    WHERE album.id = t.album_id
+      AND (album.album_gain IS NULL OR album.album_peak IS NULL)
+";
+
+struct Measurement {
+    /// integrated loudness in LUFS
+    loudness: f64,
+    /// true peak as a fraction of full scale
+    peak: f64,
+}
+
+/// Where the blob store keeps a blob, see `BlobId::relative_path`.
+fn blob_path(blob_base: &Path, blob: &str) -> Option<PathBuf> {
+    if blob.len() < 2 || !blob.is_char_boundary(2) {
+        return None;
+    }
+    Some(blob_base.join(&blob[..2]).join(blob))
+}
+
+fn measure(path: &Path) -> Result<Measurement, String> {
+    let output = try!(Command::new("ffmpeg")
+        .args(&["-nostats", "-hide_banner", "-i"])
+        .arg(path)
+        .args(&["-filter_complex", "ebur128=peak=true", "-f", "null", "-"])
+        .output()
+        .map_err(|e| format!("error running ffmpeg: {}", e)));
+    if !output.status.success() {
+        return Err(format!("ffmpeg failed with {}", output.status));
+    }
+    parse_summary(&String::from_utf8_lossy(&output.stderr))
+        .ok_or_else(|| "no loudness summary in the ffmpeg output".to_string())
+}
+
+/// Reads the summary ebur128 logs once it is done:
+///
+///     Summary:
+///
+///       Integrated loudness:
+///         I:         -16.5 LUFS
+///         ...
+///       True peak:
+///         Peak:       -0.3 dBFS
+fn parse_summary(log: &str) -> Option<Measurement> {
+    let summary = match log.rfind("Summary:") {
+        Some(at) => &log[at..],
+        None => return None,
+    };
+    let mut loudness = None;
+    let mut peak_db = None;
+    for line in summary.lines().map(|l| l.trim()) {
+        if line.starts_with("I:") {
+            loudness = first_number(&line["I:".len()..]);
+        } else if line.starts_with("Peak:") {
+            peak_db = first_number(&line["Peak:".len()..]);
+        }
+    }
+    match (loudness, peak_db) {
+        // silence has a peak of -inf dBFS, which comes out as 0
+        (Some(loudness), Some(peak_db)) => Some(Measurement {
+            loudness: loudness,
+            peak: 10f64.powf(peak_db / 20.0),
+        }),
+        _ => None,
+    }
+}
+
+fn first_number(text: &str) -> Option<f64> {
+    text.split_whitespace().next().and_then(|n| n.parse().ok())
+}
+
+fn main() {
+    let url = std::env::args().nth(1).expect("arg1: postgres url");
+    let blob_base = PathBuf::from(std::env::args().nth(2).expect("arg2: blob directory"));
+
+    let conn = Connection::connect(&url[..], TlsMode::None).unwrap();
+    let rows = conn.query("
+        SELECT id, blob FROM song
+        WHERE track_gain IS NULL OR track_peak IS NULL
+        ORDER BY id
+    ", &[]).unwrap();
+
+    let mut measured = 0;
+    for row in rows.iter() {
+        let id: i64 = row.get(0);
+        let blob: String = row.get(1);
+        let path = match blob_path(&blob_base, &blob) {
+            Some(path) => path,
+            None => {
+                println!("song {}: malformed blob id {:?}", id, blob);
+                continue;
+            }
+        };
+        let measurement = match measure(&path) {
+            Ok(measurement) => measurement,
+            Err(msg) => {
+                println!("song {}: {}", id, msg);
+                continue;
+            }
+        };
+
+        // tags are written with two decimals, so the measurements are too
+        let gain = ((REFERENCE_LOUDNESS - measurement.loudness) * 100.0).round() / 100.0;
+        let peak = (measurement.peak * 1e6).round() / 1e6;
+        let updated = conn.execute("
+            UPDATE song SET
+                track_gain = coalesce(track_gain, $2),
+                track_peak = coalesce(track_peak, $3)
+            WHERE id = $1
+        ", &[&id, &gain, &peak]);
+        if let Err(err) = updated {
+            println!("song {}: {}", id, err);
+            continue;
+        }
+        measured += 1;
+    }
+    println!("measured {} of {} songs", measured, rows.len());
+
+    let albums = conn.execute(ALBUM_GAIN_UPDATE, &[]).unwrap();
+    println!("filled in the gain of {} albums", albums);
+}
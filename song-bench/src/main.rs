@@ -0,0 +1,165 @@
+//! Times the song listing queries against a generated catalog.
+//!
+//!     song-bench postgres://user@localhost/music [songs]
+//!
+//! The driver reads through its own connection, so the catalog has to be
+//! committed.  It is deleted again afterwards, but shows up in the catalog
+//! change log, so better use a scratch database.
+
+use std::io;
+use std::time::Instant;
+
+extern crate postgres;
+extern crate serde_json;
+extern crate music_backend;
+
+use postgres::{Connection, TlsMode};
+use postgres::transaction::Transaction;
+use music_backend::database::SongQuery;
+use music_backend::database::drivers::{self, DbConnector};
+
+const DEFAULT_SONGS: i64 = 100000;
+const TRACKS_PER_ALBUM: i64 = 12;
+const RUNS: u32 = 3;
+
+/// How `get_songs` used to read songs: three correlated subqueries per row,
+/// all rows materialized at once.
+const CORRELATED_QUERY: &'static str = "
+    SELECT
+        s.id,
+        s.blob,
+        s.length_ms,
+        s.track_no,
+        (
+            SELECT jsonb_object_agg(sm.field_name, sm.value)
+            FROM song_metadata AS sm WHERE sm.song_id = s.id
+        ),
+        s.album_id,
+        (SELECT a.art_blob FROM album AS a WHERE s.album_id = a.id),
+        (
+            SELECT jsonb_object_agg(am.field_name, am.value)
+            FROM album_metadata AS am WHERE am.album_id = s.album_id
+        )
+    FROM song AS s
+    ORDER BY s.id
+";
+
+/// Adds albums of `TRACKS_PER_ALBUM` songs until there are at least `songs`
+/// new songs.  Returns how many were added and the id of the last album
+/// that was already there.
+fn generate_catalog(trans: &Transaction, songs: i64) -> postgres::Result<(i64, i64)> {
+    let rows = try!(trans.query("SELECT coalesce(max(id), 0) FROM album", &[]));
+    let first_album: i64 = rows.get(0).get(0);
+    let albums = (songs + TRACKS_PER_ALBUM - 1) / TRACKS_PER_ALBUM;
+
+    try!(trans.execute("
+        WITH new_album AS (
+            INSERT INTO album (art_blob)
+            SELECT md5('art:' || g) FROM generate_series(1, $1) AS g
+            RETURNING id
+        )
+        INSERT INTO album_metadata (album_id, field_name, value)
+        SELECT a.id, f.name, initcap(lower(f.name)) || ' ' || a.id
+        FROM new_album AS a
+        CROSS JOIN (VALUES ('ALBUM'), ('ARTIST'), ('DATE'), ('GENRE')) AS f(name)
+    ", &[&albums]));
+
+    try!(trans.execute("
+        WITH new_song AS (
+            INSERT INTO song (blob, album_id, track_no, length_ms)
+            SELECT
+                md5(a.id || ':' || t),
+                a.id,
+                t,
+                120000 + (a.id * 7919 + t * 104729) % 240000
+            FROM album AS a
+            CROSS JOIN generate_series(1, $2::int) AS t
+            WHERE a.id > $1
+            RETURNING id, track_no
+        )
+        INSERT INTO song_metadata (song_id, field_name, value)
+        SELECT s.id, f.name, CASE f.name
+            WHEN 'TRACKNUMBER' THEN s.track_no::text
+            ELSE initcap(lower(f.name)) || ' ' || s.id
+        END
+        FROM new_song AS s
+        CROSS JOIN (VALUES ('TITLE'), ('TRACKNUMBER'), ('ARTIST')) AS f(name)
+    ", &[&first_album, &(TRACKS_PER_ALBUM as i32)]));
+
+    Ok((albums * TRACKS_PER_ALBUM, first_album))
+}
+
+/// Deletes the albums after `first_album` along with their songs.
+fn delete_catalog(trans: &Transaction, first_album: i64) -> postgres::Result<()> {
+    try!(trans.execute("
+        DELETE FROM song_metadata AS sm
+        USING song AS s
+        WHERE sm.song_id = s.id AND s.album_id > $1
+    ", &[&first_album]));
+    try!(trans.execute("DELETE FROM song WHERE album_id > $1", &[&first_album]));
+    try!(trans.execute("DELETE FROM album_metadata WHERE album_id > $1", &[&first_album]));
+    try!(trans.execute("DELETE FROM album WHERE id > $1", &[&first_album]));
+    Ok(())
+}
+
+fn run_correlated(conn: &Connection) -> io::Result<usize> {
+    let rows = try!(conn.query(CORRELATED_QUERY, &[]));
+    for row in rows.iter() {
+        let _: Option<serde_json::Value> = row.get(4);
+        let _: Option<serde_json::Value> = row.get(7);
+    }
+    Ok(rows.len())
+}
+
+/// What `GET /songs` does.
+fn run_driver(driver: &DbConnector) -> io::Result<usize> {
+    let mut count = 0;
+    try!(driver.visit_songs(&SongQuery::all(), &mut |_song| {
+        count += 1;
+        Ok(())
+    }));
+    Ok(count)
+}
+
+fn time<F>(name: &str, mut run: F) -> io::Result<()>
+    where F: FnMut() -> io::Result<usize>
+{
+    let mut best = None;
+    let mut count = 0;
+    for _ in 0..RUNS {
+        let started = Instant::now();
+        count = try!(run());
+        let elapsed = started.elapsed();
+        let ms = elapsed.as_secs() * 1000 + (elapsed.subsec_nanos() / 1000000) as u64;
+        if best.map(|b| ms < b).unwrap_or(true) {
+            best = Some(ms);
+        }
+    }
+    println!("{:<12} {:>8} rows  best of {}: {} ms", name, count, RUNS, best.unwrap_or(0));
+    Ok(())
+}
+
+fn main() {
+    let url = std::env::args().nth(1).expect("arg1: postgres url");
+    let songs: i64 = std::env::args().nth(2)
+        .map(|n| n.parse().expect("arg2: song count"))
+        .unwrap_or(DEFAULT_SONGS);
+
+    let conn = Connection::connect(&url[..], TlsMode::None).unwrap();
+    let driver = drivers::get_driver(&url).unwrap();
+
+    let started = Instant::now();
+    let trans = conn.transaction().unwrap();
+    let (generated, first_album) = generate_catalog(&trans, songs).unwrap();
+    trans.commit().unwrap();
+    conn.batch_execute("ANALYZE album, album_metadata, song, song_metadata").unwrap();
+    println!("generated {} songs in {} s", generated, started.elapsed().as_secs());
+
+    let result = time("correlated", || run_correlated(&conn))
+        .and_then(|()| time("driver", || run_driver(&*driver)));
+
+    let trans = conn.transaction().unwrap();
+    delete_catalog(&trans, first_album).unwrap();
+    trans.commit().unwrap();
+    result.unwrap();
+}
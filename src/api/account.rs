@@ -0,0 +1,155 @@
+use rocket::{Response, Route, State};
+use rocket::response::{Failure, Responder};
+use rocket::http::Status;
+use rocket_contrib::JSON as Json;
+
+use ::auth::AuthTokenBlob;
+use ::config::AppConfig;
+use ::database::{AccountId, AccountUpdate, HistoryCursor, HistoryQuery, DISPLAY_NAME_MAX};
+use ::database::drivers::DbConnector;
+use ::rpc;
+use ::util::unix_now;
+use super::{read_conn, write_conn, db_failure, error_response};
+
+/// listens are read back this many at a time for an export
+const EXPORT_HISTORY_PAGE: u32 = 1000;
+
+pub fn routes() -> Vec<Route> {
+    routes![
+        me_options,
+        me_get,
+        me_patch,
+        me_delete,
+        me_export_options,
+        me_export_get,
+    ]
+}
+
+fn account_response(conn: &DbConnector, account: &AccountId) -> Result<Response<'static>, Failure> {
+    let profile = conn.get_account(account)
+        .map_err(db_failure)?
+        .ok_or(Failure(Status::NotFound))?;
+    let linked_accounts = conn.get_linked_accounts(account).map_err(db_failure)?;
+
+    Ok(::wrap_json(&rpc::AccountResponse {
+        account: profile,
+        linked_accounts: linked_accounts,
+    }))
+}
+
+#[options("/me")]
+fn me_options() -> impl Responder<'static> {
+    ::cors_options()
+}
+
+#[get("/me")]
+fn me_get(config: State<AppConfig>, auth: AuthTokenBlob) -> Result<Response<'static>, Failure> {
+    let account = config.validate_auth(&auth)?;
+
+    account_response(&*read_conn(&config)?, &account)
+}
+
+#[patch("/me", format="application/json", data="<req>")]
+fn me_patch(config: State<AppConfig>, auth: AuthTokenBlob, req: Json<rpc::AccountUpdateRequest>)
+    -> Result<Response<'static>, Failure>
+{
+    let account = config.validate_auth(&auth)?;
+    let Json(req) = req;
+
+    let display_name = req.display_name.map(|n| n.trim().to_string());
+    if let Some(ref display_name) = display_name {
+        if DISPLAY_NAME_MAX < display_name.chars().count() {
+            return Ok(error_response(Status::BadRequest, "invalid-account",
+                format!("display name is longer than {} characters", DISPLAY_NAME_MAX)));
+        }
+    }
+
+    let mut conn = write_conn(&config)?;
+    conn.update_account(&account, &AccountUpdate {
+        display_name: display_name,
+    }).map_err(db_failure)?;
+
+    account_response(&*conn, &account)
+}
+
+#[delete("/me")]
+fn me_delete(config: State<AppConfig>, auth: AuthTokenBlob) -> Result<Response<'static>, Failure> {
+    let account = config.validate_auth(&auth)?;
+
+    let mut conn = write_conn(&config)?;
+    let profile = conn.get_account(&account)
+        .map_err(db_failure)?
+        .ok_or(Failure(Status::NotFound))?;
+    conn.delete_account(&account).map_err(db_failure)?;
+
+    // what was removed, for the client's confirmation
+    Ok(::wrap_json(&rpc::AccountResponse {
+        account: profile,
+        linked_accounts: Vec::new(),
+    }))
+}
+
+#[options("/me/export")]
+fn me_export_options() -> impl Responder<'static> {
+    ::cors_options()
+}
+
+#[get("/me/export")]
+fn me_export_get(config: State<AppConfig>, auth: AuthTokenBlob) -> Result<Response<'static>, Failure> {
+    let account = config.validate_auth(&auth)?;
+
+    let conn = read_conn(&config)?;
+    let profile = conn.get_account(&account)
+        .map_err(db_failure)?
+        .ok_or(Failure(Status::NotFound))?;
+
+    let now = unix_now();
+    let mut listens = Vec::new();
+    let mut after = None;
+    loop {
+        let page = conn.get_history(&account, &HistoryQuery {
+            from: config.history.query_from(None, now),
+            to: None,
+            after: after,
+            limit: EXPORT_HISTORY_PAGE,
+        }).map_err(db_failure)?;
+        after = page.last().map(HistoryCursor::of);
+        let last_page = page.len() < EXPORT_HISTORY_PAGE as usize;
+        listens.extend(page.into_iter().map(|listen| rpc::ExportedListen {
+            song_id: listen.song.id,
+            started_at: listen.started_at,
+            position_ms: listen.position_ms,
+            duration_ms: listen.duration_ms,
+            client_id: listen.client_id,
+        }));
+        if last_page {
+            break;
+        }
+    }
+
+    let mut playlists = Vec::new();
+    for playlist in conn.get_playlists(&account).map_err(db_failure)?.into_iter() {
+        let entries = match conn.get_playlist(&account, &playlist.id).map_err(db_failure)? {
+            Some((_, entries)) => entries,
+            // deleted while we were exporting
+            None => continue,
+        };
+        playlists.push(rpc::ExportedPlaylist {
+            playlist: playlist,
+            song_ids: entries.into_iter().map(|e| e.song.id).collect(),
+        });
+    }
+
+    let mut resp = ::wrap_json(&rpc::AccountExport {
+        exported_at: now,
+        account: profile,
+        linked_accounts: conn.get_linked_accounts(&account).map_err(db_failure)?,
+        song_stats: conn.get_song_stats(&account).map_err(db_failure)?,
+        listens: listens,
+        playlists: playlists,
+        smart_playlists: conn.get_smart_playlists(&account).map_err(db_failure)?,
+        queue: conn.get_play_queue(&account).map_err(db_failure)?,
+    });
+    resp.set_raw_header("Content-Disposition", "attachment; filename=\"account.json\"");
+    Ok(resp)
+}
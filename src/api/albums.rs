@@ -0,0 +1,307 @@
+use std::collections::BTreeMap;
+use std::io::Read;
+
+use rocket::{Response, Route, State};
+use rocket::response::{Failure, Responder};
+use rocket::http::Status;
+use rocket_contrib::JSON as Json;
+use ogg::vorbis::{self, Picture};
+
+use ::auth::AuthTokenBlob;
+use ::blob::BlobId;
+use ::config::{AppConfig, VfsBackend};
+use ::database::{AlbumCreate, AlbumQuery, SongCreate, check_metadata_field};
+use ::model::{
+    AlbumId,
+    disc_number,
+    track_number,
+    is_replaygain_field,
+    uppercase_replaygain_fields,
+    track_replaygain,
+    album_replaygain,
+};
+use ::lyrics;
+use ::media;
+use ::rpc;
+use super::{read_conn, write_conn, db_failure, error_response};
+
+const ALBUMS_PER_PAGE_DEFAULT: i64 = 50;
+const ALBUMS_PER_PAGE_MAX: i64 = 500;
+
+pub fn routes() -> Vec<Route> {
+    routes![
+        albums_options,
+        albums_get,
+        albums_get_query,
+        album_get,
+        albums_post,
+    ]
+}
+
+#[derive(FromForm, Debug)]
+struct AlbumListParams {
+    // metadata field, e.g. `artist` or `date`
+    sort: Option<String>,
+    // `asc` or `desc`
+    order: Option<String>,
+    page: Option<i64>,
+    per_page: Option<i64>,
+}
+
+#[get("/albums?<params>", rank = 1)]
+fn albums_get_query(config: State<AppConfig>, auth: AuthTokenBlob, params: AlbumListParams)
+    -> Result<Response<'static>, Failure>
+{
+    config.validate_auth(&auth)?;
+
+    let per_page = params.per_page.unwrap_or(ALBUMS_PER_PAGE_DEFAULT);
+    if per_page < 1 || ALBUMS_PER_PAGE_MAX < per_page {
+        return Ok(error_response(Status::BadRequest, "invalid-parameter",
+            format!("per_page must be between 1 and {}", ALBUMS_PER_PAGE_MAX)));
+    }
+    let page = params.page.unwrap_or(0);
+    if page < 0 {
+        return Ok(error_response(Status::BadRequest, "invalid-parameter",
+            "page must not be negative".into()));
+    }
+    let offset = match page.checked_mul(per_page) {
+        Some(offset) => offset,
+        None => {
+            return Ok(error_response(Status::BadRequest, "invalid-parameter",
+                "page is out of range".into()));
+        }
+    };
+    let descending = match params.order.as_ref().map(|s| &s[..]) {
+        None | Some("asc") => false,
+        Some("desc") => true,
+        Some(other) => {
+            return Ok(error_response(Status::BadRequest, "invalid-parameter",
+                format!("unknown order {:?}", other)));
+        }
+    };
+
+    let query = AlbumQuery {
+        sort_field: params.sort.map(|field| field.to_uppercase()),
+        descending: descending,
+        limit: per_page,
+        offset: offset,
+    };
+    let albums = read_conn(&config)?
+        .get_albums(&query)
+        .map_err(db_failure)?;
+
+    Ok(::wrap_json(&rpc::AlbumSetResponse { results: albums }))
+}
+
+#[get("/albums", rank = 2)]
+fn albums_get(config: State<AppConfig>, auth: AuthTokenBlob) -> Result<Response<'static>, Failure> {
+    albums_get_query(config, auth, AlbumListParams {
+        sort: None,
+        order: None,
+        page: None,
+        per_page: None,
+    })
+}
+
+#[get("/albums/<id>")]
+fn album_get(config: State<AppConfig>, auth: AuthTokenBlob, id: i64) -> Result<Response<'static>, Failure> {
+    config.validate_auth(&auth)?;
+
+    let (album, songs) = read_conn(&config)?
+        .get_album(&AlbumId(id))
+        .map_err(db_failure)?
+        .ok_or(Failure(Status::NotFound))?;
+
+    Ok(::wrap_json(&rpc::AlbumResponse {
+        album: album,
+        songs: songs,
+    }))
+}
+
+#[options("/albums")]
+fn albums_options() -> impl Responder<'static> {
+    ::cors_options()
+}
+
+#[post("/albums", format="application/json", data="<req>")]
+fn albums_post(config: State<AppConfig>, auth: AuthTokenBlob, req: Json<rpc::AlbumCreateRequest>)
+    -> Result<Response<'static>, Failure>
+{
+    config.authorize_catalog_edit(&auth)?;
+    let Json(req) = req;
+
+    if req.songs.len() == 0 {
+        return Ok(error_response(Status::BadRequest,
+            "empty-album", "an album needs at least one song".into()));
+    }
+    // committing a staged blob moves it away, so each can only be used once
+    for (idx, song) in req.songs.iter().enumerate() {
+        if req.songs[..idx].iter().any(|other| other.blob.0 == song.blob.0) {
+            return Ok(error_response(Status::BadRequest,
+                "duplicate-blob", format!("staged blob {} is used twice", song.blob.0)));
+        }
+    }
+
+    let vfs = config.vfs_driver.boxed();
+    let mut probed = Vec::new();
+    for song in req.songs.iter() {
+        let mut buf = Vec::new();
+        let read_result = vfs.open_staged(&song.blob)
+            .and_then(|mut staged| staged.read_to_end(&mut buf));
+        if let Err(err) = read_result {
+            println!("error reading staged blob {}: {}", song.blob.0, err);
+            return Ok(error_response(Status::BadRequest,
+                "unknown-blob", format!("unknown staged blob {}", song.blob.0)));
+        }
+
+        let info = match media::probe(&buf) {
+            Ok(info) => info,
+            Err(err) => {
+                return Ok(error_response(Status::BadRequest,
+                    "invalid-blob", format!("staged blob {}: {}", song.blob.0, err)));
+            }
+        };
+        probed.push(info);
+    }
+    let art = album_art(&probed);
+
+    // the album gain is the same in every song's comments if present
+    let req_metadata = uppercase_replaygain_fields(&req.metadata);
+    let (mut album_gain, mut album_peak) = album_replaygain(&req_metadata);
+    let mut songs = Vec::new();
+    for (idx, (song, info)) in req.songs.iter().zip(probed.iter()).enumerate() {
+        let mut metadata = media::comment_map(&info.comments);
+        for (key, val) in uppercase_replaygain_fields(&song.metadata).into_iter() {
+            metadata.insert(key, val);
+        }
+        if album_gain.is_none() {
+            let (gain, peak) = album_replaygain(&metadata);
+            album_gain = gain;
+            album_peak = peak;
+        }
+        let (track_gain, track_peak) = track_replaygain(&metadata);
+
+        // stored as typed values or blobs instead
+        let typed_fields: Vec<String> = metadata.keys()
+            .filter(|key| lyrics::is_lyrics_field(key) || is_replaygain_field(key) || vorbis::is_picture_field(key))
+            .cloned()
+            .collect();
+        for key in typed_fields.iter() {
+            metadata.remove(key);
+        }
+        for (key, val) in metadata.iter() {
+            if let Err(msg) = check_metadata_field(key, Some(val)) {
+                return Ok(error_response(Status::BadRequest, "invalid-metadata",
+                    format!("staged blob {}: {}", song.blob.0, msg)));
+            }
+        }
+
+        songs.push(SongCreate {
+            blob: format!("{}", info.blob_id),
+            disc_no: disc_number(&metadata, &req.metadata),
+            track_no: track_number(&metadata).unwrap_or(idx as i16 + 1),
+            length_ms: info.length_ms,
+            audio_digest: Some(info.audio_digest.clone()),
+            metadata: metadata,
+            track_gain: track_gain,
+            track_peak: track_peak,
+            variant: info.variant(),
+            lyrics: lyrics::from_comments(&info.comments, info.length_ms),
+        });
+    }
+
+    for (idx, song) in songs.iter().enumerate() {
+        let taken = songs[..idx].iter()
+            .any(|other| other.disc_no == song.disc_no && other.track_no == song.track_no);
+        if taken {
+            return Ok(error_response(Status::BadRequest, "duplicate-track",
+                format!("duplicate track number {} on disc {}", song.track_no, song.disc_no)));
+        }
+    }
+
+    let mut album_metadata = unified_metadata(&songs);
+    let album_fields = req_metadata.iter()
+        .filter(|&(key, _)| !is_replaygain_field(key) && !vorbis::is_picture_field(key));
+    for (key, val) in album_fields {
+        if let Err(msg) = check_metadata_field(key, Some(val)) {
+            return Ok(error_response(Status::BadRequest, "invalid-metadata", msg));
+        }
+        album_metadata.insert(key.clone(), val.clone());
+    }
+    for song in songs.iter_mut() {
+        for key in album_metadata.keys() {
+            song.metadata.remove(key);
+        }
+    }
+
+    // Blobs are content-addressed, so committing them before the database
+    // transaction at worst leaves an unreferenced blob behind.
+    for (song, info) in req.songs.iter().zip(probed.iter()) {
+        vfs.commit_staged(&song.blob, &info.blob_id)
+            .map_err(|e| {
+                println!("error committing blob {}: {}", song.blob.0, e);
+                Failure(Status::InternalServerError)
+            })?;
+    }
+    let art_blob = match art {
+        Some(picture) => Some(store_picture(&*vfs, &picture)?),
+        None => None,
+    };
+
+    let mut conn = write_conn(&config)?;
+    let created = conn.create_album(&AlbumCreate {
+        art_blob: art_blob.map(|blob_id| format!("{}", blob_id)),
+        metadata: album_metadata,
+        album_gain: album_gain,
+        album_peak: album_peak,
+        songs: songs,
+    }).map_err(db_failure)?;
+
+    Ok(::wrap_json(&rpc::AlbumCreateResponse { songs: created }))
+}
+
+/// The first front cover embedded in any of the songs, or else the first
+/// picture of any type.
+fn album_art(probed: &[media::TrackInfo]) -> Option<Picture> {
+    let pictures = probed.iter()
+        .flat_map(|info| vorbis::pictures(&info.comments))
+        .collect();
+    vorbis::front_cover(pictures)
+}
+
+fn store_picture(vfs: &VfsBackend, picture: &Picture) -> Result<BlobId, Failure> {
+    let blob_id = media::blob_id_of(&picture.data);
+    let staged = vfs.stage_write(&mut &picture.data[..])
+        .map_err(|e| {
+            println!("error staging album art: {}", e);
+            Failure(Status::InternalServerError)
+        })?;
+    vfs.commit_staged(&staged, &blob_id)
+        .map_err(|e| {
+            println!("error committing album art {}: {}", blob_id, e);
+            Failure(Status::InternalServerError)
+        })?;
+    Ok(blob_id)
+}
+
+fn unified_metadata(songs: &[SongCreate]) -> BTreeMap<String, String> {
+    let mut song_iter = songs.iter();
+    let mut min = match song_iter.next() {
+        Some(song) => song.metadata.clone(),
+        None => return BTreeMap::new(),
+    };
+    for song in song_iter {
+        let mut remove_keys = Vec::new();
+        for (key, val) in min.iter() {
+            if song.metadata.get(key) != Some(val) {
+                remove_keys.push(key.clone());
+            }
+        }
+        for key in remove_keys.into_iter() {
+            min.remove(&key);
+        }
+    }
+    min.remove("TITLE");
+    min.remove("TRACKNUMBER");
+    min
+}
@@ -0,0 +1,100 @@
+use rocket::{Response, Route, State};
+use rocket::response::{Failure, Responder};
+use rocket::http::Status;
+use rocket_contrib::JSON as Json;
+
+use ::auth::AuthTokenBlob;
+use ::config::AppConfig;
+use ::database::{SongFilter, SongQuery};
+use ::model::ArtistId;
+use ::rpc;
+use super::{read_conn, write_conn, db_failure, error_response};
+
+pub fn routes() -> Vec<Route> {
+    routes![
+        artists_options,
+        artists_get,
+        artist_options,
+        artist_get,
+        artist_merge_options,
+        artist_merge_post,
+    ]
+}
+
+#[options("/artists")]
+fn artists_options() -> impl Responder<'static> {
+    ::cors_options()
+}
+
+#[get("/artists")]
+fn artists_get(config: State<AppConfig>, auth: AuthTokenBlob) -> Result<Response<'static>, Failure> {
+    config.validate_auth(&auth)?;
+
+    let artists = read_conn(&config)?
+        .get_artists()
+        .map_err(db_failure)?;
+
+    Ok(::wrap_json(&rpc::ArtistSetResponse { results: artists }))
+}
+
+#[options("/artists/<id>")]
+fn artist_options(id: i64) -> impl Responder<'static> {
+    ::cors_options()
+}
+
+#[get("/artists/<id>")]
+fn artist_get(config: State<AppConfig>, auth: AuthTokenBlob, id: i64) -> Result<Response<'static>, Failure> {
+    let account = config.validate_auth(&auth)?;
+
+    let conn = read_conn(&config)?;
+    let (artist, albums) = conn.get_artist(&ArtistId(id))
+        .map_err(db_failure)?
+        .ok_or(Failure(Status::NotFound))?;
+
+    let mut query = SongQuery::all();
+    query.account = Some(account);
+    query.filters.push(SongFilter::Artist(artist.id.clone()));
+    let songs = conn.get_songs(&query).map_err(db_failure)?;
+
+    Ok(::wrap_json(&rpc::ArtistResponse {
+        artist: artist,
+        albums: albums,
+        songs: songs,
+    }))
+}
+
+#[options("/artists/<id>/merge")]
+fn artist_merge_options(id: i64) -> impl Responder<'static> {
+    ::cors_options()
+}
+
+#[post("/artists/<id>/merge", format="application/json", data="<req>")]
+fn artist_merge_post(config: State<AppConfig>, auth: AuthTokenBlob, id: i64, req: Json<rpc::ArtistMergeRequest>)
+    -> Result<Response<'static>, Failure>
+{
+    let account = config.authorize_catalog_edit(&auth)?;
+    let Json(req) = req;
+
+    if req.duplicates.len() == 0 {
+        return Ok(error_response(Status::BadRequest, "invalid-merge",
+            "no duplicates to merge".into()));
+    }
+
+    let id = ArtistId(id);
+    let mut conn = write_conn(&config)?;
+    conn.merge_artists(&id, &req.duplicates).map_err(db_failure)?;
+
+    let (artist, albums) = conn.get_artist(&id)
+        .map_err(db_failure)?
+        .ok_or(Failure(Status::NotFound))?;
+    let mut query = SongQuery::all();
+    query.account = Some(account);
+    query.filters.push(SongFilter::Artist(id));
+    let songs = conn.get_songs(&query).map_err(db_failure)?;
+
+    Ok(::wrap_json(&rpc::ArtistResponse {
+        artist: artist,
+        albums: albums,
+        songs: songs,
+    }))
+}
@@ -0,0 +1,179 @@
+use std::collections::HashMap;
+use std::io::Read;
+
+use rocket::{Response, Route, State};
+use rocket::response::{Failure, Responder};
+use rocket::http::Status;
+use rocket_contrib::JSON as Json;
+
+use ::auth::AuthTokenBlob;
+use ::blob::BlobId;
+use ::config::AppConfig;
+use ::database::{SongFilter, SongQuery};
+use ::database::drivers::DbConnector;
+use ::media;
+use ::model::{DuplicateGroup, DuplicateKind, Song, SongId};
+use ::rpc;
+use super::{read_conn, write_conn, db_failure, error_response};
+
+const TOLERANCE_MS_DEFAULT: i32 = 2000;
+const TOLERANCE_MS_MAX: i32 = 60000;
+
+/// songs digested per backfill request, each one means reading its blob
+const BACKFILL_BATCH: usize = 100;
+
+pub fn routes() -> Vec<Route> {
+    routes![
+        duplicates_options,
+        duplicates_get,
+        duplicates_get_query,
+        duplicates_merge_options,
+        duplicates_merge_post,
+        audio_digests_options,
+        audio_digests_post,
+    ]
+}
+
+#[derive(FromForm, Debug)]
+struct DuplicateParams {
+    // how much lengths may differ for a metadata match
+    tolerance_ms: Option<i32>,
+}
+
+/// Records the audio digest of up to `BACKFILL_BATCH` songs imported before
+/// it was tracked.  Songs whose blob can't be read are left for the next
+/// run.  Returns how many were updated.
+fn backfill_audio_digests(config: &AppConfig, conn: &mut DbConnector) -> Result<usize, Failure> {
+    let vfs = config.vfs_driver.boxed();
+    let pending = conn.get_songs_without_audio_digest().map_err(db_failure)?;
+    let mut updated = 0;
+    for (song_id, blob) in pending.into_iter().take(BACKFILL_BATCH) {
+        let blob_id: BlobId = match blob.parse() {
+            Ok(blob_id) => blob_id,
+            Err(_) => {
+                println!("song {} has a malformed blob id {:?}", song_id.0, blob);
+                continue;
+            }
+        };
+        let mut buf = Vec::new();
+        if let Err(err) = vfs.open_read(&blob_id).and_then(|mut r| r.read_to_end(&mut buf)) {
+            println!("error reading blob {} of song {}: {}", blob, song_id.0, err);
+            continue;
+        }
+        match media::probe(&buf) {
+            Ok(info) => {
+                conn.set_audio_digest(&song_id, &info.audio_digest).map_err(db_failure)?;
+                updated += 1;
+            },
+            Err(err) => println!("error probing blob {} of song {}: {}", blob, song_id.0, err),
+        }
+    }
+    Ok(updated)
+}
+
+fn songs_by_id(conn: &DbConnector, ids: Vec<SongId>) -> Result<HashMap<i64, Song>, Failure> {
+    let mut query = SongQuery::all();
+    query.filters.push(SongFilter::Ids(ids));
+    let songs = conn.get_songs(&query).map_err(db_failure)?;
+    Ok(songs.into_iter().map(|s| (s.id.0, s)).collect())
+}
+
+#[options("/duplicates")]
+fn duplicates_options() -> impl Responder<'static> {
+    ::cors_options()
+}
+
+#[get("/duplicates?<params>", rank = 1)]
+fn duplicates_get_query(config: State<AppConfig>, auth: AuthTokenBlob, params: DuplicateParams)
+    -> Result<Response<'static>, Failure>
+{
+    config.validate_auth(&auth)?;
+
+    let tolerance_ms = params.tolerance_ms.unwrap_or(TOLERANCE_MS_DEFAULT);
+    if tolerance_ms < 0 || TOLERANCE_MS_MAX < tolerance_ms {
+        return Ok(error_response(Status::BadRequest, "invalid-parameter",
+            format!("tolerance_ms must be between 0 and {}", TOLERANCE_MS_MAX)));
+    }
+
+    // songs imported before audio digests were recorded only match once
+    // `POST /duplicates/audio-digests` has caught up with them
+    let conn = read_conn(&config)?;
+
+    let mut groups: Vec<(DuplicateKind, Vec<SongId>)> = Vec::new();
+    for &kind in [DuplicateKind::Blob, DuplicateKind::Audio, DuplicateKind::Metadata].iter() {
+        for ids in conn.find_duplicates(kind, tolerance_ms).map_err(db_failure)? {
+            // identical files have identical audio and usually identical
+            // tags, only report them once
+            let reported = groups.iter().any(|&(_, ref other)| ids.iter().all(|id| other.contains(id)));
+            if !reported {
+                groups.push((kind, ids));
+            }
+        }
+    }
+
+    let all_ids = groups.iter().flat_map(|&(_, ref ids)| ids.iter().cloned()).collect();
+    let songs = songs_by_id(&*conn, all_ids)?;
+
+    let mut results = Vec::new();
+    for (kind, ids) in groups.into_iter() {
+        results.push(DuplicateGroup {
+            kind: kind,
+            songs: ids.iter().filter_map(|id| songs.get(&id.0).cloned()).collect(),
+        });
+    }
+
+    Ok(::wrap_json(&rpc::DuplicateReportResponse { results: results }))
+}
+
+#[get("/duplicates", rank = 2)]
+fn duplicates_get(config: State<AppConfig>, auth: AuthTokenBlob) -> Result<Response<'static>, Failure> {
+    duplicates_get_query(config, auth, DuplicateParams { tolerance_ms: None })
+}
+
+#[options("/duplicates/merge")]
+fn duplicates_merge_options() -> impl Responder<'static> {
+    ::cors_options()
+}
+
+#[post("/duplicates/merge", format="application/json", data="<req>")]
+fn duplicates_merge_post(config: State<AppConfig>, auth: AuthTokenBlob, req: Json<rpc::DuplicateMergeRequest>)
+    -> Result<Response<'static>, Failure>
+{
+    config.authorize_catalog_edit(&auth)?;
+    let Json(req) = req;
+
+    if req.duplicates.len() == 0 {
+        return Ok(error_response(Status::BadRequest, "invalid-merge",
+            "no duplicates to merge".into()));
+    }
+
+    let mut conn = write_conn(&config)?;
+    conn.merge_songs(&req.keep, &req.duplicates).map_err(db_failure)?;
+
+    let song = songs_by_id(&*conn, vec![req.keep.clone()])?
+        .remove(&req.keep.0)
+        .ok_or(Failure(Status::NotFound))?;
+
+    Ok(::wrap_json(&rpc::DuplicateMergeResponse { song: song }))
+}
+
+#[options("/duplicates/audio-digests")]
+fn audio_digests_options() -> impl Responder<'static> {
+    ::cors_options()
+}
+
+/// Works through the songs lacking an audio digest a batch at a time, to be
+/// repeated until none remain.
+#[post("/duplicates/audio-digests")]
+fn audio_digests_post(config: State<AppConfig>, auth: AuthTokenBlob) -> Result<Response<'static>, Failure> {
+    config.authorize_catalog_edit(&auth)?;
+
+    let mut conn = write_conn(&config)?;
+    let updated = backfill_audio_digests(&config, &mut *conn)?;
+    let remaining = conn.get_songs_without_audio_digest().map_err(db_failure)?.len();
+
+    Ok(::wrap_json(&rpc::AudioDigestBackfillResponse {
+        updated: updated,
+        remaining: remaining,
+    }))
+}
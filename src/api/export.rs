@@ -0,0 +1,216 @@
+use std::io;
+
+use rocket::{Response, Route, State};
+use rocket::response::{Failure, Responder};
+use rocket::http::Status;
+
+use ::auth::{AuthTokenBlob, signed_blob_url};
+use ::blob::BlobId;
+use ::config::AppConfig;
+use ::export::{ExportFormat, render};
+use ::model::{AlbumId, PlaylistId, Song};
+use ::rpc;
+use ::util::unix_now;
+use super::{read_conn, db_failure, error_response};
+use super::songs::{SongListParams, song_query};
+
+/// how long signed links in an export keep working
+const SIGNED_LINK_TTL_SECS: i64 = 7 * 24 * 60 * 60;
+
+pub fn routes() -> Vec<Route> {
+    routes![
+        export_album_options,
+        export_album_get,
+        export_album_get_query,
+        export_playlist_options,
+        export_playlist_get,
+        export_playlist_get_query,
+        export_songs_options,
+        export_songs_get,
+        export_songs_get_query,
+    ]
+}
+
+#[derive(FromForm, Debug)]
+struct ExportParams {
+    // m3u8 (the default), xspf or json
+    format: Option<String>,
+    // `signed` URLs (the default) or paths `relative` to the blob store
+    locations: Option<String>,
+}
+
+/// `ExportParams` along with the filters of `GET /songs`.
+#[derive(FromForm, Debug)]
+struct ExportSongParams {
+    format: Option<String>,
+    locations: Option<String>,
+    rating: Option<i32>,
+    min_rating: Option<i32>,
+    max_rating: Option<i32>,
+    metadata: Option<String>,
+    sort: Option<String>,
+}
+
+#[derive(Debug, Clone, Copy)]
+enum Locations {
+    Signed,
+    Relative,
+}
+
+fn parse_params(params: &ExportParams) -> Result<(ExportFormat, Locations), String> {
+    let format = match params.format {
+        Some(ref raw) => match ExportFormat::parse(raw) {
+            Some(format) => format,
+            None => return Err(format!("unknown format {:?}, expected m3u8, xspf or json", raw)),
+        },
+        None => ExportFormat::M3u8,
+    };
+    let locations = match params.locations.as_ref().map(|l| &l[..]) {
+        Some("signed") | None => Locations::Signed,
+        Some("relative") => Locations::Relative,
+        Some(other) => return Err(format!("unknown locations {:?}, expected signed or relative", other)),
+    };
+    Ok((format, locations))
+}
+
+fn location(config: &AppConfig, locations: Locations, expires: i64, song: &Song) -> Result<String, Failure> {
+    let blob_id: BlobId = song.blob.parse().map_err(|_| {
+        println!("error: song {} has a malformed blob id {:?}", song.id.0, song.blob);
+        Failure(Status::InternalServerError)
+    })?;
+    Ok(match locations {
+        Locations::Relative => blob_id.relative_path(),
+        Locations::Signed => signed_blob_url(config, &blob_id, expires),
+    })
+}
+
+fn export_response(config: &AppConfig, params: &ExportParams, filename: &str, title: String, songs: Vec<Song>)
+    -> Result<Response<'static>, Failure>
+{
+    let (format, locations) = match parse_params(params) {
+        Ok(parsed) => parsed,
+        Err(msg) => return Ok(error_response(Status::BadRequest, "invalid-parameter", msg)),
+    };
+
+    let expires = unix_now() + SIGNED_LINK_TTL_SECS;
+    let mut tracks = Vec::with_capacity(songs.len());
+    for song in songs.into_iter() {
+        tracks.push(rpc::ExportedTrack {
+            location: location(config, locations, expires, &song)?,
+            song: song,
+        });
+    }
+    let body = render(format, &rpc::ExportResponse {
+        title: title,
+        tracks: tracks,
+    });
+
+    let mut builder = Response::build();
+    builder.status(Status::Ok);
+    builder.header(format.content_type());
+    builder.raw_header("Content-Disposition",
+        format!("attachment; filename=\"{}.{}\"", filename, format.extension()));
+    if ::ENABLE_CORS {
+        builder.raw_header("Access-Control-Allow-Origin", "*");
+        builder.raw_header("Access-Control-Allow-Methods", "GET, POST, PUT, PATCH, DELETE");
+        builder.raw_header("Access-Control-Allow-Headers", "Content-Type, Authorization");
+    }
+    builder.sized_body(io::Cursor::new(body));
+    Ok(builder.finalize())
+}
+
+#[options("/export/albums/<id>")]
+fn export_album_options(id: i64) -> impl Responder<'static> {
+    ::cors_options()
+}
+
+#[get("/export/albums/<id>?<params>", rank = 1)]
+fn export_album_get_query(config: State<AppConfig>, auth: AuthTokenBlob, id: i64, params: ExportParams)
+    -> Result<Response<'static>, Failure>
+{
+    // signed links must not be handed out to just anyone
+    config.validate_auth(&auth)?;
+
+    let (album, songs) = read_conn(&config)?
+        .get_album(&AlbumId(id))
+        .map_err(db_failure)?
+        .ok_or(Failure(Status::NotFound))?;
+    let title = album.metadata.get("ALBUM").cloned().unwrap_or_else(|| format!("Album {}", id));
+
+    export_response(&config, &params, &format!("album-{}", id), title, songs)
+}
+
+#[get("/export/albums/<id>", rank = 2)]
+fn export_album_get(config: State<AppConfig>, auth: AuthTokenBlob, id: i64) -> Result<Response<'static>, Failure> {
+    export_album_get_query(config, auth, id, ExportParams { format: None, locations: None })
+}
+
+#[options("/export/playlists/<id>")]
+fn export_playlist_options(id: i64) -> impl Responder<'static> {
+    ::cors_options()
+}
+
+#[get("/export/playlists/<id>?<params>", rank = 1)]
+fn export_playlist_get_query(config: State<AppConfig>, auth: AuthTokenBlob, id: i64, params: ExportParams)
+    -> Result<Response<'static>, Failure>
+{
+    let account = config.validate_auth(&auth)?;
+
+    let (playlist, entries) = read_conn(&config)?
+        .get_playlist(&account, &PlaylistId(id))
+        .map_err(db_failure)?
+        .ok_or(Failure(Status::NotFound))?;
+    let songs = entries.into_iter().map(|e| e.song).collect();
+
+    export_response(&config, &params, &format!("playlist-{}", id), playlist.name, songs)
+}
+
+#[get("/export/playlists/<id>", rank = 2)]
+fn export_playlist_get(config: State<AppConfig>, auth: AuthTokenBlob, id: i64) -> Result<Response<'static>, Failure> {
+    export_playlist_get_query(config, auth, id, ExportParams { format: None, locations: None })
+}
+
+#[options("/export/songs")]
+fn export_songs_options() -> impl Responder<'static> {
+    ::cors_options()
+}
+
+#[get("/export/songs?<params>", rank = 1)]
+fn export_songs_get_query(config: State<AppConfig>, auth: AuthTokenBlob, params: ExportSongParams)
+    -> Result<Response<'static>, Failure>
+{
+    let account = config.validate_auth(&auth)?;
+
+    let query = match song_query(Some(account), &SongListParams {
+        rating: params.rating,
+        min_rating: params.min_rating,
+        max_rating: params.max_rating,
+        metadata: params.metadata,
+        sort: params.sort,
+    }) {
+        Ok(query) => query,
+        Err(msg) => return Ok(error_response(Status::BadRequest, "invalid-query", msg)),
+    };
+    let songs = read_conn(&config)?
+        .get_songs(&query)
+        .map_err(db_failure)?;
+
+    let export_params = ExportParams {
+        format: params.format,
+        locations: params.locations,
+    };
+    export_response(&config, &export_params, "songs", "Songs".into(), songs)
+}
+
+#[get("/export/songs", rank = 2)]
+fn export_songs_get(config: State<AppConfig>, auth: AuthTokenBlob) -> Result<Response<'static>, Failure> {
+    export_songs_get_query(config, auth, ExportSongParams {
+        format: None,
+        locations: None,
+        rating: None,
+        min_rating: None,
+        max_rating: None,
+        metadata: None,
+        sort: None,
+    })
+}
@@ -0,0 +1,66 @@
+use rocket::{Response, Route, State};
+use rocket::response::{Failure, Responder};
+use rocket::http::Status;
+
+use ::auth::AuthTokenBlob;
+use ::config::AppConfig;
+use ::database::check_metadata_field;
+use ::rpc;
+use super::{read_conn, db_failure, error_response};
+use super::songs::{SongListParams, song_query};
+
+pub fn routes() -> Vec<Route> {
+    routes![
+        facets_options,
+        facets_get,
+    ]
+}
+
+/// `field` plus the filters of `GET /songs`.
+#[derive(FromForm, Debug)]
+struct FacetParams {
+    // metadata field to list the values of, e.g. `GENRE`
+    field: String,
+    rating: Option<i32>,
+    min_rating: Option<i32>,
+    max_rating: Option<i32>,
+    metadata: Option<String>,
+}
+
+#[options("/facets")]
+fn facets_options() -> impl Responder<'static> {
+    ::cors_options()
+}
+
+#[get("/facets?<params>")]
+fn facets_get(config: State<AppConfig>, auth: AuthTokenBlob, params: FacetParams)
+    -> Result<Response<'static>, Failure>
+{
+    config.validate_auth(&auth)?;
+
+    let field = params.field.to_uppercase();
+    if let Err(msg) = check_metadata_field(&field, None) {
+        return Ok(error_response(Status::BadRequest, "invalid-query", msg));
+    }
+
+    let account = config.validate_auth(&auth).ok();
+    let query = match song_query(account, &SongListParams {
+        rating: params.rating,
+        min_rating: params.min_rating,
+        max_rating: params.max_rating,
+        metadata: params.metadata,
+        sort: None,
+    }) {
+        Ok(query) => query,
+        Err(msg) => return Ok(error_response(Status::BadRequest, "invalid-query", msg)),
+    };
+
+    let facets = read_conn(&config)?
+        .get_facets(&field, &query)
+        .map_err(db_failure)?;
+
+    Ok(::wrap_json(&rpc::FacetResponse {
+        field: field,
+        results: facets,
+    }))
+}
@@ -0,0 +1,95 @@
+use rocket::{Response, Route, State};
+use rocket::response::{Failure, Responder};
+use rocket::http::Status;
+
+use ::auth::AuthTokenBlob;
+use ::config::AppConfig;
+use ::database::{HistoryQuery, HistoryCursor};
+use ::rpc;
+use ::util::unix_now;
+use super::{read_conn, db_failure, error_response};
+
+const HISTORY_LIMIT_DEFAULT: u32 = 100;
+const HISTORY_LIMIT_MAX: u32 = 1000;
+
+pub fn routes() -> Vec<Route> {
+    routes![
+        history_options,
+        history_get,
+        history_get_query,
+    ]
+}
+
+#[derive(FromForm, Debug)]
+struct HistoryParams {
+    // unix time range, `from` inclusive and `to` exclusive
+    from: Option<i64>,
+    to: Option<i64>,
+    // `next_cursor` of the previous page
+    cursor: Option<String>,
+    limit: Option<u32>,
+}
+
+#[options("/history")]
+fn history_options() -> impl Responder<'static> {
+    ::cors_options()
+}
+
+#[get("/history?<params>", rank = 1)]
+fn history_get_query(config: State<AppConfig>, auth: AuthTokenBlob, params: HistoryParams)
+    -> Result<Response<'static>, Failure>
+{
+    let account = config.validate_auth(&auth)?;
+
+    let limit = params.limit.unwrap_or(HISTORY_LIMIT_DEFAULT);
+    if limit < 1 || HISTORY_LIMIT_MAX < limit {
+        return Ok(error_response(Status::BadRequest, "invalid-parameter",
+            format!("limit must be between 1 and {}", HISTORY_LIMIT_MAX)));
+    }
+    if let (Some(from), Some(to)) = (params.from, params.to) {
+        if to < from {
+            return Ok(error_response(Status::BadRequest, "invalid-parameter",
+                "from must not be after to".into()));
+        }
+    }
+    let after = match params.cursor {
+        Some(ref raw) => match HistoryCursor::parse(raw) {
+            Some(cursor) => Some(cursor),
+            None => {
+                return Ok(error_response(Status::BadRequest, "invalid-parameter",
+                    format!("malformed cursor {:?}", raw)));
+            }
+        },
+        None => None,
+    };
+
+    let listens = read_conn(&config)?
+        .get_history(&account, &HistoryQuery {
+            from: config.history.query_from(params.from, unix_now()),
+            to: params.to,
+            after: after,
+            limit: limit,
+        })
+        .map_err(db_failure)?;
+
+    let next_cursor = if listens.len() == limit as usize {
+        listens.last().map(|l| HistoryCursor::of(l).to_string())
+    } else {
+        None
+    };
+
+    Ok(::wrap_json(&rpc::HistoryResponse {
+        results: listens,
+        next_cursor: next_cursor,
+    }))
+}
+
+#[get("/history", rank = 2)]
+fn history_get(config: State<AppConfig>, auth: AuthTokenBlob) -> Result<Response<'static>, Failure> {
+    history_get_query(config, auth, HistoryParams {
+        from: None,
+        to: None,
+        cursor: None,
+        limit: None,
+    })
+}
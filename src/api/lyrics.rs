@@ -0,0 +1,112 @@
+use std::io::Read;
+
+use rocket::{Data, Response, Route, State};
+use rocket::response::{Failure, Responder};
+use rocket::http::Status;
+
+use ::auth::AuthTokenBlob;
+use ::config::AppConfig;
+use ::lyrics::{check_length, parse_lrc};
+use ::model::SongId;
+use ::rpc;
+use super::{read_conn, write_conn, db_failure, error_response, get_song};
+
+const LYRICS_SIZE_MAX: u64 = 256 * 1024;
+
+pub fn routes() -> Vec<Route> {
+    routes![
+        song_lyrics_options,
+        song_lyrics_get,
+        song_lyrics_put,
+        song_lyrics_delete,
+    ]
+}
+
+#[options("/songs/<id>/lyrics")]
+fn song_lyrics_options(id: i64) -> impl Responder<'static> {
+    ::cors_options()
+}
+
+#[get("/songs/<id>/lyrics")]
+fn song_lyrics_get(config: State<AppConfig>, auth: AuthTokenBlob, id: i64) -> Result<Response<'static>, Failure> {
+    config.validate_auth(&auth)?;
+
+    let lyrics = read_conn(&config)?
+        .get_lyrics(&SongId(id))
+        .map_err(db_failure)?
+        .ok_or(Failure(Status::NotFound))?;
+
+    Ok(::wrap_json(&rpc::LyricsResponse {
+        song_id: SongId(id),
+        synced: lyrics.is_synced(),
+        text: lyrics.text,
+        lines: lyrics.lines,
+    }))
+}
+
+/// Replaces the lyrics with the body, an LRC file or plain text.
+/// Timestamps must not go past the end of the song.
+#[put("/songs/<id>/lyrics", data="<data>")]
+fn song_lyrics_put(config: State<AppConfig>, auth: AuthTokenBlob, id: i64, data: Data)
+    -> Result<Response<'static>, Failure>
+{
+    config.authorize_catalog_edit(&auth)?;
+
+    let mut raw = Vec::new();
+    data.open().take(LYRICS_SIZE_MAX + 1).read_to_end(&mut raw)
+        .map_err(|e| {
+            println!("error reading lyrics: {}", e);
+            Failure(Status::InternalServerError)
+        })?;
+    if LYRICS_SIZE_MAX < raw.len() as u64 {
+        return Ok(error_response(Status::PayloadTooLarge, "invalid-lyrics",
+            format!("lyrics are limited to {} bytes", LYRICS_SIZE_MAX)));
+    }
+    let text = match String::from_utf8(raw) {
+        Ok(text) => text,
+        Err(_) => {
+            return Ok(error_response(Status::BadRequest, "invalid-lyrics",
+                "lyrics must be UTF-8".into()));
+        }
+    };
+
+    let lyrics = match parse_lrc(&text) {
+        Ok(lyrics) => lyrics,
+        Err(msg) => return Ok(error_response(Status::BadRequest, "invalid-lyrics", msg)),
+    };
+    if lyrics.text.len() == 0 {
+        return Ok(error_response(Status::BadRequest, "invalid-lyrics",
+            "lyrics must not be empty, delete them instead".into()));
+    }
+
+    let song_id = SongId(id);
+    let mut conn = write_conn(&config)?;
+    let song = get_song(&*conn, &song_id)?;
+    if let Err(msg) = check_length(&lyrics, song.length_ms) {
+        return Ok(error_response(Status::BadRequest, "invalid-lyrics", msg));
+    }
+    conn.set_lyrics(&song_id, Some(&lyrics)).map_err(db_failure)?;
+
+    Ok(::wrap_json(&rpc::LyricsResponse {
+        song_id: song_id,
+        synced: lyrics.is_synced(),
+        text: lyrics.text,
+        lines: lyrics.lines,
+    }))
+}
+
+#[delete("/songs/<id>/lyrics")]
+fn song_lyrics_delete(config: State<AppConfig>, auth: AuthTokenBlob, id: i64) -> Result<Response<'static>, Failure> {
+    config.authorize_catalog_edit(&auth)?;
+
+    write_conn(&config)?
+        .set_lyrics(&SongId(id), None)
+        .map_err(db_failure)?;
+
+    Ok(::wrap_json(&rpc::LyricsResponse {
+        song_id: SongId(id),
+        synced: false,
+        text: String::new(),
+        lines: Vec::new(),
+    }))
+}
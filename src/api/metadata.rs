@@ -0,0 +1,146 @@
+use rocket::{Response, Route, State};
+use rocket::response::{Failure, Responder};
+use rocket::http::Status;
+use rocket_contrib::JSON as Json;
+use ogg::vorbis;
+
+use ::auth::AuthTokenBlob;
+use ::config::AppConfig;
+use ::database::{MetadataTarget, MetadataPatch, check_metadata_field};
+use ::model::{AlbumId, SongId, MetadataChangeId, is_replaygain_field};
+use ::rpc;
+use super::{read_conn, write_conn, db_failure, error_response, get_song};
+
+pub fn routes() -> Vec<Route> {
+    routes![
+        song_metadata_options,
+        song_metadata_patch,
+        song_metadata_history_get,
+        album_metadata_options,
+        album_metadata_patch,
+        album_metadata_history_get,
+        metadata_revert_options,
+        metadata_revert_post,
+    ]
+}
+
+#[options("/songs/<id>/metadata")]
+fn song_metadata_options(id: i64) -> impl Responder<'static> {
+    ::cors_options()
+}
+
+#[patch("/songs/<id>/metadata", format="application/json", data="<patch>")]
+fn song_metadata_patch(config: State<AppConfig>, auth: AuthTokenBlob, id: i64, patch: Json<rpc::MetadataPatchRequest>)
+    -> Result<Response<'static>, Failure>
+{
+    let Json(patch) = patch;
+    patch_metadata(&config, &auth, &MetadataTarget::Song(SongId(id)), patch)
+}
+
+#[get("/songs/<id>/metadata/history")]
+fn song_metadata_history_get(config: State<AppConfig>, auth: AuthTokenBlob, id: i64)
+    -> Result<Response<'static>, Failure>
+{
+    metadata_history(&config, &auth, &MetadataTarget::Song(SongId(id)))
+}
+
+#[options("/albums/<id>/metadata")]
+fn album_metadata_options(id: i64) -> impl Responder<'static> {
+    ::cors_options()
+}
+
+#[patch("/albums/<id>/metadata", format="application/json", data="<patch>")]
+fn album_metadata_patch(config: State<AppConfig>, auth: AuthTokenBlob, id: i64, patch: Json<rpc::MetadataPatchRequest>)
+    -> Result<Response<'static>, Failure>
+{
+    let Json(patch) = patch;
+    patch_metadata(&config, &auth, &MetadataTarget::Album(AlbumId(id)), patch)
+}
+
+#[get("/albums/<id>/metadata/history")]
+fn album_metadata_history_get(config: State<AppConfig>, auth: AuthTokenBlob, id: i64)
+    -> Result<Response<'static>, Failure>
+{
+    metadata_history(&config, &auth, &MetadataTarget::Album(AlbumId(id)))
+}
+
+#[options("/metadata/history/<id>/revert")]
+fn metadata_revert_options(id: i64) -> impl Responder<'static> {
+    ::cors_options()
+}
+
+#[post("/metadata/history/<id>/revert")]
+fn metadata_revert_post(config: State<AppConfig>, auth: AuthTokenBlob, id: i64)
+    -> Result<Response<'static>, Failure>
+{
+    let account = config.authorize_catalog_edit(&auth)?;
+
+    let reverted = write_conn(&config)?
+        .revert_metadata_change(&account, &MetadataChangeId(id))
+        .map_err(db_failure)?;
+
+    Ok(::wrap_json(&rpc::MetadataChangeSetResponse {
+        results: reverted.into_iter().collect(),
+    }))
+}
+
+fn patch_metadata(config: &AppConfig, auth: &AuthTokenBlob, target: &MetadataTarget, req: rpc::MetadataPatchRequest)
+    -> Result<Response<'static>, Failure>
+{
+    let account = config.authorize_catalog_edit(auth)?;
+
+    for (key, val) in req.set.iter() {
+        if let Err(msg) = check_metadata_field(key, Some(val)) {
+            return Ok(error_response(Status::BadRequest, "invalid-metadata", msg));
+        }
+        if ::lyrics::is_lyrics_field(key) {
+            return Ok(error_response(Status::BadRequest, "invalid-metadata",
+                format!("{} is set through the lyrics of the song", key)));
+        }
+        if is_replaygain_field(key) {
+            return Ok(error_response(Status::BadRequest, "invalid-metadata",
+                format!("{} is kept as the gain of the song or album", key)));
+        }
+        if vorbis::is_picture_field(key) {
+            return Ok(error_response(Status::BadRequest, "invalid-metadata",
+                format!("{} is kept as the art blob of the album", key)));
+        }
+    }
+    for key in req.remove.iter() {
+        if let Err(msg) = check_metadata_field(key, None) {
+            return Ok(error_response(Status::BadRequest, "invalid-metadata", msg));
+        }
+        if req.set.contains_key(key) {
+            return Ok(error_response(Status::BadRequest, "invalid-metadata",
+                format!("field {:?} is both set and removed", key)));
+        }
+    }
+
+    let changes = write_conn(config)?
+        .update_metadata(&account, target, &MetadataPatch {
+            set: req.set,
+            remove: req.remove,
+        })
+        .map_err(db_failure)?;
+
+    Ok(::wrap_json(&rpc::MetadataChangeSetResponse { results: changes }))
+}
+
+fn metadata_history(config: &AppConfig, auth: &AuthTokenBlob, target: &MetadataTarget)
+    -> Result<Response<'static>, Failure>
+{
+    config.validate_auth(auth)?;
+
+    let conn = read_conn(config)?;
+    match *target {
+        MetadataTarget::Song(ref id) => {
+            get_song(&*conn, id)?;
+        },
+        MetadataTarget::Album(ref id) => {
+            conn.get_album(id).map_err(db_failure)?.ok_or(Failure(Status::NotFound))?;
+        },
+    }
+    let changes = conn.get_metadata_history(target).map_err(db_failure)?;
+
+    Ok(::wrap_json(&rpc::MetadataChangeSetResponse { results: changes }))
+}
@@ -0,0 +1,214 @@
+// because having an intermediate function call tricks the linter
+#![allow(unmounted_route)]
+
+use std::io;
+
+use uuid::Uuid;
+use rocket::{Outcome, Request, Response, Route};
+use rocket::request::{self, FromRequest};
+use rocket::response::Failure;
+use rocket::http::Status;
+
+use ::config::AppConfig;
+use ::database;
+use ::database::{SongFilter, SongQuery};
+use ::database::drivers::DbConnector;
+use ::model::{Song, SongId};
+use ::rpc;
+
+mod albums;
+mod metadata;
+mod songs;
+mod playlists;
+mod queue;
+mod history;
+mod smart_playlists;
+mod artists;
+mod facets;
+mod duplicates;
+mod recommendations;
+mod radio;
+mod account;
+mod export;
+mod sync;
+mod variants;
+mod lyrics;
+
+pub fn routes() -> Vec<Route> {
+    let mut out = Vec::new();
+    out.extend(albums::routes());
+    out.extend(metadata::routes());
+    out.extend(songs::routes());
+    out.extend(playlists::routes());
+    out.extend(queue::routes());
+    out.extend(history::routes());
+    out.extend(smart_playlists::routes());
+    out.extend(artists::routes());
+    out.extend(facets::routes());
+    out.extend(duplicates::routes());
+    out.extend(recommendations::routes());
+    out.extend(radio::routes());
+    out.extend(account::routes());
+    out.extend(export::routes());
+    out.extend(sync::routes());
+    out.extend(variants::routes());
+    out.extend(lyrics::routes());
+    out
+}
+
+fn read_conn(config: &AppConfig) -> Result<Box<DbConnector>, Failure> {
+    database::drivers::get_driver(config.database.read_url())
+        .map_err(|e| {
+            println!("error: {:?}", e);
+            Failure(Status::InternalServerError)
+        })
+}
+
+fn write_conn(config: &AppConfig) -> Result<Box<DbConnector>, Failure> {
+    database::drivers::get_driver(config.database.write_url())
+        .map_err(|e| {
+            println!("error: {:?}", e);
+            Failure(Status::InternalServerError)
+        })
+}
+
+/// Maps driver errors onto HTTP statuses.  The drivers use `NotFound` and
+/// `InvalidInput` for conditions caused by the request itself.
+fn db_failure(e: io::Error) -> Failure {
+    println!("error: {:?}", e);
+    match e.kind() {
+        io::ErrorKind::NotFound => Failure(Status::NotFound),
+        io::ErrorKind::InvalidInput => Failure(Status::BadRequest),
+        io::ErrorKind::PermissionDenied => Failure(Status::Forbidden),
+        _ => Failure(Status::InternalServerError),
+    }
+}
+
+/// The song without any per-account fields, or a 404.
+fn get_song(conn: &DbConnector, id: &SongId) -> Result<Song, Failure> {
+    let mut query = SongQuery::all();
+    query.filters.push(SongFilter::Ids(vec![id.clone()]));
+    conn.get_songs(&query)
+        .map_err(db_failure)?
+        .into_iter()
+        .next()
+        .ok_or(Failure(Status::NotFound))
+}
+
+fn error_response(status: Status, kind: &str, message: String) -> Response<'static> {
+    ::wrap_json_status(status, &rpc::ErrorResponse {
+        error: rpc::Error {
+            kind: kind.into(),
+            message: message,
+        },
+    })
+}
+
+/// A seed for shuffles the client didn't ask to repeat.
+fn fresh_seed() -> u32 {
+    let uuid = Uuid::new_v4();
+    let b = uuid.as_bytes();
+    (b[0] as u32) << 24 | (b[1] as u32) << 16 | (b[2] as u32) << 8 | b[3] as u32
+}
+
+/// The `If-None-Match` header of a request, if it had one.
+struct IfNoneMatch(Option<String>);
+
+impl IfNoneMatch {
+    fn matches(&self, etag: &str) -> bool {
+        match self.0 {
+            Some(ref header) => header.split(',').any(|tag| {
+                let tag = tag.trim();
+                tag == "*" || tag.trim_left_matches("W/") == etag
+            }),
+            None => false,
+        }
+    }
+}
+
+impl<'a, 'r> FromRequest<'a, 'r> for IfNoneMatch {
+    type Error = ();
+
+    fn from_request(request: &'a Request<'r>) -> request::Outcome<IfNoneMatch, ()> {
+        Outcome::Success(IfNoneMatch(request.headers().get_one("If-None-Match").map(|h| h.to_string())))
+    }
+}
+
+/// The `Accept` header of a request, if it had one.
+struct AcceptHeader(Option<String>);
+
+impl AcceptHeader {
+    /// How much the client wants `media_type`, in thousandths, going by the
+    /// most specific range that matches it.  0 means not at all; without a
+    /// header everything is wanted equally.
+    fn quality(&self, media_type: &str) -> u32 {
+        let header = match self.0 {
+            Some(ref header) => header,
+            None => return 1000,
+        };
+        let any_subtype = format!("{}/*", media_type.split('/').next().unwrap_or(""));
+        // (specificity, quality) of the best match so far
+        let mut best: Option<(u32, u32)> = None;
+        for range in header.split(',') {
+            let mut parts = range.split(';');
+            let range_type = parts.next().unwrap_or("").trim().to_lowercase();
+            let specificity = if range_type == media_type {
+                2
+            } else if range_type == any_subtype {
+                1
+            } else if range_type == "*/*" {
+                0
+            } else {
+                continue;
+            };
+            let mut quality = 1000;
+            for param in parts {
+                let mut kv = param.splitn(2, '=');
+                let key = kv.next().unwrap_or("").trim();
+                if key == "q" || key == "Q" {
+                    quality = kv.next()
+                        .and_then(|q| q.trim().parse::<f64>().ok())
+                        .map(|q| (q.max(0.0).min(1.0) * 1000.0).round() as u32)
+                        .unwrap_or(1000);
+                }
+            }
+            match best {
+                Some((best_specificity, _)) if specificity <= best_specificity => (),
+                _ => best = Some((specificity, quality)),
+            }
+        }
+        best.map(|(_, quality)| quality).unwrap_or(0)
+    }
+}
+
+impl<'a, 'r> FromRequest<'a, 'r> for AcceptHeader {
+    type Error = ();
+
+    fn from_request(request: &'a Request<'r>) -> request::Outcome<AcceptHeader, ()> {
+        Outcome::Success(AcceptHeader(request.headers().get_one("Accept").map(|h| h.to_string())))
+    }
+}
+
+fn not_modified(etag: String) -> Response<'static> {
+    let mut builder = Response::build();
+    builder.status(Status::NotModified);
+    builder.raw_header("ETag", etag);
+    if ::ENABLE_CORS {
+        builder.raw_header("Access-Control-Allow-Origin", "*");
+        builder.raw_header("Access-Control-Allow-Methods", "GET, POST, PUT, PATCH, DELETE");
+        builder.raw_header("Access-Control-Allow-Headers", "Content-Type, Authorization");
+    }
+    builder.finalize()
+}
+
+/// For deletions, which have nothing left to show.
+fn no_content() -> Response<'static> {
+    let mut builder = Response::build();
+    builder.status(Status::NoContent);
+    if ::ENABLE_CORS {
+        builder.raw_header("Access-Control-Allow-Origin", "*");
+        builder.raw_header("Access-Control-Allow-Methods", "GET, POST, PUT, PATCH, DELETE");
+        builder.raw_header("Access-Control-Allow-Headers", "Content-Type, Authorization");
+    }
+    builder.finalize()
+}
@@ -0,0 +1,314 @@
+use std::io::Read;
+
+use rocket::{Data, Response, Route, State};
+use rocket::response::{Failure, Responder};
+use rocket::http::Status;
+use rocket_contrib::JSON as Json;
+
+use ::auth::AuthTokenBlob;
+use ::config::AppConfig;
+use ::database::{AccountId, PlaylistCreate, PlaylistUpdate, SongQuery, PLAYLIST_NAME_MAX};
+use ::database::drivers::DbConnector;
+use ::import::{ImportFormat, SongMatcher, parse};
+use ::model::{PlaylistId, PlaylistEntryId};
+use ::rpc;
+use super::{read_conn, write_conn, db_failure, error_response, no_content};
+
+/// imported playlist files larger than this are refused
+const IMPORT_SIZE_MAX: u64 = 1024 * 1024;
+/// as are those with more entries than this
+const IMPORT_ENTRIES_MAX: usize = 10000;
+
+pub fn routes() -> Vec<Route> {
+    routes![
+        playlists_options,
+        playlists_get,
+        playlists_post,
+        playlists_import_options,
+        playlists_import_post,
+        playlists_import_post_query,
+        playlist_options,
+        playlist_get,
+        playlist_patch,
+        playlist_delete,
+        playlist_entries_options,
+        playlist_entries_post,
+        playlist_entry_options,
+        playlist_entry_delete,
+        playlist_entry_move_options,
+        playlist_entry_move_post,
+    ]
+}
+
+fn check_playlist_name(name: &str) -> Result<(), String> {
+    if name.trim().len() == 0 {
+        return Err("playlist name must not be empty".into());
+    }
+    if PLAYLIST_NAME_MAX < name.chars().count() {
+        return Err(format!("playlist name is longer than {} characters", PLAYLIST_NAME_MAX));
+    }
+    Ok(())
+}
+
+/// Responds with the playlist as it is after a modification.
+fn playlist_response(conn: &DbConnector, account: &AccountId, id: &PlaylistId)
+    -> Result<Response<'static>, Failure>
+{
+    let (playlist, entries) = conn.get_playlist(account, id)
+        .map_err(db_failure)?
+        .ok_or(Failure(Status::NotFound))?;
+
+    Ok(::wrap_json(&rpc::PlaylistResponse {
+        playlist: playlist,
+        entries: entries,
+    }))
+}
+
+#[options("/playlists")]
+fn playlists_options() -> impl Responder<'static> {
+    ::cors_options()
+}
+
+#[get("/playlists")]
+fn playlists_get(config: State<AppConfig>, auth: AuthTokenBlob) -> Result<Response<'static>, Failure> {
+    let account = config.validate_auth(&auth)?;
+
+    let playlists = read_conn(&config)?
+        .get_playlists(&account)
+        .map_err(db_failure)?;
+
+    Ok(::wrap_json(&rpc::PlaylistSetResponse { results: playlists }))
+}
+
+#[post("/playlists", format="application/json", data="<req>")]
+fn playlists_post(config: State<AppConfig>, auth: AuthTokenBlob, req: Json<rpc::PlaylistCreateRequest>)
+    -> Result<Response<'static>, Failure>
+{
+    let account = config.validate_auth(&auth)?;
+    let Json(req) = req;
+
+    if let Err(msg) = check_playlist_name(&req.name) {
+        return Ok(error_response(Status::BadRequest, "invalid-playlist", msg));
+    }
+
+    let mut conn = write_conn(&config)?;
+    let id = conn.create_playlist(&account, &PlaylistCreate {
+        name: req.name,
+        description: req.description,
+        song_ids: Vec::new(),
+    }).map_err(db_failure)?;
+
+    playlist_response(&*conn, &account, &id)
+}
+
+#[derive(FromForm, Debug)]
+struct ImportParams {
+    // defaults to the title in the file, then to "Imported playlist"
+    name: Option<String>,
+    // m3u, m3u8, pls or xspf, guessed from the contents if absent
+    format: Option<String>,
+}
+
+// ranked so as not to collide with `/playlists/<id>`
+#[options("/playlists/import", rank = 1)]
+fn playlists_import_options() -> impl Responder<'static> {
+    ::cors_options()
+}
+
+#[post("/playlists/import?<params>", data="<data>", rank = 1)]
+fn playlists_import_post_query(config: State<AppConfig>, auth: AuthTokenBlob, params: ImportParams, data: Data)
+    -> Result<Response<'static>, Failure>
+{
+    let account = config.validate_auth(&auth)?;
+
+    let mut raw = Vec::new();
+    data.open().take(IMPORT_SIZE_MAX + 1).read_to_end(&mut raw)
+        .map_err(|e| {
+            println!("error reading playlist import: {}", e);
+            Failure(Status::InternalServerError)
+        })?;
+    if IMPORT_SIZE_MAX < raw.len() as u64 {
+        return Ok(error_response(Status::PayloadTooLarge, "invalid-import",
+            format!("playlist files are limited to {} bytes", IMPORT_SIZE_MAX)));
+    }
+    let text = String::from_utf8_lossy(&raw);
+
+    let format = match params.format {
+        Some(ref raw) => match ImportFormat::parse(raw) {
+            Some(format) => format,
+            None => {
+                return Ok(error_response(Status::BadRequest, "invalid-parameter",
+                    format!("unknown format {:?}, expected m3u, m3u8, pls or xspf", raw)));
+            },
+        },
+        None => ImportFormat::sniff(&text),
+    };
+    let imported = match parse(format, &text) {
+        Ok(imported) => imported,
+        Err(msg) => return Ok(error_response(Status::BadRequest, "invalid-import", msg)),
+    };
+    if IMPORT_ENTRIES_MAX < imported.entries.len() {
+        return Ok(error_response(Status::PayloadTooLarge, "invalid-import",
+            format!("playlists are limited to {} entries", IMPORT_ENTRIES_MAX)));
+    }
+
+    let name = params.name
+        .or(imported.title)
+        .unwrap_or_else(|| "Imported playlist".into());
+    if let Err(msg) = check_playlist_name(&name) {
+        return Ok(error_response(Status::BadRequest, "invalid-playlist", msg));
+    }
+
+    let mut conn = write_conn(&config)?;
+    let songs = conn.get_songs(&SongQuery::all()).map_err(db_failure)?;
+    let mut matched = Vec::new();
+    let mut unmatched = Vec::new();
+    {
+        let matcher = SongMatcher::new(&songs);
+        for (idx, entry) in imported.entries.into_iter().enumerate() {
+            match matcher.find(&entry) {
+                Some((song_id, method)) => matched.push(rpc::ImportMatch {
+                    index: idx,
+                    song_id: song_id,
+                    method: method,
+                }),
+                None => unmatched.push(rpc::ImportUnmatched {
+                    index: idx,
+                    location: entry.location,
+                    title: entry.title,
+                    artist: entry.artist,
+                    duration_ms: entry.duration_ms,
+                }),
+            }
+        }
+    }
+
+    let id = conn.create_playlist(&account, &PlaylistCreate {
+        name: name.trim().to_string(),
+        description: String::new(),
+        song_ids: matched.iter().map(|m| m.song_id.clone()).collect(),
+    }).map_err(db_failure)?;
+
+    let (playlist, entries) = conn.get_playlist(&account, &id)
+        .map_err(db_failure)?
+        .ok_or(Failure(Status::NotFound))?;
+    Ok(::wrap_json(&rpc::PlaylistImportResponse {
+        playlist: playlist,
+        entries: entries,
+        matched: matched,
+        unmatched: unmatched,
+    }))
+}
+
+#[post("/playlists/import", data="<data>", rank = 2)]
+fn playlists_import_post(config: State<AppConfig>, auth: AuthTokenBlob, data: Data)
+    -> Result<Response<'static>, Failure>
+{
+    playlists_import_post_query(config, auth, ImportParams { name: None, format: None }, data)
+}
+
+#[options("/playlists/<id>")]
+fn playlist_options(id: i64) -> impl Responder<'static> {
+    ::cors_options()
+}
+
+#[get("/playlists/<id>")]
+fn playlist_get(config: State<AppConfig>, auth: AuthTokenBlob, id: i64) -> Result<Response<'static>, Failure> {
+    let account = config.validate_auth(&auth)?;
+
+    let conn = read_conn(&config)?;
+    playlist_response(&*conn, &account, &PlaylistId(id))
+}
+
+#[patch("/playlists/<id>", format="application/json", data="<req>")]
+fn playlist_patch(config: State<AppConfig>, auth: AuthTokenBlob, id: i64, req: Json<rpc::PlaylistUpdateRequest>)
+    -> Result<Response<'static>, Failure>
+{
+    let account = config.validate_auth(&auth)?;
+    let Json(req) = req;
+
+    if let Some(ref name) = req.name {
+        if let Err(msg) = check_playlist_name(name) {
+            return Ok(error_response(Status::BadRequest, "invalid-playlist", msg));
+        }
+    }
+
+    let id = PlaylistId(id);
+    let mut conn = write_conn(&config)?;
+    conn.update_playlist(&account, &id, &PlaylistUpdate {
+        name: req.name,
+        description: req.description,
+    }).map_err(db_failure)?;
+
+    playlist_response(&*conn, &account, &id)
+}
+
+#[delete("/playlists/<id>")]
+fn playlist_delete(config: State<AppConfig>, auth: AuthTokenBlob, id: i64) -> Result<Response<'static>, Failure> {
+    let account = config.validate_auth(&auth)?;
+
+    write_conn(&config)?
+        .delete_playlist(&account, &PlaylistId(id))
+        .map_err(db_failure)?;
+
+    Ok(no_content())
+}
+
+#[options("/playlists/<id>/entries")]
+fn playlist_entries_options(id: i64) -> impl Responder<'static> {
+    ::cors_options()
+}
+
+#[post("/playlists/<id>/entries", format="application/json", data="<req>")]
+fn playlist_entries_post(config: State<AppConfig>, auth: AuthTokenBlob, id: i64, req: Json<rpc::PlaylistEntryAddRequest>)
+    -> Result<Response<'static>, Failure>
+{
+    let account = config.validate_auth(&auth)?;
+    let Json(req) = req;
+
+    let id = PlaylistId(id);
+    let mut conn = write_conn(&config)?;
+    conn.insert_playlist_entry(&account, &id, &req.song_id, req.position)
+        .map_err(db_failure)?;
+
+    playlist_response(&*conn, &account, &id)
+}
+
+#[options("/playlists/<id>/entries/<entry_id>")]
+fn playlist_entry_options(id: i64, entry_id: i64) -> impl Responder<'static> {
+    ::cors_options()
+}
+
+#[delete("/playlists/<id>/entries/<entry_id>")]
+fn playlist_entry_delete(config: State<AppConfig>, auth: AuthTokenBlob, id: i64, entry_id: i64)
+    -> Result<Response<'static>, Failure>
+{
+    let account = config.validate_auth(&auth)?;
+
+    let id = PlaylistId(id);
+    let mut conn = write_conn(&config)?;
+    conn.remove_playlist_entry(&account, &id, &PlaylistEntryId(entry_id))
+        .map_err(db_failure)?;
+
+    playlist_response(&*conn, &account, &id)
+}
+
+#[options("/playlists/<id>/entries/<entry_id>/move")]
+fn playlist_entry_move_options(id: i64, entry_id: i64) -> impl Responder<'static> {
+    ::cors_options()
+}
+
+#[post("/playlists/<id>/entries/<entry_id>/move", format="application/json", data="<req>")]
+fn playlist_entry_move_post(config: State<AppConfig>, auth: AuthTokenBlob, id: i64, entry_id: i64, req: Json<rpc::PlaylistEntryMoveRequest>)
+    -> Result<Response<'static>, Failure>
+{
+    let account = config.validate_auth(&auth)?;
+    let Json(req) = req;
+
+    let id = PlaylistId(id);
+    let mut conn = write_conn(&config)?;
+    conn.move_playlist_entry(&account, &id, &PlaylistEntryId(entry_id), req.position)
+        .map_err(db_failure)?;
+
+    playlist_response(&*conn, &account, &id)
+}
@@ -0,0 +1,85 @@
+use rocket::{Response, Route, State};
+use rocket::response::{Failure, Responder};
+use rocket::http::Status;
+use rocket_contrib::JSON as Json;
+
+use ::auth::AuthTokenBlob;
+use ::config::AppConfig;
+use ::database::{PlayQueueUpdate, PLAY_QUEUE_MAX};
+use ::rpc;
+use super::{read_conn, write_conn, db_failure, error_response};
+
+pub fn routes() -> Vec<Route> {
+    routes![
+        queue_options,
+        queue_get,
+        queue_put,
+    ]
+}
+
+fn check_queue(req: &rpc::PlayQueueRequest) -> Result<(), String> {
+    if PLAY_QUEUE_MAX < req.song_ids.len() {
+        return Err(format!("queues hold at most {} songs", PLAY_QUEUE_MAX));
+    }
+    let index_ok = if req.song_ids.len() == 0 {
+        req.current_index == 0
+    } else {
+        0 <= req.current_index && (req.current_index as usize) < req.song_ids.len()
+    };
+    if !index_ok {
+        return Err("current_index is outside of the queue".into());
+    }
+    if req.position_ms < 0 {
+        return Err("position_ms must not be negative".into());
+    }
+    Ok(())
+}
+
+#[options("/queue")]
+fn queue_options() -> impl Responder<'static> {
+    ::cors_options()
+}
+
+#[get("/queue")]
+fn queue_get(config: State<AppConfig>, auth: AuthTokenBlob) -> Result<Response<'static>, Failure> {
+    let account = config.validate_auth(&auth)?;
+
+    let queue = read_conn(&config)?
+        .get_play_queue(&account)
+        .map_err(db_failure)?;
+
+    Ok(::wrap_json(&rpc::PlayQueueResponse { queue: queue }))
+}
+
+#[put("/queue", format="application/json", data="<req>")]
+fn queue_put(config: State<AppConfig>, auth: AuthTokenBlob, req: Json<rpc::PlayQueueRequest>)
+    -> Result<Response<'static>, Failure>
+{
+    let account = config.validate_auth(&auth)?;
+    let Json(req) = req;
+
+    if let Err(msg) = check_queue(&req) {
+        return Ok(error_response(Status::BadRequest, "invalid-queue", msg));
+    }
+
+    let mut conn = write_conn(&config)?;
+    let written = conn.set_play_queue(&account, req.version, &PlayQueueUpdate {
+        song_ids: req.song_ids,
+        current_index: req.current_index,
+        position_ms: req.position_ms,
+    }).map_err(db_failure)?;
+
+    match written {
+        Some(queue) => Ok(::wrap_json(&rpc::PlayQueueResponse { queue: queue })),
+        None => {
+            let current = conn.get_play_queue(&account).map_err(db_failure)?;
+            Ok(::wrap_json_status(Status::Conflict, &rpc::PlayQueueConflictResponse {
+                error: rpc::Error {
+                    kind: "version-conflict".into(),
+                    message: format!("the queue was changed elsewhere and is now at version {}", current.version),
+                },
+                queue: current,
+            }))
+        },
+    }
+}
@@ -0,0 +1,271 @@
+use std::collections::{HashMap, HashSet};
+
+use rocket::{Response, Route, State};
+use rocket::response::{Failure, Responder};
+use rocket::http::Status;
+
+use ::auth::AuthTokenBlob;
+use ::config::AppConfig;
+use ::database::{
+    AccountId,
+    Comparison,
+    HistoryQuery,
+    RadioSession,
+    SongFilter,
+    SongQuery,
+    RADIO_RECENT_SECS,
+    RADIO_SESSION_SECS,
+    RADIO_SEQUENCE_MAX,
+    check_metadata_field,
+    radio_sequence,
+};
+use ::database::drivers::DbConnector;
+use ::model::{ArtistId, Song, SongId};
+use ::rpc;
+use ::util::unix_now;
+use super::{read_conn, write_conn, db_failure, error_response, fresh_seed};
+
+const LIMIT_DEFAULT: u32 = 25;
+const LIMIT_MAX: u32 = 100;
+
+/// how many songs similar to the seed song are candidates
+const SIMILAR_TO_SONG: u32 = 200;
+/// how many of an artist's songs lend their similar songs to artist radio,
+/// and how many each
+const ARTIST_SONGS_EXPANDED: usize = 20;
+const SIMILAR_TO_ARTIST_SONG: u32 = 10;
+
+/// the most recent listens looked at when avoiding repeats
+const RECENT_LISTENS_MAX: u32 = 1000;
+
+pub fn routes() -> Vec<Route> {
+    routes![
+        radio_options,
+        radio_get,
+        radio_get_query,
+    ]
+}
+
+#[derive(FromForm, Debug)]
+struct RadioParams {
+    // exactly one seed is required
+    seed_song: Option<i64>,
+    seed_artist: Option<i64>,
+    seed_genre: Option<String>,
+    // from a previous response, a new session is started if absent
+    session: Option<String>,
+    offset: Option<u32>,
+    limit: Option<u32>,
+}
+
+enum RadioSeed {
+    Song(SongId),
+    Artist(ArtistId),
+    Genre(String),
+}
+
+impl RadioSeed {
+    fn from_params(params: &RadioParams) -> Result<RadioSeed, String> {
+        match (params.seed_song, params.seed_artist, params.seed_genre.as_ref()) {
+            (Some(id), None, None) => Ok(RadioSeed::Song(SongId(id))),
+            (None, Some(id), None) => Ok(RadioSeed::Artist(ArtistId(id))),
+            (None, None, Some(genre)) => {
+                check_metadata_field("GENRE", Some(genre))?;
+                Ok(RadioSeed::Genre(genre.clone()))
+            },
+            _ => Err("exactly one of seed_song, seed_artist or seed_genre is required".into()),
+        }
+    }
+}
+
+/// The candidate songs for a seed, with how much each is boosted by its
+/// similarity to the seed.
+fn candidates(conn: &DbConnector, account: &AccountId, seed: &RadioSeed)
+    -> Result<(Vec<Song>, HashMap<i64, f64>), Failure>
+{
+    let mut query = SongQuery::all();
+    query.account = Some(account.clone());
+    let mut boosts = HashMap::new();
+
+    match *seed {
+        RadioSeed::Song(ref id) => {
+            let mut ids = vec![id.clone()];
+            for (similar, score) in conn.get_similar_songs(id, SIMILAR_TO_SONG).map_err(db_failure)? {
+                boosts.insert(similar.0, score);
+                ids.push(similar);
+            }
+            query.filters.push(SongFilter::Ids(ids));
+        },
+        RadioSeed::Artist(ref id) => {
+            if conn.get_artist(id).map_err(db_failure)?.is_none() {
+                return Err(Failure(Status::NotFound));
+            }
+            let mut artist_query = SongQuery::all();
+            artist_query.filters.push(SongFilter::Artist(id.clone()));
+            let mut ids = Vec::new();
+            for (idx, song) in conn.get_songs(&artist_query).map_err(db_failure)?.into_iter().enumerate() {
+                if idx < ARTIST_SONGS_EXPANDED {
+                    let similar = conn.get_similar_songs(&song.id, SIMILAR_TO_ARTIST_SONG).map_err(db_failure)?;
+                    for (similar, score) in similar.into_iter() {
+                        let boost = boosts.entry(similar.0).or_insert(score);
+                        if *boost < score {
+                            *boost = score;
+                        }
+                        ids.push(similar);
+                    }
+                }
+                ids.push(song.id);
+            }
+            // the artist's own songs count as perfectly similar
+            for id in ids.iter() {
+                boosts.entry(id.0).or_insert(1.0);
+            }
+            query.filters.push(SongFilter::Ids(ids));
+        },
+        RadioSeed::Genre(ref genre) => {
+            query.filters.push(SongFilter::Metadata("GENRE".into(), Comparison::Eq, genre.clone()));
+        },
+    }
+
+    let songs = conn.get_songs(&query).map_err(db_failure)?;
+    if let RadioSeed::Song(ref id) = *seed {
+        if !songs.iter().any(|s| s.id == *id) {
+            return Err(Failure(Status::NotFound));
+        }
+    }
+    Ok((songs, boosts))
+}
+
+/// Draws the sequence of a new session and stores it, so that later pages
+/// come from the same sequence however the plays and ratings it leads to
+/// change the weights.
+fn start_session(config: &AppConfig, account: &AccountId, seed: &RadioSeed)
+    -> Result<(RadioSession, Vec<SongId>), Failure>
+{
+    let session = RadioSession {
+        seed: fresh_seed(),
+        started_at: unix_now(),
+    };
+
+    let mut conn = write_conn(config)?;
+    let (songs, boosts) = candidates(&*conn, account, seed)?;
+
+    let recent: HashSet<i64> = conn.get_history(account, &HistoryQuery {
+        from: Some(session.started_at - RADIO_RECENT_SECS),
+        to: Some(session.started_at),
+        after: None,
+        limit: RECENT_LISTENS_MAX,
+    }).map_err(db_failure)?.into_iter().map(|l| l.song.id.0).collect();
+
+    let mut first = Vec::new();
+    let mut fresh = Vec::new();
+    let mut stale = Vec::new();
+    for song in songs.into_iter() {
+        let is_seed = match *seed {
+            RadioSeed::Song(ref id) => song.id == *id,
+            _ => false,
+        };
+        if is_seed {
+            first.push(song);
+        } else if recent.contains(&song.id.0) {
+            stale.push(song);
+        } else {
+            fresh.push(song);
+        }
+    }
+    // recently played songs only come up once everything else has
+    let mut sequence = first;
+    let room = RADIO_SEQUENCE_MAX - sequence.len();
+    sequence.extend(radio_sequence(fresh, &boosts, session.seed, room));
+    let room = RADIO_SEQUENCE_MAX - sequence.len();
+    sequence.extend(radio_sequence(stale, &boosts, session.seed, room));
+    let sequence: Vec<SongId> = sequence.into_iter().map(|song| song.id).collect();
+
+    conn.create_radio_session(account, &session, &sequence, session.started_at - RADIO_SESSION_SECS)
+        .map_err(db_failure)?;
+    Ok((session, sequence))
+}
+
+#[options("/radio")]
+fn radio_options() -> impl Responder<'static> {
+    ::cors_options()
+}
+
+#[get("/radio?<params>", rank = 1)]
+fn radio_get_query(config: State<AppConfig>, auth: AuthTokenBlob, params: RadioParams)
+    -> Result<Response<'static>, Failure>
+{
+    let account = config.validate_auth(&auth)?;
+
+    let seed = match RadioSeed::from_params(&params) {
+        Ok(seed) => seed,
+        Err(msg) => return Ok(error_response(Status::BadRequest, "invalid-parameter", msg)),
+    };
+    let offset = params.offset.unwrap_or(0);
+    let limit = params.limit.unwrap_or(LIMIT_DEFAULT);
+    if limit < 1 || LIMIT_MAX < limit {
+        return Ok(error_response(Status::BadRequest, "invalid-parameter",
+            format!("limit must be between 1 and {}", LIMIT_MAX)));
+    }
+
+    let (session, sequence) = match params.session {
+        Some(ref raw) => {
+            let session = match RadioSession::parse(raw) {
+                Some(session) => session,
+                None => {
+                    return Ok(error_response(Status::BadRequest, "invalid-parameter",
+                        format!("malformed session {:?}", raw)));
+                }
+            };
+            match read_conn(&config)?.get_radio_session(&account, &session).map_err(db_failure)? {
+                Some(sequence) => (session, sequence),
+                None => {
+                    return Ok(error_response(Status::NotFound, "unknown-session",
+                        format!("radio session {:?} is unknown or has expired", raw)));
+                }
+            }
+        },
+        None => start_session(&config, &account, &seed)?,
+    };
+
+    let total = sequence.len() as u32;
+    let page: Vec<SongId> = sequence.into_iter()
+        .skip(offset as usize)
+        .take(limit as usize)
+        .collect();
+    let results = if page.len() > 0 {
+        let mut query = SongQuery::all();
+        query.account = Some(account.clone());
+        query.filters.push(SongFilter::Ids(page.clone()));
+        let mut songs: HashMap<i64, Song> = read_conn(&config)?
+            .get_songs(&query)
+            .map_err(db_failure)?
+            .into_iter()
+            .map(|song| (song.id.0, song))
+            .collect();
+        // songs deleted since the session started are skipped
+        page.iter().filter_map(|id| songs.remove(&id.0)).collect()
+    } else {
+        Vec::new()
+    };
+    let end = offset.saturating_add(limit);
+    let next_offset = if end < total { Some(end) } else { None };
+
+    Ok(::wrap_json(&rpc::RadioResponse {
+        results: results,
+        session: session.to_string(),
+        next_offset: next_offset,
+    }))
+}
+
+#[get("/radio", rank = 2)]
+fn radio_get(config: State<AppConfig>, auth: AuthTokenBlob) -> Result<Response<'static>, Failure> {
+    radio_get_query(config, auth, RadioParams {
+        seed_song: None,
+        seed_artist: None,
+        seed_genre: None,
+        session: None,
+        offset: None,
+        limit: None,
+    })
+}
@@ -0,0 +1,126 @@
+use std::collections::HashMap;
+
+use rocket::{Response, Route, State};
+use rocket::response::{Failure, Responder};
+use rocket::http::Status;
+
+use ::auth::AuthTokenBlob;
+use ::config::AppConfig;
+use ::database::{AccountId, SongFilter, SongQuery};
+use ::database::drivers::DbConnector;
+use ::model::{ScoredSong, Song, SongId};
+use ::rpc;
+use super::{read_conn, db_failure, error_response};
+
+const LIMIT_DEFAULT: u32 = 20;
+const LIMIT_MAX: u32 = 100;
+
+pub fn routes() -> Vec<Route> {
+    routes![
+        song_similar_options,
+        song_similar_get,
+        song_similar_get_query,
+        recommendations_options,
+        recommendations_get,
+        recommendations_get_query,
+    ]
+}
+
+#[derive(FromForm, Debug)]
+struct RecommendationParams {
+    limit: Option<u32>,
+}
+
+fn check_limit(params: &RecommendationParams) -> Result<u32, Response<'static>> {
+    let limit = params.limit.unwrap_or(LIMIT_DEFAULT);
+    if limit < 1 || LIMIT_MAX < limit {
+        return Err(error_response(Status::BadRequest, "invalid-parameter",
+            format!("limit must be between 1 and {}", LIMIT_MAX)));
+    }
+    Ok(limit)
+}
+
+fn get_songs(conn: &DbConnector, account: Option<AccountId>, ids: Vec<SongId>)
+    -> Result<HashMap<i64, Song>, Failure>
+{
+    let mut query = SongQuery::all();
+    query.account = account;
+    query.filters.push(SongFilter::Ids(ids));
+    let songs = conn.get_songs(&query).map_err(db_failure)?;
+    Ok(songs.into_iter().map(|s| (s.id.0, s)).collect())
+}
+
+/// Resolves scored song ids, keeping their order.
+fn scored_songs(conn: &DbConnector, account: Option<AccountId>, scored: Vec<(SongId, f64)>)
+    -> Result<Vec<ScoredSong>, Failure>
+{
+    let mut songs = get_songs(conn, account, scored.iter().map(|s| s.0.clone()).collect())?;
+    Ok(scored.into_iter()
+        .filter_map(|(id, score)| {
+            songs.remove(&id.0).map(|song| ScoredSong {
+                song: song,
+                score: score,
+            })
+        })
+        .collect())
+}
+
+#[options("/songs/<id>/similar")]
+fn song_similar_options(id: i64) -> impl Responder<'static> {
+    ::cors_options()
+}
+
+#[get("/songs/<id>/similar?<params>", rank = 1)]
+fn song_similar_get_query(config: State<AppConfig>, auth: AuthTokenBlob, id: i64, params: RecommendationParams)
+    -> Result<Response<'static>, Failure>
+{
+    let account = config.validate_auth(&auth).ok();
+    let limit = match check_limit(&params) {
+        Ok(limit) => limit,
+        Err(resp) => return Ok(resp),
+    };
+
+    let conn = read_conn(&config)?;
+    let song = get_songs(&*conn, account.clone(), vec![SongId(id)])?
+        .remove(&id)
+        .ok_or(Failure(Status::NotFound))?;
+    let similar = conn.get_similar_songs(&song.id, limit).map_err(db_failure)?;
+    let results = scored_songs(&*conn, account, similar)?;
+
+    Ok(::wrap_json(&rpc::SimilarSongsResponse {
+        song: song,
+        results: results,
+    }))
+}
+
+#[get("/songs/<id>/similar", rank = 2)]
+fn song_similar_get(config: State<AppConfig>, auth: AuthTokenBlob, id: i64) -> Result<Response<'static>, Failure> {
+    song_similar_get_query(config, auth, id, RecommendationParams { limit: None })
+}
+
+#[options("/recommendations")]
+fn recommendations_options() -> impl Responder<'static> {
+    ::cors_options()
+}
+
+#[get("/recommendations?<params>", rank = 1)]
+fn recommendations_get_query(config: State<AppConfig>, auth: AuthTokenBlob, params: RecommendationParams)
+    -> Result<Response<'static>, Failure>
+{
+    let account = config.validate_auth(&auth)?;
+    let limit = match check_limit(&params) {
+        Ok(limit) => limit,
+        Err(resp) => return Ok(resp),
+    };
+
+    let conn = read_conn(&config)?;
+    let recommended = conn.get_recommendations(&account, limit).map_err(db_failure)?;
+    let results = scored_songs(&*conn, Some(account), recommended)?;
+
+    Ok(::wrap_json(&rpc::RecommendationResponse { results: results }))
+}
+
+#[get("/recommendations", rank = 2)]
+fn recommendations_get(config: State<AppConfig>, auth: AuthTokenBlob) -> Result<Response<'static>, Failure> {
+    recommendations_get_query(config, auth, RecommendationParams { limit: None })
+}
@@ -0,0 +1,130 @@
+use rocket::{Response, Route, State};
+use rocket::response::{Failure, Responder};
+use rocket::http::Status;
+use rocket_contrib::JSON as Json;
+
+use ::auth::AuthTokenBlob;
+use ::config::AppConfig;
+use ::database::{AccountId, SmartPlaylistCreate, PLAYLIST_NAME_MAX, compile_rules};
+use ::database::drivers::DbConnector;
+use ::model::{SmartPlaylist, SmartPlaylistId};
+use ::rpc;
+use ::util::unix_now;
+use super::{read_conn, write_conn, db_failure, error_response, fresh_seed, no_content};
+
+pub fn routes() -> Vec<Route> {
+    routes![
+        smart_playlists_options,
+        smart_playlists_get,
+        smart_playlists_post,
+        smart_playlist_options,
+        smart_playlist_get,
+        smart_playlist_get_query,
+        smart_playlist_delete,
+    ]
+}
+
+#[derive(FromForm, Debug)]
+struct SmartPlaylistParams {
+    // shuffle to use for `random` ordering, a fresh one if absent
+    seed: Option<u32>,
+}
+
+/// Responds with the playlist and the songs its rules currently select.
+fn evaluate(conn: &DbConnector, account: &AccountId, playlist: SmartPlaylist, seed: u32)
+    -> Result<Response<'static>, Failure>
+{
+    let query = match compile_rules(account, &playlist.rules, unix_now(), seed) {
+        Ok(query) => query,
+        Err(msg) => {
+            // only valid rules are stored, so the grammar must have changed
+            println!("error: stored smart playlist {} no longer compiles: {}", playlist.id.0, msg);
+            return Err(Failure(Status::InternalServerError));
+        }
+    };
+    let songs = conn.get_songs(&query).map_err(db_failure)?;
+
+    Ok(::wrap_json(&rpc::SmartPlaylistResponse {
+        playlist: playlist,
+        songs: songs,
+        seed: seed,
+    }))
+}
+
+#[options("/smart-playlists")]
+fn smart_playlists_options() -> impl Responder<'static> {
+    ::cors_options()
+}
+
+#[get("/smart-playlists")]
+fn smart_playlists_get(config: State<AppConfig>, auth: AuthTokenBlob) -> Result<Response<'static>, Failure> {
+    let account = config.validate_auth(&auth)?;
+
+    let playlists = read_conn(&config)?
+        .get_smart_playlists(&account)
+        .map_err(db_failure)?;
+
+    Ok(::wrap_json(&rpc::SmartPlaylistSetResponse { results: playlists }))
+}
+
+#[post("/smart-playlists", format="application/json", data="<req>")]
+fn smart_playlists_post(config: State<AppConfig>, auth: AuthTokenBlob, req: Json<rpc::SmartPlaylistCreateRequest>)
+    -> Result<Response<'static>, Failure>
+{
+    let account = config.validate_auth(&auth)?;
+    let Json(req) = req;
+
+    if req.name.trim().len() == 0 || PLAYLIST_NAME_MAX < req.name.chars().count() {
+        return Ok(error_response(Status::BadRequest, "invalid-playlist",
+            format!("playlist names must be between 1 and {} characters", PLAYLIST_NAME_MAX)));
+    }
+    if let Err(msg) = compile_rules(&account, &req.rules, unix_now(), 0) {
+        return Ok(error_response(Status::BadRequest, "invalid-rules", msg));
+    }
+
+    let mut conn = write_conn(&config)?;
+    let id = conn.create_smart_playlist(&account, &SmartPlaylistCreate {
+        name: req.name,
+        rules: req.rules,
+    }).map_err(db_failure)?;
+    let playlist = conn.get_smart_playlist(&account, &id)
+        .map_err(db_failure)?
+        .ok_or(Failure(Status::NotFound))?;
+
+    evaluate(&*conn, &account, playlist, fresh_seed())
+}
+
+#[options("/smart-playlists/<id>")]
+fn smart_playlist_options(id: i64) -> impl Responder<'static> {
+    ::cors_options()
+}
+
+#[get("/smart-playlists/<id>?<params>", rank = 1)]
+fn smart_playlist_get_query(config: State<AppConfig>, auth: AuthTokenBlob, id: i64, params: SmartPlaylistParams)
+    -> Result<Response<'static>, Failure>
+{
+    let account = config.validate_auth(&auth)?;
+
+    let conn = read_conn(&config)?;
+    let playlist = conn.get_smart_playlist(&account, &SmartPlaylistId(id))
+        .map_err(db_failure)?
+        .ok_or(Failure(Status::NotFound))?;
+
+    evaluate(&*conn, &account, playlist, params.seed.unwrap_or_else(fresh_seed))
+}
+
+#[get("/smart-playlists/<id>", rank = 2)]
+fn smart_playlist_get(config: State<AppConfig>, auth: AuthTokenBlob, id: i64) -> Result<Response<'static>, Failure> {
+    smart_playlist_get_query(config, auth, id, SmartPlaylistParams { seed: None })
+}
+
+#[delete("/smart-playlists/<id>")]
+fn smart_playlist_delete(config: State<AppConfig>, auth: AuthTokenBlob, id: i64) -> Result<Response<'static>, Failure> {
+    let account = config.validate_auth(&auth)?;
+
+    write_conn(&config)?
+        .delete_smart_playlist(&account, &SmartPlaylistId(id))
+        .map_err(db_failure)?;
+
+    Ok(no_content())
+}
@@ -0,0 +1,376 @@
+use std::io::{self, Write};
+use std::sync::atomic::{AtomicUsize, Ordering, ATOMIC_USIZE_INIT};
+use std::sync::mpsc;
+use std::thread;
+use std::time::Duration;
+
+use rocket::{Response, Route, State};
+use rocket::response::{Failure, Responder};
+use rocket::http::Status;
+use rocket_contrib::JSON as Json;
+
+use ::auth::AuthTokenBlob;
+use ::config::AppConfig;
+use ::database::{
+    AccountId,
+    Comparison,
+    Play,
+    SongFilter,
+    SongOrder,
+    SongQuery,
+    SongSortKey,
+    RATING_MIN,
+    RATING_MAX,
+    CLIENT_ID_MAX,
+    check_metadata_field,
+};
+use ::database::drivers::DbConnector;
+use ::model::{Song, SongId};
+use ::rpc;
+use ::util::{ChunkWriter, chunk_channel, unix_now};
+use super::{read_conn, write_conn, db_failure, error_response, not_modified, IfNoneMatch};
+
+/// 32 KiB chunks of a streamed song list waiting for the client
+const STREAM_CHUNKS_BUFFERED: usize = 8;
+/// how long a client may stop reading before its song list is abandoned,
+/// along with the database connection and transaction behind it
+const STREAM_STALL_SECS: u64 = 30;
+/// song lists streamed at once, each holding a database connection
+const STREAMS_MAX: usize = 8;
+
+static ACTIVE_STREAMS: AtomicUsize = ATOMIC_USIZE_INIT;
+
+/// how far ahead of ours a client's clock may be when it reports a play
+const PLAY_CLOCK_SKEW_SECS: i64 = 5 * 60;
+
+pub fn routes() -> Vec<Route> {
+    routes![
+        songs_options,
+        songs_get,
+        songs_get_query,
+        song_plays_options,
+        song_plays_post,
+        song_rating_options,
+        song_rating_put,
+        song_rating_delete,
+    ]
+}
+
+#[derive(FromForm, Debug)]
+pub struct SongListParams {
+    // exact rating, e.g. `5` for "my 5-star tracks"
+    pub rating: Option<i32>,
+    pub min_rating: Option<i32>,
+    pub max_rating: Option<i32>,
+    // comma separated `FIELD=value` pairs, e.g. `GENRE=Jazz,DATE=1959`
+    pub metadata: Option<String>,
+    // comma separated sort keys, prefixed with `-` for descending order
+    pub sort: Option<String>,
+}
+
+/// Translates query string parameters into a `SongQuery`.  Per-account
+/// filters and sort keys require `account`.
+pub fn song_query(account: Option<AccountId>, params: &SongListParams) -> Result<SongQuery, String> {
+    let mut query = SongQuery::all();
+
+    let rating_filters = [
+        (Comparison::Eq, params.rating),
+        (Comparison::Ge, params.min_rating),
+        (Comparison::Le, params.max_rating),
+    ];
+    for &(cmp, value) in rating_filters.iter() {
+        if let Some(value) = value {
+            if value < RATING_MIN || RATING_MAX < value {
+                return Err(format!("ratings range from {} to {}", RATING_MIN, RATING_MAX));
+            }
+            query.filters.push(SongFilter::Rating(cmp, value));
+        }
+    }
+
+    if let Some(ref sort) = params.sort {
+        for key in sort.split(',').filter(|k| k.len() > 0) {
+            let (descending, name) = if key.starts_with('-') {
+                (true, &key[1..])
+            } else {
+                (false, key)
+            };
+            let key = match name {
+                "rating" => SongSortKey::Rating,
+                "play_count" => SongSortKey::PlayCount,
+                _ => return Err(format!("unknown sort key {:?}", name)),
+            };
+            query.order.push(SongOrder {
+                key: key,
+                descending: descending,
+            });
+        }
+    }
+
+    let per_account = query.filters.len() > 0 || query.order.len() > 0;
+    if per_account && account.is_none() {
+        return Err("per-account filters and sort keys require an access token".into());
+    }
+    query.account = account;
+
+    if let Some(ref metadata) = params.metadata {
+        for pair in metadata.split(',').filter(|p| p.len() > 0) {
+            let mut parts = pair.splitn(2, '=');
+            let (field, value) = match (parts.next(), parts.next()) {
+                (Some(field), Some(value)) => (field.to_uppercase(), value),
+                _ => return Err(format!("expected FIELD=value, got {:?}", pair)),
+            };
+            check_metadata_field(&field, Some(value))?;
+            query.filters.push(SongFilter::Metadata(field, Comparison::Eq, value.into()));
+        }
+    }
+
+    Ok(query)
+}
+
+#[options("/songs")]
+fn songs_options() -> impl Responder<'static> {
+    ::cors_options()
+}
+
+#[get("/songs?<params>", rank = 1)]
+fn songs_get_query(config: State<AppConfig>, auth: AuthTokenBlob, if_none_match: IfNoneMatch, params: SongListParams)
+    -> Result<Response<'static>, Failure>
+{
+    // the catalog is public, a valid token only adds the per-account fields
+    let account = config.validate_auth(&auth).ok();
+    let query = match song_query(account, &params) {
+        Ok(query) => query,
+        Err(msg) => return Ok(error_response(Status::BadRequest, "invalid-query", msg)),
+    };
+
+    // taken before reading the songs, so at worst the listing is newer
+    // than its tag and the next request is sent in full
+    let etag = song_set_etag(&*read_conn(&config)?, &query)?;
+    if if_none_match.matches(&etag) {
+        return Ok(not_modified(etag));
+    }
+
+    let mut resp = stream_song_set(&config, query)?;
+    resp.set_raw_header("ETag", etag);
+    Ok(resp)
+}
+
+/// Changes whenever the catalog does, as well as whenever the play counts
+/// and ratings included for the account do.
+fn song_set_etag(conn: &DbConnector, query: &SongQuery) -> Result<String, Failure> {
+    let version = conn.get_catalog_version().map_err(db_failure)?;
+    let account = match query.account {
+        Some(ref account) => account,
+        None => return Ok(format!("\"{}\"", version)),
+    };
+
+    let stats_version = conn.get_stats_version(account).map_err(db_failure)?;
+    Ok(format!("\"{}-{}\"", version, stats_version))
+}
+
+/// Responds with the same document as `rpc::SongSetResponse`, written as
+/// the songs are read so that large libraries are never held in memory.
+/// Errors up to the first song are reported as usual; later ones cut the
+/// body short.
+fn stream_song_set(config: &AppConfig, query: SongQuery) -> Result<Response<'static>, Failure> {
+    let slot = match StreamSlot::take() {
+        Some(slot) => slot,
+        None => {
+            let mut resp = error_response(Status::ServiceUnavailable, "busy",
+                "too many song lists are being sent, try again shortly".into());
+            resp.set_raw_header("Retry-After", "5");
+            return Ok(resp);
+        },
+    };
+    let url = config.database.read_url().to_owned();
+    let (writer, reader) = chunk_channel(STREAM_CHUNKS_BUFFERED, Duration::from_secs(STREAM_STALL_SECS));
+    let (started_tx, started_rx) = mpsc::channel();
+
+    thread::spawn(move || {
+        let _slot = slot;
+        let conn = match ::database::drivers::get_driver(&url) {
+            Ok(conn) => conn,
+            Err(e) => {
+                let _ = started_tx.send(Err(e));
+                return;
+            },
+        };
+
+        let mut writer = writer;
+        let mut started = false;
+        let result = {
+            let writer = &mut writer;
+            let started = &mut started;
+            let mut write_song = |song: Song| -> io::Result<()> {
+                if !*started {
+                    *started = true;
+                    let _ = started_tx.send(Ok(()));
+                    writer.write_all(b"{\"results\":[")?;
+                } else {
+                    writer.write_all(b",")?;
+                }
+                ::serde_json::to_writer(&mut *writer, &song)
+                    .map_err(|e| io::Error::new(io::ErrorKind::Other, e))
+            };
+            conn.visit_songs(&query, &mut write_song)
+        };
+
+        match (result, started) {
+            (Ok(()), false) => {
+                let _ = started_tx.send(Ok(()));
+                let empty = ::serde_json::to_vec(&rpc::SongSetResponse { results: Vec::new() }).unwrap();
+                let _ = finish_song_set(writer, &empty);
+            },
+            (Ok(()), true) => {
+                let _ = finish_song_set(writer, b"]}");
+            },
+            (Err(e), false) => {
+                let _ = started_tx.send(Err(e));
+            },
+            (Err(e), true) => {
+                println!("error streaming songs: {:?}", e);
+                writer.fail(e);
+            },
+        }
+    });
+
+    match started_rx.recv() {
+        Ok(Ok(())) => Ok(::wrap_json_stream(reader)),
+        Ok(Err(e)) => Err(db_failure(e)),
+        Err(_) => Err(Failure(Status::InternalServerError)),
+    }
+}
+
+fn finish_song_set(mut writer: ChunkWriter, tail: &[u8]) -> io::Result<()> {
+    writer.write_all(tail)?;
+    writer.flush()
+}
+
+/// One of the `STREAMS_MAX` song lists that may be streamed at once, given
+/// back when dropped.
+struct StreamSlot;
+
+impl StreamSlot {
+    fn take() -> Option<StreamSlot> {
+        if STREAMS_MAX <= ACTIVE_STREAMS.fetch_add(1, Ordering::SeqCst) {
+            ACTIVE_STREAMS.fetch_sub(1, Ordering::SeqCst);
+            return None;
+        }
+        Some(StreamSlot)
+    }
+}
+
+impl Drop for StreamSlot {
+    fn drop(&mut self) {
+        ACTIVE_STREAMS.fetch_sub(1, Ordering::SeqCst);
+    }
+}
+
+#[get("/songs", rank = 2)]
+fn songs_get(config: State<AppConfig>, auth: AuthTokenBlob, if_none_match: IfNoneMatch)
+    -> Result<Response<'static>, Failure>
+{
+    songs_get_query(config, auth, if_none_match, SongListParams {
+        rating: None,
+        min_rating: None,
+        max_rating: None,
+        metadata: None,
+        sort: None,
+    })
+}
+
+#[options("/songs/<id>/plays")]
+fn song_plays_options(id: i64) -> impl Responder<'static> {
+    ::cors_options()
+}
+
+#[post("/songs/<id>/plays", format="application/json", data="<play>")]
+fn song_plays_post(config: State<AppConfig>, auth: AuthTokenBlob, id: i64, play: Json<rpc::PlayRequest>)
+    -> Result<Response<'static>, Failure>
+{
+    let account = config.validate_auth(&auth)?;
+    let Json(play) = play;
+
+    if play.position_ms < 0 {
+        return Ok(error_response(Status::BadRequest, "invalid-play",
+            "position_ms must not be negative".into()));
+    }
+    if !(0.0 <= play.completion && play.completion <= 100.0) {
+        return Ok(error_response(Status::BadRequest, "invalid-play",
+            "completion must be between 0 and 100".into()));
+    }
+    let duration_ms = play.duration_ms.unwrap_or(play.position_ms);
+    if duration_ms < 0 {
+        return Ok(error_response(Status::BadRequest, "invalid-play",
+            "duration_ms must not be negative".into()));
+    }
+    if let Some(ref client_id) = play.client_id {
+        if CLIENT_ID_MAX < client_id.chars().count() {
+            return Ok(error_response(Status::BadRequest, "invalid-play",
+                format!("client_id is longer than {} characters", CLIENT_ID_MAX)));
+        }
+    }
+
+    let now = unix_now();
+    if let Some(started_at) = play.started_at {
+        if now + PLAY_CLOCK_SKEW_SECS < started_at {
+            return Ok(error_response(Status::BadRequest, "invalid-play",
+                "started_at is in the future".into()));
+        }
+    }
+    let counted = config.playback.play_threshold_percent <= play.completion;
+    let mut conn = write_conn(&config)?;
+    let play_count = conn
+        .record_play(&account, &Play {
+            song_id: SongId(id),
+            position_ms: play.position_ms,
+            completion: play.completion,
+            counted: counted,
+            started_at: play.started_at.unwrap_or(now - duration_ms as i64 / 1000),
+            duration_ms: duration_ms,
+            client_id: play.client_id,
+        })
+        .map_err(db_failure)?;
+
+    Ok(::wrap_json(&rpc::PlayResponse {
+        counted: counted,
+        play_count: play_count,
+    }))
+}
+
+#[options("/songs/<id>/rating")]
+fn song_rating_options(id: i64) -> impl Responder<'static> {
+    ::cors_options()
+}
+
+#[put("/songs/<id>/rating", format="application/json", data="<req>")]
+fn song_rating_put(config: State<AppConfig>, auth: AuthTokenBlob, id: i64, req: Json<rpc::RatingRequest>)
+    -> Result<Response<'static>, Failure>
+{
+    let account = config.validate_auth(&auth)?;
+    let Json(req) = req;
+
+    if req.rating < RATING_MIN || RATING_MAX < req.rating {
+        return Ok(error_response(Status::BadRequest, "invalid-rating",
+            format!("ratings range from {} to {}", RATING_MIN, RATING_MAX)));
+    }
+
+    write_conn(&config)?
+        .set_rating(&account, &SongId(id), Some(req.rating))
+        .map_err(db_failure)?;
+
+    Ok(::wrap_json(&rpc::RatingResponse { rating: Some(req.rating) }))
+}
+
+#[delete("/songs/<id>/rating")]
+fn song_rating_delete(config: State<AppConfig>, auth: AuthTokenBlob, id: i64)
+    -> Result<Response<'static>, Failure>
+{
+    let account = config.validate_auth(&auth)?;
+
+    write_conn(&config)?
+        .set_rating(&account, &SongId(id), None)
+        .map_err(db_failure)?;
+
+    Ok(::wrap_json(&rpc::RatingResponse { rating: None }))
+}
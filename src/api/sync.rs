@@ -0,0 +1,50 @@
+use rocket::{Response, Route, State};
+use rocket::response::{Failure, Responder};
+
+use ::auth::AuthTokenBlob;
+use ::config::AppConfig;
+use ::rpc;
+use super::{read_conn, db_failure};
+
+pub fn routes() -> Vec<Route> {
+    routes![
+        sync_options,
+        sync_get,
+        sync_get_query,
+    ]
+}
+
+#[derive(FromForm, Debug)]
+struct SyncParams {
+    // the `version` of the previous sync, everything is sent if absent
+    since: Option<i64>,
+}
+
+#[options("/sync")]
+fn sync_options() -> impl Responder<'static> {
+    ::cors_options()
+}
+
+#[get("/sync?<params>", rank = 1)]
+fn sync_get_query(config: State<AppConfig>, auth: AuthTokenBlob, params: SyncParams)
+    -> Result<Response<'static>, Failure>
+{
+    config.validate_auth(&auth)?;
+
+    let changes = read_conn(&config)?
+        .get_catalog_changes(params.since.unwrap_or(0))
+        .map_err(db_failure)?;
+
+    Ok(::wrap_json(&rpc::SyncResponse {
+        version: changes.version,
+        songs: changes.songs,
+        albums: changes.albums,
+        deleted_song_ids: changes.deleted_song_ids,
+        deleted_album_ids: changes.deleted_album_ids,
+    }))
+}
+
+#[get("/sync", rank = 2)]
+fn sync_get(config: State<AppConfig>, auth: AuthTokenBlob) -> Result<Response<'static>, Failure> {
+    sync_get_query(config, auth, SyncParams { since: None })
+}
@@ -0,0 +1,213 @@
+use std::io::Read;
+
+use rocket::{Response, Route, State};
+use rocket::response::{Failure, Responder};
+use rocket::http::Status;
+use rocket_contrib::JSON as Json;
+
+use ::auth::{AuthTokenBlob, signed_blob_url};
+use ::blob::BlobId;
+use ::config::AppConfig;
+use ::model::{Song, SongId, SongVariant};
+use ::media;
+use ::rpc;
+use ::util::unix_now;
+use super::{read_conn, write_conn, db_failure, error_response, get_song, AcceptHeader};
+
+/// How far the length of a new variant may be off from the song's before
+/// we assume it is a different recording.
+const VARIANT_LENGTH_TOLERANCE_MS: i32 = 2000;
+
+/// How long the redirect target of `/songs/<id>/blob` stays valid, long
+/// enough to play (and seek in) the song once.
+const BLOB_LINK_TTL_SECS: i64 = 6 * 60 * 60;
+
+pub fn routes() -> Vec<Route> {
+    routes![
+        song_variants_options,
+        song_variants_post,
+        song_blob_options,
+        song_blob_get,
+        song_blob_get_query,
+    ]
+}
+
+#[options("/songs/<id>/variants")]
+fn song_variants_options(id: i64) -> impl Responder<'static> {
+    ::cors_options()
+}
+
+/// Adds another encoding of a song from a staged blob.
+#[post("/songs/<id>/variants", format="application/json", data="<req>")]
+fn song_variants_post(config: State<AppConfig>, auth: AuthTokenBlob, id: i64, req: Json<rpc::SongVariantAddRequest>)
+    -> Result<Response<'static>, Failure>
+{
+    config.authorize_catalog_edit(&auth)?;
+    let Json(req) = req;
+
+    let vfs = config.vfs_driver.boxed();
+    let mut buf = Vec::new();
+    let read_result = vfs.open_staged(&req.blob)
+        .and_then(|mut staged| staged.read_to_end(&mut buf));
+    if let Err(err) = read_result {
+        println!("error reading staged blob {}: {}", req.blob.0, err);
+        return Ok(error_response(Status::BadRequest,
+            "unknown-blob", format!("unknown staged blob {}", req.blob.0)));
+    }
+    let info = match media::probe(&buf) {
+        Ok(info) => info,
+        Err(err) => {
+            return Ok(error_response(Status::BadRequest,
+                "invalid-blob", format!("staged blob {}: {}", req.blob.0, err)));
+        }
+    };
+
+    let song_id = SongId(id);
+    let mut conn = write_conn(&config)?;
+    let song = get_song(&*conn, &song_id)?;
+    if VARIANT_LENGTH_TOLERANCE_MS < (info.length_ms - song.length_ms).abs() {
+        return Ok(error_response(Status::BadRequest, "length-mismatch",
+            format!("staged blob {} is {} ms long, the song {} ms", req.blob.0, info.length_ms, song.length_ms)));
+    }
+
+    // Songs imported before variants were tracked don't list the blob they
+    // were imported with yet.
+    let mut known_blobs: Vec<String> = song.variants.iter().map(|v| v.blob.clone()).collect();
+    if song.variants.len() == 0 {
+        match primary_variant(&config, &song) {
+            Some(variant) => {
+                conn.add_song_variant(&song_id, &variant).map_err(db_failure)?;
+                known_blobs.push(variant.blob);
+            },
+            None => println!("song {} keeps no variant for its blob {}", id, song.blob),
+        }
+    }
+
+    vfs.commit_staged(&req.blob, &info.blob_id)
+        .map_err(|e| {
+            println!("error committing blob {}: {}", req.blob.0, e);
+            Failure(Status::InternalServerError)
+        })?;
+    let variant = info.variant();
+    // posting a variant the song already has changes nothing
+    if !known_blobs.contains(&variant.blob) {
+        conn.add_song_variant(&song_id, &variant).map_err(db_failure)?;
+    }
+
+    let song = get_song(&*conn, &song_id)?;
+    Ok(::wrap_json(&rpc::SongVariantSetResponse { results: song.variants }))
+}
+
+fn primary_variant(config: &AppConfig, song: &Song) -> Option<SongVariant> {
+    let blob_id: BlobId = match song.blob.parse() {
+        Ok(blob_id) => blob_id,
+        Err(_) => {
+            println!("song {} has a malformed blob id {:?}", song.id.0, song.blob);
+            return None;
+        }
+    };
+    let mut buf = Vec::new();
+    let vfs = config.vfs_driver.boxed();
+    if let Err(err) = vfs.open_read(&blob_id).and_then(|mut r| r.read_to_end(&mut buf)) {
+        println!("error reading blob {} of song {}: {}", song.blob, song.id.0, err);
+        return None;
+    }
+    match media::probe(&buf) {
+        Ok(info) => Some(info.variant()),
+        Err(err) => {
+            println!("error probing blob {} of song {}: {}", song.blob, song.id.0, err);
+            None
+        }
+    }
+}
+
+#[derive(FromForm, Debug)]
+struct SongBlobParams {
+    // in bits per second
+    max_bitrate: Option<i32>,
+}
+
+#[options("/songs/<id>/blob")]
+fn song_blob_options(id: i64) -> impl Responder<'static> {
+    ::cors_options()
+}
+
+/// Redirects to the blob of the variant best suited to the client, see
+/// `pick_variant`.
+#[get("/songs/<id>/blob?<params>", rank = 1)]
+fn song_blob_get_query(config: State<AppConfig>, auth: AuthTokenBlob, accept: AcceptHeader, id: i64, params: SongBlobParams)
+    -> Result<Response<'static>, Failure>
+{
+    config.validate_auth(&auth)?;
+    if let Some(max_bitrate) = params.max_bitrate {
+        if max_bitrate < 1 {
+            return Ok(error_response(Status::BadRequest, "invalid-parameter",
+                "max_bitrate must be positive".into()));
+        }
+    }
+
+    let song = get_song(&*read_conn(&config)?, &SongId(id))?;
+    // nothing is known about the encoding of songs without variants
+    if song.variants.len() == 0 {
+        return Ok(see_other(signed_blob_location(&config, &song, &song.blob)?));
+    }
+    match pick_variant(&song.variants, &accept, params.max_bitrate) {
+        Some(variant) => Ok(see_other(signed_blob_location(&config, &song, &variant.blob)?)),
+        None => Ok(error_response(Status::NotAcceptable, "no-acceptable-variant",
+            format!("song {} has no variant of an accepted type", id))),
+    }
+}
+
+#[get("/songs/<id>/blob", rank = 2)]
+fn song_blob_get(config: State<AppConfig>, auth: AuthTokenBlob, accept: AcceptHeader, id: i64)
+    -> Result<Response<'static>, Failure>
+{
+    song_blob_get_query(config, auth, accept, id, SongBlobParams {
+        max_bitrate: None,
+    })
+}
+
+/// Among the variants the client accepts, the one of the most preferred
+/// type with the highest bitrate up to `max_bitrate`.  If all of them are
+/// over the limit the one with the lowest bitrate is picked anyway.
+fn pick_variant<'a>(variants: &'a [SongVariant], accept: &AcceptHeader, max_bitrate: Option<i32>)
+    -> Option<&'a SongVariant>
+{
+    let acceptable: Vec<(u32, &SongVariant)> = variants.iter()
+        .map(|variant| (accept.quality(variant.media_type()), variant))
+        .filter(|&(quality, _)| quality > 0)
+        .collect();
+    let within_limit = acceptable.iter()
+        .filter(|&&(_, variant)| max_bitrate.map(|max| variant.bitrate <= max).unwrap_or(true))
+        .max_by_key(|&&(quality, variant)| (quality, variant.bitrate));
+    match within_limit {
+        Some(&(_, variant)) => Some(variant),
+        None => acceptable.iter()
+            .min_by_key(|&&(_, variant)| variant.bitrate)
+            .map(|&(_, variant)| variant),
+    }
+}
+
+/// A link to the blob that needs no access token, since players follow
+/// redirects without sending the Authorization header again.
+fn signed_blob_location(config: &AppConfig, song: &Song, blob: &str) -> Result<String, Failure> {
+    let blob_id: BlobId = blob.parse().map_err(|_| {
+        println!("error: song {} has a malformed blob id {:?}", song.id.0, blob);
+        Failure(Status::InternalServerError)
+    })?;
+    let expires = unix_now() + BLOB_LINK_TTL_SECS;
+    Ok(signed_blob_url(config, &blob_id, expires))
+}
+
+fn see_other(location: String) -> Response<'static> {
+    let mut builder = Response::build();
+    builder.status(Status::SeeOther);
+    builder.raw_header("Location", location);
+    builder.raw_header("Vary", "Accept");
+    if ::ENABLE_CORS {
+        builder.raw_header("Access-Control-Allow-Origin", "*");
+        builder.raw_header("Access-Control-Allow-Methods", "GET, POST, PUT, PATCH, DELETE");
+        builder.raw_header("Access-Control-Allow-Headers", "Content-Type, Authorization");
+    }
+    builder.finalize()
+}
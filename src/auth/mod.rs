@@ -4,6 +4,8 @@ use rocket::request::FromRequest;
 use rocket::{Request, Outcome};
 use rocket::http::Status;
 use bincode::{serialize, deserialize, Bounded};
+use ::blob::BlobId;
+use ::config::AppConfig;
 use ::util::{dehex, hex};
 use crypto::hmac::Hmac;
 use crypto::sha2::Sha256;
@@ -27,6 +29,10 @@ impl AuthTokenInfo {
         now() < self.exp
     }
 
+    pub fn user_id(&self) -> Uuid {
+        Uuid::from_bytes(&self.id).unwrap()
+    }
+
     pub fn new(user_id: Uuid) -> AuthTokenInfo {
         AuthTokenInfo {
             id: *user_id.as_bytes(),
@@ -115,6 +121,30 @@ fn deprefix<'a>(prefix: &'static str, value: &'a str) -> Option<&'a str> {
     }
 }
 
+/// Signs a `GET /blob/<id>` link so that it works without an access token
+/// until `expires`, for players that can't send one.
+pub fn blob_signature(secret: &[u8], blob_id: &BlobId, expires: i64) -> String {
+    let mut sig = [0; 32];
+    env_secret_sig(secret, format!("blob:{}:{}", blob_id, expires).as_bytes(), &mut sig);
+    hex(&sig).unwrap()
+}
+
+/// The full signed `GET /blob/<id>` link, on `config.web.public_url` if
+/// one is set.
+pub fn signed_blob_url(config: &AppConfig, blob_id: &BlobId, expires: i64) -> String {
+    let base = config.web.public_url.as_ref().map(|u| u.trim_right_matches('/')).unwrap_or("");
+    format!("{}/blob/{}?expires={}&sig={}", base, blob_id, expires,
+        blob_signature(config.secret.as_bytes(), blob_id, expires))
+}
+
+pub fn check_blob_signature(secret: &[u8], blob_id: &BlobId, expires: i64, sig: &str) -> bool {
+    if expires <= now() {
+        return false;
+    }
+    let expected = blob_signature(secret, blob_id, expires);
+    fixed_time_eq(expected.as_bytes(), sig.as_bytes())
+}
+
 #[derive(Serialize, Deserialize)]
 struct SigEnvelope {
     ver: i32,
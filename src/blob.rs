@@ -8,6 +8,18 @@ use ::util::{dehex_fixed_size, DehexError};
 #[derive(Copy, Clone, PartialEq, Eq)]
 pub struct BlobId([u8; 32]);
 
+impl BlobId {
+    pub fn from_bytes(hash: [u8; 32]) -> BlobId {
+        BlobId(hash)
+    }
+
+    /// Where the blob lives relative to the root of a blob store.
+    pub fn relative_path(&self) -> String {
+        let hash = format!("{}", self);
+        format!("{}/{}", &hash[0..2], hash)
+    }
+}
+
 impl fmt::Display for BlobId {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
         for by in self.0.iter() {
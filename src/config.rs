@@ -1,8 +1,17 @@
+use std::cmp;
 use std::io::{self, Read};
 use std::path::PathBuf;
-use std::fs::File;
+use std::time::Duration;
+use std::fs::{self, File};
 
+use uuid::Uuid;
+use rocket::response::Failure;
+use rocket::http::Status;
+
+use ::auth::AuthTokenBlob;
 use ::blob::BlobId;
+use ::database::{AccountId, SimilarityParams};
+use ::rpc::StagedBlob;
 
 #[derive(Deserialize)]
 pub struct AppConfig {
@@ -11,6 +20,163 @@ pub struct AppConfig {
     pub database: DatabaseConfig,
     pub vfs_driver: VfsDriverConfig,
     pub web: WebConfig,
+    #[serde(default)]
+    pub playback: PlaybackConfig,
+    #[serde(default)]
+    pub history: HistoryConfig,
+    #[serde(default)]
+    pub recommendations: RecommendationConfig,
+    #[serde(default)]
+    pub catalog: CatalogConfig,
+}
+
+impl AppConfig {
+    /// The account an access token was issued to, if the token is genuine,
+    /// hasn't expired and its account hasn't been deleted since.
+    pub fn validate_auth(&self, auth: &AuthTokenBlob) -> Result<AccountId, Failure> {
+        let info = auth.decode(self.secret.as_bytes())
+            .map_err(|()| Failure(Status::Forbidden))?;
+        if !info.is_valid() {
+            return Err(Failure(Status::Forbidden));
+        }
+        let account = AccountId::from_user_id(info.user_id());
+
+        let deleted = ::database::drivers::get_driver(self.database.read_url())
+            .and_then(|conn| conn.is_account_deleted(&account))
+            .map_err(|e| {
+                println!("error: {:?}", e);
+                Failure(Status::InternalServerError)
+            })?;
+        if deleted {
+            return Err(Failure(Status::Forbidden));
+        }
+        Ok(account)
+    }
+
+    /// Like `validate_auth`, but only lets through the accounts allowed to
+    /// change the shared catalog.
+    pub fn authorize_catalog_edit(&self, auth: &AuthTokenBlob) -> Result<AccountId, Failure> {
+        let account = self.validate_auth(auth)?;
+        if !self.catalog.editors.contains(&account.get_user_id()) {
+            return Err(Failure(Status::Forbidden));
+        }
+        Ok(account)
+    }
+}
+
+#[derive(Deserialize, Default)]
+pub struct CatalogConfig {
+    /// accounts that may upload albums and edit or merge what's in the
+    /// catalog, nobody can if unset
+    #[serde(default)]
+    pub editors: Vec<Uuid>,
+}
+
+#[derive(Deserialize)]
+pub struct PlaybackConfig {
+    /// how much of a song (in percent) must be heard for it to count as played
+    #[serde(default="default_play_threshold_percent")]
+    pub play_threshold_percent: f64,
+}
+
+fn default_play_threshold_percent() -> f64 {
+    50.0
+}
+
+impl Default for PlaybackConfig {
+    fn default() -> PlaybackConfig {
+        PlaybackConfig {
+            play_threshold_percent: default_play_threshold_percent(),
+        }
+    }
+}
+
+#[derive(Deserialize, Default)]
+pub struct HistoryConfig {
+    /// listens older than this many days are deleted, unset keeps them forever
+    pub retention_days: Option<u32>,
+}
+
+impl HistoryConfig {
+    /// How long listens are kept.
+    pub fn retention_secs(&self) -> Option<i64> {
+        self.retention_days.map(|days| days as i64 * 24 * 60 * 60)
+    }
+
+    /// Unix time before which listens are no longer kept.
+    pub fn retention_cutoff(&self, now: i64) -> Option<i64> {
+        self.retention_secs().map(|secs| now - secs)
+    }
+
+    /// The start of a history query, moved up to the retention cutoff so
+    /// that listens not pruned yet are never returned.
+    pub fn query_from(&self, from: Option<i64>, now: i64) -> Option<i64> {
+        match (from, self.retention_cutoff(now)) {
+            (Some(from), Some(cutoff)) => Some(cmp::max(from, cutoff)),
+            (from, cutoff) => from.or(cutoff),
+        }
+    }
+}
+
+#[derive(Deserialize)]
+pub struct RecommendationConfig {
+    /// how often song similarity is recomputed, 0 disables the refresh
+    #[serde(default="default_refresh_interval_secs")]
+    pub refresh_interval_secs: u64,
+    /// a pause longer than this between two listens starts a new session
+    #[serde(default="default_session_gap_secs")]
+    pub session_gap_secs: i64,
+    /// how many similar songs are kept for each song
+    #[serde(default="default_neighbours")]
+    pub neighbours: usize,
+    /// how many days of listening history the similarity is computed from
+    #[serde(default="default_window_days")]
+    pub window_days: u32,
+}
+
+fn default_refresh_interval_secs() -> u64 {
+    60 * 60
+}
+
+fn default_session_gap_secs() -> i64 {
+    30 * 60
+}
+
+fn default_neighbours() -> usize {
+    50
+}
+
+fn default_window_days() -> u32 {
+    180
+}
+
+impl Default for RecommendationConfig {
+    fn default() -> RecommendationConfig {
+        RecommendationConfig {
+            refresh_interval_secs: default_refresh_interval_secs(),
+            session_gap_secs: default_session_gap_secs(),
+            neighbours: default_neighbours(),
+            window_days: default_window_days(),
+        }
+    }
+}
+
+impl RecommendationConfig {
+    /// `None` if the refresh is disabled.
+    pub fn refresh_interval(&self) -> Option<Duration> {
+        if self.refresh_interval_secs == 0 {
+            return None;
+        }
+        Some(Duration::from_secs(self.refresh_interval_secs))
+    }
+
+    pub fn similarity_params(&self) -> SimilarityParams {
+        SimilarityParams {
+            session_gap_secs: self.session_gap_secs,
+            neighbours: self.neighbours,
+            window_days: self.window_days,
+        }
+    }
 }
 
 #[derive(Deserialize)]
@@ -59,6 +225,21 @@ impl BlobDriver
     {
         self.clone()
     }
+
+    fn blob_path(&self, blob_id: &BlobId) -> PathBuf
+    {
+        self.blob_base.join(blob_id.relative_path())
+    }
+
+    fn staging_path(&self, stage_id: &StagedBlob) -> io::Result<PathBuf>
+    {
+        // stage ids come from clients, so make sure they can't escape the
+        // staging directory.
+        if Uuid::parse_str(&stage_id.0).is_err() {
+            return Err(io::Error::new(io::ErrorKind::InvalidInput, "malformed stage id"));
+        }
+        Ok(self.blob_base.join("staging").join(&stage_id.0))
+    }
 }
 
 impl VfsDriverConfig
@@ -74,21 +255,59 @@ impl VfsDriverConfig
 pub trait VfsBackend
 {
     fn open_read(&self, blob_id: &BlobId) -> io::Result<Box<Read>>;
+
+    /// Stores an upload outside of the content-addressed store until it is
+    /// claimed by `commit_staged`.
+    fn stage_write(&self, data: &mut Read) -> io::Result<StagedBlob>;
+
+    fn open_staged(&self, stage_id: &StagedBlob) -> io::Result<Box<Read>>;
+
+    /// Moves a staged upload into place under its content hash.
+    fn commit_staged(&self, stage_id: &StagedBlob, blob_id: &BlobId) -> io::Result<()>;
 }
 
 impl VfsBackend for BlobDriver
 {
     fn open_read(&self, blob_id: &BlobId) -> io::Result<Box<Read>>
     {
-        let hash = format!("{}", blob_id);
-        let path = self.blob_base.join(&hash[0..2]).join(&hash);
+        let path = self.blob_path(blob_id);
         println!("attempting to open path {}", path.display());
         let file = try!(File::open(&path));
         Ok(Box::new(file))
     }
+
+    fn stage_write(&self, data: &mut Read) -> io::Result<StagedBlob>
+    {
+        let stage_id = StagedBlob(format!("{}", Uuid::new_v4()));
+        let path = self.staging_path(&stage_id)?;
+        fs::create_dir_all(path.parent().unwrap())?;
+
+        let mut file = try!(File::create(&path));
+        try!(io::copy(data, &mut file));
+        Ok(stage_id)
+    }
+
+    fn open_staged(&self, stage_id: &StagedBlob) -> io::Result<Box<Read>>
+    {
+        let path = self.staging_path(stage_id)?;
+        let file = try!(File::open(&path));
+        Ok(Box::new(file))
+    }
+
+    fn commit_staged(&self, stage_id: &StagedBlob, blob_id: &BlobId) -> io::Result<()>
+    {
+        let staged = self.staging_path(stage_id)?;
+        let path = self.blob_path(blob_id);
+        fs::create_dir_all(path.parent().unwrap())?;
+        fs::rename(&staged, &path)
+    }
 }
 
 #[derive(Deserialize)]
 pub struct WebConfig {
     pub allow_origins: Vec<String>,
+    /// e.g. `https://music.example.org`, used to make links in exported
+    /// playlists absolute.  Links are root-relative if unset.
+    #[serde(default)]
+    pub public_url: Option<String>,
 }
\ No newline at end of file
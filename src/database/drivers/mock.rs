@@ -1,7 +1,11 @@
-use std::io;
-use std::collections::{HashMap, BTreeMap};
-use std::path::PathBuf;
-use std::fs::File;
+use std::io::{self, Write};
+use std::cmp::Ordering;
+use std::collections::BTreeMap;
+use std::path::{Path, PathBuf};
+use std::fs::{self, File};
+use std::sync::atomic::{self, AtomicBool, ATOMIC_BOOL_INIT};
+use std::thread;
+use std::time::Duration;
 
 use serde_json;
 use url::Url;
@@ -10,76 +14,1816 @@ use super::{DbConnector, SongQuery};
 use ::database::{
     Song,
     SongId,
+    SongVariant,
+    Lyrics,
     Album,
     AlbumId,
+    AlbumSummary,
+    AlbumQuery,
+    AlbumCreate,
     AccountId,
+    MetadataChangeId,
+    MetadataChange,
+    MetadataTarget,
+    MetadataPatch,
+    Play,
+    SongFilter,
+    SongOrder,
+    SongSortKey,
+    PlaylistId,
+    PlaylistEntryId,
+    Playlist,
+    PlaylistEntry,
+    PlaylistCreate,
+    PlaylistUpdate,
+    PlayQueue,
+    PlayQueueUpdate,
+    ListenId,
+    Listen,
+    HistoryQuery,
+    SmartPlaylistId,
+    SmartPlaylist,
+    SmartPlaylistCreate,
+    ArtistId,
+    Artist,
+    ArtistSummary,
+    FacetValue,
+    DuplicateKind,
+    SimilarityParams,
+    AccountProfile,
+    AccountUpdate,
+    LinkedAccount,
+    SongStats,
+    CatalogChanges,
+    SessionListen,
+    SongTraits,
+    SongSimilarity,
+    ArtistCredit,
+    Comparison,
+    RadioSession,
+    song_artist_credits,
+    album_artist_credits,
+    metadata_values,
+    field_values,
+    is_multi_valued_field,
+    metadata_duplicate_groups,
+    compute_similarity,
+    shuffle_key,
 };
+use ::util::unix_now;
 use ::foreign_auth::{
     ForeignAccount as AuthForeignAccount,
+    PROVIDERS,
 };
 
 pub const DRIVER_NAME: &'static str = "mock";
 
+const CATALOG_SONG: &'static str = "song";
+const CATALOG_ALBUM: &'static str = "album";
+
 pub fn get_conn(url: &str) -> io::Result<MockConnector> {
     let url = Url::parse(url)
         .map_err(|e| io::Error::new(io::ErrorKind::Other, e))
         ?;
 
     let data_dir = PathBuf::from(format!(".{}", url.path()));
-    let database_path = data_dir.join("database.json");
+    let fixtures = read_fixtures(&data_dir)?;
+    let mut conn = MockConnector {
+        base_path: data_dir,
+        db: fixtures,
+        write_lock: None,
+    };
+    conn.fill_in_catalog_versions();
+    Ok(conn)
+}
 
-    let mut db_json = File::open(&database_path)?;
-    let fixtures: Fixtures = serde_json::from_reader(&mut db_json)
+fn read_fixtures(data_dir: &Path) -> io::Result<Fixtures> {
+    let mut db_json = File::open(data_dir.join("database.json"))?;
+    serde_json::from_reader(&mut db_json)
         .map_err(|e| io::Error::new(io::ErrorKind::Other, e))
-        ?;
-    
-    return Ok(MockConnector {
-        base_path: data_dir,
-        songs: fixtures.songs.clone(),
-        albums: fixtures.albums.clone(),
-        accounts: HashMap::new(),
-    });
+}
+
+/// Set while a connection may change `database.json`, see
+/// `MockConnector::lock_for_write`.
+static WRITE_LOCKED: AtomicBool = ATOMIC_BOOL_INIT;
+
+const WRITE_LOCK_POLL_MS: u64 = 5;
 
-    return Err(io::Error::new(io::ErrorKind::Other, "unimplemented"));
+/// Holds `WRITE_LOCKED` until dropped.
+struct WriteLock;
+
+impl WriteLock {
+    fn acquire() -> WriteLock {
+        while WRITE_LOCKED.compare_and_swap(false, true, atomic::Ordering::Acquire) {
+            thread::sleep(Duration::from_millis(WRITE_LOCK_POLL_MS));
+        }
+        WriteLock
+    }
+}
+
+impl Drop for WriteLock {
+    fn drop(&mut self) {
+        WRITE_LOCKED.store(false, atomic::Ordering::Release);
+    }
 }
 
-#[derive(Debug, Deserialize)]
+/// The on-disk layout of `database.json`.  Everything but the catalog is
+/// optional so that older fixture files still load.
+#[derive(Debug, Serialize, Deserialize)]
 struct Fixtures {
     albums: Vec<RawAlbum>,
     songs: Vec<RawSong>,
+    #[serde(default)]
+    accounts: Vec<RawAccount>,
+    #[serde(default)]
+    foreign_accounts: Vec<RawForeignAccount>,
+    #[serde(default)]
+    metadata_changes: Vec<MetadataChange>,
+    #[serde(default)]
+    account_songs: Vec<RawAccountSong>,
+    #[serde(default)]
+    playlists: Vec<RawPlaylist>,
+    #[serde(default)]
+    playlist_entries: Vec<RawPlaylistEntry>,
+    #[serde(default)]
+    play_queues: Vec<RawPlayQueue>,
+    #[serde(default)]
+    listens: Vec<RawListen>,
+    #[serde(default)]
+    smart_playlists: Vec<SmartPlaylist>,
+    #[serde(default)]
+    artists: Vec<Artist>,
+    #[serde(default)]
+    artist_aliases: Vec<RawArtistAlias>,
+    #[serde(default)]
+    song_artists: Vec<RawSongArtist>,
+    #[serde(default)]
+    album_artists: Vec<RawAlbumArtist>,
+    #[serde(default)]
+    song_similarity: Vec<SongSimilarity>,
+    #[serde(default)]
+    catalog_changes: Vec<RawCatalogChange>,
+    #[serde(default)]
+    song_variants: Vec<RawSongVariant>,
+    #[serde(default)]
+    song_lyrics: Vec<RawSongLyrics>,
+    #[serde(default)]
+    radio_sessions: Vec<RawRadioSession>,
+    #[serde(default)]
+    deleted_accounts: Vec<Uuid>,
 }
 
 pub struct MockConnector {
     base_path: PathBuf,
-    songs: Vec<RawSong>,
-    albums: Vec<RawAlbum>,
-    accounts: HashMap<String, AccountId>,
+    db: Fixtures,
+    /// taken by the first write and kept until the connection is dropped
+    write_lock: Option<WriteLock>,
+}
+
+impl MockConnector {
+    /// Every method that calls `save` starts with this.  Each connection
+    /// writes back all of the state it holds, so writers take turns through
+    /// a process-wide lock, and the state is read again under it so that a
+    /// write builds on what other connections saved in the meantime.
+    fn lock_for_write(&mut self) -> io::Result<()> {
+        if self.write_lock.is_none() {
+            let lock = WriteLock::acquire();
+            self.db = read_fixtures(&self.base_path)?;
+            self.fill_in_catalog_versions();
+            self.write_lock = Some(lock);
+        }
+        Ok(())
+    }
+
+    /// Fixtures written by hand start out with a version for everything, as
+    /// the migration does for existing catalogs.
+    fn fill_in_catalog_versions(&mut self) {
+        let albums: Vec<i64> = self.db.albums.iter().map(|a| a.id.0).collect();
+        for id in albums.into_iter() {
+            if !self.db.catalog_changes.iter().any(|c| c.kind == CATALOG_ALBUM && c.target_id == id) {
+                self.record_catalog_change(CATALOG_ALBUM, id, false);
+            }
+        }
+        let songs: Vec<i64> = self.db.songs.iter().map(|s| s.id.0).collect();
+        for id in songs.into_iter() {
+            if !self.db.catalog_changes.iter().any(|c| c.kind == CATALOG_SONG && c.target_id == id) {
+                self.record_catalog_change(CATALOG_SONG, id, false);
+            }
+        }
+    }
+
+    /// Writes the current state back to `database.json`.  The data goes to a
+    /// temporary file first and is then renamed over the original, so readers
+    /// never observe a partially written database.
+    fn save(&self) -> io::Result<()> {
+        let database_path = self.base_path.join("database.json");
+        let temp_path = self.base_path.join(format!("database.json.{}.tmp", Uuid::new_v4()));
+
+        {
+            let mut out = File::create(&temp_path)?;
+            serde_json::to_writer_pretty(&mut out, &self.db)
+                .map_err(|e| io::Error::new(io::ErrorKind::Other, e))
+                ?;
+            out.write_all(b"\n")?;
+            out.sync_all()?;
+        }
+        fs::rename(&temp_path, &database_path)
+    }
+
+    /// Moves a song or album to the next catalog version, replacing the
+    /// version it was at before.
+    fn record_catalog_change(&mut self, kind: &str, target_id: i64, deleted: bool) {
+        let version = self.db.catalog_changes.iter().map(|c| c.version).max().unwrap_or(0) + 1;
+        self.db.catalog_changes.retain(|c| !(c.kind == kind && c.target_id == target_id));
+        self.db.catalog_changes.push(RawCatalogChange {
+            version: version,
+            kind: kind.to_string(),
+            target_id: target_id,
+            deleted: deleted,
+        });
+    }
+
+    fn next_album_id(&self) -> AlbumId {
+        AlbumId(self.db.albums.iter().map(|a| a.id.0).max().unwrap_or(0) + 1)
+    }
+
+    fn next_song_id(&self) -> SongId {
+        SongId(self.db.songs.iter().map(|s| s.id.0).max().unwrap_or(0) + 1)
+    }
+
+    fn account_song(&self, account: &AccountId, song_id: &SongId) -> Option<&RawAccountSong> {
+        let user_id = account.get_user_id();
+        self.db.account_songs
+            .iter()
+            .filter(|asm| asm.account_id == user_id && asm.song_id == *song_id)
+            .nth(0)
+    }
+
+    fn last_played(&self, account: Option<&AccountId>, song_id: &SongId) -> Option<i64> {
+        let user_id = match account {
+            Some(account) => account.get_user_id(),
+            None => return None,
+        };
+        self.db.listens
+            .iter()
+            .filter(|l| l.account_id == user_id && l.song_id == *song_id)
+            .map(|l| l.started_at)
+            .max()
+    }
+
+    /// The songs passing the query's filters, in no particular order.
+    fn matching_songs(&self, query: &SongQuery) -> io::Result<Vec<Song>> {
+        let mut out = Vec::new();
+        for song in self.db.songs.iter() {
+            let mut cooked = song.cook(self)?;
+            if let Some(ref account) = query.account {
+                let asm = self.account_song(account, &song.id);
+                cooked.play_count = Some(asm.map(|asm| asm.play_count).unwrap_or(0));
+                cooked.rating = asm.and_then(|asm| asm.score);
+            }
+            if query.filters.iter().all(|f| self.song_matches(query.account.as_ref(), &cooked, f)) {
+                out.push(cooked);
+            }
+        }
+        Ok(out)
+    }
+
+    /// `song` must carry the per-account fields of `account`.
+    fn song_matches(&self, account: Option<&AccountId>, song: &Song, filter: &SongFilter) -> bool {
+        match *filter {
+            SongFilter::Ids(ref ids) => ids.contains(&song.id),
+            SongFilter::Rating(cmp, value) => {
+                song.rating.map(|rating| cmp.test(&rating, &value)).unwrap_or(false)
+            },
+            SongFilter::PlayCount(cmp, value) => {
+                cmp.test(&song.play_count.unwrap_or(0), &value)
+            },
+            SongFilter::Metadata(ref field, cmp, ref value) => {
+                let value = value.to_lowercase();
+                match song.effective_metadata(field) {
+                    Some(raw) if is_multi_valued_field(field) => {
+                        let mut values = metadata_values(raw).into_iter().map(|v| v.to_lowercase());
+                        if cmp == Comparison::Ne {
+                            values.all(|v| v != value)
+                        } else {
+                            values.any(|v| cmp.test(&v, &value))
+                        }
+                    },
+                    Some(raw) => cmp.test(&raw.to_lowercase(), &value),
+                    None => false,
+                }
+            },
+            SongFilter::PlayedSince(since) => {
+                self.last_played(account, &song.id).map(|t| since <= t).unwrap_or(false)
+            },
+            SongFilter::NotPlayedSince(since) => {
+                self.last_played(account, &song.id).map(|t| t < since).unwrap_or(true)
+            },
+            SongFilter::Artist(ref artist_id) => {
+                self.db.song_artists.iter().any(|sa| sa.song_id == song.id && sa.artist_id == *artist_id)
+            },
+        }
+    }
+
+    /// The artist going by `credit.name`, created if we've never seen the
+    /// name.  Mirrors the unique index on `lower(name)`.
+    fn find_or_create_artist(&mut self, credit: &ArtistCredit) -> ArtistId {
+        let name = credit.name.to_lowercase();
+        let alias = self.db.artist_aliases.iter().filter(|a| a.name.to_lowercase() == name).nth(0);
+        if let Some(alias) = alias {
+            return alias.artist_id.clone();
+        }
+        if let Some(artist) = self.db.artists.iter_mut().filter(|a| a.name.to_lowercase() == name).nth(0) {
+            if artist.sort_name.is_none() {
+                artist.sort_name = credit.sort_name.clone();
+            }
+            return artist.id.clone();
+        }
+
+        let id = ArtistId(self.db.artists.iter().map(|a| a.id.0).max().unwrap_or(0) + 1);
+        self.db.artists.push(Artist {
+            id: id.clone(),
+            name: credit.name.clone(),
+            sort_name: credit.sort_name.clone(),
+        });
+        id
+    }
+
+    /// The account's row for the song, created on first use like the
+    /// upsert in the postgres driver.  Bumps the account's stats version,
+    /// as the row is about to change.
+    fn account_song_mut(&mut self, account: &AccountId, song_id: &SongId) -> &mut RawAccountSong {
+        let user_id = account.get_user_id();
+        for raw in self.db.accounts.iter_mut().filter(|a| a.id == user_id) {
+            raw.stats_version += 1;
+        }
+        let position = self.db.account_songs
+            .iter()
+            .position(|asm| asm.account_id == user_id && asm.song_id == *song_id);
+
+        let idx = match position {
+            Some(idx) => idx,
+            None => {
+                let id = self.db.account_songs.iter().map(|asm| asm.id).max().unwrap_or(0) + 1;
+                self.db.account_songs.push(RawAccountSong {
+                    id: id,
+                    account_id: user_id,
+                    song_id: song_id.clone(),
+                    play_count: 0,
+                    score: None,
+                });
+                self.db.account_songs.len() - 1
+            }
+        };
+        &mut self.db.account_songs[idx]
+    }
+
+    fn playlist_mut(&mut self, account: &AccountId, id: &PlaylistId) -> io::Result<&mut RawPlaylist> {
+        let user_id = account.get_user_id();
+        self.db.playlists
+            .iter_mut()
+            .filter(|p| p.id == *id && p.account_id == user_id)
+            .nth(0)
+            .ok_or_else(|| io::Error::new(io::ErrorKind::NotFound, "no such playlist"))
+    }
+
+    /// Entries of a playlist, in order.
+    fn playlist_entries(&self, id: &PlaylistId) -> Vec<&RawPlaylistEntry> {
+        let mut entries: Vec<&RawPlaylistEntry> = self.db.playlist_entries
+            .iter()
+            .filter(|e| e.playlist_id == *id)
+            .collect();
+        entries.sort_by_key(|e| e.position);
+        entries
+    }
+
+    /// Rewrites the positions of a playlist's entries to follow `order`.
+    fn reorder_playlist(&mut self, order: &[PlaylistEntryId]) {
+        for entry in self.db.playlist_entries.iter_mut() {
+            if let Some(position) = order.iter().position(|id| *id == entry.id) {
+                entry.position = position as i32;
+            }
+        }
+    }
+
+    fn metadata_mut(&mut self, target: &MetadataTarget) -> io::Result<&mut BTreeMap<String, String>> {
+        let found = match *target {
+            MetadataTarget::Song(ref id) => {
+                self.db.songs.iter_mut().filter(|s| s.id == *id).nth(0).map(|s| &mut s.metadata)
+            },
+            MetadataTarget::Album(ref id) => {
+                self.db.albums.iter_mut().filter(|a| a.id == *id).nth(0).map(|a| &mut a.metadata)
+            },
+        };
+        found.ok_or_else(|| io::Error::new(io::ErrorKind::NotFound, "no such song or album"))
+    }
+
+    /// Sets (or with `None`, removes) one field and records the change.
+    /// Nothing is recorded if the field already has the requested value.
+    fn apply_metadata_change(
+        &mut self,
+        account: &AccountId,
+        target: &MetadataTarget,
+        field_name: &str,
+        new_value: Option<&str>,
+        reverts: Option<&MetadataChangeId>,
+    ) -> io::Result<Option<MetadataChange>> {
+        let old_value = {
+            let metadata = self.metadata_mut(target)?;
+            let old_value = match new_value {
+                Some(value) => metadata.insert(field_name.to_string(), value.to_string()),
+                None => metadata.remove(field_name),
+            };
+            if old_value.as_ref().map(|v| &v[..]) == new_value {
+                return Ok(None);
+            }
+            old_value
+        };
+
+        let (song_id, album_id) = match *target {
+            MetadataTarget::Song(ref id) => {
+                self.record_catalog_change(CATALOG_SONG, id.0, false);
+                (Some(id.clone()), None)
+            },
+            MetadataTarget::Album(ref id) => {
+                self.record_catalog_change(CATALOG_ALBUM, id.0, false);
+                (None, Some(id.clone()))
+            },
+        };
+        let change = MetadataChange {
+            id: MetadataChangeId(self.db.metadata_changes.iter().map(|c| c.id.0).max().unwrap_or(0) + 1),
+            account_id: Some(account.get_user_id()),
+            song_id: song_id,
+            album_id: album_id,
+            field_name: field_name.to_string(),
+            old_value: old_value,
+            new_value: new_value.map(|v| v.to_string()),
+            reverts: reverts.cloned(),
+            changed_at: unix_now(),
+        };
+        self.db.metadata_changes.push(change.clone());
+        Ok(Some(change))
+    }
 }
 
 impl DbConnector for MockConnector {
     fn get_songs(&self, query: &SongQuery) -> io::Result<Vec<Song>>
     {
+        let mut out = self.matching_songs(query)?;
+        out.sort_by(|a, b| compare_songs(&query.order, a, b));
+        if let Some(limit) = query.limit {
+            out.truncate(limit as usize);
+        }
+        Ok(out)
+    }
+
+    fn get_facets(&self, field: &str, query: &SongQuery) -> io::Result<Vec<FacetValue>>
+    {
+        // lowercased value => (representative spelling, song count).  The
+        // spelling that sorts first byte-wise represents its group, as
+        // `COLLATE "C"` does in the postgres driver.
+        let mut groups: BTreeMap<String, (String, i64)> = BTreeMap::new();
+        for song in self.matching_songs(query)?.iter() {
+            let raw = match song.effective_metadata(field) {
+                Some(raw) => raw,
+                None => continue,
+            };
+            let mut seen = Vec::new();
+            for value in field_values(field, raw).into_iter() {
+                let key = value.to_lowercase();
+                if seen.contains(&key) {
+                    continue;
+                }
+                seen.push(key.clone());
+
+                let group = groups.entry(key).or_insert_with(|| (value.to_string(), 0));
+                if value < &group.0[..] {
+                    group.0 = value.to_string();
+                }
+                group.1 += 1;
+            }
+        }
+
+        let mut out: Vec<FacetValue> = groups.into_iter()
+            .map(|(_, (value, count))| FacetValue { value: value, count: count })
+            .collect();
+        out.sort_by(|a, b| match b.count.cmp(&a.count) {
+            Ordering::Equal => a.value.to_lowercase().cmp(&b.value.to_lowercase()),
+            other => other,
+        });
+        Ok(out)
+    }
+
+    fn get_albums(&self, query: &AlbumQuery) -> io::Result<Vec<AlbumSummary>>
+    {
+        let mut albums: Vec<&RawAlbum> = self.db.albums.iter().collect();
+        albums.sort_by(|a, b| {
+            let (a_val, b_val) = match query.sort_field {
+                Some(ref field) => (a.metadata.get(field), b.metadata.get(field)),
+                None => (None, None),
+            };
+            let by_value = match (a_val, b_val) {
+                (Some(a_val), Some(b_val)) if query.descending => b_val.cmp(a_val),
+                (Some(a_val), Some(b_val)) => a_val.cmp(b_val),
+                (Some(_), None) => Ordering::Less,
+                (None, Some(_)) => Ordering::Greater,
+                (None, None) => Ordering::Equal,
+            };
+            match by_value {
+                Ordering::Equal => a.id.0.cmp(&b.id.0),
+                ordering => ordering,
+            }
+        });
+
         let mut out = Vec::new();
-        for song in self.songs.iter() {
-            out.push(song.cook(self)?);
+        let page = albums.into_iter()
+            .skip(query.offset as usize)
+            .take(query.limit as usize);
+        for album in page {
+            let mut track_count = 0;
+            let mut length_ms = 0;
+            for song in self.db.songs.iter().filter(|s| s.album_id == album.id) {
+                track_count += 1;
+                length_ms += song.length_ms as i64;
+            }
+            out.push(AlbumSummary {
+                album: album.cook(self)?,
+                track_count: track_count,
+                length_ms: length_ms,
+            });
         }
         Ok(out)
     }
 
+    fn get_album(&self, id: &AlbumId) -> io::Result<Option<(Album, Vec<Song>)>>
+    {
+        let album = match self.db.albums.iter().filter(|a| a.id == *id).nth(0) {
+            Some(album) => album.cook(self)?,
+            None => return Ok(None),
+        };
+
+        let mut raw_songs: Vec<&RawSong> = self.db.songs
+            .iter()
+            .filter(|s| s.album_id == *id)
+            .collect();
+        raw_songs.sort_by_key(|s| (s.disc_no, s.track_no, s.id.0));
+
+        let mut songs = Vec::new();
+        for song in raw_songs {
+            songs.push(song.cook(self)?);
+        }
+        Ok(Some((album, songs)))
+    }
+
     fn find_or_create_user(&mut self, acc: &AuthForeignAccount) -> io::Result<AccountId>
     {
-        use std::collections::hash_map::Entry::{Occupied, Vacant};
+        self.lock_for_write()?;
+        let provider_id = acc.provider.uuid();
+        let existing = self.db.foreign_accounts
+            .iter()
+            .filter(|fa| fa.provider_id == provider_id && fa.foreign_id == acc.account_id)
+            .nth(0);
+
+        if let Some(fa) = existing {
+            return Ok(AccountId(fa.account_id));
+        }
+
+        let account_id = Uuid::new_v4();
+        self.db.accounts.push(RawAccount {
+            id: account_id,
+            display_name: String::new(),
+            stats_version: 0,
+        });
+        self.db.foreign_accounts.push(RawForeignAccount {
+            account_id: account_id,
+            provider_id: provider_id,
+            foreign_id: acc.account_id.clone(),
+            created_at: unix_now(),
+        });
+        self.save()?;
+
+        Ok(AccountId(account_id))
+    }
+
+    fn get_account(&self, account: &AccountId) -> io::Result<Option<AccountProfile>>
+    {
+        let user_id = account.get_user_id();
+        Ok(self.db.accounts
+            .iter()
+            .filter(|a| a.id == user_id)
+            .nth(0)
+            .map(|a| AccountProfile {
+                id: a.id,
+                display_name: a.display_name.clone(),
+            }))
+    }
+
+    fn update_account(&mut self, account: &AccountId, update: &AccountUpdate) -> io::Result<()>
+    {
+        self.lock_for_write()?;
+        let user_id = account.get_user_id();
+        {
+            let raw = self.db.accounts
+                .iter_mut()
+                .filter(|a| a.id == user_id)
+                .nth(0)
+                .ok_or_else(|| io::Error::new(io::ErrorKind::NotFound, "no such account"))?;
+            if let Some(ref display_name) = update.display_name {
+                raw.display_name = display_name.clone();
+            }
+        }
+        self.save()
+    }
+
+    fn get_linked_accounts(&self, account: &AccountId) -> io::Result<Vec<LinkedAccount>>
+    {
+        let user_id = account.get_user_id();
+        let mut out: Vec<LinkedAccount> = self.db.foreign_accounts
+            .iter()
+            .filter(|fa| fa.account_id == user_id)
+            .map(|fa| LinkedAccount {
+                provider: PROVIDERS.iter()
+                    .filter(|p| p.uuid() == fa.provider_id)
+                    .map(|p| p.name.to_string())
+                    .nth(0)
+                    .unwrap_or_else(|| fa.provider_id.to_string()),
+                foreign_id: fa.foreign_id.clone(),
+                created_at: fa.created_at,
+            })
+            .collect();
+        out.sort_by(|a, b| (a.created_at, &a.provider).cmp(&(b.created_at, &b.provider)));
+        Ok(out)
+    }
+
+    fn get_song_stats(&self, account: &AccountId) -> io::Result<Vec<SongStats>>
+    {
+        let user_id = account.get_user_id();
+        let mut out: Vec<SongStats> = self.db.account_songs
+            .iter()
+            .filter(|asm| asm.account_id == user_id)
+            .map(|asm| SongStats {
+                song_id: asm.song_id.clone(),
+                play_count: asm.play_count,
+                rating: asm.score,
+            })
+            .collect();
+        out.sort_by_key(|s| s.song_id.0);
+        Ok(out)
+    }
+
+    fn get_stats_version(&self, account: &AccountId) -> io::Result<i64>
+    {
+        let user_id = account.get_user_id();
+        Ok(self.db.accounts.iter().filter(|a| a.id == user_id).map(|a| a.stats_version).nth(0).unwrap_or(0))
+    }
+
+    fn delete_account(&mut self, account: &AccountId) -> io::Result<()>
+    {
+        self.lock_for_write()?;
+        let user_id = account.get_user_id();
+        if !self.db.accounts.iter().any(|a| a.id == user_id) {
+            return Err(io::Error::new(io::ErrorKind::NotFound, "no such account"));
+        }
+
+        for change in self.db.metadata_changes.iter_mut() {
+            if change.account_id == Some(user_id) {
+                change.account_id = None;
+            }
+        }
+        self.db.account_songs.retain(|asm| asm.account_id != user_id);
+        self.db.listens.retain(|l| l.account_id != user_id);
+        self.db.play_queues.retain(|q| q.account_id != user_id);
+        self.db.radio_sessions.retain(|r| r.account_id != user_id);
+        let playlist_ids: Vec<PlaylistId> = self.db.playlists
+            .iter()
+            .filter(|p| p.account_id == user_id)
+            .map(|p| p.id.clone())
+            .collect();
+        self.db.playlist_entries.retain(|e| !playlist_ids.contains(&e.playlist_id));
+        self.db.playlists.retain(|p| p.account_id != user_id);
+        self.db.smart_playlists.retain(|p| p.account_id != user_id);
+        self.db.foreign_accounts.retain(|fa| fa.account_id != user_id);
+        self.db.accounts.retain(|a| a.id != user_id);
+        self.db.deleted_accounts.push(user_id);
+        self.save()
+    }
+
+    fn is_account_deleted(&self, account: &AccountId) -> io::Result<bool>
+    {
+        Ok(self.db.deleted_accounts.contains(&account.get_user_id()))
+    }
+
+    fn create_album(&mut self, ac: &AlbumCreate) -> io::Result<Vec<Song>>
+    {
+        self.lock_for_write()?;
+        let album_id = self.next_album_id();
+        self.db.albums.push(RawAlbum {
+            id: album_id.clone(),
+            art_blob: ac.art_blob.clone(),
+            metadata: ac.metadata.clone(),
+            album_gain: ac.album_gain,
+            album_peak: ac.album_peak,
+        });
+        self.record_catalog_change(CATALOG_ALBUM, album_id.0, false);
+        for (position, credit) in album_artist_credits(&ac.metadata).iter().enumerate() {
+            let artist_id = self.find_or_create_artist(credit);
+            self.db.album_artists.push(RawAlbumArtist {
+                album_id: album_id.clone(),
+                artist_id: artist_id,
+                position: position as i32,
+            });
+        }
+
+        let mut song_ids = Vec::new();
+        for song in ac.songs.iter() {
+            let song_id = self.next_song_id();
+            let taken = self.db.songs.iter().any(|s| {
+                s.album_id == album_id && s.disc_no == song.disc_no && s.track_no == song.track_no
+            });
+            if taken {
+                // mirrors song_album_disc_track_uniq
+                return Err(io::Error::new(io::ErrorKind::InvalidInput,
+                    format!("duplicate track number {} on disc {}", song.track_no, song.disc_no)));
+            }
+            self.db.songs.push(RawSong {
+                id: song_id.clone(),
+                blob: song.blob.clone(),
+                length_ms: song.length_ms,
+                disc_no: song.disc_no,
+                track_no: song.track_no,
+                metadata: song.metadata.clone(),
+                album_id: album_id.clone(),
+                audio_digest: song.audio_digest.clone(),
+                track_gain: song.track_gain,
+                track_peak: song.track_peak,
+            });
+            self.db.song_variants.push(RawSongVariant::new(&song_id, &song.variant));
+            if let Some(ref lyrics) = song.lyrics {
+                self.db.song_lyrics.push(RawSongLyrics {
+                    song_id: song_id.clone(),
+                    lyrics: lyrics.clone(),
+                });
+            }
+            self.record_catalog_change(CATALOG_SONG, song_id.0, false);
+            for (position, credit) in song_artist_credits(&song.metadata, &ac.metadata).iter().enumerate() {
+                let artist_id = self.find_or_create_artist(credit);
+                self.db.song_artists.push(RawSongArtist {
+                    song_id: song_id.clone(),
+                    artist_id: artist_id,
+                    position: position as i32,
+                });
+            }
+            song_ids.push(song_id);
+        }
+        self.save()?;
+
+        let mut out = Vec::new();
+        for song in self.db.songs.iter().filter(|s| song_ids.contains(&s.id)) {
+            out.push(song.cook(self)?);
+        }
+        Ok(out)
+    }
+
+    fn get_songs_without_audio_digest(&self) -> io::Result<Vec<(SongId, String)>>
+    {
+        Ok(self.db.songs
+            .iter()
+            .filter(|s| s.audio_digest.is_none())
+            .map(|s| (s.id.clone(), s.blob.clone()))
+            .collect())
+    }
+
+    fn set_audio_digest(&mut self, song_id: &SongId, digest: &str) -> io::Result<()>
+    {
+        self.lock_for_write()?;
+        match self.db.songs.iter_mut().filter(|s| s.id == *song_id).nth(0) {
+            Some(song) => song.audio_digest = Some(digest.to_string()),
+            None => return Err(io::Error::new(io::ErrorKind::NotFound, "no such song")),
+        }
+        self.save()
+    }
+
+    fn add_song_variant(&mut self, song_id: &SongId, variant: &SongVariant) -> io::Result<()>
+    {
+        self.lock_for_write()?;
+        if !self.db.songs.iter().any(|s| s.id == *song_id) {
+            return Err(io::Error::new(io::ErrorKind::NotFound, "no such song"));
+        }
+        if self.db.song_variants.iter().any(|v| v.song_id == *song_id && v.blob == variant.blob) {
+            // mirrors song_variant_song_blob_uniq
+            return Err(io::Error::new(io::ErrorKind::InvalidInput,
+                "the song already has a variant with that blob"));
+        }
+        self.db.song_variants.push(RawSongVariant::new(song_id, variant));
+        self.record_catalog_change(CATALOG_SONG, song_id.0, false);
+        self.save()
+    }
+
+    fn get_lyrics(&self, song_id: &SongId) -> io::Result<Option<Lyrics>>
+    {
+        if !self.db.songs.iter().any(|s| s.id == *song_id) {
+            return Err(io::Error::new(io::ErrorKind::NotFound, "no such song"));
+        }
+        Ok(self.db.song_lyrics
+            .iter()
+            .filter(|sl| sl.song_id == *song_id)
+            .map(|sl| sl.lyrics.clone())
+            .nth(0))
+    }
+
+    fn set_lyrics(&mut self, song_id: &SongId, lyrics: Option<&Lyrics>) -> io::Result<()>
+    {
+        self.lock_for_write()?;
+        if !self.db.songs.iter().any(|s| s.id == *song_id) {
+            return Err(io::Error::new(io::ErrorKind::NotFound, "no such song"));
+        }
+        self.db.song_lyrics.retain(|sl| sl.song_id != *song_id);
+        if let Some(lyrics) = lyrics {
+            self.db.song_lyrics.push(RawSongLyrics {
+                song_id: song_id.clone(),
+                lyrics: lyrics.clone(),
+            });
+        }
+        self.save()
+    }
+
+    fn find_duplicates(&self, kind: DuplicateKind, tolerance_ms: i32) -> io::Result<Vec<Vec<SongId>>>
+    {
+        if kind == DuplicateKind::Metadata {
+            let songs = self.get_songs(&SongQuery::all())?;
+            return Ok(metadata_duplicate_groups(&songs, tolerance_ms));
+        }
+
+        let mut groups: BTreeMap<String, Vec<SongId>> = BTreeMap::new();
+        for song in self.db.songs.iter() {
+            let key = match kind {
+                DuplicateKind::Audio => song.audio_digest.clone(),
+                _ => Some(song.blob.clone()),
+            };
+            if let Some(key) = key {
+                groups.entry(key).or_insert_with(Vec::new).push(song.id.clone());
+            }
+        }
+
+        let mut out: Vec<Vec<SongId>> = groups.into_iter()
+            .map(|(_, mut ids)| {
+                ids.sort_by_key(|id| id.0);
+                ids
+            })
+            .filter(|ids| ids.len() > 1)
+            .collect();
+        out.sort_by_key(|ids| ids[0].0);
+        Ok(out)
+    }
+
+    fn merge_songs(&mut self, keep: &SongId, duplicates: &[SongId]) -> io::Result<()>
+    {
+        self.lock_for_write()?;
+        if duplicates.contains(keep) {
+            return Err(io::Error::new(io::ErrorKind::InvalidInput, "cannot merge a song into itself"));
+        }
+        for id in Some(keep).into_iter().chain(duplicates.iter()) {
+            if !self.db.songs.iter().any(|s| s.id == *id) {
+                return Err(io::Error::new(io::ErrorKind::NotFound, "no such song"));
+            }
+        }
+
+        for entry in self.db.playlist_entries.iter_mut().filter(|e| duplicates.contains(&e.song_id)) {
+            entry.song_id = keep.clone();
+        }
+        for listen in self.db.listens.iter_mut().filter(|l| duplicates.contains(&l.song_id)) {
+            listen.song_id = keep.clone();
+        }
+        let now = unix_now();
+        for queue in self.db.play_queues.iter_mut() {
+            if !queue.song_ids.iter().any(|id| duplicates.contains(id)) {
+                continue;
+            }
+            for id in queue.song_ids.iter_mut().filter(|id| duplicates.contains(id)) {
+                *id = keep.clone();
+            }
+            queue.version += 1;
+            queue.updated_at = now;
+        }
+        // in place, so that offsets into a session being paged stay put
+        for session in self.db.radio_sessions.iter_mut() {
+            for id in session.song_ids.iter_mut().filter(|id| duplicates.contains(id)) {
+                *id = keep.clone();
+            }
+        }
+
+        // play counts add up, the surviving song's rating wins over the
+        // highest of the duplicates', as in the postgres driver
+        let rated: Vec<Uuid> = self.db.account_songs
+            .iter()
+            .filter(|asm| asm.song_id == *keep && asm.score.is_some())
+            .map(|asm| asm.account_id)
+            .collect();
+        let merged: Vec<RawAccountSong> = self.db.account_songs
+            .iter()
+            .filter(|asm| duplicates.contains(&asm.song_id))
+            .cloned()
+            .collect();
+        for dup in merged.into_iter() {
+            let account = AccountId::from_user_id(dup.account_id);
+            let asm = self.account_song_mut(&account, keep);
+            asm.play_count += dup.play_count;
+            if !rated.contains(&dup.account_id) {
+                asm.score = ::std::cmp::max(asm.score, dup.score);
+            }
+        }
+        self.db.account_songs.retain(|asm| !duplicates.contains(&asm.song_id));
+
+        self.db.metadata_changes.retain(|c| {
+            c.song_id.as_ref().map(|id| !duplicates.contains(id)).unwrap_or(true)
+        });
+        self.db.song_artists.retain(|sa| !duplicates.contains(&sa.song_id));
+        self.db.song_variants.retain(|v| !duplicates.contains(&v.song_id));
+        self.db.song_lyrics.retain(|sl| !duplicates.contains(&sl.song_id));
+        self.db.song_similarity.retain(|sim| {
+            !duplicates.contains(&sim.song_id) && !duplicates.contains(&sim.similar_song_id)
+        });
+        self.db.songs.retain(|s| !duplicates.contains(&s.id));
+        for id in duplicates.iter() {
+            self.record_catalog_change(CATALOG_SONG, id.0, true);
+        }
+
+        self.save()
+    }
+
+    fn get_catalog_version(&self) -> io::Result<i64>
+    {
+        Ok(self.db.catalog_changes.iter().map(|c| c.version).max().unwrap_or(0))
+    }
+
+    fn get_catalog_changes(&self, since: i64) -> io::Result<CatalogChanges>
+    {
+        let mut changes: Vec<&RawCatalogChange> = self.db.catalog_changes
+            .iter()
+            .filter(|c| since < c.version)
+            .collect();
+        changes.sort_by_key(|c| c.version);
+
+        let mut out = CatalogChanges {
+            version: changes.last().map(|c| c.version).unwrap_or(since),
+            songs: Vec::new(),
+            albums: Vec::new(),
+            deleted_song_ids: Vec::new(),
+            deleted_album_ids: Vec::new(),
+        };
+        let mut song_ids = Vec::new();
+        for change in changes.into_iter() {
+            match (&change.kind[..], change.deleted) {
+                (CATALOG_SONG, false) => song_ids.push(SongId(change.target_id)),
+                (CATALOG_SONG, true) => out.deleted_song_ids.push(SongId(change.target_id)),
+                (CATALOG_ALBUM, false) => {
+                    if let Some(album) = self.db.albums.iter().filter(|a| a.id.0 == change.target_id).nth(0) {
+                        out.albums.push(album.cook(self)?);
+                    }
+                },
+                (CATALOG_ALBUM, true) => out.deleted_album_ids.push(AlbumId(change.target_id)),
+                _ => return Err(io::Error::new(io::ErrorKind::Other, "unknown catalog change kind")),
+            }
+        }
+        if song_ids.len() > 0 {
+            let mut query = SongQuery::all();
+            query.filters.push(SongFilter::Ids(song_ids));
+            out.songs = self.get_songs(&query)?;
+        }
+        out.albums.sort_by_key(|a| a.id.0);
+        Ok(out)
+    }
+
+    fn get_artists(&self) -> io::Result<Vec<ArtistSummary>>
+    {
+        let mut out: Vec<ArtistSummary> = self.db.artists.iter().map(|artist| ArtistSummary {
+            artist: artist.clone(),
+            album_count: self.db.album_artists.iter().filter(|aa| aa.artist_id == artist.id).count() as i64,
+            track_count: self.db.song_artists.iter().filter(|sa| sa.artist_id == artist.id).count() as i64,
+        }).collect();
+        out.sort_by_key(|s| {
+            let sort_name = s.artist.sort_name.as_ref().unwrap_or(&s.artist.name).to_lowercase();
+            (sort_name, s.artist.id.0)
+        });
+        Ok(out)
+    }
+
+    fn get_artist(&self, id: &ArtistId) -> io::Result<Option<(Artist, Vec<Album>)>>
+    {
+        let artist = match self.db.artists.iter().filter(|a| a.id == *id).nth(0) {
+            Some(artist) => artist.clone(),
+            None => return Ok(None),
+        };
+
+        let credited: Vec<&AlbumId> = self.db.album_artists.iter()
+            .filter(|aa| aa.artist_id == *id)
+            .map(|aa| &aa.album_id)
+            .collect();
+        let appears_on: Vec<&AlbumId> = self.db.songs.iter()
+            .filter(|s| self.db.song_artists.iter().any(|sa| sa.song_id == s.id && sa.artist_id == *id))
+            .map(|s| &s.album_id)
+            .collect();
+
+        // albums credited to the artist, then the ones they only appear on
+        let mut raw_albums: Vec<&RawAlbum> = self.db.albums.iter()
+            .filter(|a| credited.contains(&&a.id) || appears_on.contains(&&a.id))
+            .collect();
+        raw_albums.sort_by_key(|a| (!credited.contains(&&a.id), a.id.0));
+
+        let mut albums = Vec::new();
+        for album in raw_albums.into_iter() {
+            albums.push(album.cook(self)?);
+        }
+        Ok(Some((artist, albums)))
+    }
+
+    fn merge_artists(&mut self, into: &ArtistId, duplicates: &[ArtistId]) -> io::Result<()>
+    {
+        self.lock_for_write()?;
+        if duplicates.contains(into) {
+            return Err(io::Error::new(io::ErrorKind::InvalidInput, "cannot merge an artist into itself"));
+        }
+        for id in Some(into).into_iter().chain(duplicates.iter()) {
+            if !self.db.artists.iter().any(|a| a.id == *id) {
+                return Err(io::Error::new(io::ErrorKind::NotFound, "no such artist"));
+            }
+        }
+
+        for sa in self.db.song_artists.iter_mut().filter(|sa| duplicates.contains(&sa.artist_id)) {
+            sa.artist_id = into.clone();
+        }
+        for aa in self.db.album_artists.iter_mut().filter(|aa| duplicates.contains(&aa.artist_id)) {
+            aa.artist_id = into.clone();
+        }
+        // a song or album credited to several of the merged artists keeps
+        // its first credit only
+        let mut seen = Vec::new();
+        self.db.song_artists.retain(|sa| {
+            let key = (sa.song_id.0, sa.artist_id.0);
+            if seen.contains(&key) {
+                return false;
+            }
+            seen.push(key);
+            true
+        });
+        let mut seen = Vec::new();
+        self.db.album_artists.retain(|aa| {
+            let key = (aa.album_id.0, aa.artist_id.0);
+            if seen.contains(&key) {
+                return false;
+            }
+            seen.push(key);
+            true
+        });
+
+        for alias in self.db.artist_aliases.iter_mut().filter(|a| duplicates.contains(&a.artist_id)) {
+            alias.artist_id = into.clone();
+        }
+        for artist in self.db.artists.iter().filter(|a| duplicates.contains(&a.id)) {
+            let name = artist.name.to_lowercase();
+            if !self.db.artist_aliases.iter().any(|a| a.name.to_lowercase() == name) {
+                self.db.artist_aliases.push(RawArtistAlias {
+                    name: artist.name.clone(),
+                    artist_id: into.clone(),
+                });
+            }
+        }
+        self.db.artists.retain(|a| !duplicates.contains(&a.id));
+
+        self.save()
+    }
+
+    fn update_metadata(&mut self, account: &AccountId, target: &MetadataTarget, patch: &MetadataPatch)
+        -> io::Result<Vec<MetadataChange>>
+    {
+        self.lock_for_write()?;
+        self.metadata_mut(target)?;
+
+        let mut out = Vec::new();
+        for (key, val) in patch.set.iter() {
+            if let Some(change) = self.apply_metadata_change(account, target, key, Some(val), None)? {
+                out.push(change);
+            }
+        }
+        for key in patch.remove.iter() {
+            if let Some(change) = self.apply_metadata_change(account, target, key, None, None)? {
+                out.push(change);
+            }
+        }
+        self.save()?;
+
+        Ok(out)
+    }
+
+    fn get_metadata_history(&self, target: &MetadataTarget) -> io::Result<Vec<MetadataChange>>
+    {
+        let mut out: Vec<MetadataChange> = self.db.metadata_changes
+            .iter()
+            .filter(|c| match *target {
+                MetadataTarget::Song(ref id) => c.song_id.as_ref() == Some(id),
+                MetadataTarget::Album(ref id) => c.album_id.as_ref() == Some(id),
+            })
+            .cloned()
+            .collect();
+        out.reverse();
+        Ok(out)
+    }
+
+    fn revert_metadata_change(&mut self, account: &AccountId, id: &MetadataChangeId)
+        -> io::Result<Option<MetadataChange>>
+    {
+        self.lock_for_write()?;
+        let change = self.db.metadata_changes
+            .iter()
+            .filter(|c| c.id == *id)
+            .cloned()
+            .nth(0)
+            .ok_or_else(|| io::Error::new(io::ErrorKind::NotFound, "no such metadata change"))?;
+
+        let target = match (change.song_id, change.album_id) {
+            (Some(song_id), _) => MetadataTarget::Song(song_id),
+            (None, Some(album_id)) => MetadataTarget::Album(album_id),
+            (None, None) => return Err(io::Error::new(io::ErrorKind::Other, "change without target")),
+        };
+
+        let reverted = self.apply_metadata_change(account, &target,
+            &change.field_name, change.old_value.as_ref().map(|v| &v[..]), Some(id))?;
+        self.save()?;
+
+        Ok(reverted)
+    }
+
+    fn record_play(&mut self, account: &AccountId, play: &Play) -> io::Result<i32>
+    {
+        self.lock_for_write()?;
+        if !self.db.songs.iter().any(|s| s.id == play.song_id) {
+            return Err(io::Error::new(io::ErrorKind::NotFound, "no such song"));
+        }
+
+        let listen_id = ListenId(self.db.listens.iter().map(|l| l.id.0).max().unwrap_or(0) + 1);
+        self.db.listens.push(RawListen {
+            id: listen_id,
+            account_id: account.get_user_id(),
+            song_id: play.song_id.clone(),
+            started_at: play.started_at,
+            position_ms: play.position_ms,
+            duration_ms: play.duration_ms,
+            client_id: play.client_id.clone(),
+        });
+
+        let play_count = if play.counted {
+            let asm = self.account_song_mut(account, &play.song_id);
+            asm.play_count += 1;
+            asm.play_count
+        } else {
+            self.account_song(account, &play.song_id)
+                .map(|asm| asm.play_count)
+                .unwrap_or(0)
+        };
+        self.save()?;
+
+        Ok(play_count)
+    }
+
+    fn get_history(&self, account: &AccountId, query: &HistoryQuery) -> io::Result<Vec<Listen>>
+    {
+        let user_id = account.get_user_id();
+        let mut raw_listens: Vec<&RawListen> = self.db.listens
+            .iter()
+            .filter(|l| l.account_id == user_id)
+            .filter(|l| query.from.map(|from| from <= l.started_at).unwrap_or(true))
+            .filter(|l| query.to.map(|to| l.started_at < to).unwrap_or(true))
+            .filter(|l| match query.after {
+                Some(ref after) => (l.started_at, l.id.0) < (after.started_at, after.id.0),
+                None => true,
+            })
+            .collect();
+        raw_listens.sort_by(|a, b| (b.started_at, b.id.0).cmp(&(a.started_at, a.id.0)));
+        raw_listens.truncate(query.limit as usize);
+
+        let songs = self.get_songs(&SongQuery {
+            account: Some(account.clone()),
+            filters: vec![SongFilter::Ids(raw_listens.iter().map(|l| l.song_id.clone()).collect())],
+            order: Vec::new(),
+            limit: None,
+        })?;
+
+        let mut out = Vec::new();
+        for listen in raw_listens.into_iter() {
+            let song = songs.iter()
+                .filter(|s| s.id == listen.song_id)
+                .nth(0)
+                .cloned()
+                .ok_or_else(|| io::Error::new(io::ErrorKind::Other, "listen of missing song"))?;
+            out.push(Listen {
+                id: listen.id.clone(),
+                started_at: listen.started_at,
+                position_ms: listen.position_ms,
+                duration_ms: listen.duration_ms,
+                client_id: listen.client_id.clone(),
+                song: song,
+            });
+        }
+        Ok(out)
+    }
+
+    fn prune_history(&mut self, before: i64) -> io::Result<()>
+    {
+        self.lock_for_write()?;
+        let count = self.db.listens.len();
+        self.db.listens.retain(|l| before <= l.started_at);
+        if self.db.listens.len() == count {
+            return Ok(());
+        }
+        self.save()
+    }
+
+    fn refresh_song_similarity(&mut self, params: &SimilarityParams) -> io::Result<usize>
+    {
+        self.lock_for_write()?;
+        let since = params.listens_since(unix_now());
+        let mut listens: Vec<SessionListen> = self.db.listens
+            .iter()
+            .filter(|l| since <= l.started_at)
+            .map(|l| SessionListen {
+                account_id: l.account_id,
+                song_id: l.song_id.clone(),
+                started_at: l.started_at,
+            })
+            .collect();
+        listens.sort_by_key(|l| (l.account_id, l.started_at));
+
+        let mut songs = Vec::new();
+        for song in self.db.songs.iter() {
+            let cooked = song.cook(self)?;
+            let mut artists: Vec<&RawSongArtist> = self.db.song_artists
+                .iter()
+                .filter(|sa| sa.song_id == song.id)
+                .collect();
+            artists.sort_by_key(|sa| sa.position);
+            songs.push(SongTraits {
+                song_id: song.id.clone(),
+                artist_ids: artists.into_iter().map(|sa| sa.artist_id.clone()).collect(),
+                genres: cooked.effective_metadata("GENRE")
+                    .map(|g| metadata_values(g).into_iter().map(|v| v.to_lowercase()).collect())
+                    .unwrap_or_else(Vec::new),
+            });
+        }
+
+        self.db.song_similarity = compute_similarity(&listens, &songs, params);
+        self.save()?;
+        Ok(self.db.song_similarity.len())
+    }
+
+    fn get_similar_songs(&self, song_id: &SongId, limit: u32) -> io::Result<Vec<(SongId, f64)>>
+    {
+        // stored best first
+        Ok(self.db.song_similarity
+            .iter()
+            .filter(|sim| sim.song_id == *song_id)
+            .take(limit as usize)
+            .map(|sim| (sim.similar_song_id.clone(), sim.score))
+            .collect())
+    }
+
+    fn get_recommendations(&self, account: &AccountId, limit: u32) -> io::Result<Vec<(SongId, f64)>>
+    {
+        let user_id = account.get_user_id();
+        let heard = |song_id: &SongId| {
+            self.account_song(account, song_id)
+                .map(|asm| 0 < asm.play_count || asm.score.is_some())
+                .unwrap_or(false)
+        };
+
+        let mut scores: BTreeMap<i64, f64> = BTreeMap::new();
+        for asm in self.db.account_songs.iter().filter(|asm| asm.account_id == user_id && 0 < asm.play_count) {
+            let weight = (1.0 + asm.play_count as f64).ln();
+            for sim in self.db.song_similarity.iter().filter(|sim| sim.song_id == asm.song_id) {
+                if heard(&sim.similar_song_id) {
+                    continue;
+                }
+                *scores.entry(sim.similar_song_id.0).or_insert(0.0) += sim.score * weight;
+            }
+        }
+
+        let mut out: Vec<(SongId, f64)> = scores.into_iter().map(|(id, score)| (SongId(id), score)).collect();
+        out.sort_by(|a, b| {
+            match b.1.partial_cmp(&a.1).unwrap_or(Ordering::Equal) {
+                Ordering::Equal => (a.0).0.cmp(&(b.0).0),
+                ordering => ordering,
+            }
+        });
+        out.truncate(limit as usize);
+        Ok(out)
+    }
+
+    fn create_radio_session(&mut self, account: &AccountId, session: &RadioSession, song_ids: &[SongId],
+        expired_before: i64) -> io::Result<()>
+    {
+        self.lock_for_write()?;
+        let user_id = account.get_user_id();
+        self.db.radio_sessions.retain(|r| {
+            r.account_id != user_id || (expired_before <= r.started_at
+                && (r.seed, r.started_at) != (session.seed, session.started_at))
+        });
+        self.db.radio_sessions.push(RawRadioSession {
+            account_id: user_id,
+            seed: session.seed,
+            started_at: session.started_at,
+            song_ids: song_ids.to_vec(),
+        });
+        self.save()
+    }
+
+    fn get_radio_session(&self, account: &AccountId, session: &RadioSession) -> io::Result<Option<Vec<SongId>>>
+    {
+        let user_id = account.get_user_id();
+        Ok(self.db.radio_sessions
+            .iter()
+            .filter(|r| r.account_id == user_id && r.seed == session.seed && r.started_at == session.started_at)
+            .map(|r| r.song_ids.clone())
+            .nth(0))
+    }
+
+    fn set_rating(&mut self, account: &AccountId, song_id: &SongId, rating: Option<i32>) -> io::Result<()>
+    {
+        self.lock_for_write()?;
+        if !self.db.songs.iter().any(|s| s.id == *song_id) {
+            return Err(io::Error::new(io::ErrorKind::NotFound, "no such song"));
+        }
+
+        self.account_song_mut(account, song_id).score = rating;
+        self.save()
+    }
+
+    fn get_playlists(&self, account: &AccountId) -> io::Result<Vec<Playlist>>
+    {
+        let user_id = account.get_user_id();
+        let mut out: Vec<Playlist> = self.db.playlists
+            .iter()
+            .filter(|p| p.account_id == user_id)
+            .map(|p| p.cook(self))
+            .collect();
+        out.sort_by(|a, b| (&a.name, a.id.0).cmp(&(&b.name, b.id.0)));
+        Ok(out)
+    }
+
+    fn get_playlist(&self, account: &AccountId, id: &PlaylistId)
+        -> io::Result<Option<(Playlist, Vec<PlaylistEntry>)>>
+    {
+        let user_id = account.get_user_id();
+        let playlist = match self.db.playlists.iter().filter(|p| p.id == *id && p.account_id == user_id).nth(0) {
+            Some(playlist) => playlist.cook(self),
+            None => return Ok(None),
+        };
+
+        let raw_entries = self.playlist_entries(id);
+        let songs = self.get_songs(&SongQuery {
+            account: Some(account.clone()),
+            filters: vec![SongFilter::Ids(raw_entries.iter().map(|e| e.song_id.clone()).collect())],
+            order: Vec::new(),
+            limit: None,
+        })?;
+
+        let mut entries = Vec::new();
+        for entry in raw_entries.into_iter() {
+            let song = songs.iter()
+                .filter(|s| s.id == entry.song_id)
+                .nth(0)
+                .cloned()
+                .ok_or_else(|| io::Error::new(io::ErrorKind::Other, "dangling playlist entry"))?;
+            entries.push(PlaylistEntry {
+                id: entry.id.clone(),
+                position: entry.position,
+                song: song,
+            });
+        }
+        Ok(Some((playlist, entries)))
+    }
+
+    fn create_playlist(&mut self, account: &AccountId, playlist: &PlaylistCreate) -> io::Result<PlaylistId>
+    {
+        self.lock_for_write()?;
+        for song_id in playlist.song_ids.iter() {
+            if !self.db.songs.iter().any(|s| s.id == *song_id) {
+                return Err(io::Error::new(io::ErrorKind::NotFound, "no such song"));
+            }
+        }
+
+        let id = PlaylistId(self.db.playlists.iter().map(|p| p.id.0).max().unwrap_or(0) + 1);
+        let now = unix_now();
+        self.db.playlists.push(RawPlaylist {
+            id: id.clone(),
+            account_id: account.get_user_id(),
+            name: playlist.name.clone(),
+            description: playlist.description.clone(),
+            created_at: now,
+            updated_at: now,
+        });
+        let first_entry_id = self.db.playlist_entries.iter().map(|e| e.id.0).max().unwrap_or(0) + 1;
+        for (position, song_id) in playlist.song_ids.iter().enumerate() {
+            self.db.playlist_entries.push(RawPlaylistEntry {
+                id: PlaylistEntryId(first_entry_id + position as i64),
+                playlist_id: id.clone(),
+                song_id: song_id.clone(),
+                position: position as i32,
+            });
+        }
+        self.save()?;
+
+        Ok(id)
+    }
+
+    fn update_playlist(&mut self, account: &AccountId, id: &PlaylistId, update: &PlaylistUpdate) -> io::Result<()>
+    {
+        self.lock_for_write()?;
+        {
+            let playlist = self.playlist_mut(account, id)?;
+            if let Some(ref name) = update.name {
+                playlist.name = name.clone();
+            }
+            if let Some(ref description) = update.description {
+                playlist.description = description.clone();
+            }
+            playlist.updated_at = unix_now();
+        }
+        self.save()
+    }
+
+    fn delete_playlist(&mut self, account: &AccountId, id: &PlaylistId) -> io::Result<()>
+    {
+        self.lock_for_write()?;
+        self.playlist_mut(account, id)?;
+        self.db.playlists.retain(|p| p.id != *id);
+        self.db.playlist_entries.retain(|e| e.playlist_id != *id);
+        self.save()
+    }
+
+    fn insert_playlist_entry(&mut self, account: &AccountId, id: &PlaylistId, song_id: &SongId, position: Option<i32>)
+        -> io::Result<PlaylistEntryId>
+    {
+        self.lock_for_write()?;
+        self.playlist_mut(account, id)?.updated_at = unix_now();
+        if !self.db.songs.iter().any(|s| s.id == *song_id) {
+            return Err(io::Error::new(io::ErrorKind::NotFound, "no such song"));
+        }
 
-        let name = format!("{}@{}", acc.provider.name, acc.account_id);
-        let (created, accid) = match self.accounts.entry(name) {
-            Occupied(mut occ) => (false, occ.into_mut()),
-            Vacant(mut vac) => (true, vac.insert(AccountId(Uuid::new_v4()))),
+        let entry_id = PlaylistEntryId(self.db.playlist_entries.iter().map(|e| e.id.0).max().unwrap_or(0) + 1);
+        let mut order: Vec<PlaylistEntryId> = self.playlist_entries(id).iter().map(|e| e.id.clone()).collect();
+        let position = match position {
+            Some(position) if 0 <= position && (position as usize) < order.len() => position as usize,
+            Some(position) if position < 0 => 0,
+            _ => order.len(),
         };
-        if created {
-            // changed - lets write out the new data
+        order.insert(position, entry_id.clone());
+
+        self.db.playlist_entries.push(RawPlaylistEntry {
+            id: entry_id.clone(),
+            playlist_id: id.clone(),
+            song_id: song_id.clone(),
+            position: position as i32,
+        });
+        self.reorder_playlist(&order);
+        self.save()?;
+
+        Ok(entry_id)
+    }
+
+    fn move_playlist_entry(&mut self, account: &AccountId, id: &PlaylistId, entry: &PlaylistEntryId, position: i32)
+        -> io::Result<()>
+    {
+        self.lock_for_write()?;
+        self.playlist_mut(account, id)?.updated_at = unix_now();
+
+        let mut order: Vec<PlaylistEntryId> = self.playlist_entries(id).iter().map(|e| e.id.clone()).collect();
+        let current = order.iter().position(|e| *e == *entry)
+            .ok_or_else(|| io::Error::new(io::ErrorKind::NotFound, "no such playlist entry"))?;
+        let moved = order.remove(current);
+        let position = ::std::cmp::min(::std::cmp::max(position, 0) as usize, order.len());
+        order.insert(position, moved);
+
+        self.reorder_playlist(&order);
+        self.save()
+    }
+
+    fn remove_playlist_entry(&mut self, account: &AccountId, id: &PlaylistId, entry: &PlaylistEntryId)
+        -> io::Result<()>
+    {
+        self.lock_for_write()?;
+        self.playlist_mut(account, id)?.updated_at = unix_now();
+
+        let mut order: Vec<PlaylistEntryId> = self.playlist_entries(id).iter().map(|e| e.id.clone()).collect();
+        let current = order.iter().position(|e| *e == *entry)
+            .ok_or_else(|| io::Error::new(io::ErrorKind::NotFound, "no such playlist entry"))?;
+        order.remove(current);
+
+        self.db.playlist_entries.retain(|e| e.id != *entry);
+        self.reorder_playlist(&order);
+        self.save()
+    }
+
+    fn get_play_queue(&self, account: &AccountId) -> io::Result<PlayQueue>
+    {
+        let user_id = account.get_user_id();
+        let queue = match self.db.play_queues.iter().filter(|q| q.account_id == user_id).nth(0) {
+            Some(queue) => queue.cook(),
+            None => PlayQueue {
+                song_ids: Vec::new(),
+                current_index: 0,
+                position_ms: 0,
+                version: 0,
+                updated_at: 0,
+            },
+        };
+        Ok(queue)
+    }
+
+    fn set_play_queue(&mut self, account: &AccountId, version: i64, queue: &PlayQueueUpdate)
+        -> io::Result<Option<PlayQueue>>
+    {
+        self.lock_for_write()?;
+        for song_id in queue.song_ids.iter() {
+            if !self.db.songs.iter().any(|s| s.id == *song_id) {
+                return Err(io::Error::new(io::ErrorKind::InvalidInput,
+                    format!("no such song: {}", song_id.0)));
+            }
         }
-        Ok(accid.clone())
+
+        if self.get_play_queue(account)?.version != version {
+            return Ok(None);
+        }
+
+        let user_id = account.get_user_id();
+        self.db.play_queues.retain(|q| q.account_id != user_id);
+        let raw = RawPlayQueue {
+            account_id: user_id,
+            song_ids: queue.song_ids.clone(),
+            current_index: queue.current_index,
+            position_ms: queue.position_ms,
+            version: version + 1,
+            updated_at: unix_now(),
+        };
+        let cooked = raw.cook();
+        self.db.play_queues.push(raw);
+        self.save()?;
+        Ok(Some(cooked))
     }
+
+    fn get_smart_playlists(&self, account: &AccountId) -> io::Result<Vec<SmartPlaylist>>
+    {
+        let user_id = account.get_user_id();
+        let mut out: Vec<SmartPlaylist> = self.db.smart_playlists
+            .iter()
+            .filter(|p| p.account_id == user_id)
+            .cloned()
+            .collect();
+        out.sort_by(|a, b| (&a.name, a.id.0).cmp(&(&b.name, b.id.0)));
+        Ok(out)
+    }
+
+    fn get_smart_playlist(&self, account: &AccountId, id: &SmartPlaylistId) -> io::Result<Option<SmartPlaylist>>
+    {
+        let user_id = account.get_user_id();
+        Ok(self.db.smart_playlists
+            .iter()
+            .filter(|p| p.id == *id && p.account_id == user_id)
+            .nth(0)
+            .cloned())
+    }
+
+    fn create_smart_playlist(&mut self, account: &AccountId, playlist: &SmartPlaylistCreate)
+        -> io::Result<SmartPlaylistId>
+    {
+        self.lock_for_write()?;
+        let id = SmartPlaylistId(self.db.smart_playlists.iter().map(|p| p.id.0).max().unwrap_or(0) + 1);
+        let now = unix_now();
+        self.db.smart_playlists.push(SmartPlaylist {
+            id: id.clone(),
+            account_id: account.get_user_id(),
+            name: playlist.name.clone(),
+            rules: playlist.rules.clone(),
+            created_at: now,
+            updated_at: now,
+        });
+        self.save()?;
+
+        Ok(id)
+    }
+
+    fn delete_smart_playlist(&mut self, account: &AccountId, id: &SmartPlaylistId) -> io::Result<()>
+    {
+        self.lock_for_write()?;
+        let user_id = account.get_user_id();
+        let count = self.db.smart_playlists.len();
+        self.db.smart_playlists.retain(|p| !(p.id == *id && p.account_id == user_id));
+        if self.db.smart_playlists.len() == count {
+            return Err(io::Error::new(io::ErrorKind::NotFound, "no such smart playlist"));
+        }
+        self.save()
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct RawArtistAlias {
+    pub name: String,
+    pub artist_id: ArtistId,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct RawCatalogChange {
+    pub version: i64,
+    // `song` or `album`
+    pub kind: String,
+    pub target_id: i64,
+    pub deleted: bool,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct RawSongVariant {
+    pub song_id: SongId,
+    pub blob: String,
+    pub codec: String,
+    pub bitrate: i32,
+    pub channels: i16,
+    pub sample_rate: i32,
+    pub size: i64,
+}
+
+impl RawSongVariant
+{
+    fn new(song_id: &SongId, variant: &SongVariant) -> RawSongVariant
+    {
+        RawSongVariant {
+            song_id: song_id.clone(),
+            blob: variant.blob.clone(),
+            codec: variant.codec.clone(),
+            bitrate: variant.bitrate,
+            channels: variant.channels,
+            sample_rate: variant.sample_rate,
+            size: variant.size,
+        }
+    }
+
+    fn cook(&self) -> SongVariant
+    {
+        SongVariant {
+            blob: self.blob.clone(),
+            codec: self.codec.clone(),
+            bitrate: self.bitrate,
+            channels: self.channels,
+            sample_rate: self.sample_rate,
+            size: self.size,
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct RawSongLyrics {
+    pub song_id: SongId,
+    pub lyrics: Lyrics,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct RawSongArtist {
+    pub song_id: SongId,
+    pub artist_id: ArtistId,
+    pub position: i32,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct RawAlbumArtist {
+    pub album_id: AlbumId,
+    pub artist_id: ArtistId,
+    pub position: i32,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct RawListen {
+    pub id: ListenId,
+    pub account_id: Uuid,
+    pub song_id: SongId,
+    pub started_at: i64,
+    #[serde(default)]
+    pub position_ms: i32,
+    pub duration_ms: i32,
+    pub client_id: Option<String>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct RawRadioSession {
+    pub account_id: Uuid,
+    pub seed: u32,
+    pub started_at: i64,
+    pub song_ids: Vec<SongId>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct RawPlayQueue {
+    pub account_id: Uuid,
+    pub song_ids: Vec<SongId>,
+    pub current_index: i32,
+    pub position_ms: i64,
+    pub version: i64,
+    pub updated_at: i64,
+}
+
+impl RawPlayQueue
+{
+    fn cook(&self) -> PlayQueue
+    {
+        PlayQueue {
+            song_ids: self.song_ids.clone(),
+            current_index: self.current_index,
+            position_ms: self.position_ms,
+            version: self.version,
+            updated_at: self.updated_at,
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct RawPlaylist {
+    pub id: PlaylistId,
+    pub account_id: Uuid,
+    pub name: String,
+    pub description: String,
+    pub created_at: i64,
+    pub updated_at: i64,
+}
+
+impl RawPlaylist
+{
+    fn cook(&self, conn: &MockConnector) -> Playlist
+    {
+        Playlist {
+            id: self.id.clone(),
+            account_id: self.account_id,
+            name: self.name.clone(),
+            description: self.description.clone(),
+            entry_count: conn.db.playlist_entries.iter().filter(|e| e.playlist_id == self.id).count() as i64,
+            created_at: self.created_at,
+            updated_at: self.updated_at,
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct RawPlaylistEntry {
+    pub id: PlaylistEntryId,
+    pub playlist_id: PlaylistId,
+    pub song_id: SongId,
+    pub position: i32,
+}
+
+fn compare_songs(order: &[SongOrder], a: &Song, b: &Song) -> Ordering {
+    for item in order.iter() {
+        let (a_key, b_key) = match item.key {
+            SongSortKey::Rating => (a.rating.map(|r| r as i64), b.rating.map(|r| r as i64)),
+            SongSortKey::PlayCount => (
+                Some(a.play_count.unwrap_or(0) as i64),
+                Some(b.play_count.unwrap_or(0) as i64),
+            ),
+            SongSortKey::Random(seed) => (Some(shuffle_key(seed, &a.id)), Some(shuffle_key(seed, &b.id))),
+        };
+        let ordering = match (a_key, b_key) {
+            (Some(a_key), Some(b_key)) if item.descending => b_key.cmp(&a_key),
+            (Some(a_key), Some(b_key)) => a_key.cmp(&b_key),
+            (Some(_), None) => Ordering::Less,
+            (None, Some(_)) => Ordering::Greater,
+            (None, None) => Ordering::Equal,
+        };
+        if ordering != Ordering::Equal {
+            return ordering;
+        }
+    }
+    a.id.0.cmp(&b.id.0)
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct RawAccountSong {
+    pub id: i64,
+    pub account_id: Uuid,
+    pub song_id: SongId,
+    pub play_count: i32,
+    #[serde(default)]
+    pub score: Option<i32>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct RawAccount {
+    pub id: Uuid,
+    pub display_name: String,
+    #[serde(default)]
+    pub stats_version: i64,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct RawForeignAccount {
+    pub account_id: Uuid,
+    pub provider_id: Uuid,
+    pub foreign_id: String,
+    #[serde(default)]
+    pub created_at: i64,
 }
 
 
@@ -88,6 +1832,10 @@ pub struct RawAlbum {
     pub id: AlbumId,
     pub art_blob: Option<String>,
     pub metadata: BTreeMap<String, String>,
+    #[serde(default)]
+    pub album_gain: Option<f64>,
+    #[serde(default)]
+    pub album_peak: Option<f64>,
 }
 
 #[derive(Serialize, Deserialize, Debug, Clone)]
@@ -95,9 +1843,17 @@ pub struct RawSong {
     pub id: SongId,
     pub blob: String,
     pub length_ms: i32,
+    #[serde(default="first_disc")]
+    pub disc_no: i16,
     pub track_no: i16,
     pub metadata: BTreeMap<String, String>,
     pub album_id: AlbumId,
+    #[serde(default)]
+    pub audio_digest: Option<String>,
+    #[serde(default)]
+    pub track_gain: Option<f64>,
+    #[serde(default)]
+    pub track_peak: Option<f64>,
 }
 
 impl RawAlbum
@@ -106,9 +1862,9 @@ impl RawAlbum
         -> io::Result<Album>
     {
         Ok(Album {
-            id: self.id.clone(),
-            art_blob: self.art_blob.clone(),
-            metadata: self.metadata.clone(),
+            album_gain: self.album_gain,
+            album_peak: self.album_peak,
+            ..Album::new(self.id.clone(), self.art_blob.clone(), self.metadata.clone())
         })
     }
 }
@@ -118,7 +1874,7 @@ impl RawSong
     fn cook(&self, conn: &MockConnector)
         -> io::Result<Song>
     {
-        let album = conn.albums
+        let album = conn.db.albums
             .iter()
             .filter(|a| a.id == self.album_id)
             .nth(0)
@@ -127,13 +1883,172 @@ impl RawSong
             })
             ?.cook(conn)?;
         
+        let song = Song::new(
+            self.id.clone(),
+            self.blob.clone(),
+            self.length_ms,
+            self.disc_no,
+            self.track_no,
+            self.metadata.clone(),
+            album);
+
+        // highest bitrate first, as the postgres driver returns them
+        let mut variants: Vec<SongVariant> = conn.db.song_variants
+            .iter()
+            .filter(|v| v.song_id == self.id)
+            .map(|v| v.cook())
+            .collect();
+        variants.sort_by(|a, b| b.bitrate.cmp(&a.bitrate));
         Ok(Song {
-            id: self.id.clone(),
-            blob: self.blob.clone(),
-            length_ms: self.length_ms,
-            track_no: self.track_no,
-            metadata: self.metadata.clone(),
-            album: album,
+            track_gain: self.track_gain,
+            track_peak: self.track_peak,
+            variants: variants,
+            ..song
         })
     }
-}
\ No newline at end of file
+}
+
+fn first_disc() -> i16 {
+    1
+}
+#[cfg(test)]
+mod test {
+    use std::env;
+    use std::f64;
+    use std::fs::{self, File};
+    use std::io::Write;
+
+    use uuid::Uuid;
+
+    use ::database::{AccountId, SimilarityParams, SongId};
+    use ::database::drivers::DbConnector;
+    use ::util::unix_now;
+    use super::{MockConnector, read_fixtures};
+
+    const ACCOUNT: &'static str = "00000000-0000-0000-0000-00000000000a";
+    const OTHER_ACCOUNT: &'static str = "00000000-0000-0000-0000-00000000000b";
+    const DAY_SECS: i64 = 24 * 60 * 60;
+
+    /// A connector on `fixtures`, saved to a directory of its own.
+    fn connector(fixtures: &str) -> MockConnector {
+        let dir = env::temp_dir().join(format!("mock-driver-test-{}", Uuid::new_v4()));
+        fs::create_dir(&dir).unwrap();
+        File::create(dir.join("database.json")).unwrap().write_all(fixtures.as_bytes()).unwrap();
+        MockConnector {
+            db: read_fixtures(&dir).unwrap(),
+            base_path: dir,
+            write_lock: None,
+        }
+    }
+
+    /// An album with a song of each genre, numbered from 1.
+    fn catalog(genres: &[&str]) -> String {
+        let songs: Vec<String> = genres.iter().enumerate()
+            .map(|(idx, genre)| format!(r#"{{"id": {id}, "blob": "blob-{id}", "length_ms": 180000,
+                "track_no": {id}, "album_id": 1, "metadata": {{"TITLE": "Song {id}", "GENRE": "{genre}"}}}}"#,
+                id=idx + 1, genre=genre))
+            .collect();
+        format!(r#""albums": [{{"id": 1, "art_blob": null, "metadata": {{"ALBUM": "Fixture"}}}}],
+            "songs": [{}]"#, songs.join(", "))
+    }
+
+    fn listen(id: i64, account: &str, song_id: i64, started_at: i64) -> String {
+        format!(r#"{{"id": {}, "account_id": "{}", "song_id": {}, "started_at": {},
+            "duration_ms": 180000, "client_id": null}}"#, id, account, song_id, started_at)
+    }
+
+    fn account() -> AccountId {
+        AccountId::from_user_id(Uuid::parse_str(ACCOUNT).unwrap())
+    }
+
+    #[test]
+    fn test_refresh_song_similarity() {
+        let recent = unix_now() - 10 * DAY_SECS;
+        let stale = unix_now() - 60 * DAY_SECS;
+        // 1 and 2 are played together in two sessions, 3 on its own in
+        // between; 4 and 5 only together outside the window
+        let listens = vec![
+            listen(1, OTHER_ACCOUNT, 1, recent),
+            listen(2, OTHER_ACCOUNT, 2, recent + 60),
+            listen(3, OTHER_ACCOUNT, 3, recent + 3 * 60 * 60),
+            listen(4, OTHER_ACCOUNT, 1, recent + 6 * 60 * 60),
+            listen(5, OTHER_ACCOUNT, 2, recent + 6 * 60 * 60 + 60),
+            listen(6, OTHER_ACCOUNT, 4, stale),
+            listen(7, OTHER_ACCOUNT, 5, stale + 60),
+            listen(8, OTHER_ACCOUNT, 4, stale + DAY_SECS),
+            listen(9, OTHER_ACCOUNT, 5, stale + DAY_SECS + 60),
+        ];
+        let mut conn = connector(&format!("{{{}, \"listens\": [{}]}}",
+            catalog(&["rock", "rock", "rock", "jazz", "jazz"]), listens.join(", ")));
+
+        let params = SimilarityParams {
+            session_gap_secs: 30 * 60,
+            neighbours: 2,
+            window_days: 30,
+        };
+        assert_eq!(conn.refresh_song_similarity(&params).unwrap(), 8);
+        // co-listened songs rank above the ones only sharing a genre
+        assert_eq!(conn.get_similar_songs(&SongId(1), 10).unwrap(),
+            vec![(SongId(2), 2.0), (SongId(3), 0.25)]);
+        assert_eq!(conn.get_similar_songs(&SongId(3), 10).unwrap(),
+            vec![(SongId(1), 0.25), (SongId(2), 0.25)]);
+        assert_eq!(conn.get_similar_songs(&SongId(4), 10).unwrap(),
+            vec![(SongId(5), 0.25)]);
+        assert_eq!(conn.get_similar_songs(&SongId(1), 1).unwrap(),
+            vec![(SongId(2), 2.0)]);
+
+        fs::remove_dir_all(&conn.base_path).unwrap();
+    }
+
+    const RECOMMENDATION_FIXTURES: &'static str = r#",
+        "account_songs": [
+            {"id": 1, "account_id": "00000000-0000-0000-0000-00000000000a", "song_id": 1, "play_count": 3},
+            {"id": 2, "account_id": "00000000-0000-0000-0000-00000000000a", "song_id": 3, "play_count": 0, "score": 4},
+            {"id": 3, "account_id": "00000000-0000-0000-0000-00000000000b", "song_id": 2, "play_count": 5}
+        ],
+        "song_similarity": [
+            {"song_id": 1, "similar_song_id": 2, "score": 2.0},
+            {"song_id": 1, "similar_song_id": 3, "score": 1.5},
+            {"song_id": 1, "similar_song_id": 5, "score": 0.25},
+            {"song_id": 1, "similar_song_id": 4, "score": 0.25},
+            {"song_id": 2, "similar_song_id": 4, "score": 1.5}
+        ]}"#;
+
+    #[test]
+    fn test_recommendations() {
+        let mut fixtures = format!("{{{}", catalog(&["rock", "rock", "rock", "jazz", "jazz"]));
+        fixtures.push_str(RECOMMENDATION_FIXTURES);
+        let conn = connector(&fixtures);
+
+        // weighted by how often the account played song 1; 3 is rated and
+        // so heard already, 2 only counts as heard for the other account,
+        // ties go to the lower id
+        let weight = (1.0f64 + 3.0).ln();
+        assert_eq!(conn.get_recommendations(&account(), 10).unwrap(), vec![
+            (SongId(2), 2.0 * weight),
+            (SongId(4), 0.25 * weight),
+            (SongId(5), 0.25 * weight),
+        ]);
+        assert_eq!(conn.get_recommendations(&account(), 2).unwrap(), vec![
+            (SongId(2), 2.0 * weight),
+            (SongId(4), 0.25 * weight),
+        ]);
+        let nobody = AccountId::from_user_id(Uuid::nil());
+        assert_eq!(conn.get_recommendations(&nobody, 10).unwrap(), vec![]);
+
+        fs::remove_dir_all(&conn.base_path).unwrap();
+    }
+
+    #[test]
+    fn test_recommendations_nan_score() {
+        let mut fixtures = format!("{{{}", catalog(&["rock", "rock", "rock", "jazz", "jazz"]));
+        fixtures.push_str(RECOMMENDATION_FIXTURES);
+        let mut conn = connector(&fixtures);
+        conn.db.song_similarity[0].score = f64::NAN;
+
+        let recommended = conn.get_recommendations(&account(), 10).unwrap();
+        assert_eq!(recommended.len(), 3);
+
+        fs::remove_dir_all(&conn.base_path).unwrap();
+    }
+}
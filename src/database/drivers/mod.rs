@@ -5,8 +5,46 @@ pub mod mock;
 
 use ::database::{
     Song,
+    SongId,
+    SongVariant,
+    Lyrics,
     SongQuery,
+    Album,
+    AlbumId,
+    AlbumSummary,
+    AlbumQuery,
+    AlbumCreate,
     AccountId,
+    MetadataChangeId,
+    MetadataChange,
+    MetadataTarget,
+    MetadataPatch,
+    Play,
+    PlaylistId,
+    PlaylistEntryId,
+    Playlist,
+    PlaylistEntry,
+    PlaylistCreate,
+    PlaylistUpdate,
+    PlayQueue,
+    PlayQueueUpdate,
+    Listen,
+    HistoryQuery,
+    SmartPlaylistId,
+    SmartPlaylist,
+    SmartPlaylistCreate,
+    ArtistId,
+    Artist,
+    ArtistSummary,
+    FacetValue,
+    DuplicateKind,
+    SimilarityParams,
+    AccountProfile,
+    AccountUpdate,
+    LinkedAccount,
+    SongStats,
+    CatalogChanges,
+    RadioSession,
 };
 use ::foreign_auth::{
     ForeignAccount as AuthForeignAccount,
@@ -15,7 +53,188 @@ use ::foreign_auth::{
 pub trait DbConnector {
     fn get_songs(&self, query: &SongQuery) -> io::Result<Vec<Song>>;
 
+    /// Calls `visit` with each song matching `query`, in order.  Drivers
+    /// that can should avoid holding the whole result in memory.
+    fn visit_songs(&self, query: &SongQuery, visit: &mut FnMut(Song) -> io::Result<()>) -> io::Result<()> {
+        for song in self.get_songs(query)?.into_iter() {
+            visit(song)?;
+        }
+        Ok(())
+    }
+
+    /// The distinct values of a metadata field among the songs matching
+    /// `query`, most common first.  Values are split like multi-valued
+    /// fields and grouped case-insensitively; the order and limit of the
+    /// query are ignored.
+    fn get_facets(&self, field: &str, query: &SongQuery) -> io::Result<Vec<FacetValue>>;
+
+    fn get_albums(&self, query: &AlbumQuery) -> io::Result<Vec<AlbumSummary>>;
+
+    /// The album and its songs in track order, if it exists.
+    fn get_album(&self, id: &AlbumId) -> io::Result<Option<(Album, Vec<Song>)>>;
+
     fn find_or_create_user(&mut self, acc: &AuthForeignAccount) -> io::Result<AccountId>;
+
+    fn get_account(&self, account: &AccountId) -> io::Result<Option<AccountProfile>>;
+
+    fn update_account(&mut self, account: &AccountId, update: &AccountUpdate) -> io::Result<()>;
+
+    fn get_linked_accounts(&self, account: &AccountId) -> io::Result<Vec<LinkedAccount>>;
+
+    /// Every song the account has played or rated.
+    fn get_song_stats(&self, account: &AccountId) -> io::Result<Vec<SongStats>>;
+
+    /// Increases whenever the account's play counts or ratings change.
+    fn get_stats_version(&self, account: &AccountId) -> io::Result<i64>;
+
+    /// Deletes the account along with everything tied to it.  Metadata
+    /// edits are kept but no longer attributed to anyone.  The account's id
+    /// is remembered so that the tokens issued to it stop working.
+    fn delete_account(&mut self, account: &AccountId) -> io::Result<()>;
+
+    fn is_account_deleted(&self, account: &AccountId) -> io::Result<bool>;
+
+    /// Inserts the album, its songs and all metadata atomically, linking
+    /// the artists named in the metadata.
+    fn create_album(&mut self, album: &AlbumCreate) -> io::Result<Vec<Song>>;
+
+    /// Songs whose audio digest hasn't been recorded yet, with their blobs.
+    fn get_songs_without_audio_digest(&self) -> io::Result<Vec<(SongId, String)>>;
+
+    fn set_audio_digest(&mut self, song_id: &SongId, digest: &str) -> io::Result<()>;
+
+    /// Records another encoding of the song.  Fails with `InvalidInput` if
+    /// the song already has a variant with that blob.
+    fn add_song_variant(&mut self, song_id: &SongId, variant: &SongVariant) -> io::Result<()>;
+
+    /// Fails with `NotFound` if the song doesn't exist.
+    fn get_lyrics(&self, song_id: &SongId) -> io::Result<Option<Lyrics>>;
+
+    /// Replaces the song's lyrics, `None` removes them.  Fails with
+    /// `NotFound` if the song doesn't exist.
+    fn set_lyrics(&mut self, song_id: &SongId, lyrics: Option<&Lyrics>) -> io::Result<()>;
+
+    /// Groups of two or more songs that are duplicates of one another for
+    /// the given reason.  `tolerance_ms` only applies to `Metadata`.
+    fn find_duplicates(&self, kind: DuplicateKind, tolerance_ms: i32) -> io::Result<Vec<Vec<SongId>>>;
+
+    /// Deletes the duplicates after moving their playlist entries, queue
+    /// entries, listens, play counts and ratings to `keep`.
+    fn merge_songs(&mut self, keep: &SongId, duplicates: &[SongId]) -> io::Result<()>;
+
+    /// Increases whenever a song or album is added, changed or removed.
+    fn get_catalog_version(&self) -> io::Result<i64>;
+
+    /// Songs and albums that changed after catalog version `since`, along
+    /// with the ids of those removed.  Songs carry no per-account fields.
+    fn get_catalog_changes(&self, since: i64) -> io::Result<CatalogChanges>;
+
+    /// Ordered by sort name.
+    fn get_artists(&self) -> io::Result<Vec<ArtistSummary>>;
+
+    /// The artist and the albums credited to them.  Their songs are found
+    /// with `SongFilter::Artist`.
+    fn get_artist(&self, id: &ArtistId) -> io::Result<Option<(Artist, Vec<Album>)>>;
+
+    /// Moves every credit of the `duplicates` to `into` and deletes them,
+    /// keeping their names as aliases of `into`.
+    fn merge_artists(&mut self, into: &ArtistId, duplicates: &[ArtistId]) -> io::Result<()>;
+
+    /// Applies the patch and records every effective change in the history.
+    /// Fails with `NotFound` if the target doesn't exist.
+    fn update_metadata(&mut self, account: &AccountId, target: &MetadataTarget, patch: &MetadataPatch)
+        -> io::Result<Vec<MetadataChange>>;
+
+    /// Newest change first.
+    fn get_metadata_history(&self, target: &MetadataTarget) -> io::Result<Vec<MetadataChange>>;
+
+    /// Restores the value a change replaced, recording the revert as a new
+    /// change.  Returns `None` if the field already holds that value.
+    fn revert_metadata_change(&mut self, account: &AccountId, id: &MetadataChangeId)
+        -> io::Result<Option<MetadataChange>>;
+
+    /// Records a play in the listening history, bumping the account's play
+    /// count for the song if the play counts.  Returns the resulting play
+    /// count.
+    fn record_play(&mut self, account: &AccountId, play: &Play) -> io::Result<i32>;
+
+    fn get_history(&self, account: &AccountId, query: &HistoryQuery) -> io::Result<Vec<Listen>>;
+
+    /// Drops every account's listens that started before `before`.
+    fn prune_history(&mut self, before: i64) -> io::Result<()>;
+
+    /// Replaces the precomputed song similarity, see `database::similarity`.
+    /// Returns the number of pairs stored.
+    fn refresh_song_similarity(&mut self, params: &SimilarityParams) -> io::Result<usize>;
+
+    /// The songs most similar to `song_id` as of the last refresh, best
+    /// first, with their scores.
+    fn get_similar_songs(&self, song_id: &SongId, limit: u32) -> io::Result<Vec<(SongId, f64)>>;
+
+    /// Songs similar to those the account listens to, weighted by play
+    /// count.  Songs the account has played or rated are left out.
+    fn get_recommendations(&self, account: &AccountId, limit: u32) -> io::Result<Vec<(SongId, f64)>>;
+
+    /// Stores the sequence of a new radio session, and drops the account's
+    /// sessions that started before `expired_before`.
+    fn create_radio_session(&mut self, account: &AccountId, session: &RadioSession, song_ids: &[SongId],
+        expired_before: i64) -> io::Result<()>;
+
+    /// The sequence stored for a radio session, `None` if it is unknown or
+    /// has expired.
+    fn get_radio_session(&self, account: &AccountId, session: &RadioSession) -> io::Result<Option<Vec<SongId>>>;
+
+    /// Sets the account's rating for a song, `None` clears it.
+    fn set_rating(&mut self, account: &AccountId, song_id: &SongId, rating: Option<i32>) -> io::Result<()>;
+
+    // Playlists are private to their owner; other accounts get `NotFound`.
+
+    fn get_playlists(&self, account: &AccountId) -> io::Result<Vec<Playlist>>;
+
+    /// The playlist and its entries in order.
+    fn get_playlist(&self, account: &AccountId, id: &PlaylistId)
+        -> io::Result<Option<(Playlist, Vec<PlaylistEntry>)>>;
+
+    /// Creates the playlist along with its entries, or nothing at all if
+    /// one of the songs doesn't exist.
+    fn create_playlist(&mut self, account: &AccountId, playlist: &PlaylistCreate) -> io::Result<PlaylistId>;
+
+    fn update_playlist(&mut self, account: &AccountId, id: &PlaylistId, update: &PlaylistUpdate) -> io::Result<()>;
+
+    fn delete_playlist(&mut self, account: &AccountId, id: &PlaylistId) -> io::Result<()>;
+
+    /// Inserts a song before `position`, or appends it if `position` is
+    /// `None` or past the end.
+    fn insert_playlist_entry(&mut self, account: &AccountId, id: &PlaylistId, song_id: &SongId, position: Option<i32>)
+        -> io::Result<PlaylistEntryId>;
+
+    /// Moves an entry so that it ends up at `position`, clamped to the end.
+    fn move_playlist_entry(&mut self, account: &AccountId, id: &PlaylistId, entry: &PlaylistEntryId, position: i32)
+        -> io::Result<()>;
+
+    fn remove_playlist_entry(&mut self, account: &AccountId, id: &PlaylistId, entry: &PlaylistEntryId)
+        -> io::Result<()>;
+
+    /// The account's queue; an empty queue at version 0 if it was never set.
+    fn get_play_queue(&self, account: &AccountId) -> io::Result<PlayQueue>;
+
+    /// Replaces the queue if it is still at `version`, bumping the version.
+    /// Returns `None` without writing anything if another write got there
+    /// first.  Fails with `InvalidInput` for unknown songs.
+    fn set_play_queue(&mut self, account: &AccountId, version: i64, queue: &PlayQueueUpdate)
+        -> io::Result<Option<PlayQueue>>;
+
+    // Smart playlists are private to their owner as well.  Their songs are
+    // found by compiling the rules and calling `get_songs`.
+
+    fn get_smart_playlists(&self, account: &AccountId) -> io::Result<Vec<SmartPlaylist>>;
+
+    fn get_smart_playlist(&self, account: &AccountId, id: &SmartPlaylistId) -> io::Result<Option<SmartPlaylist>>;
+
+    fn create_smart_playlist(&mut self, account: &AccountId, playlist: &SmartPlaylistCreate)
+        -> io::Result<SmartPlaylistId>;
+
+    fn delete_smart_playlist(&mut self, account: &AccountId, id: &SmartPlaylistId) -> io::Result<()>;
 }
 
 pub fn get_driver(url_raw: &str) -> io::Result<Box<DbConnector>> {
@@ -32,6 +251,17 @@ pub fn get_driver(url_raw: &str) -> io::Result<Box<DbConnector>> {
     }
 }
 
+/// Whether connections of several threads may write at the same time.  The
+/// mock driver writes back all of the state it loaded, so its writers take
+/// turns, and a long background job would hold up every request that
+/// writes.
+pub fn allows_concurrent_writers(url_raw: &str) -> bool {
+    match ::url::Url::parse(url_raw) {
+        Ok(url) => url.scheme() == postgres::DRIVER_NAME,
+        Err(_) => false,
+    }
+}
+
 fn unknown_scheme(scheme: &str) -> io::Error {
     io::Error::new(io::ErrorKind::Other, format!("unknown scheme: {}", scheme))
 }
\ No newline at end of file
@@ -4,13 +4,63 @@ use std::error::Error;
 
 use uuid::Uuid;
 use postgres::{Connection, TlsMode};
+use postgres::error::SqlState;
 use postgres::tls::native_tls::NativeTls;
-use postgres::types::FromSql;
-use postgres::rows::Rows;
+use postgres::types::{FromSql, ToSql};
+use postgres::rows::{Row, Rows};
+use postgres::transaction::Transaction;
 
 use ::util::json::JsonDocument;
-use ::model::{AlbumId, Album, SongId, Song};
+use ::util::unix_now;
+use ::model::{AlbumId, Album, AlbumSummary, SongId, Song, SongVariant, Lyrics, ListenId};
 use super::{DbConnector, SongQuery};
+use ::database::{
+    AlbumCreate,
+    AlbumQuery,
+    MetadataChangeId,
+    MetadataChange,
+    MetadataTarget,
+    MetadataPatch,
+    Play,
+    SongFilter,
+    SongOrder,
+    SongSortKey,
+    PlaylistId,
+    PlaylistEntryId,
+    Playlist,
+    PlaylistEntry,
+    PlaylistCreate,
+    PlaylistUpdate,
+    PlayQueue,
+    PlayQueueUpdate,
+    Listen,
+    HistoryQuery,
+    SmartPlaylistId,
+    SmartPlaylist,
+    SmartPlaylistCreate,
+    ArtistId,
+    Artist,
+    ArtistSummary,
+    FacetValue,
+    DuplicateKind,
+    SimilarityParams,
+    AccountProfile,
+    AccountUpdate,
+    LinkedAccount,
+    SongStats,
+    CatalogChanges,
+    SessionListen,
+    SongTraits,
+    ArtistCredit,
+    Comparison,
+    RadioSession,
+    song_artist_credits,
+    album_artist_credits,
+    metadata_duplicate_groups,
+    metadata_values,
+    is_multi_valued_field,
+    compute_similarity,
+};
 
 use ::foreign_auth::{
     ForeignAccount as AuthForeignAccount,
@@ -39,8 +89,227 @@ impl PostgresConnector {
 
 impl DbConnector for PostgresConnector {
     fn get_songs(&self, query: &SongQuery) -> io::Result<Vec<Song>>
+    {
+        let mut out = Vec::new();
+        try!(self.visit_songs(query, &mut |song| {
+            out.push(song);
+            Ok(())
+        }));
+        Ok(out)
+    }
+
+    fn visit_songs(&self, query: &SongQuery, visit: &mut FnMut(Song) -> io::Result<()>) -> io::Result<()>
+    {
+        let mut params = QueryParams::new();
+        let account_param = params.push(query.account.as_ref().map(|a| a.get_user_id()));
+        let (where_clause, order_clause) = song_query_clauses(query, &account_param, &mut params);
+        let limit_clause = match query.limit {
+            Some(limit) => format!("LIMIT {}", params.push(limit as i64)),
+            None => String::new(),
+        };
+
+        // Metadata is aggregated once for the whole page rather than per
+        // row, and each album's columns are only filled in on the first of
+        // its songs; later songs reuse the album we already decoded.
+        let trans = try!(self.pgconn.transaction());
+        try!(trans.execute(&format!("
+            DECLARE song_cursor NO SCROLL CURSOR FOR
+            WITH page AS (
+                SELECT
+                    s.id, s.blob, s.length_ms, s.disc_no, s.track_no, s.album_id,
+                    s.track_gain, s.track_peak,
+                    asm.play_count, asm.score,
+                    row_number() OVER (ORDER BY {order}) AS ord
+                FROM song AS s
+                LEFT JOIN account_song_metadata AS asm
+                    ON asm.song_id = s.id AND asm.account_id = {account}
+                WHERE {conditions}
+                ORDER BY {order}
+                {limit}
+            ),
+            page_song_metadata AS (
+                SELECT sm.song_id, jsonb_object_agg(sm.field_name, sm.value) AS metadata
+                FROM song_metadata AS sm
+                WHERE sm.song_id IN (SELECT id FROM page)
+                GROUP BY sm.song_id
+            ),
+            page_album AS (
+                SELECT
+                    a.id,
+                    a.art_blob,
+                    jsonb_object_agg(am.field_name, am.value)
+                        FILTER (WHERE am.field_name IS NOT NULL) AS metadata,
+                    a.album_gain,
+                    a.album_peak
+                FROM album AS a
+                LEFT JOIN album_metadata AS am ON am.album_id = a.id
+                WHERE a.id IN (SELECT album_id FROM page)
+                GROUP BY a.id
+            ),
+            page_song_variant AS (
+                SELECT sv.song_id, {variants} AS variants
+                FROM song_variant AS sv
+                WHERE sv.song_id IN (SELECT id FROM page)
+                GROUP BY sv.song_id
+            )
+            SELECT
+                page.id AS song_id,
+                page.blob AS song_blob,
+                page.length_ms AS song_length_ms,
+                page.track_no AS song_track_no,
+                psm.metadata AS song_metadata,
+                page.album_id AS album_id,
+                row_number() OVER (PARTITION BY page.album_id ORDER BY page.ord) = 1 AS album_first,
+                pa.art_blob AS album_art_blob,
+                pa.metadata AS album_metadata,
+                page.play_count AS play_count,
+                page.score AS rating,
+                page.disc_no AS song_disc_no,
+                psv.variants AS song_variants,
+                page.track_gain AS song_track_gain,
+                page.track_peak AS song_track_peak,
+                pa.album_gain AS album_gain,
+                pa.album_peak AS album_peak
+            FROM page
+            LEFT JOIN page_song_metadata AS psm ON psm.song_id = page.id
+            LEFT JOIN page_album AS pa ON pa.id = page.album_id
+            LEFT JOIN page_song_variant AS psv ON psv.song_id = page.id
+            ORDER BY page.ord
+        ", account=account_param, conditions=where_clause, order=order_clause, limit=limit_clause,
+            variants=VARIANTS_AGG), &params.as_refs()));
+
+        let mut albums: HashMap<i64, Album> = HashMap::new();
+        loop {
+            let rows = try!(trans.query(&format!("FETCH {} FROM song_cursor", SONG_FETCH_ROWS), &[]));
+            if rows.len() == 0 {
+                break;
+            }
+            for row in rows.iter() {
+                let album_id: i64 = row.get(5);
+                let album_first: bool = row.get(6);
+                if album_first {
+                    let album = Album::new(AlbumId(album_id), row.get(7), try!(json_metadata(row.get(8))));
+                    albums.insert(album_id, with_album_gain(album, &row, 15));
+                }
+                let album = match albums.get(&album_id) {
+                    Some(album) => album.clone(),
+                    None => return Err(internal_error()),
+                };
+                let song = Song::new(
+                    SongId(row.get(0)),
+                    row.get(1),
+                    row.get(2),
+                    row.get(11),
+                    row.get(3),
+                    try!(json_metadata(row.get(4))),
+                    album);
+                try!(visit(Song {
+                    track_gain: row.get(13),
+                    track_peak: row.get(14),
+                    variants: try!(json_variants(row.get(12))),
+                    play_count: query.account.as_ref()
+                        .map(|_| row.get::<_, Option<i32>>(9).unwrap_or(0)),
+                    rating: row.get(10),
+                    ..song
+                }));
+            }
+        }
+        try!(trans.commit());
+        Ok(())
+    }
+
+    fn get_facets(&self, field: &str, query: &SongQuery) -> io::Result<Vec<FacetValue>>
+    {
+        let mut params = QueryParams::new();
+        let account_param = params.push(query.account.as_ref().map(|a| a.get_user_id()));
+        let effective = effective_metadata(&params.push(field.to_string()));
+        let values = if is_multi_valued_field(field) {
+            format!("SELECT trim(raw) AS value FROM regexp_split_to_table({}, ';') AS raw", effective)
+        } else {
+            format!("SELECT {} AS value", effective)
+        };
+        let (where_clause, _) = song_query_clauses(query, &account_param, &mut params);
+
+        // min() picks one spelling to represent each group.  Comparing
+        // bytes rather than by the database's collation matches the mock
+        // driver.
+        let rows = try!(self.pgconn.query(&format!("
+            SELECT min(part.value COLLATE \"C\"), count(DISTINCT s.id) AS song_count
+            FROM song AS s
+            LEFT JOIN account_song_metadata AS asm
+                ON asm.song_id = s.id AND asm.account_id = {}
+            CROSS JOIN LATERAL ({}) AS part
+            WHERE part.value <> '' AND {}
+            GROUP BY lower(part.value)
+            ORDER BY song_count DESC, lower(part.value) COLLATE \"C\"
+        ", account_param, values, where_clause), &params.as_refs()));
+
+        Ok(rows.iter().map(|row| FacetValue {
+            value: row.get(0),
+            count: row.get(1),
+        }).collect())
+    }
+
+    fn get_albums(&self, query: &AlbumQuery) -> io::Result<Vec<AlbumSummary>>
+    {
+        let direction = if query.descending { "DESC" } else { "ASC" };
+        let rows = try!(self.pgconn.query(&format!("
+            SELECT
+                a.id AS album_id,
+                a.art_blob AS album_art_blob,
+                (
+                    SELECT jsonb_object_agg(am.field_name, am.value) AS album_metadata
+                    FROM album_metadata AS am WHERE am.album_id = a.id
+                ) AS album_metadata,
+                count(s.id) AS track_count,
+                coalesce(sum(s.length_ms), 0)::bigint AS length_ms,
+                a.album_gain,
+                a.album_peak
+            FROM album AS a
+            LEFT JOIN song AS s ON s.album_id = a.id
+            GROUP BY a.id
+            ORDER BY
+                (
+                    SELECT am.value FROM album_metadata AS am
+                    WHERE am.album_id = a.id AND am.field_name = $1
+                ) {} NULLS LAST,
+                a.id
+            LIMIT $2 OFFSET $3
+        ", direction), &[&query.sort_field, &query.limit, &query.offset]));
+
+        let mut out = Vec::new();
+        for row in rows.iter() {
+            let album = Album::new(AlbumId(row.get(0)), row.get(1), try!(json_metadata(row.get(2))));
+            out.push(AlbumSummary {
+                album: with_album_gain(album, &row, 5),
+                track_count: row.get(3),
+                length_ms: row.get(4),
+            });
+        }
+        Ok(out)
+    }
+
+    fn get_album(&self, id: &AlbumId) -> io::Result<Option<(Album, Vec<Song>)>>
     {
         let rows = try!(self.pgconn.query("
+            SELECT
+                a.art_blob AS album_art_blob,
+                (
+                    SELECT jsonb_object_agg(am.field_name, am.value) AS album_metadata
+                    FROM album_metadata AS am WHERE am.album_id = a.id
+                ) AS album_metadata,
+                a.album_gain,
+                a.album_peak
+            FROM album AS a
+            WHERE a.id = $1
+        ", &[&id.0]));
+
+        let album = match rows.iter().next() {
+            Some(row) => with_album_gain(Album::new(id.clone(), row.get(0), try!(json_metadata(row.get(1)))), &row, 2),
+            None => return Ok(None),
+        };
+
+        let rows = try!(self.pgconn.query(&format!("
             SELECT
                 s.id AS song_id,
                 s.blob AS song_blob,
@@ -50,43 +319,35 @@ impl DbConnector for PostgresConnector {
                     SELECT jsonb_object_agg(sm.field_name, sm.value) AS song_metadata
                     FROM song_metadata AS sm WHERE sm.song_id = s.id
                 ) AS song_metadata,
-                s.album_id AS album_id,
-                (SELECT a.art_blob FROM album AS a WHERE s.album_id = a.id) AS album_art_blob,
+                s.disc_no AS song_disc_no,
                 (
-                    SELECT jsonb_object_agg(am.field_name, am.value) AS album_metadata
-                    FROM album_metadata AS am WHERE am.album_id = s.album_id
-                ) AS album_metadata
+                    SELECT {} FROM song_variant AS sv WHERE sv.song_id = s.id
+                ) AS song_variants,
+                s.track_gain AS song_track_gain,
+                s.track_peak AS song_track_peak
             FROM song AS s
-        ", &[]));
-        let mut out = Vec::new();
+            WHERE s.album_id = $1
+            ORDER BY s.disc_no, s.track_no, s.id
+        ", VARIANTS_AGG), &[&id.0]));
+
+        let mut songs = Vec::new();
         for row in rows.iter() {
-            let album = Album {
-                id: AlbumId(row.get(5)),
-                art_blob: row.get(6),
-                metadata: {
-                    row.get::<_, Option<JsonDocument>>(7)
-                        .unwrap_or_else(JsonDocument::empty)
-                        .deserialize()
-                        .map_err(adapt_error_tagged("error deserializing json"))
-                        ?
-                }
-            };
-            out.push(Song {
-                id: SongId(row.get(0)),
-                album: album,
-                blob: row.get(1),
-                length_ms: row.get(2),
-                track_no: row.get(3),
-                metadata: {
-                    row.get::<_, Option<JsonDocument>>(4)
-                        .unwrap_or_else(JsonDocument::empty)
-                        .deserialize()
-                        .map_err(adapt_error_tagged("error deserializing json"))
-                        ?
-                }
+            let song = Song::new(
+                SongId(row.get(0)),
+                row.get(1),
+                row.get(2),
+                row.get(5),
+                row.get(3),
+                try!(json_metadata(row.get(4))),
+                album.clone());
+            songs.push(Song {
+                track_gain: row.get(7),
+                track_peak: row.get(8),
+                variants: try!(json_variants(row.get(6))),
+                ..song
             });
         }
-        Ok(out)
+        Ok(Some((album, songs)))
     }
 
     fn find_or_create_user(&mut self, acc: &AuthForeignAccount) -> io::Result<AccountId> {
@@ -122,26 +383,1603 @@ impl DbConnector for PostgresConnector {
         
         Ok(AccountId(user_id))
     }
-}
 
-use std::boxed::FnBox;
+    fn get_account(&self, account: &AccountId) -> io::Result<Option<AccountProfile>>
+    {
+        let rows = try!(self.pgconn.query("
+            SELECT a.id, a.display_name FROM account AS a WHERE a.id = $1
+        ", &[&account.get_user_id()]));
 
-fn adapt_error_tagged<'a, E: Error>(tag: &'a str) -> Box<FnBox(E) -> io::Error + 'a> {
-    Box::new(move |e| {
-        io::Error::new(io::ErrorKind::Other, format!("{}: {}", tag, e))
-    })
-}
+        Ok(rows.iter().next().map(|row| AccountProfile {
+            id: row.get(0),
+            display_name: row.get(1),
+        }))
+    }
 
-fn extract_single<T: FromSql>(rows: Rows) -> Result<T, ()> {
-    rows.iter().next().map(|r| r.get(0)).ok_or(())
-}
+    fn update_account(&mut self, account: &AccountId, update: &AccountUpdate) -> io::Result<()>
+    {
+        let updated = try!(self.pgconn.execute("
+            UPDATE account SET
+                display_name = coalesce($2, display_name)
+            WHERE id = $1
+        ", &[&account.get_user_id(), &update.display_name]));
+        if updated == 0 {
+            return Err(not_found("no such account"));
+        }
+        Ok(())
+    }
 
-fn extract_single2<T: FromSql>(rows: Rows) -> io::Result<T> {
-    rows.iter().next().map(|r| r.get(0))
-        .ok_or_else(internal_error)
-}
+    fn get_linked_accounts(&self, account: &AccountId) -> io::Result<Vec<LinkedAccount>>
+    {
+        let rows = try!(self.pgconn.query("
+            SELECT fap.name, fa.foreign_id, extract(epoch FROM fa.created_at)::bigint
+            FROM foreign_account AS fa
+            JOIN foreign_account_provider AS fap ON fap.id = fa.provider_id
+            WHERE fa.account_id = $1
+            ORDER BY fa.created_at, fap.name
+        ", &[&account.get_user_id()]));
 
-/// returned for really unexpected errors
-fn internal_error() -> io::Error {
-    io::Error::new(io::ErrorKind::Other, "DB Error")
+        Ok(rows.iter().map(|row| LinkedAccount {
+            provider: row.get(0),
+            foreign_id: row.get(1),
+            created_at: row.get(2),
+        }).collect())
+    }
+
+    fn get_song_stats(&self, account: &AccountId) -> io::Result<Vec<SongStats>>
+    {
+        let rows = try!(self.pgconn.query("
+            SELECT asm.song_id, asm.play_count, asm.score
+            FROM account_song_metadata AS asm
+            WHERE asm.account_id = $1
+            ORDER BY asm.song_id
+        ", &[&account.get_user_id()]));
+
+        Ok(rows.iter().map(|row| SongStats {
+            song_id: SongId(row.get(0)),
+            play_count: row.get(1),
+            rating: row.get(2),
+        }).collect())
+    }
+
+    fn get_stats_version(&self, account: &AccountId) -> io::Result<i64>
+    {
+        let rows = try!(self.pgconn.query("
+            SELECT stats_version FROM account WHERE id = $1
+        ", &[&account.get_user_id()]));
+        Ok(rows.iter().next().map(|row| row.get(0)).unwrap_or(0))
+    }
+
+    fn delete_account(&mut self, account: &AccountId) -> io::Result<()>
+    {
+        let user_id = account.get_user_id();
+        let trans = try!(self.pgconn.transaction());
+
+        let rows = try!(trans.query("
+            SELECT 1 FROM account WHERE id = $1 FOR UPDATE
+        ", &[&user_id]));
+        if rows.len() == 0 {
+            return Err(not_found("no such account"));
+        }
+
+        try!(trans.execute("
+            UPDATE metadata_change SET account_id = NULL WHERE account_id = $1
+        ", &[&user_id]));
+        // playlist entries go along with their playlists
+        for table in ["account_song_metadata", "listen_history", "play_queue", "radio_session",
+                      "playlist", "smart_playlist", "foreign_account"].iter() {
+            try!(trans.execute(&format!("DELETE FROM {} WHERE account_id = $1", table), &[&user_id]));
+        }
+        try!(trans.execute("
+            DELETE FROM account WHERE id = $1
+        ", &[&user_id]));
+        try!(trans.execute("
+            INSERT INTO deleted_account (id) VALUES ($1)
+        ", &[&user_id]));
+
+        try!(trans.commit());
+        Ok(())
+    }
+
+    fn is_account_deleted(&self, account: &AccountId) -> io::Result<bool>
+    {
+        let rows = try!(self.pgconn.query("
+            SELECT 1 FROM deleted_account WHERE id = $1
+        ", &[&account.get_user_id()]));
+        Ok(rows.len() > 0)
+    }
+
+    fn create_album(&mut self, ac: &AlbumCreate) -> io::Result<Vec<Song>> {
+        let trans = try!(self.pgconn.transaction());
+
+        let album_id: i64 = {
+            let rows = try!(trans.query("
+                INSERT INTO album (art_blob, album_gain, album_peak)
+                VALUES ($1, $2, $3)
+                RETURNING id
+            ", &[&ac.art_blob, &ac.album_gain, &ac.album_peak]));
+            try!(extract_single2(rows))
+        };
+
+        for (key, val) in ac.metadata.iter() {
+            try!(trans.execute("
+                INSERT INTO album_metadata (album_id, field_name, value)
+                VALUES ($1, $2, $3)
+            ", &[&album_id, key, val]));
+        }
+        for (position, credit) in album_artist_credits(&ac.metadata).iter().enumerate() {
+            let artist_id = try!(find_or_create_artist(&trans, credit));
+            try!(trans.execute("
+                INSERT INTO album_artist (album_id, artist_id, position)
+                VALUES ($1, $2, $3)
+            ", &[&album_id, &artist_id, &(position as i32)]));
+        }
+
+        let album = Album {
+            album_gain: ac.album_gain,
+            album_peak: ac.album_peak,
+            ..Album::new(AlbumId(album_id), ac.art_blob.clone(), ac.metadata.clone())
+        };
+
+        let mut out = Vec::new();
+        for song in ac.songs.iter() {
+            let song_id: i64 = {
+                let rows = try!(trans.query("
+                    INSERT INTO song (blob, disc_no, track_no, album_id, length_ms, audio_digest, track_gain, track_peak)
+                    VALUES ($1, $2, $3, $4, $5, $6, $7, $8)
+                    RETURNING id
+                ", &[&song.blob, &song.disc_no, &song.track_no, &album_id, &song.length_ms, &song.audio_digest,
+                    &song.track_gain, &song.track_peak])
+                    .map_err(duplicate_track_error));
+                try!(extract_single2(rows))
+            };
+
+            for (key, val) in song.metadata.iter() {
+                try!(trans.execute("
+                    INSERT INTO song_metadata (song_id, field_name, value)
+                    VALUES ($1, $2, $3)
+                ", &[&song_id, key, val]));
+            }
+            for (position, credit) in song_artist_credits(&song.metadata, &ac.metadata).iter().enumerate() {
+                let artist_id = try!(find_or_create_artist(&trans, credit));
+                try!(trans.execute("
+                    INSERT INTO song_artist (song_id, artist_id, position)
+                    VALUES ($1, $2, $3)
+                ", &[&song_id, &artist_id, &(position as i32)]));
+            }
+            try!(insert_song_variant(&trans, song_id, &song.variant));
+            if let Some(ref lyrics) = song.lyrics {
+                try!(upsert_lyrics(&trans, song_id, lyrics));
+            }
+
+            let created = Song::new(
+                SongId(song_id),
+                song.blob.clone(),
+                song.length_ms,
+                song.disc_no,
+                song.track_no,
+                song.metadata.clone(),
+                album.clone());
+            out.push(Song {
+                track_gain: song.track_gain,
+                track_peak: song.track_peak,
+                variants: vec![song.variant.clone()],
+                ..created
+            });
+        }
+
+        try!(trans.commit());
+
+        Ok(out)
+    }
+
+    fn get_songs_without_audio_digest(&self) -> io::Result<Vec<(SongId, String)>>
+    {
+        let rows = try!(self.pgconn.query("
+            SELECT s.id, s.blob FROM song AS s WHERE s.audio_digest IS NULL ORDER BY s.id
+        ", &[]));
+        Ok(rows.iter().map(|row| (SongId(row.get(0)), row.get(1))).collect())
+    }
+
+    fn set_audio_digest(&mut self, song_id: &SongId, digest: &str) -> io::Result<()>
+    {
+        let updated = try!(self.pgconn.execute("
+            UPDATE song SET audio_digest = $2 WHERE id = $1
+        ", &[&song_id.0, &digest]));
+        if updated == 0 {
+            return Err(not_found("no such song"));
+        }
+        Ok(())
+    }
+
+    fn add_song_variant(&mut self, song_id: &SongId, variant: &SongVariant) -> io::Result<()>
+    {
+        let trans = try!(self.pgconn.transaction());
+        let exists = try!(trans.query("SELECT 1 FROM song WHERE id = $1 FOR SHARE", &[&song_id.0]));
+        if exists.len() == 0 {
+            return Err(not_found("no such song"));
+        }
+        try!(insert_song_variant(&trans, song_id.0, variant));
+        try!(trans.commit());
+        Ok(())
+    }
+
+    fn get_lyrics(&self, song_id: &SongId) -> io::Result<Option<Lyrics>>
+    {
+        let rows = try!(self.pgconn.query("
+            SELECT sl.text, sl.lines
+            FROM song AS s
+            LEFT JOIN song_lyrics AS sl ON sl.song_id = s.id
+            WHERE s.id = $1
+        ", &[&song_id.0]));
+        let row = match rows.iter().next() {
+            Some(row) => row,
+            None => return Err(not_found("no such song")),
+        };
+        let text: Option<String> = row.get(0);
+        match (text, row.get::<_, Option<JsonDocument>>(1)) {
+            (Some(text), Some(lines)) => Ok(Some(Lyrics {
+                text: text,
+                lines: try!(lines.deserialize().map_err(adapt_error_tagged("error deserializing json"))),
+            })),
+            _ => Ok(None),
+        }
+    }
+
+    fn set_lyrics(&mut self, song_id: &SongId, lyrics: Option<&Lyrics>) -> io::Result<()>
+    {
+        let trans = try!(self.pgconn.transaction());
+        let exists = try!(trans.query("SELECT 1 FROM song WHERE id = $1 FOR SHARE", &[&song_id.0]));
+        if exists.len() == 0 {
+            return Err(not_found("no such song"));
+        }
+        match lyrics {
+            Some(lyrics) => try!(upsert_lyrics(&trans, song_id.0, lyrics)),
+            None => {
+                try!(trans.execute("DELETE FROM song_lyrics WHERE song_id = $1", &[&song_id.0]));
+            },
+        }
+        try!(trans.commit());
+        Ok(())
+    }
+
+    fn find_duplicates(&self, kind: DuplicateKind, tolerance_ms: i32) -> io::Result<Vec<Vec<SongId>>>
+    {
+        let column = match kind {
+            DuplicateKind::Blob => "blob",
+            DuplicateKind::Audio => "audio_digest",
+            DuplicateKind::Metadata => {
+                let songs = try!(self.get_songs(&SongQuery::all()));
+                return Ok(metadata_duplicate_groups(&songs, tolerance_ms));
+            },
+        };
+
+        let rows = try!(self.pgconn.query(&format!("
+            SELECT array_agg(s.id ORDER BY s.id)
+            FROM song AS s
+            WHERE s.{column} IS NOT NULL
+            GROUP BY s.{column}
+            HAVING count(*) > 1
+            ORDER BY min(s.id)
+        ", column=column), &[]));
+
+        Ok(rows.iter().map(|row| {
+            let ids: Vec<i64> = row.get(0);
+            ids.into_iter().map(SongId).collect()
+        }).collect())
+    }
+
+    fn merge_songs(&mut self, keep: &SongId, duplicates: &[SongId]) -> io::Result<()>
+    {
+        if duplicates.contains(keep) {
+            return Err(io::Error::new(io::ErrorKind::InvalidInput, "cannot merge a song into itself"));
+        }
+        let mut duplicate_ids: Vec<i64> = duplicates.iter().map(|d| d.0).collect();
+        duplicate_ids.sort();
+        duplicate_ids.dedup();
+        let trans = try!(self.pgconn.transaction());
+
+        let rows = try!(trans.query("
+            SELECT count(*) FROM song WHERE id = $1 OR id = ANY($2)
+        ", &[&keep.0, &duplicate_ids]));
+        let found: i64 = try!(extract_single2(rows));
+        if found != duplicate_ids.len() as i64 + 1 {
+            return Err(not_found("no such song"));
+        }
+
+        try!(trans.execute("
+            UPDATE playlist_entry SET song_id = $1 WHERE song_id = ANY($2)
+        ", &[&keep.0, &duplicate_ids]));
+        try!(trans.execute("
+            UPDATE listen_history SET song_id = $1 WHERE song_id = ANY($2)
+        ", &[&keep.0, &duplicate_ids]));
+        try!(trans.execute("
+            UPDATE play_queue SET
+                song_ids = (
+                    SELECT array_agg(CASE WHEN u.id = ANY($2) THEN $1 ELSE u.id END ORDER BY u.ord)
+                    FROM unnest(song_ids) WITH ORDINALITY AS u(id, ord)
+                ),
+                version = version + 1,
+                updated_at = NOW()
+            WHERE song_ids && $2
+        ", &[&keep.0, &duplicate_ids]));
+        // in place, so that offsets into a session being paged stay put
+        try!(trans.execute("
+            UPDATE radio_session SET
+                song_ids = (
+                    SELECT array_agg(CASE WHEN u.id = ANY($2) THEN $1 ELSE u.id END ORDER BY u.ord)
+                    FROM unnest(song_ids) WITH ORDINALITY AS u(id, ord)
+                )
+            WHERE song_ids && $2
+        ", &[&keep.0, &duplicate_ids]));
+
+        // play counts add up, the surviving song's rating wins over the
+        // highest of the duplicates'
+        try!(trans.execute("
+            INSERT INTO account_song_metadata AS asm (account_id, song_id, play_count, score)
+            SELECT d.account_id, $1, sum(d.play_count), max(d.score)
+            FROM account_song_metadata AS d
+            WHERE d.song_id = ANY($2)
+            GROUP BY d.account_id
+            ON CONFLICT ON CONSTRAINT account_song_metadata_account_song_uniq
+            DO UPDATE SET
+                play_count = asm.play_count + EXCLUDED.play_count,
+                score = COALESCE(asm.score, EXCLUDED.score)
+        ", &[&keep.0, &duplicate_ids]));
+        try!(trans.execute("
+            DELETE FROM account_song_metadata WHERE song_id = ANY($1)
+        ", &[&duplicate_ids]));
+
+        // the edit history of a deleted song has nothing left to apply to
+        try!(trans.execute("
+            DELETE FROM metadata_change WHERE song_id = ANY($1)
+        ", &[&duplicate_ids]));
+        try!(trans.execute("
+            DELETE FROM song_metadata WHERE song_id = ANY($1)
+        ", &[&duplicate_ids]));
+        try!(trans.execute("
+            DELETE FROM song WHERE id = ANY($1)
+        ", &[&duplicate_ids]));
+
+        try!(trans.commit());
+        Ok(())
+    }
+
+    fn get_catalog_version(&self) -> io::Result<i64>
+    {
+        let rows = try!(self.pgconn.query("
+            SELECT coalesce(max(version), 0) FROM catalog_change
+        ", &[]));
+        extract_single2(rows)
+    }
+
+    fn get_catalog_changes(&self, since: i64) -> io::Result<CatalogChanges>
+    {
+        let rows = try!(self.pgconn.query("
+            SELECT kind, target_id, deleted, version
+            FROM catalog_change
+            WHERE version > $1
+            ORDER BY version
+        ", &[&since]));
+
+        // anything committed later has a higher version and comes up in
+        // the next sync
+        let mut version = since;
+        let mut song_ids = Vec::new();
+        let mut album_ids = Vec::new();
+        let mut deleted_song_ids = Vec::new();
+        let mut deleted_album_ids = Vec::new();
+        for row in rows.iter() {
+            let kind: String = row.get(0);
+            let target_id: i64 = row.get(1);
+            let deleted: bool = row.get(2);
+            version = row.get(3);
+            match (&kind[..], deleted) {
+                ("song", false) => song_ids.push(SongId(target_id)),
+                ("song", true) => deleted_song_ids.push(SongId(target_id)),
+                ("album", false) => album_ids.push(target_id),
+                ("album", true) => deleted_album_ids.push(AlbumId(target_id)),
+                _ => return Err(internal_error()),
+            }
+        }
+
+        let songs = if song_ids.len() > 0 {
+            let mut query = SongQuery::all();
+            query.filters.push(SongFilter::Ids(song_ids));
+            try!(self.get_songs(&query))
+        } else {
+            Vec::new()
+        };
+
+        let rows = try!(self.pgconn.query("
+            SELECT
+                a.id,
+                a.art_blob,
+                jsonb_object_agg(am.field_name, am.value)
+                    FILTER (WHERE am.field_name IS NOT NULL),
+                a.album_gain,
+                a.album_peak
+            FROM album AS a
+            LEFT JOIN album_metadata AS am ON am.album_id = a.id
+            WHERE a.id = ANY($1)
+            GROUP BY a.id
+            ORDER BY a.id
+        ", &[&album_ids]));
+        let mut albums = Vec::new();
+        for row in rows.iter() {
+            let album = Album::new(AlbumId(row.get(0)), row.get(1), try!(json_metadata(row.get(2))));
+            albums.push(with_album_gain(album, &row, 3));
+        }
+
+        Ok(CatalogChanges {
+            version: version,
+            songs: songs,
+            albums: albums,
+            deleted_song_ids: deleted_song_ids,
+            deleted_album_ids: deleted_album_ids,
+        })
+    }
+
+    fn get_artists(&self) -> io::Result<Vec<ArtistSummary>>
+    {
+        let rows = try!(self.pgconn.query("
+            SELECT
+                ar.id, ar.name, ar.sort_name,
+                (SELECT count(*) FROM album_artist AS aa WHERE aa.artist_id = ar.id),
+                (SELECT count(*) FROM song_artist AS sa WHERE sa.artist_id = ar.id)
+            FROM artist AS ar
+            ORDER BY lower(COALESCE(ar.sort_name, ar.name)), ar.id
+        ", &[]));
+
+        Ok(rows.iter().map(|row| ArtistSummary {
+            artist: Artist {
+                id: ArtistId(row.get(0)),
+                name: row.get(1),
+                sort_name: row.get(2),
+            },
+            album_count: row.get(3),
+            track_count: row.get(4),
+        }).collect())
+    }
+
+    fn get_artist(&self, id: &ArtistId) -> io::Result<Option<(Artist, Vec<Album>)>>
+    {
+        let rows = try!(self.pgconn.query("
+            SELECT ar.name, ar.sort_name FROM artist AS ar WHERE ar.id = $1
+        ", &[&id.0]));
+        let artist = match rows.iter().next() {
+            Some(row) => Artist {
+                id: id.clone(),
+                name: row.get(0),
+                sort_name: row.get(1),
+            },
+            None => return Ok(None),
+        };
+
+        // albums credited to the artist, then the ones they only appear on
+        let rows = try!(self.pgconn.query("
+            SELECT
+                a.id AS album_id,
+                a.art_blob AS album_art_blob,
+                (
+                    SELECT jsonb_object_agg(am.field_name, am.value) AS album_metadata
+                    FROM album_metadata AS am WHERE am.album_id = a.id
+                ) AS album_metadata,
+                a.album_gain,
+                a.album_peak
+            FROM album AS a
+            WHERE a.id IN (SELECT aa.album_id FROM album_artist AS aa WHERE aa.artist_id = $1)
+               OR a.id IN (
+                    SELECT s.album_id FROM song AS s
+                    JOIN song_artist AS sa ON sa.song_id = s.id
+                    WHERE sa.artist_id = $1
+                )
+            ORDER BY
+                a.id NOT IN (SELECT aa.album_id FROM album_artist AS aa WHERE aa.artist_id = $1),
+                a.id
+        ", &[&id.0]));
+
+        let mut albums = Vec::new();
+        for row in rows.iter() {
+            let album = Album::new(AlbumId(row.get(0)), row.get(1), try!(json_metadata(row.get(2))));
+            albums.push(with_album_gain(album, &row, 3));
+        }
+        Ok(Some((artist, albums)))
+    }
+
+    fn merge_artists(&mut self, into: &ArtistId, duplicates: &[ArtistId]) -> io::Result<()>
+    {
+        if duplicates.contains(into) {
+            return Err(io::Error::new(io::ErrorKind::InvalidInput, "cannot merge an artist into itself"));
+        }
+        let duplicate_ids: Vec<i64> = duplicates.iter().map(|d| d.0).collect();
+        let trans = try!(self.pgconn.transaction());
+
+        let rows = try!(trans.query("
+            SELECT count(*) FROM artist WHERE id = $1 OR id = ANY($2)
+        ", &[&into.0, &duplicate_ids]));
+        let found: i64 = try!(extract_single2(rows));
+        let mut wanted = duplicate_ids.clone();
+        wanted.sort();
+        wanted.dedup();
+        if found != wanted.len() as i64 + 1 {
+            return Err(not_found("no such artist"));
+        }
+
+        // credits the surviving artist already has are dropped, not moved
+        for &(table, owner) in [("song_artist", "song_id"), ("album_artist", "album_id")].iter() {
+            try!(trans.execute(&format!("
+                DELETE FROM {table} AS dup
+                WHERE dup.artist_id = ANY($2)
+                  AND EXISTS (
+                    SELECT 1 FROM {table} AS keep
+                    WHERE keep.{owner} = dup.{owner} AND keep.artist_id = $1
+                  )
+            ", table=table, owner=owner), &[&into.0, &duplicate_ids]));
+            try!(trans.execute(&format!("
+                DELETE FROM {table} AS dup
+                WHERE dup.artist_id = ANY($1)
+                  AND EXISTS (
+                    SELECT 1 FROM {table} AS other
+                    WHERE other.{owner} = dup.{owner} AND other.artist_id = ANY($1)
+                      AND other.artist_id < dup.artist_id
+                  )
+            ", table=table, owner=owner), &[&duplicate_ids]));
+            try!(trans.execute(&format!("
+                UPDATE {} SET artist_id = $1 WHERE artist_id = ANY($2)
+            ", table), &[&into.0, &duplicate_ids]));
+        }
+
+        try!(trans.execute("
+            UPDATE artist_alias SET artist_id = $1 WHERE artist_id = ANY($2)
+        ", &[&into.0, &duplicate_ids]));
+        try!(trans.execute("
+            INSERT INTO artist_alias (name, artist_id)
+            SELECT ar.name, $1 FROM artist AS ar WHERE ar.id = ANY($2)
+            ON CONFLICT DO NOTHING
+        ", &[&into.0, &duplicate_ids]));
+        try!(trans.execute("
+            DELETE FROM artist WHERE id = ANY($1)
+        ", &[&duplicate_ids]));
+
+        try!(trans.commit());
+        Ok(())
+    }
+
+    fn update_metadata(&mut self, account: &AccountId, target: &MetadataTarget, patch: &MetadataPatch)
+        -> io::Result<Vec<MetadataChange>>
+    {
+        let trans = try!(self.pgconn.transaction());
+        try!(lock_metadata_target(&trans, target));
+
+        let mut out = Vec::new();
+        for (key, val) in patch.set.iter() {
+            if let Some(change) = try!(apply_metadata_change(&trans, account, target, key, Some(val), None)) {
+                out.push(change);
+            }
+        }
+        for key in patch.remove.iter() {
+            if let Some(change) = try!(apply_metadata_change(&trans, account, target, key, None, None)) {
+                out.push(change);
+            }
+        }
+
+        try!(trans.commit());
+        Ok(out)
+    }
+
+    fn get_metadata_history(&self, target: &MetadataTarget) -> io::Result<Vec<MetadataChange>>
+    {
+        let (_, _, column, target_id) = metadata_tables(target);
+        let rows = try!(self.pgconn.query(&format!("
+            SELECT
+                mc.id, mc.account_id, mc.song_id, mc.album_id, mc.field_name,
+                mc.old_value, mc.new_value, mc.reverts,
+                extract(epoch FROM mc.changed_at)::bigint
+            FROM metadata_change AS mc
+            WHERE mc.{} = $1
+            ORDER BY mc.id DESC
+        ", column), &[&target_id]));
+
+        Ok(rows.iter().map(|row| metadata_change_from_row(&row)).collect())
+    }
+
+    fn revert_metadata_change(&mut self, account: &AccountId, id: &MetadataChangeId)
+        -> io::Result<Option<MetadataChange>>
+    {
+        let trans = try!(self.pgconn.transaction());
+
+        let change = {
+            let rows = try!(trans.query("
+                SELECT
+                    mc.id, mc.account_id, mc.song_id, mc.album_id, mc.field_name,
+                    mc.old_value, mc.new_value, mc.reverts,
+                    extract(epoch FROM mc.changed_at)::bigint
+                FROM metadata_change AS mc
+                WHERE mc.id = $1
+            ", &[&id.0]));
+            match rows.iter().next() {
+                Some(row) => metadata_change_from_row(&row),
+                None => return Err(not_found("no such metadata change")),
+            }
+        };
+
+        let target = match (change.song_id, change.album_id) {
+            (Some(song_id), _) => MetadataTarget::Song(song_id),
+            (None, Some(album_id)) => MetadataTarget::Album(album_id),
+            (None, None) => return Err(internal_error()),
+        };
+        try!(lock_metadata_target(&trans, &target));
+
+        let reverted = try!(apply_metadata_change(&trans, account, &target,
+            &change.field_name, change.old_value.as_ref().map(|v| &v[..]), Some(id)));
+
+        try!(trans.commit());
+        Ok(reverted)
+    }
+
+    fn record_play(&mut self, account: &AccountId, play: &Play) -> io::Result<i32>
+    {
+        let trans = try!(self.pgconn.transaction());
+
+        let rows = try!(trans.query("
+            SELECT 1 FROM song WHERE id = $1
+        ", &[&play.song_id.0]));
+        if rows.len() == 0 {
+            return Err(not_found("no such song"));
+        }
+
+        try!(trans.execute("
+            INSERT INTO listen_history (account_id, song_id, started_at, position_ms, duration_ms, client_id)
+            VALUES ($1, $2, to_timestamp($3) AT TIME ZONE 'UTC', $4, $5, $6)
+        ", &[
+            &account.get_user_id(), &play.song_id.0, &(play.started_at as f64),
+            &play.position_ms, &play.duration_ms, &play.client_id,
+        ]));
+
+        let play_count = if play.counted {
+            let rows = try!(trans.query("
+                INSERT INTO account_song_metadata (account_id, song_id, play_count)
+                VALUES ($1, $2, 1)
+                ON CONFLICT ON CONSTRAINT account_song_metadata_account_song_uniq
+                DO UPDATE SET play_count = account_song_metadata.play_count + 1
+                RETURNING play_count
+            ", &[&account.get_user_id(), &play.song_id.0]));
+            try!(extract_single2(rows))
+        } else {
+            let rows = try!(trans.query("
+                SELECT asm.play_count FROM account_song_metadata AS asm
+                WHERE asm.account_id = $1 AND asm.song_id = $2
+            ", &[&account.get_user_id(), &play.song_id.0]));
+            rows.iter().next().map(|r| r.get(0)).unwrap_or(0)
+        };
+
+        try!(trans.commit());
+        Ok(play_count)
+    }
+
+    fn get_history(&self, account: &AccountId, query: &HistoryQuery) -> io::Result<Vec<Listen>>
+    {
+        let mut params = QueryParams::new();
+        let mut clauses = vec![format!("lh.account_id = {}", params.push(account.get_user_id()))];
+        if let Some(from) = query.from {
+            clauses.push(format!("{} <= lh.started_at", utc_timestamp(params.push(from as f64))));
+        }
+        if let Some(to) = query.to {
+            clauses.push(format!("lh.started_at < {}", utc_timestamp(params.push(to as f64))));
+        }
+        if let Some(ref after) = query.after {
+            clauses.push(format!("(lh.started_at, lh.id) < ({}, {})",
+                utc_timestamp(params.push(after.started_at as f64)), params.push(after.id.0)));
+        }
+        let limit = format!("LIMIT {}", params.push(query.limit as i64));
+
+        let rows = try!(self.pgconn.query(&format!("
+            SELECT lh.id, extract(epoch FROM lh.started_at)::bigint, lh.position_ms, lh.duration_ms, lh.client_id,
+                lh.song_id
+            FROM listen_history AS lh
+            WHERE {}
+            ORDER BY lh.started_at DESC, lh.id DESC
+            {}
+        ", clauses.join(" AND "), limit), &params.as_refs()));
+        let raw_listens: Vec<(i64, i64, i32, i32, Option<String>, i64)> = rows.iter()
+            .map(|row| (row.get(0), row.get(1), row.get(2), row.get(3), row.get(4), row.get(5)))
+            .collect();
+
+        let songs = try!(self.get_songs(&SongQuery {
+            account: Some(account.clone()),
+            filters: vec![SongFilter::Ids(raw_listens.iter().map(|l| SongId(l.5)).collect())],
+            order: Vec::new(),
+            limit: None,
+        }));
+        let songs: HashMap<i64, Song> = songs.into_iter().map(|s| (s.id.0, s)).collect();
+
+        let mut out = Vec::new();
+        for (id, started_at, position_ms, duration_ms, client_id, song_id) in raw_listens.into_iter() {
+            let song = try!(songs.get(&song_id).cloned().ok_or_else(internal_error));
+            out.push(Listen {
+                id: ListenId(id),
+                started_at: started_at,
+                position_ms: position_ms,
+                duration_ms: duration_ms,
+                client_id: client_id,
+                song: song,
+            });
+        }
+        Ok(out)
+    }
+
+    fn prune_history(&mut self, before: i64) -> io::Result<()>
+    {
+        try!(self.pgconn.execute("
+            DELETE FROM listen_history
+            WHERE started_at < to_timestamp($1) AT TIME ZONE 'UTC'
+        ", &[&(before as f64)]));
+        Ok(())
+    }
+
+    fn refresh_song_similarity(&mut self, params: &SimilarityParams) -> io::Result<usize>
+    {
+        let rows = try!(self.pgconn.query("
+            SELECT lh.account_id, lh.song_id, extract(epoch FROM lh.started_at)::bigint
+            FROM listen_history AS lh
+            WHERE to_timestamp($1) AT TIME ZONE 'UTC' <= lh.started_at
+            ORDER BY lh.account_id, lh.started_at, lh.id
+        ", &[&(params.listens_since(unix_now()) as f64)]));
+        let listens: Vec<SessionListen> = rows.iter().map(|row| SessionListen {
+            account_id: row.get(0),
+            song_id: SongId(row.get(1)),
+            started_at: row.get(2),
+        }).collect();
+
+        let mut params_sql = QueryParams::new();
+        let genre = effective_metadata(&params_sql.push("GENRE".to_string()));
+        let rows = try!(self.pgconn.query(&format!("
+            SELECT
+                s.id,
+                ARRAY(SELECT sa.artist_id FROM song_artist AS sa WHERE sa.song_id = s.id ORDER BY sa.position),
+                {}
+            FROM song AS s
+            ORDER BY s.id
+        ", genre), &params_sql.as_refs()));
+        let songs: Vec<SongTraits> = rows.iter().map(|row| {
+            let artist_ids: Vec<i64> = row.get(1);
+            let genre: Option<String> = row.get(2);
+            SongTraits {
+                song_id: SongId(row.get(0)),
+                artist_ids: artist_ids.into_iter().map(ArtistId).collect(),
+                genres: genre.as_ref()
+                    .map(|g| metadata_values(g).into_iter().map(|v| v.to_lowercase()).collect())
+                    .unwrap_or_else(Vec::new),
+            }
+        }).collect();
+
+        let similarity = compute_similarity(&listens, &songs, params);
+        let song_ids: Vec<i64> = similarity.iter().map(|s| s.song_id.0).collect();
+        let similar_song_ids: Vec<i64> = similarity.iter().map(|s| s.similar_song_id.0).collect();
+        let scores: Vec<f64> = similarity.iter().map(|s| s.score).collect();
+
+        let trans = try!(self.pgconn.transaction());
+        try!(trans.execute("DELETE FROM song_similarity", &[]));
+        // songs deleted since they were read are skipped
+        try!(trans.execute("
+            INSERT INTO song_similarity (song_id, similar_song_id, score)
+            SELECT u.song_id, u.similar_song_id, u.score
+            FROM unnest($1::bigint[], $2::bigint[], $3::double precision[]) AS u(song_id, similar_song_id, score)
+            WHERE EXISTS (SELECT 1 FROM song WHERE id = u.song_id)
+                AND EXISTS (SELECT 1 FROM song WHERE id = u.similar_song_id)
+        ", &[&song_ids, &similar_song_ids, &scores]));
+        try!(trans.commit());
+
+        Ok(similarity.len())
+    }
+
+    fn get_similar_songs(&self, song_id: &SongId, limit: u32) -> io::Result<Vec<(SongId, f64)>>
+    {
+        let rows = try!(self.pgconn.query("
+            SELECT sim.similar_song_id, sim.score
+            FROM song_similarity AS sim
+            WHERE sim.song_id = $1
+            ORDER BY sim.score DESC, sim.similar_song_id
+            LIMIT $2
+        ", &[&song_id.0, &(limit as i64)]));
+
+        Ok(rows.iter().map(|row| (SongId(row.get(0)), row.get(1))).collect())
+    }
+
+    fn get_recommendations(&self, account: &AccountId, limit: u32) -> io::Result<Vec<(SongId, f64)>>
+    {
+        let rows = try!(self.pgconn.query("
+            SELECT sim.similar_song_id, sum(sim.score * ln(1 + asm.play_count::double precision)) AS score
+            FROM account_song_metadata AS asm
+            JOIN song_similarity AS sim ON sim.song_id = asm.song_id
+            WHERE asm.account_id = $1 AND 0 < asm.play_count
+                AND NOT EXISTS (
+                    SELECT 1 FROM account_song_metadata AS heard
+                    WHERE heard.account_id = $1 AND heard.song_id = sim.similar_song_id
+                        AND (0 < heard.play_count OR heard.score IS NOT NULL)
+                )
+            GROUP BY sim.similar_song_id
+            ORDER BY score DESC, sim.similar_song_id
+            LIMIT $2
+        ", &[&account.get_user_id(), &(limit as i64)]));
+
+        Ok(rows.iter().map(|row| (SongId(row.get(0)), row.get(1))).collect())
+    }
+
+    fn create_radio_session(&mut self, account: &AccountId, session: &RadioSession, song_ids: &[SongId],
+        expired_before: i64) -> io::Result<()>
+    {
+        let song_ids: Vec<i64> = song_ids.iter().map(|s| s.0).collect();
+        let trans = try!(self.pgconn.transaction());
+
+        try!(trans.execute("
+            DELETE FROM radio_session
+            WHERE account_id = $1 AND started_at < to_timestamp($2) AT TIME ZONE 'UTC'
+        ", &[&account.get_user_id(), &(expired_before as f64)]));
+        try!(trans.execute("
+            INSERT INTO radio_session (account_id, seed, started_at, song_ids)
+            VALUES ($1, $2, to_timestamp($3) AT TIME ZONE 'UTC', $4)
+            ON CONFLICT (account_id, seed, started_at) DO UPDATE SET song_ids = excluded.song_ids
+        ", &[&account.get_user_id(), &(session.seed as i64), &(session.started_at as f64), &song_ids]));
+
+        try!(trans.commit());
+        Ok(())
+    }
+
+    fn get_radio_session(&self, account: &AccountId, session: &RadioSession) -> io::Result<Option<Vec<SongId>>>
+    {
+        let rows = try!(self.pgconn.query("
+            SELECT song_ids FROM radio_session
+            WHERE account_id = $1 AND seed = $2 AND started_at = to_timestamp($3) AT TIME ZONE 'UTC'
+        ", &[&account.get_user_id(), &(session.seed as i64), &(session.started_at as f64)]));
+
+        Ok(rows.iter().next().map(|row| {
+            let song_ids: Vec<i64> = row.get(0);
+            song_ids.into_iter().map(SongId).collect()
+        }))
+    }
+
+    fn set_rating(&mut self, account: &AccountId, song_id: &SongId, rating: Option<i32>) -> io::Result<()>
+    {
+        let trans = try!(self.pgconn.transaction());
+
+        let rows = try!(trans.query("
+            SELECT 1 FROM song WHERE id = $1
+        ", &[&song_id.0]));
+        if rows.len() == 0 {
+            return Err(not_found("no such song"));
+        }
+
+        try!(trans.execute("
+            INSERT INTO account_song_metadata (account_id, song_id, score)
+            VALUES ($1, $2, $3)
+            ON CONFLICT ON CONSTRAINT account_song_metadata_account_song_uniq
+            DO UPDATE SET score = EXCLUDED.score
+        ", &[&account.get_user_id(), &song_id.0, &rating]));
+
+        try!(trans.commit());
+        Ok(())
+    }
+
+    fn get_playlists(&self, account: &AccountId) -> io::Result<Vec<Playlist>>
+    {
+        let rows = try!(self.pgconn.query(&format!("
+            SELECT {}
+            FROM playlist AS p
+            WHERE p.account_id = $1
+            ORDER BY p.name, p.id
+        ", PLAYLIST_COLUMNS), &[&account.get_user_id()]));
+
+        Ok(rows.iter().map(|row| playlist_from_row(&row)).collect())
+    }
+
+    fn get_playlist(&self, account: &AccountId, id: &PlaylistId)
+        -> io::Result<Option<(Playlist, Vec<PlaylistEntry>)>>
+    {
+        let rows = try!(self.pgconn.query(&format!("
+            SELECT {}
+            FROM playlist AS p
+            WHERE p.id = $1 AND p.account_id = $2
+        ", PLAYLIST_COLUMNS), &[&id.0, &account.get_user_id()]));
+        let playlist = match rows.iter().next() {
+            Some(row) => playlist_from_row(&row),
+            None => return Ok(None),
+        };
+
+        let rows = try!(self.pgconn.query("
+            SELECT pe.id, pe.position, pe.song_id
+            FROM playlist_entry AS pe
+            WHERE pe.playlist_id = $1
+            ORDER BY pe.position
+        ", &[&id.0]));
+        let raw_entries: Vec<(i64, i32, i64)> = rows.iter()
+            .map(|row| (row.get(0), row.get(1), row.get(2)))
+            .collect();
+
+        let songs = try!(self.get_songs(&SongQuery {
+            account: Some(account.clone()),
+            filters: vec![SongFilter::Ids(raw_entries.iter().map(|e| SongId(e.2)).collect())],
+            order: Vec::new(),
+            limit: None,
+        }));
+        let songs: HashMap<i64, Song> = songs.into_iter().map(|s| (s.id.0, s)).collect();
+
+        let mut entries = Vec::new();
+        for (entry_id, position, song_id) in raw_entries.into_iter() {
+            let song = try!(songs.get(&song_id).cloned().ok_or_else(internal_error));
+            entries.push(PlaylistEntry {
+                id: PlaylistEntryId(entry_id),
+                position: position,
+                song: song,
+            });
+        }
+        Ok(Some((playlist, entries)))
+    }
+
+    fn create_playlist(&mut self, account: &AccountId, playlist: &PlaylistCreate) -> io::Result<PlaylistId>
+    {
+        let song_ids: Vec<i64> = playlist.song_ids.iter().map(|s| s.0).collect();
+        let trans = try!(self.pgconn.transaction());
+
+        let rows = try!(trans.query("
+            SELECT 1 FROM unnest($1::bigint[]) AS u(id)
+            WHERE NOT EXISTS (SELECT 1 FROM song AS s WHERE s.id = u.id)
+            LIMIT 1
+        ", &[&song_ids]));
+        if rows.len() > 0 {
+            return Err(not_found("no such song"));
+        }
+
+        let rows = try!(trans.query("
+            INSERT INTO playlist (account_id, name, description)
+            VALUES ($1, $2, $3)
+            RETURNING id
+        ", &[&account.get_user_id(), &playlist.name, &playlist.description]));
+        let id: i64 = try!(extract_single2(rows));
+        try!(trans.execute("
+            INSERT INTO playlist_entry (playlist_id, song_id, position)
+            SELECT $1, u.song_id, u.position - 1
+            FROM unnest($2::bigint[]) WITH ORDINALITY AS u(song_id, position)
+        ", &[&id, &song_ids]));
+
+        try!(trans.commit());
+        Ok(PlaylistId(id))
+    }
+
+    fn update_playlist(&mut self, account: &AccountId, id: &PlaylistId, update: &PlaylistUpdate) -> io::Result<()>
+    {
+        let updated = try!(self.pgconn.execute("
+            UPDATE playlist SET
+                name = coalesce($3, name),
+                description = coalesce($4, description),
+                updated_at = NOW()
+            WHERE id = $1 AND account_id = $2
+        ", &[&id.0, &account.get_user_id(), &update.name, &update.description]));
+        if updated == 0 {
+            return Err(not_found("no such playlist"));
+        }
+        Ok(())
+    }
+
+    fn delete_playlist(&mut self, account: &AccountId, id: &PlaylistId) -> io::Result<()>
+    {
+        let deleted = try!(self.pgconn.execute("
+            DELETE FROM playlist WHERE id = $1 AND account_id = $2
+        ", &[&id.0, &account.get_user_id()]));
+        if deleted == 0 {
+            return Err(not_found("no such playlist"));
+        }
+        Ok(())
+    }
+
+    fn insert_playlist_entry(&mut self, account: &AccountId, id: &PlaylistId, song_id: &SongId, position: Option<i32>)
+        -> io::Result<PlaylistEntryId>
+    {
+        let trans = try!(self.pgconn.transaction());
+        let entry_count = try!(lock_playlist(&trans, account, id));
+
+        let rows = try!(trans.query("
+            SELECT 1 FROM song WHERE id = $1
+        ", &[&song_id.0]));
+        if rows.len() == 0 {
+            return Err(not_found("no such song"));
+        }
+
+        let position = match position {
+            Some(position) if 0 <= position && position < entry_count => position,
+            Some(position) if position < 0 => 0,
+            _ => entry_count,
+        };
+        try!(trans.execute("
+            UPDATE playlist_entry SET position = position + 1
+            WHERE playlist_id = $1 AND $2 <= position
+        ", &[&id.0, &position]));
+        let rows = try!(trans.query("
+            INSERT INTO playlist_entry (playlist_id, song_id, position)
+            VALUES ($1, $2, $3)
+            RETURNING id
+        ", &[&id.0, &song_id.0, &position]));
+        let entry_id = PlaylistEntryId(try!(extract_single2(rows)));
+        try!(touch_playlist(&trans, id));
+
+        try!(trans.commit());
+        Ok(entry_id)
+    }
+
+    fn move_playlist_entry(&mut self, account: &AccountId, id: &PlaylistId, entry: &PlaylistEntryId, position: i32)
+        -> io::Result<()>
+    {
+        let trans = try!(self.pgconn.transaction());
+        let entry_count = try!(lock_playlist(&trans, account, id));
+        let current = try!(playlist_entry_position(&trans, id, entry));
+
+        let position = ::std::cmp::max(0, ::std::cmp::min(position, entry_count - 1));
+        if current < position {
+            try!(trans.execute("
+                UPDATE playlist_entry SET position = position - 1
+                WHERE playlist_id = $1 AND $2 < position AND position <= $3
+            ", &[&id.0, &current, &position]));
+        } else if position < current {
+            try!(trans.execute("
+                UPDATE playlist_entry SET position = position + 1
+                WHERE playlist_id = $1 AND $2 <= position AND position < $3
+            ", &[&id.0, &position, &current]));
+        }
+        try!(trans.execute("
+            UPDATE playlist_entry SET position = $2 WHERE id = $1
+        ", &[&entry.0, &position]));
+        try!(touch_playlist(&trans, id));
+
+        try!(trans.commit());
+        Ok(())
+    }
+
+    fn remove_playlist_entry(&mut self, account: &AccountId, id: &PlaylistId, entry: &PlaylistEntryId)
+        -> io::Result<()>
+    {
+        let trans = try!(self.pgconn.transaction());
+        try!(lock_playlist(&trans, account, id));
+        let current = try!(playlist_entry_position(&trans, id, entry));
+
+        try!(trans.execute("
+            DELETE FROM playlist_entry WHERE id = $1
+        ", &[&entry.0]));
+        try!(trans.execute("
+            UPDATE playlist_entry SET position = position - 1
+            WHERE playlist_id = $1 AND $2 < position
+        ", &[&id.0, &current]));
+        try!(touch_playlist(&trans, id));
+
+        try!(trans.commit());
+        Ok(())
+    }
+
+    fn get_play_queue(&self, account: &AccountId) -> io::Result<PlayQueue>
+    {
+        let rows = try!(self.pgconn.query(&format!("
+            SELECT {} FROM play_queue AS q WHERE q.account_id = $1
+        ", PLAY_QUEUE_COLUMNS), &[&account.get_user_id()]));
+
+        let queue = match rows.iter().next() {
+            Some(row) => play_queue_from_row(&row),
+            None => PlayQueue {
+                song_ids: Vec::new(),
+                current_index: 0,
+                position_ms: 0,
+                version: 0,
+                updated_at: 0,
+            },
+        };
+        Ok(queue)
+    }
+
+    fn set_play_queue(&mut self, account: &AccountId, version: i64, queue: &PlayQueueUpdate)
+        -> io::Result<Option<PlayQueue>>
+    {
+        let song_ids: Vec<i64> = queue.song_ids.iter().map(|s| s.0).collect();
+        let trans = try!(self.pgconn.transaction());
+
+        let rows = try!(trans.query("
+            SELECT u.id FROM unnest($1::bigint[]) AS u(id)
+            WHERE NOT EXISTS (SELECT 1 FROM song AS s WHERE s.id = u.id)
+            LIMIT 1
+        ", &[&song_ids]));
+        if let Some(row) = rows.iter().next() {
+            let missing: i64 = row.get(0);
+            return Err(io::Error::new(io::ErrorKind::InvalidInput,
+                format!("no such song: {}", missing)));
+        }
+
+        // version 0 means the client has never seen a stored queue, so the
+        // row must not exist yet.
+        let rows = if version == 0 {
+            try!(trans.query(&format!("
+                INSERT INTO play_queue AS q (account_id, song_ids, current_index, position_ms, version)
+                VALUES ($1, $2, $3, $4, 1)
+                ON CONFLICT (account_id) DO NOTHING
+                RETURNING {}
+            ", PLAY_QUEUE_COLUMNS), &[
+                &account.get_user_id(), &song_ids, &queue.current_index, &queue.position_ms,
+            ]))
+        } else {
+            try!(trans.query(&format!("
+                UPDATE play_queue AS q
+                SET song_ids = $3, current_index = $4, position_ms = $5,
+                    version = q.version + 1, updated_at = NOW()
+                WHERE q.account_id = $1 AND q.version = $2
+                RETURNING {}
+            ", PLAY_QUEUE_COLUMNS), &[
+                &account.get_user_id(), &version, &song_ids, &queue.current_index, &queue.position_ms,
+            ]))
+        };
+        let out = rows.iter().next().map(|row| play_queue_from_row(&row));
+
+        try!(trans.commit());
+        Ok(out)
+    }
+
+    fn get_smart_playlists(&self, account: &AccountId) -> io::Result<Vec<SmartPlaylist>>
+    {
+        let rows = try!(self.pgconn.query(&format!("
+            SELECT {}
+            FROM smart_playlist AS sp
+            WHERE sp.account_id = $1
+            ORDER BY sp.name, sp.id
+        ", SMART_PLAYLIST_COLUMNS), &[&account.get_user_id()]));
+
+        let mut out = Vec::new();
+        for row in rows.iter() {
+            out.push(try!(smart_playlist_from_row(&row)));
+        }
+        Ok(out)
+    }
+
+    fn get_smart_playlist(&self, account: &AccountId, id: &SmartPlaylistId) -> io::Result<Option<SmartPlaylist>>
+    {
+        let rows = try!(self.pgconn.query(&format!("
+            SELECT {}
+            FROM smart_playlist AS sp
+            WHERE sp.id = $1 AND sp.account_id = $2
+        ", SMART_PLAYLIST_COLUMNS), &[&id.0, &account.get_user_id()]));
+
+        match rows.iter().next() {
+            Some(row) => Ok(Some(try!(smart_playlist_from_row(&row)))),
+            None => Ok(None),
+        }
+    }
+
+    fn create_smart_playlist(&mut self, account: &AccountId, playlist: &SmartPlaylistCreate)
+        -> io::Result<SmartPlaylistId>
+    {
+        let rules = try!(JsonDocument::serialize(&playlist.rules)
+            .map_err(adapt_error_tagged("error serializing json")));
+        let rows = try!(self.pgconn.query("
+            INSERT INTO smart_playlist (account_id, name, rules)
+            VALUES ($1, $2, $3)
+            RETURNING id
+        ", &[&account.get_user_id(), &playlist.name, &rules]));
+        Ok(SmartPlaylistId(try!(extract_single2(rows))))
+    }
+
+    fn delete_smart_playlist(&mut self, account: &AccountId, id: &SmartPlaylistId) -> io::Result<()>
+    {
+        let deleted = try!(self.pgconn.execute("
+            DELETE FROM smart_playlist WHERE id = $1 AND account_id = $2
+        ", &[&id.0, &account.get_user_id()]));
+        if deleted == 0 {
+            return Err(not_found("no such smart playlist"));
+        }
+        Ok(())
+    }
+}
+
+/// rows fetched from the song cursor at a time, which bounds how much of a
+/// large library is held in memory
+const SONG_FETCH_ROWS: u32 = 500;
+
+/// Aggregates the `song_variant` rows aliased `sv` into what `SongVariant`
+/// deserializes from, highest bitrate first.
+const VARIANTS_AGG: &'static str = "
+    jsonb_agg(jsonb_build_object(
+        'blob', sv.blob,
+        'codec', sv.codec,
+        'bitrate', sv.bitrate,
+        'channels', sv.channels,
+        'sample_rate', sv.sample_rate,
+        'size', sv.size
+    ) ORDER BY sv.bitrate DESC, sv.id)";
+
+const SMART_PLAYLIST_COLUMNS: &'static str = "
+    sp.id, sp.account_id, sp.name, sp.rules,
+    extract(epoch FROM sp.created_at)::bigint,
+    extract(epoch FROM sp.updated_at)::bigint
+";
+
+fn smart_playlist_from_row(row: &Row) -> io::Result<SmartPlaylist> {
+    let rules = try!(row.get::<_, JsonDocument>(3)
+        .deserialize()
+        .map_err(adapt_error_tagged("error deserializing json")));
+    Ok(SmartPlaylist {
+        id: SmartPlaylistId(row.get(0)),
+        account_id: row.get(1),
+        name: row.get(2),
+        rules: rules,
+        created_at: row.get(4),
+        updated_at: row.get(5),
+    })
+}
+
+const PLAY_QUEUE_COLUMNS: &'static str = "
+    q.song_ids, q.current_index, q.position_ms, q.version,
+    extract(epoch FROM q.updated_at)::bigint
+";
+
+fn play_queue_from_row(row: &Row) -> PlayQueue {
+    let song_ids: Vec<i64> = row.get(0);
+    PlayQueue {
+        song_ids: song_ids.into_iter().map(SongId).collect(),
+        current_index: row.get(1),
+        position_ms: row.get(2),
+        version: row.get(3),
+        updated_at: row.get(4),
+    }
+}
+
+const PLAYLIST_COLUMNS: &'static str = "
+    p.id, p.account_id, p.name, p.description,
+    (SELECT count(*) FROM playlist_entry AS pe WHERE pe.playlist_id = p.id),
+    extract(epoch FROM p.created_at)::bigint,
+    extract(epoch FROM p.updated_at)::bigint
+";
+
+fn playlist_from_row(row: &Row) -> Playlist {
+    Playlist {
+        id: PlaylistId(row.get(0)),
+        account_id: row.get(1),
+        name: row.get(2),
+        description: row.get(3),
+        entry_count: row.get(4),
+        created_at: row.get(5),
+        updated_at: row.get(6),
+    }
+}
+
+/// Locks the playlist against concurrent edits, returning its length.
+fn lock_playlist(trans: &Transaction, account: &AccountId, id: &PlaylistId) -> io::Result<i32> {
+    let rows = try!(trans.query("
+        SELECT 1 FROM playlist WHERE id = $1 AND account_id = $2 FOR UPDATE
+    ", &[&id.0, &account.get_user_id()]));
+    if rows.len() == 0 {
+        return Err(not_found("no such playlist"));
+    }
+
+    let rows = try!(trans.query("
+        SELECT count(*) FROM playlist_entry WHERE playlist_id = $1
+    ", &[&id.0]));
+    let entry_count: i64 = try!(extract_single2(rows));
+    Ok(entry_count as i32)
+}
+
+fn playlist_entry_position(trans: &Transaction, id: &PlaylistId, entry: &PlaylistEntryId) -> io::Result<i32> {
+    let rows = try!(trans.query("
+        SELECT position FROM playlist_entry WHERE id = $1 AND playlist_id = $2
+    ", &[&entry.0, &id.0]));
+    rows.iter().next().map(|r| r.get(0))
+        .ok_or_else(|| not_found("no such playlist entry"))
+}
+
+fn touch_playlist(trans: &Transaction, id: &PlaylistId) -> io::Result<()> {
+    try!(trans.execute("
+        UPDATE playlist SET updated_at = NOW() WHERE id = $1
+    ", &[&id.0]));
+    Ok(())
+}
+
+/// Accumulates query parameters, handing out their `$n` placeholders.
+struct QueryParams {
+    values: Vec<Box<ToSql>>,
+}
+
+impl QueryParams {
+    fn new() -> QueryParams {
+        QueryParams { values: Vec::new() }
+    }
+
+    fn push<T: ToSql + 'static>(&mut self, value: T) -> String {
+        self.values.push(Box::new(value));
+        format!("${}", self.values.len())
+    }
+
+    fn as_refs(&self) -> Vec<&ToSql> {
+        self.values.iter().map(|v| &**v).collect()
+    }
+}
+
+/// SQL converting a unix timestamp parameter to our UTC timestamp columns.
+fn utc_timestamp(param: String) -> String {
+    format!("(to_timestamp({}) AT TIME ZONE 'UTC')", param)
+}
+
+/// Builds the WHERE and ORDER BY clauses for a song query.  Expects `song`
+/// aliased as `s` and the account's `account_song_metadata` row as `asm`,
+/// with the account id bound to `account_param`.
+fn song_query_clauses(query: &SongQuery, account_param: &str, params: &mut QueryParams) -> (String, String) {
+    let mut conditions = vec!["TRUE".to_string()];
+    for filter in query.filters.iter() {
+        conditions.push(match *filter {
+            SongFilter::Ids(ref ids) => {
+                let ids: Vec<i64> = ids.iter().map(|id| id.0).collect();
+                format!("s.id = ANY({})", params.push(ids))
+            },
+            SongFilter::Rating(cmp, value) => {
+                format!("asm.score {} {}", cmp.sql_operator(), params.push(value))
+            },
+            SongFilter::PlayCount(cmp, value) => {
+                format!("COALESCE(asm.play_count, 0) {} {}", cmp.sql_operator(), params.push(value))
+            },
+            SongFilter::Metadata(ref field, cmp, ref value) => {
+                let multi_valued = is_multi_valued_field(field);
+                let effective = effective_metadata(&params.push(field.clone()));
+                let value = params.push(value.clone());
+                if !multi_valued {
+                    // byte-wise like the mock driver, whatever the database's collation
+                    format!("lower({}) COLLATE \"C\" {} lower({})", effective, cmp.sql_operator(), value)
+                } else if cmp == Comparison::Ne {
+                    format!("{effective} IS NOT NULL AND NOT EXISTS (
+                        SELECT 1 FROM regexp_split_to_table({effective}, ';') AS part
+                        WHERE lower(trim(part)) = lower({value})
+                    )", effective=effective, value=value)
+                } else {
+                    format!("EXISTS (
+                        SELECT 1 FROM regexp_split_to_table({effective}, ';') AS part
+                        WHERE trim(part) <> '' AND lower(trim(part)) COLLATE \"C\" {op} lower({value})
+                    )", effective=effective, op=cmp.sql_operator(), value=value)
+                }
+            },
+            SongFilter::PlayedSince(since) => {
+                format!("EXISTS ({})", listened_since(account_param, params, since))
+            },
+            SongFilter::NotPlayedSince(since) => {
+                format!("NOT EXISTS ({})", listened_since(account_param, params, since))
+            },
+            SongFilter::Artist(ref artist_id) => {
+                format!("EXISTS (
+                    SELECT 1 FROM song_artist AS sa WHERE sa.song_id = s.id AND sa.artist_id = {}
+                )", params.push(artist_id.0))
+            },
+        });
+    }
+
+    let mut ordering = Vec::new();
+    for order in query.order.iter() {
+        let direction = if order.descending { "DESC" } else { "ASC" };
+        ordering.push(match order.key {
+            SongSortKey::Rating => format!("asm.score {} NULLS LAST", direction),
+            SongSortKey::PlayCount => format!("COALESCE(asm.play_count, 0) {}", direction),
+            SongSortKey::Random(seed) => {
+                format!("shuffle_key({}, s.id) {}", params.push(seed as i64), direction)
+            },
+        });
+    }
+    ordering.push("s.id".to_string());
+
+    (conditions.join(" AND "), ordering.join(", "))
+}
+
+/// SQL for a song's value of the field bound to `field_param`, falling back
+/// to its album's, as `Song::effective_metadata` does.  Expects `song`
+/// aliased as `s`.
+fn effective_metadata(field_param: &str) -> String {
+    format!("COALESCE(
+        (SELECT sm.value FROM song_metadata AS sm
+            WHERE sm.song_id = s.id AND sm.field_name = {field}),
+        (SELECT am.value FROM album_metadata AS am
+            WHERE am.album_id = s.album_id AND am.field_name = {field})
+    )", field=field_param)
+}
+
+/// The artist going by `credit.name`, created if we've never seen the name.
+fn find_or_create_artist(trans: &Transaction, credit: &ArtistCredit) -> io::Result<i64> {
+    let rows = try!(trans.query("
+        SELECT artist_id FROM artist_alias WHERE lower(name) = lower($1)
+    ", &[&credit.name]));
+    if let Some(row) = rows.iter().next() {
+        return Ok(row.get(0));
+    }
+
+    let rows = try!(trans.query("
+        INSERT INTO artist (name, sort_name)
+        VALUES ($1, $2)
+        ON CONFLICT ((lower(name)))
+        DO UPDATE SET sort_name = COALESCE(artist.sort_name, EXCLUDED.sort_name)
+        RETURNING id
+    ", &[&credit.name, &credit.sort_name]));
+    extract_single2(rows)
+}
+
+fn listened_since(account_param: &str, params: &mut QueryParams, since: i64) -> String {
+    format!("
+        SELECT 1 FROM listen_history AS lh
+        WHERE lh.account_id = {} AND lh.song_id = s.id AND {} <= lh.started_at
+    ", account_param, utc_timestamp(params.push(since as f64)))
+}
+
+/// (owner table, metadata table, foreign key column, owner id)
+fn metadata_tables(target: &MetadataTarget) -> (&'static str, &'static str, &'static str, i64) {
+    match *target {
+        MetadataTarget::Song(ref id) => ("song", "song_metadata", "song_id", id.0),
+        MetadataTarget::Album(ref id) => ("album", "album_metadata", "album_id", id.0),
+    }
+}
+
+fn lock_metadata_target(trans: &Transaction, target: &MetadataTarget) -> io::Result<()> {
+    let (table, _, _, target_id) = metadata_tables(target);
+    let rows = try!(trans.query(&format!("
+        SELECT 1 FROM {} WHERE id = $1 FOR UPDATE
+    ", table), &[&target_id]));
+    if rows.len() == 0 {
+        return Err(not_found("no such song or album"));
+    }
+    Ok(())
+}
+
+/// Sets (or with `None`, removes) one field and records the change.  Nothing
+/// is recorded if the field already has the requested value.
+fn apply_metadata_change(
+    trans: &Transaction,
+    account: &AccountId,
+    target: &MetadataTarget,
+    field_name: &str,
+    new_value: Option<&str>,
+    reverts: Option<&MetadataChangeId>,
+) -> io::Result<Option<MetadataChange>> {
+    let (_, table, column, target_id) = metadata_tables(target);
+
+    let old_value: Option<String> = {
+        let rows = try!(trans.query(&format!("
+            SELECT value FROM {} WHERE {} = $1 AND field_name = $2
+        ", table, column), &[&target_id, &field_name]));
+        rows.iter().next().map(|r| r.get(0))
+    };
+    if old_value.as_ref().map(|v| &v[..]) == new_value {
+        return Ok(None);
+    }
+
+    match new_value {
+        Some(value) => {
+            try!(trans.execute(&format!("
+                INSERT INTO {0} ({1}, field_name, value)
+                VALUES ($1, $2, $3)
+                ON CONFLICT ({1}, field_name) DO UPDATE SET value = EXCLUDED.value
+            ", table, column), &[&target_id, &field_name, &value]));
+        },
+        None => {
+            try!(trans.execute(&format!("
+                DELETE FROM {} WHERE {} = $1 AND field_name = $2
+            ", table, column), &[&target_id, &field_name]));
+        },
+    }
+
+    let (song_id, album_id) = match *target {
+        MetadataTarget::Song(ref id) => (Some(id.0), None),
+        MetadataTarget::Album(ref id) => (None, Some(id.0)),
+    };
+    let rows = try!(trans.query("
+        INSERT INTO metadata_change
+            (account_id, song_id, album_id, field_name, old_value, new_value, reverts)
+        VALUES ($1, $2, $3, $4, $5, $6, $7)
+        RETURNING
+            id, account_id, song_id, album_id, field_name,
+            old_value, new_value, reverts,
+            extract(epoch FROM changed_at)::bigint
+    ", &[
+        &account.get_user_id(), &song_id, &album_id, &field_name,
+        &old_value, &new_value, &reverts.map(|r| r.0),
+    ]));
+    let change = try!(rows.iter().next().map(|row| metadata_change_from_row(&row))
+        .ok_or_else(internal_error));
+    Ok(Some(change))
+}
+
+fn metadata_change_from_row(row: &Row) -> MetadataChange {
+    MetadataChange {
+        id: MetadataChangeId(row.get(0)),
+        account_id: row.get(1),
+        song_id: row.get::<_, Option<i64>>(2).map(SongId),
+        album_id: row.get::<_, Option<i64>>(3).map(AlbumId),
+        field_name: row.get(4),
+        old_value: row.get(5),
+        new_value: row.get(6),
+        reverts: row.get::<_, Option<i64>>(7).map(MetadataChangeId),
+        changed_at: row.get(8),
+    }
+}
+
+use std::boxed::FnBox;
+
+fn adapt_error_tagged<'a, E: Error>(tag: &'a str) -> Box<FnBox(E) -> io::Error + 'a> {
+    Box::new(move |e| {
+        io::Error::new(io::ErrorKind::Other, format!("{}: {}", tag, e))
+    })
+}
+
+fn extract_single<T: FromSql>(rows: Rows) -> Result<T, ()> {
+    rows.iter().next().map(|r| r.get(0)).ok_or(())
+}
+
+fn extract_single2<T: FromSql>(rows: Rows) -> io::Result<T> {
+    rows.iter().next().map(|r| r.get(0))
+        .ok_or_else(internal_error)
+}
+
+/// Decodes a `jsonb_object_agg` of metadata, which is NULL when there were
+/// no fields to aggregate.
+fn json_metadata(doc: Option<JsonDocument>) -> io::Result<BTreeMap<String, String>> {
+    doc.unwrap_or_else(JsonDocument::empty)
+        .deserialize()
+        .map_err(adapt_error_tagged("error deserializing json"))
+}
+
+/// Fills in the album gain and peak from the two columns starting at
+/// `first`.
+fn with_album_gain(album: Album, row: &Row, first: usize) -> Album {
+    Album {
+        album_gain: row.get(first),
+        album_peak: row.get(first + 1),
+        ..album
+    }
+}
+
+/// Decodes a `VARIANTS_AGG`, which is NULL when the song has no variants.
+fn json_variants(doc: Option<JsonDocument>) -> io::Result<Vec<SongVariant>> {
+    match doc {
+        Some(doc) => doc.deserialize().map_err(adapt_error_tagged("error deserializing json")),
+        None => Ok(Vec::new()),
+    }
+}
+
+fn insert_song_variant(trans: &Transaction, song_id: i64, variant: &SongVariant) -> io::Result<()> {
+    try!(trans.execute("
+        INSERT INTO song_variant (song_id, blob, codec, bitrate, channels, sample_rate, size)
+        VALUES ($1, $2, $3, $4, $5, $6, $7)
+    ", &[&song_id, &variant.blob, &variant.codec, &variant.bitrate, &variant.channels,
+        &variant.sample_rate, &variant.size])
+        .map_err(|e| {
+            if e.code() == Some(&SqlState::UniqueViolation) {
+                return io::Error::new(io::ErrorKind::InvalidInput, "the song already has a variant with that blob");
+            }
+            io::Error::from(e)
+        }));
+    Ok(())
+}
+
+fn upsert_lyrics(trans: &Transaction, song_id: i64, lyrics: &Lyrics) -> io::Result<()> {
+    let lines = try!(JsonDocument::serialize(&lyrics.lines)
+        .map_err(adapt_error_tagged("error serializing json")));
+    try!(trans.execute("
+        INSERT INTO song_lyrics (song_id, text, lines)
+        VALUES ($1, $2, $3)
+        ON CONFLICT (song_id) DO UPDATE
+            SET text = EXCLUDED.text, lines = EXCLUDED.lines, updated_at = NOW()
+    ", &[&song_id, &lyrics.text, &lines]));
+    Ok(())
+}
+
+/// Reports a clash with `song_album_disc_track_uniq` as bad input, as the
+/// mock driver does.
+fn duplicate_track_error(e: ::postgres::error::Error) -> io::Error {
+    if e.code() == Some(&SqlState::UniqueViolation) {
+        return io::Error::new(io::ErrorKind::InvalidInput, "duplicate disc and track number");
+    }
+    io::Error::from(e)
+}
+
+/// returned for really unexpected errors
+fn internal_error() -> io::Error {
+    io::Error::new(io::ErrorKind::Other, "DB Error")
+}
+
+fn not_found(what: &str) -> io::Error {
+    io::Error::new(io::ErrorKind::NotFound, what)
 }
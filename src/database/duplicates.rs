@@ -0,0 +1,69 @@
+//! Grouping of songs that are probably the same recording by their
+//! metadata.  Identical files and identical audio are grouped by the
+//! drivers, which can do so with a plain GROUP BY.
+
+use std::collections::BTreeMap;
+
+use ::model::{Song, SongId};
+use super::metadata_values;
+
+/// Lowercases and reduces punctuation and runs of whitespace to single
+/// spaces, so that "Blue in Green" and "blue  in green!" compare equal.
+pub fn normalize_text(text: &str) -> String {
+    let mut out = String::new();
+    for word in text.split(|c: char| !c.is_alphanumeric()).filter(|w| w.len() > 0) {
+        if out.len() > 0 {
+            out.push(' ');
+        }
+        out.push_str(&word.to_lowercase());
+    }
+    out
+}
+
+/// Groups songs with the same normalized title and artists whose lengths
+/// are within `tolerance_ms` of the next shorter one.  Songs without a
+/// title are never grouped.
+pub fn metadata_duplicate_groups(songs: &[Song], tolerance_ms: i32) -> Vec<Vec<SongId>> {
+    let mut by_name: BTreeMap<(String, String), Vec<&Song>> = BTreeMap::new();
+    for song in songs.iter() {
+        let title = match song.metadata.get("TITLE") {
+            Some(title) => normalize_text(title),
+            None => continue,
+        };
+        if title.len() == 0 {
+            continue;
+        }
+        let artist = song.effective_metadata("ARTIST")
+            .map(|artists| {
+                let mut names: Vec<String> = metadata_values(artists).into_iter()
+                    .map(normalize_text)
+                    .collect();
+                names.sort();
+                names.join(";")
+            })
+            .unwrap_or_else(String::new);
+        by_name.entry((title, artist)).or_insert_with(Vec::new).push(song);
+    }
+
+    let mut out = Vec::new();
+    for (_, mut candidates) in by_name.into_iter() {
+        candidates.sort_by_key(|s| (s.length_ms, s.id.0));
+
+        let mut group: Vec<SongId> = Vec::new();
+        let mut last_length = 0;
+        for song in candidates.into_iter() {
+            if group.len() > 0 && tolerance_ms < song.length_ms - last_length {
+                if group.len() > 1 {
+                    out.push(group);
+                }
+                group = Vec::new();
+            }
+            group.push(song.id.clone());
+            last_length = song.length_ms;
+        }
+        if group.len() > 1 {
+            out.push(group);
+        }
+    }
+    out
+}
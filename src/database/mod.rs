@@ -8,9 +8,64 @@ use postgres::types::FromSql;
 use postgres::rows::Rows;
 
 use ::util::json::JsonDocument;
-use ::model::{AlbumId, Album, SongId, Song};
+use ::model::{
+    AlbumId,
+    Album,
+    AlbumSummary,
+    SongId,
+    Song,
+    SongVariant,
+    Lyrics,
+    MetadataChangeId,
+    MetadataChange,
+    PlaylistId,
+    PlaylistEntryId,
+    Playlist,
+    PlaylistEntry,
+    PlayQueue,
+    ListenId,
+    Listen,
+    SmartPlaylistId,
+    SmartPlaylist,
+    SmartRules,
+    ArtistId,
+    Artist,
+    ArtistSummary,
+    FacetValue,
+    DuplicateKind,
+    DuplicateGroup,
+    AccountProfile,
+    LinkedAccount,
+    SongStats,
+    CatalogChanges,
+};
 
 pub mod drivers;
+mod rules;
+mod duplicates;
+mod similarity;
+mod radio;
+mod retention;
+
+pub use self::rules::compile_rules;
+pub use self::duplicates::{metadata_duplicate_groups, normalize_text};
+pub use self::similarity::{
+    SimilarityParams,
+    SessionListen,
+    SongTraits,
+    SongSimilarity,
+    compute_similarity,
+    spawn_refresh as spawn_similarity_refresh,
+};
+pub use self::radio::{
+    RadioSession,
+    RADIO_RECENT_SECS,
+    RADIO_SESSION_SECS,
+    RADIO_SEQUENCE_MAX,
+    shuffle_key,
+    radio_sequence,
+};
+pub use self::retention::spawn_pruning as spawn_history_pruning;
 
 #[derive(Debug)]
 pub struct AccountSongMetadataId(pub i64);
@@ -21,13 +76,17 @@ pub struct AccountSongMetadata {
     pub account_id: Uuid,
     pub song_id: SongId,
     pub play_count: i32,
-    pub score: i32,
+    pub score: Option<i32>,
 }
 
 #[derive(Debug, Clone)]
 pub struct AccountId(Uuid);
 
 impl AccountId {
+    pub fn from_user_id(user_id: Uuid) -> AccountId {
+        AccountId(user_id)
+    }
+
     pub fn get_user_id(&self) -> Uuid {
         self.0.clone()
     }
@@ -60,5 +119,361 @@ pub struct ForeignAccount {
 }
 
 pub struct SongQuery {
-    //
-}
\ No newline at end of file
+    /// the account whose play counts and ratings are included with each
+    /// song, and which per-account filters and sort keys refer to
+    pub account: Option<AccountId>,
+    pub filters: Vec<SongFilter>,
+    /// applied in order, ties are broken by song id
+    pub order: Vec<SongOrder>,
+    pub limit: Option<u32>,
+}
+
+impl SongQuery {
+    pub fn all() -> SongQuery {
+        SongQuery {
+            account: None,
+            filters: Vec::new(),
+            order: Vec::new(),
+            limit: None,
+        }
+    }
+}
+
+/// Songs without a value for the filtered property never match.
+#[derive(Debug, Clone)]
+pub enum SongFilter {
+    Ids(Vec<SongId>),
+    Rating(Comparison, i32),
+    /// songs never played count as played zero times
+    PlayCount(Comparison, i32),
+    /// Compares a metadata field case-insensitively.  The song's own value
+    /// takes precedence over its album's.  Multi-valued fields (see
+    /// `MULTI_VALUED_FIELDS`) match if any of their values does, except for
+    /// `Ne` which requires all to.
+    Metadata(String, Comparison, String),
+    /// listened to at or after the given unix time
+    PlayedSince(i64),
+    /// not listened to since the given unix time, including never
+    NotPlayedSince(i64),
+    /// credited to the artist
+    Artist(ArtistId),
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Comparison {
+    Eq,
+    Ne,
+    Lt,
+    Le,
+    Gt,
+    Ge,
+}
+
+impl Comparison {
+    pub fn sql_operator(&self) -> &'static str {
+        match *self {
+            Comparison::Eq => "=",
+            Comparison::Ne => "<>",
+            Comparison::Lt => "<",
+            Comparison::Le => "<=",
+            Comparison::Gt => ">",
+            Comparison::Ge => ">=",
+        }
+    }
+
+    pub fn test<T: PartialOrd>(&self, lhs: &T, rhs: &T) -> bool {
+        match *self {
+            Comparison::Eq => lhs == rhs,
+            Comparison::Ne => lhs != rhs,
+            Comparison::Lt => lhs < rhs,
+            Comparison::Le => lhs <= rhs,
+            Comparison::Gt => lhs > rhs,
+            Comparison::Ge => lhs >= rhs,
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct SongOrder {
+    pub key: SongSortKey,
+    pub descending: bool,
+}
+
+/// Songs without a value for the sort key come last in either direction.
+#[derive(Debug, Clone)]
+pub enum SongSortKey {
+    Rating,
+    PlayCount,
+    /// a shuffle that is stable for a given seed
+    Random(u32),
+}
+
+pub const RATING_MIN: i32 = 1;
+pub const RATING_MAX: i32 = 5;
+
+/// A (possibly partial) playback of a song reported by a client.
+#[derive(Debug)]
+pub struct Play {
+    pub song_id: SongId,
+    /// how far playback got, kept with the listen
+    pub position_ms: i32,
+    /// percentage of the song that was listened to
+    pub completion: f64,
+    /// whether this play reached the configured threshold
+    pub counted: bool,
+    pub started_at: i64,
+    /// how long the client actually played the song for
+    pub duration_ms: i32,
+    pub client_id: Option<String>,
+}
+
+pub const CLIENT_ID_MAX: usize = 64;
+
+/// Listens newest first, optionally limited to `[from, to)` and continuing
+/// after a cursor.
+#[derive(Debug)]
+pub struct HistoryQuery {
+    pub from: Option<i64>,
+    pub to: Option<i64>,
+    pub after: Option<HistoryCursor>,
+    pub limit: u32,
+}
+
+/// The last listen of the previous page.
+#[derive(Debug, Clone)]
+pub struct HistoryCursor {
+    pub started_at: i64,
+    pub id: ListenId,
+}
+
+impl HistoryCursor {
+    pub fn of(listen: &Listen) -> HistoryCursor {
+        HistoryCursor {
+            started_at: listen.started_at,
+            id: listen.id.clone(),
+        }
+    }
+
+    pub fn parse(raw: &str) -> Option<HistoryCursor> {
+        let mut parts = raw.splitn(2, '.');
+        let started_at = parts.next().and_then(|p| p.parse().ok());
+        let id = parts.next().and_then(|p| p.parse().ok());
+        match (started_at, id) {
+            (Some(started_at), Some(id)) => Some(HistoryCursor {
+                started_at: started_at,
+                id: ListenId(id),
+            }),
+            _ => None,
+        }
+    }
+
+    pub fn to_string(&self) -> String {
+        format!("{}.{}", self.started_at, self.id.0)
+    }
+}
+
+pub struct AlbumQuery {
+    /// metadata field to order by; albums lacking it sort last
+    pub sort_field: Option<String>,
+    pub descending: bool,
+    pub limit: i64,
+    pub offset: i64,
+}
+
+/// A new album along with all of its songs, to be inserted in one go.
+#[derive(Debug)]
+pub struct AlbumCreate {
+    pub art_blob: Option<String>,
+    pub metadata: BTreeMap<String, String>,
+    pub album_gain: Option<f64>,
+    pub album_peak: Option<f64>,
+    pub songs: Vec<SongCreate>,
+}
+
+#[derive(Debug)]
+pub struct SongCreate {
+    pub blob: String,
+    pub disc_no: i16,
+    pub track_no: i16,
+    pub length_ms: i32,
+    pub audio_digest: Option<String>,
+    pub metadata: BTreeMap<String, String>,
+    pub track_gain: Option<f64>,
+    pub track_peak: Option<f64>,
+    /// the encoding `blob` itself is, see `media::TrackInfo::variant`
+    pub variant: SongVariant,
+    pub lyrics: Option<Lyrics>,
+}
+
+pub const ARTIST_NAME_MAX: usize = 256;
+
+/// Separates the values of a multi-valued field, e.g. several artists or
+/// genres.  Repeated comments are joined with it on import (see
+/// `media::comment_map`).
+pub const METADATA_VALUE_SEPARATOR: char = ';';
+
+/// The fields that may hold several values.  Any other field is a single
+/// value, even if it contains the separator.
+pub const MULTI_VALUED_FIELDS: &'static [&'static str] = &[
+    "ARTIST",
+    "ARTISTSORT",
+    "ALBUMARTIST",
+    "ALBUMARTISTSORT",
+    "GENRE",
+];
+
+pub fn is_multi_valued_field(field: &str) -> bool {
+    let field = field.to_uppercase();
+    MULTI_VALUED_FIELDS.iter().any(|name| field == *name)
+}
+
+/// The values `raw` holds as the value of `field`: split up if the field is
+/// multi-valued, else all of it unless it's empty.
+pub fn field_values<'a>(field: &str, raw: &'a str) -> Vec<&'a str> {
+    if is_multi_valued_field(field) {
+        return metadata_values(raw);
+    }
+    if raw.len() == 0 {
+        return Vec::new();
+    }
+    vec![raw]
+}
+
+/// The individual values of a possibly multi-valued field.
+pub fn metadata_values(value: &str) -> Vec<&str> {
+    value.split(METADATA_VALUE_SEPARATOR)
+        .map(|part| part.trim())
+        .filter(|part| part.len() > 0)
+        .collect()
+}
+
+/// An artist named by the metadata of a song or album.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ArtistCredit {
+    pub name: String,
+    pub sort_name: Option<String>,
+}
+
+/// Splits `ARTIST` style values, pairing each name with the `ARTISTSORT`
+/// entry at the same position.
+pub fn artist_credits(names: Option<&String>, sort_names: Option<&String>) -> Vec<ArtistCredit> {
+    fn split(value: Option<&String>) -> Vec<String> {
+        value.map(|v| {
+            metadata_values(v).into_iter()
+                .map(|part| part.chars().take(ARTIST_NAME_MAX).collect())
+                .collect()
+        }).unwrap_or_else(Vec::new)
+    }
+
+    let sort_names = split(sort_names);
+    let mut out: Vec<ArtistCredit> = Vec::new();
+    for (idx, name) in split(names).into_iter().enumerate() {
+        if out.iter().any(|c| c.name.to_lowercase() == name.to_lowercase()) {
+            continue;
+        }
+        out.push(ArtistCredit {
+            name: name,
+            sort_name: sort_names.get(idx).cloned(),
+        });
+    }
+    out
+}
+
+/// A song's artists.  Fields shared by the whole album live in the album's
+/// metadata, so those are consulted as well.
+pub fn song_artist_credits(song: &BTreeMap<String, String>, album: &BTreeMap<String, String>)
+    -> Vec<ArtistCredit>
+{
+    match song.get("ARTIST") {
+        Some(names) => artist_credits(Some(names), song.get("ARTISTSORT")),
+        None => artist_credits(album.get("ARTIST"), album.get("ARTISTSORT")),
+    }
+}
+
+/// An album's artists, from `ALBUMARTIST` or else a shared `ARTIST`.
+pub fn album_artist_credits(album: &BTreeMap<String, String>) -> Vec<ArtistCredit> {
+    match album.get("ALBUMARTIST") {
+        Some(names) => artist_credits(Some(names), album.get("ALBUMARTISTSORT")),
+        None => artist_credits(album.get("ARTIST"), album.get("ARTISTSORT")),
+    }
+}
+
+/// Limits imposed by the `character varying` columns of the metadata tables.
+pub const METADATA_FIELD_NAME_MAX: usize = 32;
+pub const METADATA_VALUE_MAX: usize = 256;
+
+#[derive(Debug, Clone)]
+pub enum MetadataTarget {
+    Song(SongId),
+    Album(AlbumId),
+}
+
+#[derive(Debug)]
+pub struct MetadataPatch {
+    pub set: BTreeMap<String, String>,
+    pub remove: Vec<String>,
+}
+
+/// Checks a metadata field against the column limits and the Vorbis comment
+/// field name rules (printable ASCII, no `=`).
+pub fn check_metadata_field(field_name: &str, value: Option<&str>) -> Result<(), String> {
+    if field_name.len() == 0 {
+        return Err("field name must not be empty".into());
+    }
+    if METADATA_FIELD_NAME_MAX < field_name.chars().count() {
+        return Err(format!("field name {:?} is longer than {} characters",
+            field_name, METADATA_FIELD_NAME_MAX));
+    }
+    if field_name.bytes().any(|b| b < 0x20 || 0x7D < b || b == b'=') {
+        return Err(format!("field name {:?} contains invalid characters", field_name));
+    }
+    if let Some(value) = value {
+        if METADATA_VALUE_MAX < value.chars().count() {
+            return Err(format!("value of {:?} is longer than {} characters",
+                field_name, METADATA_VALUE_MAX));
+        }
+    }
+    Ok(())
+}
+
+pub const DISPLAY_NAME_MAX: usize = 256;
+
+/// Fields left as `None` are kept.
+#[derive(Debug)]
+pub struct AccountUpdate {
+    pub display_name: Option<String>,
+}
+
+pub const PLAYLIST_NAME_MAX: usize = 256;
+
+#[derive(Debug)]
+pub struct PlaylistCreate {
+    pub name: String,
+    pub description: String,
+    /// the playlist's first entries, in order
+    pub song_ids: Vec<SongId>,
+}
+
+/// Fields left as `None` are kept.
+#[derive(Debug)]
+pub struct PlaylistUpdate {
+    pub name: Option<String>,
+    pub description: Option<String>,
+}
+
+pub const PLAY_QUEUE_MAX: usize = 10000;
+
+#[derive(Debug)]
+pub struct PlayQueueUpdate {
+    pub song_ids: Vec<SongId>,
+    pub current_index: i32,
+    pub position_ms: i64,
+}
+
+pub const SMART_PLAYLIST_LIMIT_MAX: u32 = 1000;
+
+#[derive(Debug)]
+pub struct SmartPlaylistCreate {
+    pub name: String,
+    pub rules: SmartRules,
+}
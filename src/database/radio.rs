@@ -0,0 +1,243 @@
+//! Weighted shuffles for radio sessions.
+//!
+//! A session's sequence is a weighted random permutation of the candidate
+//! songs (Efraimidis-Spirakis: each song draws `u^(1/weight)` and the draws
+//! are sorted), rearranged so that an artist doesn't play twice in a row.
+//! Every draw depends only on the session seed and the song, but playing
+//! the sequence changes the weights, so the sequence is computed once when
+//! the session starts and stored for paging through it.
+
+use std::cmp::Ordering;
+use std::collections::{HashMap, VecDeque};
+
+use ::model::{Song, SongId};
+use super::{RATING_MAX, metadata_values};
+
+/// songs listened to this long before a session started are left out
+pub const RADIO_RECENT_SECS: i64 = 3 * 60 * 60;
+
+/// stored sessions are dropped this long after they started
+pub const RADIO_SESSION_SECS: i64 = 24 * 60 * 60;
+
+/// the most songs a session's sequence holds, however many are candidates
+pub const RADIO_SEQUENCE_MAX: usize = 500;
+
+/// A radio session as handed to clients so they can page through it.
+#[derive(Debug, Clone)]
+pub struct RadioSession {
+    pub seed: u32,
+    /// unix time the session started, recent listens are relative to it
+    pub started_at: i64,
+}
+
+impl RadioSession {
+    pub fn parse(raw: &str) -> Option<RadioSession> {
+        let mut parts = raw.splitn(2, '.');
+        let seed = parts.next().and_then(|p| p.parse().ok());
+        let started_at = parts.next().and_then(|p| p.parse().ok());
+        match (seed, started_at) {
+            (Some(seed), Some(started_at)) => Some(RadioSession {
+                seed: seed,
+                started_at: started_at,
+            }),
+            _ => None,
+        }
+    }
+
+    pub fn to_string(&self) -> String {
+        format!("{}.{}", self.seed, self.started_at)
+    }
+}
+
+/// A stand-in for a random number, fixed for a given seed and song.  The
+/// `shuffle_key` SQL function computes the same.
+pub fn shuffle_key(seed: u32, song_id: &SongId) -> i64 {
+    // splitmix64 finalizer
+    let mut z = ((seed as u64) << 32 ^ song_id.0 as u64).wrapping_add(0x9E37_79B9_7F4A_7C15);
+    z = (z ^ (z >> 30)).wrapping_mul(0xBF58_476D_1CE4_E5B9);
+    z = (z ^ (z >> 27)).wrapping_mul(0x94D0_49BB_1331_11EB);
+    (z ^ (z >> 31)) as i64
+}
+
+/// How likely a song is to come up early.  Songs rated highly or played
+/// often are favoured; unrated, unplayed songs weigh 1.  `song` must carry
+/// the listener's ratings and play counts.
+fn radio_weight(song: &Song) -> f64 {
+    let rating = match song.rating {
+        Some(rating) => 2.0 * rating as f64 / RATING_MAX as f64,
+        None => 1.0,
+    };
+    let plays = 1.0 + 0.25 * (1.0 + song.play_count.unwrap_or(0) as f64).ln();
+    rating * plays
+}
+
+/// The session's sequence of at most `max` of `songs`.  `boosts`
+/// multiplies the weight of individual songs, e.g. by their similarity to
+/// the seed song.
+pub fn radio_sequence(songs: Vec<Song>, boosts: &HashMap<i64, f64>, seed: u32, max: usize) -> Vec<Song> {
+    let mut drawn: Vec<(f64, Song)> = songs.into_iter()
+        .map(|song| {
+            let weight = radio_weight(&song) * boosts.get(&song.id.0).cloned().unwrap_or(1.0);
+            // uniform in (0, 1]
+            let u = ((shuffle_key(seed, &song.id) as u64 >> 11) + 1) as f64 / (1u64 << 53) as f64;
+            (u.powf(1.0 / weight), song)
+        })
+        .collect();
+    drawn.sort_by(|a, b| {
+        match b.0.partial_cmp(&a.0).unwrap_or(Ordering::Equal) {
+            Ordering::Equal => a.1.id.0.cmp(&b.1.id.0),
+            ordering => ordering,
+        }
+    });
+    // spacing out artists looks ahead through what's left, so only the
+    // songs that can make it into the sequence are kept
+    drawn.truncate(max);
+
+    // take the best song not by the previous artist, unless nothing else
+    // is left
+    let mut pending: VecDeque<(Option<String>, Song)> = drawn.into_iter()
+        .map(|(_, song)| (primary_artist(&song), song))
+        .collect();
+    let mut out: Vec<Song> = Vec::with_capacity(pending.len());
+    let mut previous: Option<String> = None;
+    while pending.len() > 0 {
+        let idx = pending.iter()
+            .position(|&(ref artist, _)| previous.is_none() || *artist != previous)
+            .unwrap_or(0);
+        let (artist, song) = pending.remove(idx).unwrap();
+        previous = artist;
+        out.push(song);
+    }
+    out
+}
+
+fn primary_artist(song: &Song) -> Option<String> {
+    song.effective_metadata("ARTIST")
+        .and_then(|artists| metadata_values(artists).into_iter().nth(0))
+        .map(|artist| artist.to_lowercase())
+}
+
+#[cfg(test)]
+mod test {
+    use std::collections::{BTreeMap, HashMap};
+    use std::f64;
+
+    use ::model::{Album, AlbumId, Song, SongId};
+    use super::{RadioSession, primary_artist, radio_sequence};
+
+    fn song(id: i64, artist: &str, rating: Option<i32>) -> Song {
+        let mut metadata = BTreeMap::new();
+        metadata.insert("ARTIST".to_string(), artist.to_string());
+        let album = Album::new(AlbumId(1), None, BTreeMap::new());
+        let mut song = Song::new(SongId(id), format!("blob{}", id), 180000, 1, id as i16, metadata, album);
+        song.rating = rating;
+        song
+    }
+
+    fn ids(songs: &[Song]) -> Vec<i64> {
+        songs.iter().map(|song| song.id.0).collect()
+    }
+
+    fn position(songs: &[Song], id: i64) -> usize {
+        songs.iter().position(|song| song.id.0 == id).unwrap()
+    }
+
+    #[test]
+    fn test_session_token() {
+        let session = RadioSession { seed: 4000000000, started_at: 1500000000 };
+        let raw = session.to_string();
+        assert_eq!(raw, "4000000000.1500000000");
+        let parsed = RadioSession::parse(&raw).unwrap();
+        assert_eq!(parsed.seed, session.seed);
+        assert_eq!(parsed.started_at, session.started_at);
+
+        assert!(RadioSession::parse("12").is_none());
+        assert!(RadioSession::parse("12.").is_none());
+        assert!(RadioSession::parse("x.1500000000").is_none());
+        assert!(RadioSession::parse("4294967296.1500000000").is_none());
+        assert!(RadioSession::parse("12.1500000000.3").is_none());
+    }
+
+    #[test]
+    fn test_deterministic() {
+        let songs: Vec<Song> = (1..41).map(|id| song(id, &format!("artist {}", id), None)).collect();
+        let first = radio_sequence(songs.clone(), &HashMap::new(), 7, 100);
+        let again = radio_sequence(songs.clone(), &HashMap::new(), 7, 100);
+        let other = radio_sequence(songs.clone(), &HashMap::new(), 8, 100);
+        assert_eq!(ids(&first), ids(&again));
+        assert!(ids(&first) != ids(&other));
+
+        let mut sorted = ids(&first);
+        sorted.sort();
+        assert_eq!(sorted, (1..41).collect::<Vec<i64>>());
+    }
+
+    #[test]
+    fn test_weighted_draw() {
+        let songs: Vec<Song> = (1..21)
+            .map(|id| song(id, &format!("artist {}", id), if id == 2 { Some(5) } else { Some(1) }))
+            .collect();
+        let mut boosts = HashMap::new();
+        boosts.insert(7, 1000.0);
+
+        let mut boosted_first = 0;
+        let mut rated_earlier = 0;
+        for seed in 0..200 {
+            let sequence = radio_sequence(songs.clone(), &boosts, seed, 100);
+            if sequence[0].id.0 == 7 {
+                boosted_first += 1;
+            }
+            if position(&sequence, 2) < position(&sequence, 3) {
+                rated_earlier += 1;
+            }
+        }
+        assert!(boosted_first >= 190, "boosted song first {} times", boosted_first);
+        assert!(rated_earlier >= 150, "highly rated song earlier {} times", rated_earlier);
+    }
+
+    #[test]
+    fn test_tie_break() {
+        // infinitely boosted songs all draw 1.0
+        let songs: Vec<Song> = vec![5, 3, 9, 1].into_iter()
+            .map(|id| song(id, &format!("artist {}", id), None))
+            .collect();
+        let boosts: HashMap<i64, f64> = vec![1, 3, 5, 9].into_iter().map(|id| (id, f64::INFINITY)).collect();
+        for seed in 0..10 {
+            assert_eq!(ids(&radio_sequence(songs.clone(), &boosts, seed, 100)), vec![1, 3, 5, 9]);
+        }
+
+        // and a NaN draw doesn't panic
+        let boosts: HashMap<i64, f64> = vec![(3, f64::NAN)].into_iter().collect();
+        assert_eq!(radio_sequence(songs, &boosts, 1, 100).len(), 4);
+    }
+
+    #[test]
+    fn test_artist_spacing() {
+        let mut songs: Vec<Song> = (1..13).map(|id| song(id, "Prolific", None)).collect();
+        songs.extend((13..17).map(|id| song(id, "Rare", None)));
+        songs.push(song(17, "PROLIFIC", None));
+        songs.push(song(18, "Other; Prolific", None));
+
+        for seed in 0..50 {
+            let sequence = radio_sequence(songs.clone(), &HashMap::new(), seed, 100);
+            assert_eq!(sequence.len(), songs.len());
+            let artists: Vec<Option<String>> = sequence.iter().map(primary_artist).collect();
+            for i in 1..artists.len() {
+                // an artist only plays twice in a row once nobody else is left
+                if artists[i] == artists[i - 1] {
+                    assert!(artists[i..].iter().all(|a| *a == artists[i]),
+                        "seed {}: {:?}", seed, artists);
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn test_max() {
+        let songs: Vec<Song> = (1..31).map(|id| song(id, &format!("artist {}", id), None)).collect();
+        let all = radio_sequence(songs.clone(), &HashMap::new(), 3, 100);
+        let capped = radio_sequence(songs.clone(), &HashMap::new(), 3, 5);
+        assert_eq!(ids(&capped), ids(&all[..5]));
+        assert_eq!(radio_sequence(songs, &HashMap::new(), 3, 0).len(), 0);
+    }
+}
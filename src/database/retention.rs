@@ -0,0 +1,35 @@
+//! Deletes listens once they are past the configured retention.
+
+use std::io;
+use std::thread;
+use std::time::Duration;
+
+use ::util::unix_now;
+use super::drivers;
+
+/// How often old listens are deleted.  History queries already skip them
+/// in between, see `HistoryConfig::query_from`.
+const PRUNE_INTERVAL_SECS: u64 = 60 * 60;
+
+/// Deletes listens older than `retention_secs` every `PRUNE_INTERVAL_SECS`,
+/// for as long as the process runs.  Failures are logged and retried on the
+/// next round.
+pub fn spawn_pruning(url: String, retention_secs: i64) {
+    let spawned = thread::Builder::new()
+        .name("history-pruning".into())
+        .spawn(move || {
+            loop {
+                if let Err(err) = prune(&url, unix_now() - retention_secs) {
+                    println!("error pruning listening history: {}", err);
+                }
+                thread::sleep(Duration::from_secs(PRUNE_INTERVAL_SECS));
+            }
+        });
+    if let Err(err) = spawned {
+        println!("error starting history pruning: {}", err);
+    }
+}
+
+fn prune(url: &str, before: i64) -> io::Result<()> {
+    drivers::get_driver(url)?.prune_history(before)
+}
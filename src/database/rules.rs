@@ -0,0 +1,262 @@
+//! Compiles smart playlist rules into song queries.
+//!
+//! ```json
+//! {"rules": [
+//!     {"field": "genre", "op": "=", "value": "Jazz"},
+//!     {"field": "rating", "op": ">=", "value": 4},
+//!     {"field": "last_played", "op": "not_within_days", "value": 30}
+//!  ],
+//!  "sort": "random",
+//!  "limit": 50}
+//! ```
+
+use serde_json::Value;
+
+use ::model::{SmartRules, SmartRule};
+use super::{
+    AccountId,
+    Comparison,
+    SongFilter,
+    SongOrder,
+    SongQuery,
+    SongSortKey,
+    RATING_MIN,
+    RATING_MAX,
+    SMART_PLAYLIST_LIMIT_MAX,
+    check_metadata_field,
+};
+
+const SECONDS_PER_DAY: i64 = 24 * 60 * 60;
+
+/// Builds the query a smart playlist stands for, as evaluated at `now`.
+/// `seed` picks the shuffle for `random` ordering.
+pub fn compile_rules(account: &AccountId, rules: &SmartRules, now: i64, seed: u32) -> Result<SongQuery, String> {
+    let mut query = SongQuery::all();
+    query.account = Some(account.clone());
+
+    for (idx, rule) in rules.rules.iter().enumerate() {
+        let filter = compile_rule(rule, now)
+            .map_err(|msg| format!("rule {} ({}): {}", idx + 1, rule.field, msg))?;
+        query.filters.push(filter);
+    }
+
+    if let Some(ref sort) = rules.sort {
+        for key in sort.split(',').filter(|k| k.len() > 0) {
+            let (descending, name) = if key.starts_with('-') {
+                (true, &key[1..])
+            } else {
+                (false, key)
+            };
+            let key = match name {
+                "rating" => SongSortKey::Rating,
+                "play_count" => SongSortKey::PlayCount,
+                "random" if !descending => SongSortKey::Random(seed),
+                _ => return Err(format!("unknown sort key {:?}", key)),
+            };
+            query.order.push(SongOrder {
+                key: key,
+                descending: descending,
+            });
+        }
+    }
+
+    if let Some(limit) = rules.limit {
+        if limit < 1 || SMART_PLAYLIST_LIMIT_MAX < limit {
+            return Err(format!("limit must be between 1 and {}", SMART_PLAYLIST_LIMIT_MAX));
+        }
+        query.limit = Some(limit);
+    }
+
+    Ok(query)
+}
+
+fn compile_rule(rule: &SmartRule, now: i64) -> Result<SongFilter, String> {
+    match &rule.field[..] {
+        "rating" => {
+            let value = int_value(&rule.value)?;
+            if value < RATING_MIN as i64 || (RATING_MAX as i64) < value {
+                return Err(format!("ratings range from {} to {}", RATING_MIN, RATING_MAX));
+            }
+            Ok(SongFilter::Rating(comparison(&rule.op)?, value as i32))
+        },
+        "play_count" => {
+            let value = int_value(&rule.value)?;
+            if value < 0 || (i32::max_value() as i64) < value {
+                return Err(format!("play counts range from 0 to {}", i32::max_value()));
+            }
+            Ok(SongFilter::PlayCount(comparison(&rule.op)?, value as i32))
+        },
+        "last_played" => {
+            let days = int_value(&rule.value)?;
+            if days < 1 || 100 * 365 < days {
+                return Err("the number of days must be between 1 and 36500".into());
+            }
+            let since = now - days * SECONDS_PER_DAY;
+            match &rule.op[..] {
+                "within_days" => Ok(SongFilter::PlayedSince(since)),
+                "not_within_days" => Ok(SongFilter::NotPlayedSince(since)),
+                other => Err(format!("unknown operator {:?}, expected within_days or not_within_days", other)),
+            }
+        },
+        field => {
+            let value = match rule.value.as_str() {
+                Some(value) => value,
+                None => return Err("expected a string value".into()),
+            };
+            check_metadata_field(field, Some(value))?;
+            Ok(SongFilter::Metadata(field.to_uppercase(), comparison(&rule.op)?, value.into()))
+        },
+    }
+}
+
+fn comparison(op: &str) -> Result<Comparison, String> {
+    match op {
+        "=" => Ok(Comparison::Eq),
+        "!=" => Ok(Comparison::Ne),
+        "<" => Ok(Comparison::Lt),
+        "<=" => Ok(Comparison::Le),
+        ">" => Ok(Comparison::Gt),
+        ">=" => Ok(Comparison::Ge),
+        other => Err(format!("unknown operator {:?}", other)),
+    }
+}
+
+fn int_value(value: &Value) -> Result<i64, String> {
+    value.as_i64().ok_or_else(|| "expected an integer value".into())
+}
+
+#[cfg(test)]
+mod test {
+    use serde_json;
+    use uuid::Uuid;
+
+    use ::model::SmartRules;
+    use super::compile_rules;
+    use ::database::{AccountId, Comparison, SongFilter, SongQuery, SongSortKey};
+
+    const NOW: i64 = 1_500_000_000;
+
+    fn compile(json: &str) -> Result<SongQuery, String> {
+        let rules: SmartRules = serde_json::from_str(json).unwrap();
+        compile_rules(&AccountId::from_user_id(Uuid::nil()), &rules, NOW, 7)
+    }
+
+    fn error(json: &str) -> String {
+        match compile(json) {
+            Ok(_) => panic!("expected {} to be rejected", json),
+            Err(msg) => msg,
+        }
+    }
+
+    fn filter(rule: &str) -> Result<SongFilter, String> {
+        compile(&format!("{{\"rules\": [{}]}}", rule))
+            .map(|mut query| query.filters.remove(0))
+    }
+
+    #[test]
+    fn test_empty() {
+        let query = compile("{}").unwrap();
+        assert_eq!(query.account.map(|a| a.get_user_id()), Some(Uuid::nil()));
+        assert!(query.filters.is_empty());
+        assert!(query.order.is_empty());
+        assert_eq!(query.limit, None);
+    }
+
+    #[test]
+    fn test_rating() {
+        match filter(r#"{"field": "rating", "op": ">=", "value": 4}"#) {
+            Ok(SongFilter::Rating(Comparison::Ge, 4)) => (),
+            other => panic!("expected a rating filter, got {:?}", other),
+        }
+        assert_eq!(filter(r#"{"field": "rating", "op": "=", "value": 0}"#).unwrap_err(),
+            "rule 1 (rating): ratings range from 1 to 5");
+        assert_eq!(filter(r#"{"field": "rating", "op": "=", "value": 6}"#).unwrap_err(),
+            "rule 1 (rating): ratings range from 1 to 5");
+        assert_eq!(filter(r#"{"field": "rating", "op": "=", "value": "4"}"#).unwrap_err(),
+            "rule 1 (rating): expected an integer value");
+        assert_eq!(filter(r#"{"field": "rating", "op": "~", "value": 4}"#).unwrap_err(),
+            "rule 1 (rating): unknown operator \"~\"");
+    }
+
+    #[test]
+    fn test_play_count() {
+        match filter(r#"{"field": "play_count", "op": "<", "value": 0}"#) {
+            Ok(SongFilter::PlayCount(Comparison::Lt, 0)) => (),
+            other => panic!("expected a play count filter, got {:?}", other),
+        }
+        assert_eq!(filter(r#"{"field": "play_count", "op": ">", "value": -1}"#).unwrap_err(),
+            "rule 1 (play_count): play counts range from 0 to 2147483647");
+        assert_eq!(filter(r#"{"field": "play_count", "op": ">", "value": 2147483648}"#).unwrap_err(),
+            "rule 1 (play_count): play counts range from 0 to 2147483647");
+        assert_eq!(filter(r#"{"field": "play_count", "op": ">", "value": 1.5}"#).unwrap_err(),
+            "rule 1 (play_count): expected an integer value");
+    }
+
+    #[test]
+    fn test_last_played() {
+        match filter(r#"{"field": "last_played", "op": "within_days", "value": 30}"#) {
+            Ok(SongFilter::PlayedSince(since)) => assert_eq!(since, NOW - 30 * 24 * 60 * 60),
+            other => panic!("expected a played since filter, got {:?}", other),
+        }
+        match filter(r#"{"field": "last_played", "op": "not_within_days", "value": 1}"#) {
+            Ok(SongFilter::NotPlayedSince(since)) => assert_eq!(since, NOW - 24 * 60 * 60),
+            other => panic!("expected a not played since filter, got {:?}", other),
+        }
+        assert_eq!(filter(r#"{"field": "last_played", "op": "within_days", "value": 0}"#).unwrap_err(),
+            "rule 1 (last_played): the number of days must be between 1 and 36500");
+        assert_eq!(filter(r#"{"field": "last_played", "op": "within_days", "value": 36501}"#).unwrap_err(),
+            "rule 1 (last_played): the number of days must be between 1 and 36500");
+        assert_eq!(filter(r#"{"field": "last_played", "op": "<", "value": 30}"#).unwrap_err(),
+            "rule 1 (last_played): unknown operator \"<\", expected within_days or not_within_days");
+    }
+
+    #[test]
+    fn test_metadata() {
+        match filter(r#"{"field": "genre", "op": "!=", "value": "Jazz"}"#) {
+            Ok(SongFilter::Metadata(ref field, Comparison::Ne, ref value)) => {
+                assert_eq!(field, "GENRE");
+                assert_eq!(value, "Jazz");
+            },
+            other => panic!("expected a metadata filter, got {:?}", other),
+        }
+        assert_eq!(filter(r#"{"field": "genre", "op": "=", "value": 1}"#).unwrap_err(),
+            "rule 1 (genre): expected a string value");
+        assert_eq!(filter(r#"{"field": "a=b", "op": "=", "value": "x"}"#).unwrap_err(),
+            "rule 1 (a=b): field name \"a=b\" contains invalid characters");
+        assert!(filter(r#"{"field": "", "op": "=", "value": "x"}"#).is_err());
+        assert!(filter(&format!(r#"{{"field": "title", "op": "=", "value": "{}"}}"#, "x".repeat(257))).is_err());
+    }
+
+    #[test]
+    fn test_rule_numbering() {
+        let err = error(r#"{"rules": [
+            {"field": "genre", "op": "=", "value": "Jazz"},
+            {"field": "rating", "op": "=", "value": 9}
+        ]}"#);
+        assert_eq!(err, "rule 2 (rating): ratings range from 1 to 5");
+    }
+
+    #[test]
+    fn test_sort() {
+        let query = compile(r#"{"sort": "-rating,play_count,random"}"#).unwrap();
+        let keys: Vec<String> = query.order.iter()
+            .map(|o| format!("{}{:?}", if o.descending { "-" } else { "" }, o.key))
+            .collect();
+        assert_eq!(keys, vec!["-Rating", "PlayCount", "Random(7)"]);
+        match query.order[2].key {
+            SongSortKey::Random(7) => (),
+            ref other => panic!("expected the seed to be used, got {:?}", other),
+        }
+        assert_eq!(error(r#"{"sort": "-random"}"#), "unknown sort key \"-random\"");
+        assert_eq!(error(r#"{"sort": "title"}"#), "unknown sort key \"title\"");
+        assert!(compile(r#"{"sort": ""}"#).unwrap().order.is_empty());
+    }
+
+    #[test]
+    fn test_limit() {
+        assert_eq!(compile(r#"{"limit": 50}"#).unwrap().limit, Some(50));
+        assert_eq!(compile(r#"{"limit": 1000}"#).unwrap().limit, Some(1000));
+        assert_eq!(error(r#"{"limit": 0}"#), "limit must be between 1 and 1000");
+        assert_eq!(error(r#"{"limit": 1001}"#), "limit must be between 1 and 1000");
+    }
+}
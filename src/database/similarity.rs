@@ -0,0 +1,220 @@
+//! Precomputed item-item similarity between songs.
+//!
+//! Two songs are similar in proportion to how often they are listened to in
+//! the same session, by any account, relative to how often each of them is
+//! listened to at all (the cosine of their session vectors).  Songs with too
+//! little listening data are padded with songs sharing an artist or genre.
+//! Co-listening scores lie in (1, 2] and metadata scores below 1, so songs
+//! people actually played together always rank first.
+
+use std::cmp::Ordering;
+use std::collections::{BTreeMap, HashMap, HashSet};
+use std::io;
+use std::thread;
+use std::time::Duration;
+
+use uuid::Uuid;
+
+use ::model::{ArtistId, SongId};
+use super::drivers;
+
+/// the fewest sessions two songs must share to count as co-listened
+const SHARED_SESSIONS_MIN: u32 = 2;
+
+/// songs beyond this many in one session are ignored, so that a radio left
+/// running overnight doesn't relate everything to everything
+const SESSION_SONGS_MAX: usize = 200;
+
+const SHARED_ARTIST_SCORE: f64 = 0.5;
+const SHARED_GENRE_SCORE: f64 = 0.25;
+
+#[derive(Debug, Clone)]
+pub struct SimilarityParams {
+    /// a pause longer than this between two listens starts a new session
+    pub session_gap_secs: i64,
+    /// how many similar songs are kept for each song
+    pub neighbours: usize,
+    /// only listens from this many days back are taken into account
+    pub window_days: u32,
+}
+
+impl SimilarityParams {
+    /// Unix time of the oldest listen to take into account.
+    pub fn listens_since(&self, now: i64) -> i64 {
+        now - self.window_days as i64 * 24 * 60 * 60
+    }
+}
+
+#[derive(Debug)]
+pub struct SessionListen {
+    pub account_id: Uuid,
+    pub song_id: SongId,
+    pub started_at: i64,
+}
+
+#[derive(Debug)]
+pub struct SongTraits {
+    pub song_id: SongId,
+    pub artist_ids: Vec<ArtistId>,
+    /// lowercased
+    pub genres: Vec<String>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct SongSimilarity {
+    pub song_id: SongId,
+    pub similar_song_id: SongId,
+    pub score: f64,
+}
+
+/// Scores every song against its most similar ones.  `listens` must be
+/// ordered by account and then start time.  Listens of songs missing from
+/// `songs` are ignored.
+pub fn compute_similarity(listens: &[SessionListen], songs: &[SongTraits], params: &SimilarityParams)
+    -> Vec<SongSimilarity>
+{
+    let known: HashSet<i64> = songs.iter().map(|s| s.song_id.0).collect();
+
+    let mut sessions: Vec<Vec<i64>> = Vec::new();
+    let mut session: Vec<i64> = Vec::new();
+    let mut previous: Option<(Uuid, i64)> = None;
+    for listen in listens.iter().filter(|l| known.contains(&l.song_id.0)) {
+        let continues = match previous {
+            Some((account_id, at)) => {
+                account_id == listen.account_id && listen.started_at - at <= params.session_gap_secs
+            },
+            None => false,
+        };
+        if !continues && session.len() > 0 {
+            sessions.push(session);
+            session = Vec::new();
+        }
+        if session.len() < SESSION_SONGS_MAX && !session.contains(&listen.song_id.0) {
+            session.push(listen.song_id.0);
+        }
+        previous = Some((listen.account_id, listen.started_at));
+    }
+    if session.len() > 0 {
+        sessions.push(session);
+    }
+
+    let mut session_counts: HashMap<i64, u32> = HashMap::new();
+    let mut shared_counts: HashMap<(i64, i64), u32> = HashMap::new();
+    for session in sessions.iter() {
+        for (idx, &a) in session.iter().enumerate() {
+            *session_counts.entry(a).or_insert(0) += 1;
+            for &b in session[idx + 1..].iter() {
+                let pair = if a < b { (a, b) } else { (b, a) };
+                *shared_counts.entry(pair).or_insert(0) += 1;
+            }
+        }
+    }
+
+    let mut scores: BTreeMap<i64, HashMap<i64, f64>> = BTreeMap::new();
+    for (&(a, b), &shared) in shared_counts.iter() {
+        if shared < SHARED_SESSIONS_MIN {
+            continue;
+        }
+        let cosine = shared as f64 / (session_counts[&a] as f64 * session_counts[&b] as f64).sqrt();
+        scores.entry(a).or_insert_with(HashMap::new).insert(b, 1.0 + cosine);
+        scores.entry(b).or_insert_with(HashMap::new).insert(a, 1.0 + cosine);
+    }
+
+    let mut by_artist: BTreeMap<i64, Vec<&SongTraits>> = BTreeMap::new();
+    let mut by_genre: BTreeMap<&str, Vec<&SongTraits>> = BTreeMap::new();
+    for song in songs.iter() {
+        for artist_id in song.artist_ids.iter() {
+            by_artist.entry(artist_id.0).or_insert_with(Vec::new).push(song);
+        }
+        for genre in song.genres.iter() {
+            by_genre.entry(&genre[..]).or_insert_with(Vec::new).push(song);
+        }
+    }
+
+    let mut out = Vec::new();
+    for song in songs.iter() {
+        let mut similar = scores.remove(&song.song_id.0).unwrap_or_else(HashMap::new);
+
+        if similar.len() < params.neighbours {
+            // only songs not scored yet take up the remaining places
+            let mut fallback: HashMap<i64, f64> = HashMap::new();
+            // prolific artists have as many candidates as genres do, so
+            // these are capped the same way
+            for artist_id in song.artist_ids.iter() {
+                for other in by_artist[&artist_id.0].iter()
+                    .filter(|o| o.song_id != song.song_id && !similar.contains_key(&o.song_id.0)) {
+                    if params.neighbours <= similar.len() + fallback.len() {
+                        break;
+                    }
+                    let shares_genre = other.genres.iter().any(|g| song.genres.contains(g));
+                    let score = if shares_genre {
+                        SHARED_ARTIST_SCORE + SHARED_GENRE_SCORE
+                    } else {
+                        SHARED_ARTIST_SCORE
+                    };
+                    fallback.insert(other.song_id.0, score);
+                }
+            }
+            // genres can be shared by much of the catalog, only take as
+            // many as are needed, lowest ids first
+            for genre in song.genres.iter() {
+                for other in by_genre[&genre[..]].iter()
+                    .filter(|o| o.song_id != song.song_id && !similar.contains_key(&o.song_id.0)) {
+                    if params.neighbours <= similar.len() + fallback.len() {
+                        break;
+                    }
+                    fallback.entry(other.song_id.0).or_insert(SHARED_GENRE_SCORE);
+                }
+            }
+            for (other, score) in fallback.into_iter() {
+                similar.entry(other).or_insert(score);
+            }
+        }
+
+        let mut similar: Vec<(i64, f64)> = similar.into_iter().collect();
+        similar.sort_by(|a, b| {
+            match b.1.partial_cmp(&a.1).unwrap_or(Ordering::Equal) {
+                Ordering::Equal => a.0.cmp(&b.0),
+                ordering => ordering,
+            }
+        });
+        similar.truncate(params.neighbours);
+
+        for (other, score) in similar.into_iter() {
+            out.push(SongSimilarity {
+                song_id: song.song_id.clone(),
+                similar_song_id: SongId(other),
+                score: score,
+            });
+        }
+    }
+    out
+}
+
+/// Recomputes the similarity table every `interval`, for as long as the
+/// process runs.  Failures are logged and retried on the next round.
+/// Nothing is started for drivers that can't take a second writer.
+pub fn spawn_refresh(url: String, params: SimilarityParams, interval: Duration) {
+    if !drivers::allows_concurrent_writers(&url) {
+        println!("not refreshing song similarity in the background, the driver doesn't allow it");
+        return;
+    }
+    let spawned = thread::Builder::new()
+        .name("similarity-refresh".into())
+        .spawn(move || {
+            loop {
+                match refresh(&url, &params) {
+                    Ok(count) => println!("refreshed song similarity, {} pairs", count),
+                    Err(err) => println!("error refreshing song similarity: {}", err),
+                }
+                thread::sleep(interval);
+            }
+        });
+    if let Err(err) = spawned {
+        println!("error starting similarity refresh: {}", err);
+    }
+}
+
+fn refresh(url: &str, params: &SimilarityParams) -> io::Result<usize> {
+    drivers::get_driver(url)?.refresh_song_similarity(params)
+}
@@ -0,0 +1,129 @@
+//! Renders lists of songs as playlists for other players.
+
+use rocket::http::ContentType;
+
+use ::rpc::{ExportResponse, ExportedTrack};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ExportFormat {
+    /// extended M3U, UTF-8
+    M3u8,
+    Xspf,
+    Json,
+}
+
+impl ExportFormat {
+    pub fn parse(raw: &str) -> Option<ExportFormat> {
+        match raw {
+            "m3u8" => Some(ExportFormat::M3u8),
+            "xspf" => Some(ExportFormat::Xspf),
+            "json" => Some(ExportFormat::Json),
+            _ => None,
+        }
+    }
+
+    pub fn extension(&self) -> &'static str {
+        match *self {
+            ExportFormat::M3u8 => "m3u8",
+            ExportFormat::Xspf => "xspf",
+            ExportFormat::Json => "json",
+        }
+    }
+
+    pub fn content_type(&self) -> ContentType {
+        match *self {
+            ExportFormat::M3u8 => ContentType::new("audio", "x-mpegurl"),
+            ExportFormat::Xspf => ContentType::new("application", "xspf+xml"),
+            ExportFormat::Json => ContentType::JSON,
+        }
+    }
+}
+
+pub fn render(format: ExportFormat, export: &ExportResponse) -> Vec<u8> {
+    match format {
+        ExportFormat::M3u8 => render_m3u8(export).into_bytes(),
+        ExportFormat::Xspf => render_xspf(export).into_bytes(),
+        ExportFormat::Json => ::serde_json::to_vec_pretty(export).unwrap(),
+    }
+}
+
+fn render_m3u8(export: &ExportResponse) -> String {
+    let mut out = String::from("#EXTM3U\n");
+    out.push_str(&format!("#PLAYLIST:{}\n", single_line(&export.title)));
+    for track in export.tracks.iter() {
+        let seconds = (track.song.length_ms + 500) / 1000;
+        let label = match artist(track) {
+            Some(artist) => format!("{} - {}", artist, title(track)),
+            None => title(track),
+        };
+        out.push_str(&format!("#EXTINF:{},{}\n", seconds, single_line(&label)));
+        if let Some(album) = album(track) {
+            out.push_str(&format!("#EXTALB:{}\n", single_line(&album)));
+        }
+        out.push_str(&track.location);
+        out.push('\n');
+    }
+    out
+}
+
+fn render_xspf(export: &ExportResponse) -> String {
+    let mut out = String::new();
+    out.push_str("<?xml version=\"1.0\" encoding=\"UTF-8\"?>\n");
+    out.push_str("<playlist version=\"1\" xmlns=\"http://xspf.org/ns/0/\">\n");
+    out.push_str(&format!("  <title>{}</title>\n", xml_escape(&export.title)));
+    out.push_str("  <trackList>\n");
+    for track in export.tracks.iter() {
+        out.push_str("    <track>\n");
+        out.push_str(&format!("      <location>{}</location>\n", xml_escape(&track.location)));
+        out.push_str(&format!("      <title>{}</title>\n", xml_escape(&title(track))));
+        if let Some(artist) = artist(track) {
+            out.push_str(&format!("      <creator>{}</creator>\n", xml_escape(&artist)));
+        }
+        if let Some(album) = album(track) {
+            out.push_str(&format!("      <album>{}</album>\n", xml_escape(&album)));
+        }
+        out.push_str(&format!("      <trackNum>{}</trackNum>\n", track.song.track_no));
+        out.push_str(&format!("      <duration>{}</duration>\n", track.song.length_ms));
+        out.push_str("    </track>\n");
+    }
+    out.push_str("  </trackList>\n");
+    out.push_str("</playlist>\n");
+    out
+}
+
+fn title(track: &ExportedTrack) -> String {
+    match track.song.metadata.get("TITLE") {
+        Some(title) => title.clone(),
+        None => format!("Track {}", track.song.track_no),
+    }
+}
+
+fn artist(track: &ExportedTrack) -> Option<String> {
+    track.song.effective_metadata("ARTIST").cloned()
+}
+
+fn album(track: &ExportedTrack) -> Option<String> {
+    track.song.effective_metadata("ALBUM").cloned()
+}
+
+/// M3U is line based, so values can't contain line breaks.
+fn single_line(value: &str) -> String {
+    value.replace(|c: char| c == '\r' || c == '\n', " ")
+}
+
+fn xml_escape(value: &str) -> String {
+    let mut out = String::with_capacity(value.len());
+    for ch in value.chars() {
+        match ch {
+            '&' => out.push_str("&amp;"),
+            '<' => out.push_str("&lt;"),
+            '>' => out.push_str("&gt;"),
+            '"' => out.push_str("&quot;"),
+            '\'' => out.push_str("&apos;"),
+            // not allowed in XML 1.0 at all
+            '\u{0}'...'\u{8}' | '\u{b}' | '\u{c}' | '\u{e}'...'\u{1f}' => {},
+            ch => out.push(ch),
+        }
+    }
+    out
+}
@@ -13,7 +13,7 @@ use super::{
     AuthErrorKind,
 };
 
-const GOOGLE_AUTH_PROVIDER: &'static Provider = &Provider {
+pub const GOOGLE_AUTH_PROVIDER: &'static Provider = &Provider {
     id: "ba946dd1-94a0-4eae-8260-7bb1f127f286",
     name: "google",
 };
@@ -21,6 +21,11 @@ impl Provider {
     }
 }
 
+/// Every provider accounts can be linked with.
+pub const PROVIDERS: &'static [&'static Provider] = &[
+    google::GOOGLE_AUTH_PROVIDER,
+];
+
 #[derive(Debug)]
 pub enum AuthErrorKind {
     RemoteServiceError,
@@ -0,0 +1,498 @@
+//! Reads playlists written by other players and finds their entries in the
+//! library.
+
+use std::collections::HashMap;
+
+use ::database::{metadata_values, normalize_text};
+use ::model::{Song, SongId};
+
+/// how far an entry's length may be off for a metadata match
+const DURATION_TOLERANCE_MS: i32 = 5000;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ImportFormat {
+    /// M3U and extended M3U, in any encoding close enough to UTF-8
+    M3u,
+    Pls,
+    Xspf,
+}
+
+impl ImportFormat {
+    pub fn parse(raw: &str) -> Option<ImportFormat> {
+        match raw {
+            "m3u" | "m3u8" => Some(ImportFormat::M3u),
+            "pls" => Some(ImportFormat::Pls),
+            "xspf" => Some(ImportFormat::Xspf),
+            _ => None,
+        }
+    }
+
+    /// Guesses the format from the first non-blank line.
+    pub fn sniff(text: &str) -> ImportFormat {
+        let head = text.trim_left_matches('\u{feff}').trim_left();
+        if head.starts_with("<") {
+            ImportFormat::Xspf
+        } else if head.to_lowercase().starts_with("[playlist]") {
+            ImportFormat::Pls
+        } else {
+            ImportFormat::M3u
+        }
+    }
+}
+
+#[derive(Debug, Default)]
+pub struct ImportedPlaylist {
+    pub title: Option<String>,
+    pub entries: Vec<ImportEntry>,
+}
+
+#[derive(Debug, Default, Clone)]
+pub struct ImportEntry {
+    pub location: String,
+    pub title: Option<String>,
+    pub artist: Option<String>,
+    pub duration_ms: Option<i32>,
+}
+
+pub fn parse(format: ImportFormat, text: &str) -> Result<ImportedPlaylist, String> {
+    let text = text.trim_left_matches('\u{feff}');
+    match format {
+        ImportFormat::M3u => Ok(parse_m3u(text)),
+        ImportFormat::Pls => parse_pls(text),
+        ImportFormat::Xspf => parse_xspf(text),
+    }
+}
+
+fn parse_m3u(text: &str) -> ImportedPlaylist {
+    let mut out = ImportedPlaylist::default();
+    let mut pending = ImportEntry::default();
+    for line in text.lines().map(|l| l.trim()).filter(|l| l.len() > 0) {
+        if line.starts_with("#EXTINF:") {
+            let mut parts = line["#EXTINF:".len()..].splitn(2, ',');
+            let seconds = parts.next().and_then(|s| s.trim().parse::<i32>().ok());
+            let (artist, title) = split_label(parts.next().unwrap_or(""));
+            pending.duration_ms = seconds.and_then(seconds_to_ms);
+            pending.artist = artist;
+            pending.title = title;
+        } else if line.starts_with("#PLAYLIST:") {
+            out.title = Some(line["#PLAYLIST:".len()..].trim().to_string());
+        } else if !line.starts_with('#') {
+            pending.location = line.to_string();
+            out.entries.push(pending);
+            pending = ImportEntry::default();
+        }
+    }
+    out
+}
+
+/// Lengths are given in whole seconds, and zero or less for "unknown".
+fn seconds_to_ms(seconds: i32) -> Option<i32> {
+    if 0 < seconds {
+        seconds.checked_mul(1000)
+    } else {
+        None
+    }
+}
+
+/// `#EXTINF` labels are conventionally "Artist - Title".
+fn split_label(label: &str) -> (Option<String>, Option<String>) {
+    let label = label.trim();
+    if label.len() == 0 {
+        return (None, None);
+    }
+    match label.find(" - ") {
+        Some(idx) => (Some(label[..idx].trim().to_string()), Some(label[idx + 3..].trim().to_string())),
+        None => (None, Some(label.to_string())),
+    }
+}
+
+fn parse_pls(text: &str) -> Result<ImportedPlaylist, String> {
+    let mut entries: HashMap<u32, ImportEntry> = HashMap::new();
+    for line in text.lines().map(|l| l.trim()) {
+        let mut parts = line.splitn(2, '=');
+        let (key, value) = match (parts.next(), parts.next()) {
+            (Some(key), Some(value)) => (key.trim().to_lowercase(), value.trim()),
+            _ => continue,
+        };
+        let split = match key.find(|c: char| c.is_digit(10)) {
+            Some(split) => split,
+            None => continue,
+        };
+        let index: u32 = match key[split..].parse() {
+            Ok(index) => index,
+            Err(_) => continue,
+        };
+        let entry = entries.entry(index).or_insert_with(ImportEntry::default);
+        match &key[..split] {
+            "file" => entry.location = value.to_string(),
+            "title" => {
+                let (artist, title) = split_label(value);
+                entry.artist = artist;
+                entry.title = title;
+            },
+            "length" => {
+                entry.duration_ms = value.parse::<i32>().ok().and_then(seconds_to_ms);
+            },
+            _ => {},
+        }
+    }
+
+    let mut indices: Vec<u32> = entries.keys().cloned().collect();
+    indices.sort();
+    let mut out = ImportedPlaylist::default();
+    for index in indices.into_iter() {
+        let entry = entries.remove(&index).unwrap();
+        if entry.location.len() == 0 {
+            return Err(format!("entry {} has no File{} line", index, index));
+        }
+        out.entries.push(entry);
+    }
+    Ok(out)
+}
+
+fn parse_xspf(text: &str) -> Result<ImportedPlaylist, String> {
+    let track_list = match element(text, "trackList") {
+        Some(track_list) => track_list,
+        None => return Err("no <trackList> element".into()),
+    };
+    let mut out = ImportedPlaylist::default();
+    // the playlist's own title is the one before the track list
+    out.title = element(&text[..text.find("<trackList").unwrap_or(0)], "title").map(xml_unescape);
+
+    let mut rest = track_list;
+    while let Some(track) = element(rest, "track") {
+        let end = rest.find("</track>").map(|e| e + "</track>".len()).unwrap_or(rest.len());
+        rest = &rest[end..];
+
+        let location = match element(track, "location") {
+            Some(location) => xml_unescape(location),
+            None => continue,
+        };
+        out.entries.push(ImportEntry {
+            location: location,
+            title: element(track, "title").map(xml_unescape),
+            artist: element(track, "creator").map(xml_unescape),
+            duration_ms: element(track, "duration").and_then(|d| d.trim().parse().ok()),
+        });
+    }
+    Ok(out)
+}
+
+/// The contents of the first `<name>` element, without looking at nesting.
+/// Good enough for XSPF, whose elements of interest never nest.
+fn element<'a>(text: &'a str, name: &str) -> Option<&'a str> {
+    let open = format!("<{}", name);
+    let close = format!("</{}>", name);
+    let mut search = 0;
+    while let Some(found) = text[search..].find(&open) {
+        let start = search + found + open.len();
+        // make sure we didn't find a longer name with the same prefix
+        match text[start..].chars().next() {
+            Some('>') | Some(' ') | Some('\t') | Some('\r') | Some('\n') => {},
+            Some('/') => return Some(""),
+            _ => {
+                search = start;
+                continue;
+            },
+        }
+        let content_start = match text[start..].find('>') {
+            Some(idx) => start + idx + 1,
+            None => return None,
+        };
+        return text[content_start..].find(&close[..])
+            .map(|len| &text[content_start..content_start + len]);
+    }
+    None
+}
+
+fn xml_unescape(value: &str) -> String {
+    let value = value.trim();
+    if value.starts_with("<![CDATA[") && value.ends_with("]]>") {
+        return value["<![CDATA[".len()..value.len() - "]]>".len()].to_string();
+    }
+    value.replace("&lt;", "<")
+        .replace("&gt;", ">")
+        .replace("&quot;", "\"")
+        .replace("&apos;", "'")
+        .replace("&amp;", "&")
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+pub enum MatchMethod {
+    /// a content hash in the location named one of the song's blobs
+    #[serde(rename="hash")]
+    Hash,
+    /// the file and directory names matched title and album
+    #[serde(rename="path")]
+    Path,
+    /// title, artist and length were close enough
+    #[serde(rename="metadata")]
+    Metadata,
+}
+
+/// Finds library songs for playlist entries.
+pub struct SongMatcher<'a> {
+    by_blob: HashMap<&'a str, &'a Song>,
+    by_title: HashMap<String, Vec<&'a Song>>,
+}
+
+impl<'a> SongMatcher<'a> {
+    pub fn new(songs: &'a [Song]) -> SongMatcher<'a> {
+        let mut by_blob = HashMap::new();
+        let mut by_title = HashMap::new();
+        for song in songs.iter() {
+            by_blob.insert(&song.blob[..], song);
+            for variant in song.variants.iter() {
+                by_blob.insert(&variant.blob[..], song);
+            }
+            if let Some(title) = song.metadata.get("TITLE") {
+                by_title.entry(normalize_text(title)).or_insert_with(Vec::new).push(song);
+            }
+        }
+        SongMatcher {
+            by_blob: by_blob,
+            by_title: by_title,
+        }
+    }
+
+    /// Tries the entry's embedded hash, then its path, then its metadata.
+    pub fn find(&self, entry: &ImportEntry) -> Option<(SongId, MatchMethod)> {
+        let path = path_components(&entry.location);
+
+        for component in path.iter() {
+            let stem = file_stem(component).to_lowercase();
+            if let Some(song) = self.by_blob.get(&stem[..]) {
+                return Some((song.id.clone(), MatchMethod::Hash));
+            }
+        }
+
+        if let Some(song) = self.find_by_path(&path) {
+            return Some((song.id.clone(), MatchMethod::Path));
+        }
+
+        let title = match entry.title {
+            Some(ref title) => normalize_text(title),
+            None => normalize_text(&strip_track_number(file_stem(path.last().map(|p| &p[..]).unwrap_or("")))),
+        };
+        let artist = entry.artist.as_ref().map(|a| normalize_text(a));
+        let mut best: Option<(i32, &Song)> = None;
+        for &song in self.by_title.get(&title).map(|s| &s[..]).unwrap_or(&[]) {
+            if let Some(ref artist) = artist {
+                let credited = song.effective_metadata("ARTIST")
+                    .map(|a| metadata_values(a).into_iter().any(|v| normalize_text(v) == *artist))
+                    .unwrap_or(false);
+                if !credited {
+                    continue;
+                }
+            }
+            let distance = match entry.duration_ms {
+                Some(duration_ms) => (song.length_ms - duration_ms).abs(),
+                None => 0,
+            };
+            if DURATION_TOLERANCE_MS < distance {
+                continue;
+            }
+            let better = match best {
+                Some((best_distance, best_song)) => {
+                    distance < best_distance || (distance == best_distance && song.id.0 < best_song.id.0)
+                },
+                None => true,
+            };
+            if better {
+                best = Some((distance, song));
+            }
+        }
+        best.map(|(_, song)| (song.id.clone(), MatchMethod::Metadata))
+    }
+
+    /// ".../Album/01 - Title.ogg": a song with that title on an album of
+    /// that name, preferring the one with that track number.
+    fn find_by_path(&self, path: &[String]) -> Option<&'a Song> {
+        if path.len() < 2 {
+            return None;
+        }
+        let stem = file_stem(&path[path.len() - 1]);
+        let title = normalize_text(&strip_track_number(stem));
+        let album = normalize_text(&path[path.len() - 2]);
+        let track_no: Option<i16> = stem.split(|c: char| !c.is_digit(10)).next().and_then(|n| n.parse().ok());
+
+        let candidates: Vec<&Song> = self.by_title.get(&title).map(|s| &s[..]).unwrap_or(&[])
+            .iter()
+            .cloned()
+            .filter(|song| song.effective_metadata("ALBUM").map(|a| normalize_text(a) == album).unwrap_or(false))
+            .collect();
+        candidates.iter()
+            .filter(|song| Some(song.track_no) == track_no)
+            .nth(0)
+            .or_else(|| candidates.iter().nth(0))
+            .cloned()
+    }
+}
+
+/// The directories and file name of a location, whether it is a URL, a
+/// Windows path or a relative path.
+fn path_components(location: &str) -> Vec<String> {
+    let location = location.trim();
+    let location = if location.starts_with("file://") {
+        &location["file://".len()..]
+    } else {
+        location
+    };
+    let location = location.split(|c| c == '?' || c == '#').next().unwrap_or("");
+    location.split(|c| c == '/' || c == '\\')
+        .filter(|c| c.len() > 0)
+        .map(percent_decode)
+        .collect()
+}
+
+fn file_stem(name: &str) -> &str {
+    match name.rfind('.') {
+        Some(idx) if 0 < idx => &name[..idx],
+        _ => name,
+    }
+}
+
+/// "01 - Title", "1-01 Title" and "01. Title" all become "Title".
+fn strip_track_number(stem: &str) -> String {
+    let stripped = stem.trim_left_matches(|c: char| c.is_digit(10) || c == '-' || c == '.' || c == '_' || c == ' ');
+    if stripped.len() == 0 {
+        stem.to_string()
+    } else {
+        stripped.to_string()
+    }
+}
+
+fn percent_decode(value: &str) -> String {
+    let bytes = value.as_bytes();
+    let mut out = Vec::with_capacity(bytes.len());
+    let mut idx = 0;
+    while idx < bytes.len() {
+        if bytes[idx] == b'%' && idx + 3 <= bytes.len() {
+            let hex = ::std::str::from_utf8(&bytes[idx + 1..idx + 3]).unwrap_or("");
+            if let Ok(byte) = u8::from_str_radix(hex, 16) {
+                out.push(byte);
+                idx += 3;
+                continue;
+            }
+        }
+        out.push(bytes[idx]);
+        idx += 1;
+    }
+    String::from_utf8_lossy(&out).into_owned()
+}
+
+#[cfg(test)]
+mod test {
+    use super::{ImportFormat, parse};
+
+    #[test]
+    fn test_sniff() {
+        assert_eq!(ImportFormat::sniff("\u{feff}#EXTM3U\n"), ImportFormat::M3u);
+        assert_eq!(ImportFormat::sniff("song.ogg\n"), ImportFormat::M3u);
+        assert_eq!(ImportFormat::sniff("\n[Playlist]\nFile1=a.ogg\n"), ImportFormat::Pls);
+        assert_eq!(ImportFormat::sniff("  <?xml version=\"1.0\"?>"), ImportFormat::Xspf);
+    }
+
+    #[test]
+    fn test_m3u() {
+        let text = "\u{feff}#EXTM3U\r\n\
+                    #PLAYLIST: Road trip \r\n\
+                    #EXTINF:215,Miles Davis - So What\r\n\
+                    Kind of Blue/01 - So What.flac\r\n\
+                    \r\n\
+                    # a comment\r\n\
+                    #EXTINF:-1,Untitled\r\n\
+                    http://example.com/stream\r\n\
+                    bare.ogg\r\n";
+        let playlist = parse(ImportFormat::M3u, text).unwrap();
+        assert_eq!(playlist.title, Some("Road trip".to_string()));
+        assert_eq!(playlist.entries.len(), 3);
+
+        assert_eq!(playlist.entries[0].location, "Kind of Blue/01 - So What.flac");
+        assert_eq!(playlist.entries[0].artist, Some("Miles Davis".to_string()));
+        assert_eq!(playlist.entries[0].title, Some("So What".to_string()));
+        assert_eq!(playlist.entries[0].duration_ms, Some(215000));
+
+        assert_eq!(playlist.entries[1].artist, None);
+        assert_eq!(playlist.entries[1].title, Some("Untitled".to_string()));
+        assert_eq!(playlist.entries[1].duration_ms, None);
+
+        // #EXTINF only applies to the entry right after it
+        assert_eq!(playlist.entries[2].location, "bare.ogg");
+        assert_eq!(playlist.entries[2].title, None);
+    }
+
+    #[test]
+    fn test_m3u_overlong_duration() {
+        let playlist = parse(ImportFormat::M3u, "#EXTINF:2147484,Title\na.ogg\n").unwrap();
+        assert_eq!(playlist.entries[0].duration_ms, None);
+        let playlist = parse(ImportFormat::M3u, "#EXTINF:2147483,Title\na.ogg\n").unwrap();
+        assert_eq!(playlist.entries[0].duration_ms, Some(2147483000));
+    }
+
+    #[test]
+    fn test_pls() {
+        let text = "[playlist]\n\
+                    File2=b.ogg\n\
+                    Title2=Only a title\n\
+                    File1 = a.ogg\n\
+                    title1=Artist - Song\n\
+                    Length1=61\n\
+                    Length2=2147484\n\
+                    NumberOfEntries=2\n\
+                    Version=2\n";
+        let playlist = parse(ImportFormat::Pls, text).unwrap();
+        assert_eq!(playlist.title, None);
+        assert_eq!(playlist.entries.len(), 2);
+
+        assert_eq!(playlist.entries[0].location, "a.ogg");
+        assert_eq!(playlist.entries[0].artist, Some("Artist".to_string()));
+        assert_eq!(playlist.entries[0].title, Some("Song".to_string()));
+        assert_eq!(playlist.entries[0].duration_ms, Some(61000));
+
+        assert_eq!(playlist.entries[1].location, "b.ogg");
+        assert_eq!(playlist.entries[1].artist, None);
+        assert_eq!(playlist.entries[1].title, Some("Only a title".to_string()));
+        assert_eq!(playlist.entries[1].duration_ms, None);
+    }
+
+    #[test]
+    fn test_pls_missing_file() {
+        assert!(parse(ImportFormat::Pls, "[playlist]\nFile1=a.ogg\nTitle2=b\n").is_err());
+    }
+
+    #[test]
+    fn test_xspf() {
+        let text = "<?xml version=\"1.0\" encoding=\"UTF-8\"?>\n\
+                    <playlist version=\"1\" xmlns=\"http://xspf.org/ns/0/\">\n\
+                    <title>Rock &amp; Roll</title>\n\
+                    <trackList>\n\
+                    <track>\n\
+                    <location>file:///music/a%20b.ogg</location>\n\
+                    <title><![CDATA[Fish & Chips]]></title>\n\
+                    <creator>Someone</creator>\n\
+                    <duration>1234</duration>\n\
+                    </track>\n\
+                    <track><title>No location</title></track>\n\
+                    <track><location>c.ogg</location><titles>x</titles></track>\n\
+                    </trackList>\n\
+                    </playlist>\n";
+        let playlist = parse(ImportFormat::Xspf, text).unwrap();
+        assert_eq!(playlist.title, Some("Rock & Roll".to_string()));
+        assert_eq!(playlist.entries.len(), 2);
+
+        assert_eq!(playlist.entries[0].location, "file:///music/a%20b.ogg");
+        assert_eq!(playlist.entries[0].title, Some("Fish & Chips".to_string()));
+        assert_eq!(playlist.entries[0].artist, Some("Someone".to_string()));
+        assert_eq!(playlist.entries[0].duration_ms, Some(1234));
+
+        // <titles> isn't <title>
+        assert_eq!(playlist.entries[1].location, "c.ogg");
+        assert_eq!(playlist.entries[1].title, None);
+    }
+
+    #[test]
+    fn test_xspf_no_track_list() {
+        assert!(parse(ImportFormat::Xspf, "<playlist><title>x</title></playlist>").is_err());
+    }
+}
@@ -0,0 +1,304 @@
+#![feature(custom_derive)]
+#![feature(plugin)]
+#![plugin(rocket_codegen)]
+#![feature(conservative_impl_trait)]
+#![feature(fnbox)]
+
+#[macro_use] extern crate serde_derive;
+#[macro_use] extern crate rocket_contrib;
+extern crate uuid;
+extern crate serde;
+extern crate serde_json;
+#[macro_use] extern crate postgres;
+extern crate rocket;
+extern crate hyper;
+extern crate hyper_native_tls;
+extern crate bincode;
+extern crate crypto;
+extern crate toml;
+extern crate url;
+extern crate ogg;
+
+use std::path::PathBuf;
+use std::io::{self, Read};
+use std::fs::File;
+use rocket::{Response, State};
+use rocket_contrib::{JSON as Json};
+use rocket::response::content::{
+    JSON as JsonResp,
+    HTML as HtmlResp,
+};
+use rocket::response::{Responder, Failure};
+use rocket::http::{Status, ContentType};
+use rocket::response::Stream;
+use rocket::Data;
+use postgres::{Connection, TlsMode};
+
+mod util;
+mod blob;
+mod asset;
+pub mod database;
+mod rpc;
+mod foreign_auth;
+mod auth;
+mod model;
+mod config;
+mod webby;
+mod media;
+mod export;
+mod import;
+mod lyrics;
+mod api;
+
+use self::config::{AppConfig, VfsBackend};
+
+use self::blob::BlobId;
+use self::auth::{
+    AuthTokenBlob,
+    AuthTokenInfo,
+};
+use self::foreign_auth::{
+    ForeignAuthProvider,
+    GoogleAuthProvider,
+    GoogleAuthToken
+};
+
+const ENABLE_CORS: bool = true;
+
+const ALLOW_ORIGINS: &'static [&'static str] = &[
+    "http://music-dev.yshi.org",
+];
+
+#[options("/blob/<id>")]
+fn blob_obj_options(/* request: &rocket::Request, */ id: BlobId) -> impl Responder<'static> {
+    let mut builder = Response::build();
+
+    // webby::Cors {
+    //     allow_origins: ALLOW_ORIGINS,
+    //     allow_methods: &["GET", "POST"],
+    //     allow_headers: &["Content-Type", "Authorization"],
+    //     expose_headers: &[],
+    // }
+    //     .set_headers(&request, &mut builder)
+    //     .map_err(|()| Failure(Status::Forbidden))
+    //     ?;
+
+    builder.finalize()
+}
+
+#[derive(FromForm, Debug)]
+struct SignedBlob {
+    expires: i64,
+    sig: String,
+}
+
+/// For links handed out in exported playlists, see `auth::blob_signature`.
+/// A malformed signature falls through to `blob_obj_get`, which wants a
+/// token.
+#[get("/blob/<id>?<signed>", rank = 1)]
+fn blob_obj_get_signed(config: State<AppConfig>, id: BlobId, signed: SignedBlob)
+    -> Result<impl Responder<'static>, Failure>
+{
+    if !auth::check_blob_signature(config.secret.as_bytes(), &id, signed.expires, &signed.sig) {
+        return Err(Failure(Status::Forbidden));
+    }
+    open_blob(&config, &id)
+}
+
+#[get("/blob/<id>", rank = 2)]
+fn blob_obj_get(config: State<AppConfig>, auth: AuthTokenBlob, id: BlobId)
+    -> Result<impl Responder<'static>, Failure>
+{
+    config.validate_auth(&auth)?;
+    open_blob(&config, &id)
+}
+
+fn open_blob(config: &AppConfig, id: &BlobId) -> Result<impl Responder<'static>, Failure> {
+    let vfs = config.vfs_driver.boxed();
+    let mut stream = match vfs.open_read(id) {
+        Ok(stream) => stream,
+        Err(err) => {
+            println!("error opening blob: {}", err);
+            return Err(Failure(Status::InternalServerError));
+        }
+    };
+    let mut head = Vec::with_capacity(media::SNIFF_LEN);
+    if let Err(err) = (&mut stream).take(media::SNIFF_LEN as u64).read_to_end(&mut head) {
+        println!("error reading blob {}: {}", id, err);
+        return Err(Failure(Status::InternalServerError));
+    }
+    let media_type = media::sniff_media_type(&head);
+    Ok(wrap_blob(io::Cursor::new(head).chain(stream), media_type))
+}
+
+#[post("/blob", data="<data>")]
+fn blob_obj_post(config: State<AppConfig>, auth: AuthTokenBlob, data: Data) -> impl Responder<'static> {
+    config.validate_auth(&auth)?;
+
+    let vfs = config.vfs_driver.boxed();
+    let stage_id = vfs.stage_write(&mut data.open())
+        .map_err(|e| {
+            println!("error staging blob: {}", e);
+            Failure(Status::InternalServerError)
+        })?;
+
+    Ok(wrap_json(&rpc::BlobUploadResponse {
+        stage_id: stage_id,
+    }))
+}
+
+#[derive(FromForm, Debug)]
+struct Search {
+   q: String,
+}
+
+#[get("/tracks/search?<search>")]
+fn tracks_search_get(config: State<AppConfig>, auth: AuthTokenBlob, search: Search) -> impl Responder<'static> {
+    config.validate_auth(&auth)?;
+
+    Ok(format!("{:?}", search))
+}
+
+// Access-Control-Allow-Origin: *
+// Access-Control-Allow-Methods: POST
+// Access-Control-Allow-Headers: Content-Type
+
+#[options("/login")]
+fn login_options() -> impl Responder<'static> {
+    cors_options()
+}
+
+#[post("/login", format="application/json", data="<login>")]
+fn login_post(config: State<AppConfig>, login: Json<rpc::LoginRequest>) -> impl Responder<'static> {
+    let Json(login) = login;
+
+    let mut conn = database::drivers::get_driver(config.database.read_url())
+        .map_err(|e| {
+            println!("error: {:?}", e);
+            Failure(Status::InternalServerError)
+        })?;
+
+    let mut auth_data = None;
+    if login.fap == "google" {
+        let token = GoogleAuthToken(login.faat.clone());
+        let prov = GoogleAuthProvider::new(&config.google_auth.audience);
+        let auth = try!(prov.authenticate(&token)
+            .map_err(|e| {
+                println!("auth error: {:?}", e);
+                Failure(Status::Forbidden)
+            }));
+        auth_data = Some(auth);
+    }
+
+    if auth_data.is_none() {
+        return Err(Failure(Status::Forbidden));
+    }
+    let auth_data = auth_data.unwrap();
+
+    let account = conn.find_or_create_user(&auth_data)
+        .map_err(|e| {
+            println!("error: {}", e);
+            Failure(Status::Forbidden)
+        })?;
+
+    println!("auth_data = {:?}", auth_data);
+    let ainfo = AuthTokenInfo::new(account.get_user_id());
+    let token = AuthTokenBlob::sign(config.secret.as_bytes(), &ainfo).into_inner();
+
+    Ok(wrap_json(&rpc::LoginResponse {
+        access_token: token,
+    }))
+}
+
+fn cors_options() -> impl Responder<'static> {
+    let mut builder = Response::build();
+    if ENABLE_CORS {
+        builder.raw_header("Access-Control-Allow-Origin", "*");
+        builder.raw_header("Access-Control-Allow-Methods", "GET, POST, PUT, PATCH, DELETE");
+        builder.raw_header("Access-Control-Allow-Headers", "Content-Type, Authorization");
+    }
+    builder.finalize()
+}
+
+fn wrap_json<T: serde::Serialize>(ser: &T) -> Response<'static> {
+    wrap_json_status(Status::Ok, ser)
+}
+
+fn wrap_json_status<T: serde::Serialize>(status: Status, ser: &T) -> Response<'static> {
+    let body = serde_json::to_vec(ser).unwrap();
+
+    let mut builder = Response::build();
+    builder.status(status);
+    builder.header(ContentType::JSON);
+    if ENABLE_CORS {
+        builder.raw_header("Access-Control-Allow-Origin", "*");
+        builder.raw_header("Access-Control-Allow-Methods", "GET, POST, PUT, PATCH, DELETE");
+        builder.raw_header("Access-Control-Allow-Headers", "Content-Type, Authorization");
+    }
+    builder.sized_body(io::Cursor::new(body));
+    builder.finalize()
+}
+
+/// Like `wrap_json`, for a body that is already serialized and may still
+/// be being written.
+fn wrap_json_stream<T: 'static + Read>(rr: T) -> Response<'static> {
+    let mut builder = Response::build();
+    builder.status(Status::Ok);
+    builder.header(ContentType::JSON);
+    if ENABLE_CORS {
+        builder.raw_header("Access-Control-Allow-Origin", "*");
+        builder.raw_header("Access-Control-Allow-Methods", "GET, POST, PUT, PATCH, DELETE");
+        builder.raw_header("Access-Control-Allow-Headers", "Content-Type, Authorization");
+    }
+    builder.chunked_body(rr, 32 * 1024);
+    builder.finalize()
+}
+
+fn wrap_blob<T: 'static + Read>(rr: T, media_type: Option<&'static str>) -> impl Responder<'static> {
+    let mut builder = Response::build();
+    builder.status(Status::Ok);
+    if let Some(media_type) = media_type {
+        builder.raw_header("Content-Type", media_type);
+    }
+    if ENABLE_CORS {
+        builder.raw_header("Access-Control-Allow-Origin", "*");
+        builder.raw_header("Access-Control-Allow-Methods", "GET, POST, PUT, PATCH, DELETE");
+        builder.raw_header("Access-Control-Allow-Headers", "Content-Type, Authorization");
+    }
+    builder.chunked_body(rr, 32 * 1024);
+    builder.finalize()
+}
+
+/// Serves the app with the configuration file named by the first argument.
+pub fn run() {
+    let config_file = std::env::args_os().nth(1).expect("arg0: config.toml");
+    let mut config = File::open(&config_file).unwrap();
+    let mut config_str = String::new();
+    config.read_to_string(&mut config_str).unwrap();
+    let app: AppConfig = toml::from_str(&mut config_str).expect("error reading toml");
+
+    if let Some(interval) = app.recommendations.refresh_interval() {
+        database::spawn_similarity_refresh(
+            app.database.write_url().to_owned(),
+            app.recommendations.similarity_params(),
+            interval);
+    }
+    if let Some(retention_secs) = app.history.retention_secs() {
+        database::spawn_history_pruning(app.database.write_url().to_owned(), retention_secs);
+    }
+
+    rocket::ignite()
+        .mount("/static", asset::statics())
+        .mount("/", routes![
+            blob_obj_get,
+            blob_obj_get_signed,
+            blob_obj_options,
+            blob_obj_post,
+            tracks_search_get,
+            login_post,
+            login_options,
+        ])
+        .mount("/", api::routes())
+        .manage(app)
+        .launch()
+}
@@ -0,0 +1,278 @@
+//! Reads lyrics from Vorbis comments and LRC files.
+
+use ::model::{Lyrics, LyricLine};
+
+/// Comments that may hold lyrics, in order of preference.
+const LYRICS_COMMENTS: &'static [&'static str] = &["LYRICS", "UNSYNCEDLYRICS"];
+
+/// How far `[offset:...]` may shift the lyrics either way.
+const OFFSET_MAX_MS: i64 = 24 * 60 * 60 * 1000;
+
+/// Whether a metadata field holds lyrics, which are kept apart from the
+/// other metadata.
+pub fn is_lyrics_field(field: &str) -> bool {
+    let field = field.to_uppercase();
+    LYRICS_COMMENTS.iter().any(|name| field == *name)
+}
+
+/// The lyrics among a file's comments.  Synced lyrics that don't parse or
+/// run past the end of the song are kept as plain text.
+pub fn from_comments(comments: &[(String, String)], length_ms: i32) -> Option<Lyrics> {
+    for name in LYRICS_COMMENTS.iter() {
+        let value = comments.iter()
+            .filter(|&&(ref key, ref value)| key.to_uppercase() == *name && value.trim().len() > 0)
+            .map(|&(_, ref value)| value)
+            .next();
+        let value = match value {
+            Some(value) => value,
+            None => continue,
+        };
+        return Some(match parse_lrc(value) {
+            Ok(lyrics) => match check_length(&lyrics, length_ms) {
+                Ok(()) => lyrics,
+                Err(_) => Lyrics::plain(lyrics.text),
+            },
+            Err(_) => Lyrics::plain(value.trim().to_string()),
+        });
+    }
+    None
+}
+
+/// Reads LRC, or plain text if there isn't a single timestamp in it.  Lines
+/// may carry several timestamps, `[offset:...]` is applied and word
+/// timestamps (`<mm:ss.xx>`) are dropped.  Other tags such as `[ar:...]`
+/// are ignored.
+pub fn parse_lrc(text: &str) -> Result<Lyrics, String> {
+    let text = text.trim_left_matches('\u{feff}');
+    let mut offset_ms: i64 = 0;
+    let mut timed: Vec<(i64, String)> = Vec::new();
+    // (line number, text) of the lines without a timestamp
+    let mut untimed: Vec<(usize, String)> = Vec::new();
+
+    for (idx, raw) in text.lines().enumerate() {
+        let line_no = idx + 1;
+        let mut rest = raw.trim();
+        let mut times = Vec::new();
+        let mut tagged = false;
+        while rest.starts_with('[') {
+            let close = match rest.find(']') {
+                Some(close) => close,
+                None => break,
+            };
+            let tag = &rest[1..close];
+            if tag.starts_with(|c: char| c.is_digit(10)) {
+                match parse_timestamp(tag) {
+                    Some(time) => times.push(time),
+                    None => return Err(format!("line {}: invalid timestamp [{}]", line_no, tag)),
+                }
+            } else if let Some(colon) = tag.find(':') {
+                if tag[..colon].trim().to_lowercase() == "offset" {
+                    let value = tag[colon + 1..].trim().trim_left_matches('+');
+                    offset_ms = match value.parse::<i64>() {
+                        Ok(offset) if -OFFSET_MAX_MS <= offset && offset <= OFFSET_MAX_MS => offset,
+                        Ok(_) => return Err(format!("line {}: offset [{}] is out of range", line_no, tag)),
+                        Err(_) => return Err(format!("line {}: invalid offset [{}]", line_no, tag)),
+                    };
+                }
+                tagged = true;
+            } else {
+                break;
+            }
+            rest = &rest[close + 1..];
+        }
+
+        let line_text = strip_word_times(rest.trim());
+        if times.len() > 0 {
+            for time in times.into_iter() {
+                timed.push((time, line_text.clone()));
+            }
+        } else if !(tagged && line_text.len() == 0) {
+            untimed.push((line_no, line_text));
+        }
+    }
+
+    if timed.len() == 0 {
+        let lines: Vec<String> = untimed.into_iter().map(|(_, text)| text).collect();
+        return Ok(Lyrics::plain(lines.join("\n").trim().to_string()));
+    }
+    if let Some(&(line_no, _)) = untimed.iter().filter(|&&(_, ref text)| text.len() > 0).next() {
+        return Err(format!("line {} has no timestamp", line_no));
+    }
+
+    // a positive offset makes the lyrics come earlier
+    let mut lines = Vec::new();
+    for (time, text) in timed.into_iter() {
+        let time_ms = time - offset_ms;
+        if i32::max_value() as i64 <= time_ms {
+            return Err(format!("timestamp {} ms is out of range", time_ms));
+        }
+        lines.push(LyricLine {
+            time_ms: if time_ms < 0 { 0 } else { time_ms as i32 },
+            text: text,
+        });
+    }
+    lines.sort_by_key(|line| line.time_ms);
+
+    let text = {
+        let texts: Vec<&str> = lines.iter().map(|line| &line.text[..]).collect();
+        texts.join("\n").trim().to_string()
+    };
+    Ok(Lyrics {
+        text: text,
+        lines: lines,
+    })
+}
+
+/// Fails naming the first line that starts after the song has ended.
+pub fn check_length(lyrics: &Lyrics, length_ms: i32) -> Result<(), String> {
+    match lyrics.lines.iter().filter(|line| length_ms < line.time_ms).next() {
+        Some(line) => Err(format!("line {:?} at [{}] starts after the song ends at [{}]",
+            line.text, format_timestamp(line.time_ms), format_timestamp(length_ms))),
+        None => Ok(()),
+    }
+}
+
+/// `mm:ss.xx` as written in LRC files.
+pub fn format_timestamp(time_ms: i32) -> String {
+    format!("{:02}:{:02}.{:02}", time_ms / 60000, time_ms / 1000 % 60, time_ms % 1000 / 10)
+}
+
+/// Milliseconds of `mm:ss`, `mm:ss.x` up to `mm:ss.xxx`, or `mm:ss:xx` as
+/// some files have it.
+fn parse_timestamp(tag: &str) -> Option<i64> {
+    let colon = match tag.find(':') {
+        Some(colon) => colon,
+        None => return None,
+    };
+    let minutes = match digits(&tag[..colon]) {
+        Some(minutes) => minutes,
+        None => return None,
+    };
+    let rest = &tag[colon + 1..];
+    let (seconds, fraction) = match rest.find(|c: char| c == '.' || c == ':') {
+        Some(sep) => (&rest[..sep], &rest[sep + 1..]),
+        None => (rest, ""),
+    };
+    let seconds = match digits(seconds) {
+        Some(s) if s < 60 && seconds.len() <= 2 => s,
+        _ => return None,
+    };
+    let fraction_ms = match fraction.len() {
+        0 => 0,
+        1...3 => match digits(fraction) {
+            Some(f) => f * [100, 10, 1][fraction.len() - 1],
+            None => return None,
+        },
+        _ => return None,
+    };
+    Some((minutes * 60 + seconds) * 1000 + fraction_ms)
+}
+
+fn digits(text: &str) -> Option<i64> {
+    if text.len() == 0 || 6 < text.len() || !text.chars().all(|c| c.is_digit(10)) {
+        return None;
+    }
+    text.parse().ok()
+}
+
+/// Drops the `<mm:ss.xx>` marks of enhanced LRC, leaving other `<...>`
+/// alone.
+fn strip_word_times(text: &str) -> String {
+    let mut out = String::new();
+    let mut rest = text;
+    let mut stripped = false;
+    while let Some(open) = rest.find('<') {
+        let close = match rest[open..].find('>') {
+            Some(close) => open + close,
+            None => break,
+        };
+        if parse_timestamp(&rest[open + 1..close]).is_some() {
+            out.push_str(&rest[..open]);
+            stripped = true;
+        } else {
+            out.push_str(&rest[..close + 1]);
+        }
+        rest = &rest[close + 1..];
+    }
+    out.push_str(rest);
+    if stripped {
+        let words: Vec<&str> = out.split_whitespace().collect();
+        return words.join(" ");
+    }
+    out
+}
+
+#[cfg(test)]
+mod test {
+    use super::{parse_lrc, parse_timestamp, strip_word_times};
+
+    #[test]
+    fn test_parse_timestamp() {
+        assert_eq!(parse_timestamp("00:00"), Some(0));
+        assert_eq!(parse_timestamp("01:02"), Some(62000));
+        assert_eq!(parse_timestamp("01:02."), Some(62000));
+        assert_eq!(parse_timestamp("01:02.3"), Some(62300));
+        assert_eq!(parse_timestamp("01:02.34"), Some(62340));
+        assert_eq!(parse_timestamp("01:02.345"), Some(62345));
+        assert_eq!(parse_timestamp("01:02:34"), Some(62340));
+        assert_eq!(parse_timestamp("123:00"), Some(7380000));
+    }
+
+    #[test]
+    fn test_parse_timestamp_invalid() {
+        assert_eq!(parse_timestamp(""), None);
+        assert_eq!(parse_timestamp("12"), None);
+        assert_eq!(parse_timestamp(":12"), None);
+        assert_eq!(parse_timestamp("01:60"), None);
+        assert_eq!(parse_timestamp("01:002"), None);
+        assert_eq!(parse_timestamp("01:02.3456"), None);
+        assert_eq!(parse_timestamp("01:02.x"), None);
+        assert_eq!(parse_timestamp("-1:02"), None);
+        assert_eq!(parse_timestamp("1234567:00"), None);
+        assert_eq!(parse_timestamp("ar:someone"), None);
+    }
+
+    #[test]
+    fn test_strip_word_times() {
+        assert_eq!(strip_word_times("plain line"), "plain line");
+        assert_eq!(strip_word_times("<00:01.00> one <00:01.50> two"), "one two");
+        assert_eq!(strip_word_times("a <b> c"), "a <b> c");
+        assert_eq!(strip_word_times("<00:01.00>one <i>two</i>"), "one <i>two</i>");
+        assert_eq!(strip_word_times("unclosed <00:01.00"), "unclosed <00:01.00");
+    }
+
+    #[test]
+    fn test_parse_lrc_plain() {
+        let lyrics = parse_lrc("\u{feff}[ar:Someone]\n\nfirst line\nsecond line\n").unwrap();
+        assert!(!lyrics.is_synced());
+        assert_eq!(lyrics.text, "first line\nsecond line");
+    }
+
+    #[test]
+    fn test_parse_lrc_synced() {
+        let text = "[ti:Song]\n[00:05.00][00:01.00]chorus\n[00:03.00]<00:03.00>verse <00:03.50>line\n";
+        let lyrics = parse_lrc(text).unwrap();
+        let lines: Vec<(i32, &str)> = lyrics.lines.iter().map(|l| (l.time_ms, &l.text[..])).collect();
+        assert_eq!(lines, vec![(1000, "chorus"), (3000, "verse line"), (5000, "chorus")]);
+        assert_eq!(lyrics.text, "chorus\nverse line\nchorus");
+    }
+
+    #[test]
+    fn test_parse_lrc_offset() {
+        let lyrics = parse_lrc("[offset:+1500]\n[00:01.00]early\n[00:02.00]late\n").unwrap();
+        let times: Vec<i32> = lyrics.lines.iter().map(|l| l.time_ms).collect();
+        assert_eq!(times, vec![0, 500]);
+        let lyrics = parse_lrc("[offset:-500]\n[00:01.00]line\n").unwrap();
+        assert_eq!(lyrics.lines[0].time_ms, 1500);
+    }
+
+    #[test]
+    fn test_parse_lrc_invalid() {
+        assert!(parse_lrc("[00:01.00]timed\nuntimed\n").is_err());
+        assert!(parse_lrc("[00:61.00]line\n").is_err());
+        assert!(parse_lrc("[offset:soon]\n[00:01.00]line\n").is_err());
+        assert!(parse_lrc("[offset:-9223372036854775808]\n[00:01.00]line\n").is_err());
+        assert!(parse_lrc("[offset:86400001]\n[00:01.00]line\n").is_err());
+        assert!(parse_lrc("[999999:00]line\n").is_err());
+    }
+}
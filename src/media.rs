@@ -0,0 +1,189 @@
+use std::cmp;
+use std::fmt;
+use std::collections::BTreeMap;
+
+use crypto::digest::Digest;
+use crypto::sha2::Sha256;
+use ogg::{OggTrack, OggPageCheckError};
+use ogg::vorbis::{VorbisPacket, IdentificationHeader};
+
+use ::blob::BlobId;
+use ::database::{METADATA_VALUE_SEPARATOR, is_multi_valued_field};
+use ::model::SongVariant;
+
+/// Granule position of a page on which no packet finishes.
+const GRANULE_NONE: u64 = 0xFFFF_FFFF_FFFF_FFFF;
+
+#[derive(Debug)]
+pub enum ProbeError {
+    Empty,
+    NotOgg(OggPageCheckError),
+    MissingIdentificationHeader,
+    MissingComments,
+    /// 0, or too high to store as a signed 32-bit number
+    SampleRateOutOfRange(u32),
+    /// the stream claims to be longer than a length in milliseconds can hold
+    TooLong,
+}
+
+impl fmt::Display for ProbeError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            ProbeError::Empty => write!(f, "empty file"),
+            ProbeError::NotOgg(ref err) => write!(f, "not an ogg stream: {:?}", err),
+            ProbeError::MissingIdentificationHeader => {
+                write!(f, "no vorbis identification header")
+            },
+            ProbeError::MissingComments => write!(f, "no vorbis comment header"),
+            ProbeError::SampleRateOutOfRange(rate) => write!(f, "sample rate of {} out of range", rate),
+            ProbeError::TooLong => write!(f, "length out of range"),
+        }
+    }
+}
+
+/// Everything we learn about an Ogg/Vorbis file by reading it once.
+pub struct TrackInfo {
+    pub blob_id: BlobId,
+    /// in bytes
+    pub size: i64,
+    pub length_ms: i32,
+    /// see `audio_digest`
+    pub audio_digest: String,
+    pub identification: IdentificationHeader,
+    pub vendor: String,
+    pub comments: Vec<(String, String)>,
+}
+
+/// Validates `buf` as an Ogg/Vorbis stream and extracts its headers.
+pub fn probe(buf: &[u8]) -> Result<TrackInfo, ProbeError> {
+    if buf.len() == 0 {
+        return Err(ProbeError::Empty);
+    }
+    let track = OggTrack::new(buf).map_err(ProbeError::NotOgg)?;
+
+    let mut page_iter = track.pages();
+    let identification = VorbisPacket::find_identification(&mut page_iter)
+        .map_err(|()| ProbeError::MissingIdentificationHeader)?
+        .identification_header()
+        .ok_or(ProbeError::MissingIdentificationHeader)?;
+    let comments = VorbisPacket::find_comments(&mut page_iter)
+        .map_err(|()| ProbeError::MissingComments)?
+        .comments()
+        .ok_or(ProbeError::MissingComments)?;
+
+    let mut granule_pos_max = 0;
+    for page in track.pages() {
+        let position = page.position();
+        if position != GRANULE_NONE && granule_pos_max < position {
+            granule_pos_max = position;
+        }
+    }
+    let sample_rate = identification.audio_sample_rate as u64;
+    if sample_rate == 0 || sample_rate > i32::max_value() as u64 {
+        return Err(ProbeError::SampleRateOutOfRange(identification.audio_sample_rate));
+    }
+    let length_ms = match granule_pos_max.checked_mul(1000) {
+        Some(samples_ms) if samples_ms / sample_rate <= i32::max_value() as u64 => {
+            (samples_ms / sample_rate) as i32
+        },
+        _ => return Err(ProbeError::TooLong),
+    };
+
+    Ok(TrackInfo {
+        blob_id: blob_id_of(buf),
+        size: buf.len() as i64,
+        length_ms: length_ms,
+        audio_digest: audio_digest(track),
+        identification: identification,
+        vendor: comments.vendor,
+        comments: comments.comments,
+    })
+}
+
+impl TrackInfo {
+    /// The file as one encoding of a song.  Encoders may leave the nominal
+    /// bitrate out (it is then 0, or -1 read as unsigned), in which case
+    /// the average over the file is used.
+    pub fn variant(&self) -> SongVariant {
+        let nominal = self.identification.bitrate_nominal;
+        let bitrate = if 0 < nominal && nominal <= i32::max_value() as u32 {
+            nominal as i32
+        } else {
+            let length_ms = cmp::max(self.length_ms, 1) as i64;
+            cmp::min(cmp::max(self.size * 8 * 1000 / length_ms, 1), i32::max_value() as i64) as i32
+        };
+        SongVariant {
+            blob: format!("{}", self.blob_id),
+            codec: "vorbis".to_string(),
+            bitrate: bitrate,
+            channels: self.identification.audio_channels as i16,
+            // in range, `probe` checks
+            sample_rate: self.identification.audio_sample_rate as i32,
+            size: self.size,
+        }
+    }
+}
+
+/// Vorbis comments as a metadata map.  Field names are case-insensitive and
+/// may repeat, e.g. one `ARTIST` per performer.  Repeated values of
+/// multi-valued fields are joined, for any other field the first one wins.
+pub fn comment_map(comments: &[(String, String)]) -> BTreeMap<String, String> {
+    let mut out: BTreeMap<String, String> = BTreeMap::new();
+    for &(ref key, ref value) in comments.iter() {
+        let key = key.to_uppercase();
+        let joined = match out.get(&key) {
+            Some(existing) if is_multi_valued_field(&key) => {
+                format!("{}{} {}", existing, METADATA_VALUE_SEPARATOR, value)
+            },
+            Some(_) => continue,
+            None => value.clone(),
+        };
+        out.insert(key, joined);
+    }
+    out
+}
+
+/// How much of a blob `sniff_media_type` needs to see.
+pub const SNIFF_LEN: usize = 12;
+
+/// The media type of a blob going by its first `SNIFF_LEN` bytes, for the
+/// audio we store and the picture formats embedded as album art.  Blobs
+/// don't keep the type they were uploaded with.
+pub fn sniff_media_type(head: &[u8]) -> Option<&'static str> {
+    if head.starts_with(b"OggS") {
+        Some("audio/ogg")
+    } else if head.starts_with(b"\xFF\xD8\xFF") {
+        Some("image/jpeg")
+    } else if head.starts_with(b"\x89PNG\r\n\x1A\n") {
+        Some("image/png")
+    } else if head.starts_with(b"GIF87a") || head.starts_with(b"GIF89a") {
+        Some("image/gif")
+    } else if head.starts_with(b"RIFF") && head.len() >= 12 && &head[8..12] == b"WEBP" {
+        Some("image/webp")
+    } else if head.starts_with(b"BM") {
+        Some("image/bmp")
+    } else {
+        None
+    }
+}
+
+/// Hex sha256 of the audio data alone, so that files differing only in
+/// their comments compare equal.  The header pages all have granule
+/// position 0 and audio always starts on a fresh page, so everything from
+/// the first page with a non-zero position on is audio.  Page headers are
+/// left out as they carry sequence numbers and checksums.
+pub fn audio_digest(track: &OggTrack) -> String {
+    let mut hasher = Sha256::new();
+    for page in track.pages().skip_while(|page| page.position() == 0) {
+        hasher.input(page.body());
+    }
+    hasher.result_str()
+}
+
+pub fn blob_id_of(buf: &[u8]) -> BlobId {
+    let mut hasher = Sha256::new();
+    hasher.input(buf);
+    let mut hash = [0; 32];
+    hasher.result(&mut hash);
+    BlobId::from_bytes(hash)
+}
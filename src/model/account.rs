@@ -0,0 +1,24 @@
+use uuid::Uuid;
+use super::song::SongId;
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct AccountProfile {
+    pub id: Uuid,
+    pub display_name: String,
+}
+
+/// An identity at an external provider that logs in to the account.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct LinkedAccount {
+    pub provider: String,
+    pub foreign_id: String,
+    pub created_at: i64,
+}
+
+/// An account's play count and rating for one song.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct SongStats {
+    pub song_id: SongId,
+    pub play_count: i32,
+    pub rating: Option<i32>,
+}
@@ -1,5 +1,7 @@
 use std::collections::BTreeMap;
 
+use super::tags;
+
 #[derive(Serialize, Deserialize, PartialEq, Eq, Debug, Clone)]
 pub struct AlbumId(pub i64);
 
@@ -8,4 +10,46 @@ pub struct Album {
     pub id: AlbumId,
     pub art_blob: Option<String>,
     pub metadata: BTreeMap<String, String>,
-}
\ No newline at end of file
+    // typed copies of what `metadata` says, see `Album::new`
+    #[serde(default)]
+    pub disc_total: Option<i16>,
+    #[serde(default)]
+    pub track_total: Option<i16>,
+    #[serde(default)]
+    pub date: Option<String>,
+    #[serde(default)]
+    pub year: Option<i16>,
+    /// ReplayGain in dB, see `Song::track_gain`
+    #[serde(default)]
+    pub album_gain: Option<f64>,
+    /// the highest sample of any track, 1.0 being full scale
+    #[serde(default)]
+    pub album_peak: Option<f64>,
+}
+
+impl Album {
+    /// Fills in the typed fields from `metadata`.  The gain is stored
+    /// apart from the metadata and left out.
+    pub fn new(id: AlbumId, art_blob: Option<String>, metadata: BTreeMap<String, String>) -> Album {
+        let (date, year) = tags::date(&metadata);
+        Album {
+            id: id,
+            art_blob: art_blob,
+            disc_total: tags::disc_total(&metadata),
+            track_total: tags::track_total(&metadata),
+            date: date,
+            year: year,
+            metadata: metadata,
+            album_gain: None,
+            album_peak: None,
+        }
+    }
+}
+
+/// An album as listed in the catalog, without its songs.
+#[derive(Serialize, Debug, Clone)]
+pub struct AlbumSummary {
+    pub album: Album,
+    pub track_count: i64,
+    pub length_ms: i64,
+}
@@ -0,0 +1,18 @@
+#[derive(Serialize, Deserialize, PartialEq, Eq, Debug, Clone)]
+pub struct ArtistId(pub i64);
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct Artist {
+    pub id: ArtistId,
+    pub name: String,
+    /// from `ARTISTSORT`, e.g. "Beatles, The"
+    pub sort_name: Option<String>,
+}
+
+/// An artist as listed in the catalog, without albums or songs.
+#[derive(Serialize, Debug, Clone)]
+pub struct ArtistSummary {
+    pub artist: Artist,
+    pub album_count: i64,
+    pub track_count: i64,
+}
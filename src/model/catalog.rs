@@ -0,0 +1,11 @@
+use super::{Album, AlbumId, Song, SongId};
+
+/// What changed in the catalog after some version, up to `version`.
+#[derive(Serialize, Debug, Clone)]
+pub struct CatalogChanges {
+    pub version: i64,
+    pub songs: Vec<Song>,
+    pub albums: Vec<Album>,
+    pub deleted_song_ids: Vec<SongId>,
+    pub deleted_album_ids: Vec<AlbumId>,
+}
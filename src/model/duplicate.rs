@@ -0,0 +1,21 @@
+use super::song::Song;
+
+/// Why the songs of a group are thought to be the same.
+#[derive(Serialize, Deserialize, PartialEq, Eq, Debug, Clone, Copy)]
+pub enum DuplicateKind {
+    /// the very same file
+    #[serde(rename="blob")]
+    Blob,
+    /// the same audio with different comments
+    #[serde(rename="audio")]
+    Audio,
+    /// same title and artist, about as long
+    #[serde(rename="metadata")]
+    Metadata,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct DuplicateGroup {
+    pub kind: DuplicateKind,
+    pub songs: Vec<Song>,
+}
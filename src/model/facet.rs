@@ -0,0 +1,6 @@
+/// One distinct value of a metadata field and how many songs have it.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct FacetValue {
+    pub value: String,
+    pub count: i64,
+}
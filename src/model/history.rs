@@ -0,0 +1,15 @@
+use super::song::Song;
+
+#[derive(Serialize, Deserialize, PartialEq, Eq, Debug, Clone)]
+pub struct ListenId(pub i64);
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct Listen {
+    pub id: ListenId,
+    pub started_at: i64,
+    /// how far playback got
+    pub position_ms: i32,
+    pub duration_ms: i32,
+    pub client_id: Option<String>,
+    pub song: Song,
+}
@@ -0,0 +1,30 @@
+/// A song's lyrics.  Time-synced lyrics have a line for every timestamp,
+/// plain ones only the text.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct Lyrics {
+    /// the lyrics without any timestamps
+    pub text: String,
+    /// in order of time; empty unless the lyrics are time-synced
+    #[serde(default)]
+    pub lines: Vec<LyricLine>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct LyricLine {
+    /// from the start of the song
+    pub time_ms: i32,
+    pub text: String,
+}
+
+impl Lyrics {
+    pub fn plain(text: String) -> Lyrics {
+        Lyrics {
+            text: text,
+            lines: Vec::new(),
+        }
+    }
+
+    pub fn is_synced(&self) -> bool {
+        self.lines.len() > 0
+    }
+}
@@ -0,0 +1,21 @@
+use uuid::Uuid;
+use super::song::SongId;
+use super::album::AlbumId;
+
+#[derive(Serialize, Deserialize, PartialEq, Eq, Debug, Clone)]
+pub struct MetadataChangeId(pub i64);
+
+/// One entry of the metadata edit history.  Exactly one of `song_id` and
+/// `album_id` is set.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct MetadataChange {
+    pub id: MetadataChangeId,
+    pub account_id: Option<Uuid>,
+    pub song_id: Option<SongId>,
+    pub album_id: Option<AlbumId>,
+    pub field_name: String,
+    pub old_value: Option<String>,
+    pub new_value: Option<String>,
+    pub reverts: Option<MetadataChangeId>,
+    pub changed_at: i64,
+}
@@ -1,11 +1,76 @@
 mod song;
 mod album;
+mod metadata;
+mod playlist;
+mod queue;
+mod history;
+mod smart_playlist;
+mod artist;
+mod facet;
+mod duplicate;
+mod recommendation;
+mod account;
+mod catalog;
+mod lyrics;
+mod tags;
 
 pub use self::song::{
     SongId,
     Song,
+    SongVariant,
 };
 pub use self::album::{
     AlbumId,
     Album,
+    AlbumSummary,
+};
+pub use self::metadata::{
+    MetadataChangeId,
+    MetadataChange,
+};
+pub use self::playlist::{
+    PlaylistId,
+    PlaylistEntryId,
+    Playlist,
+    PlaylistEntry,
+};
+pub use self::queue::PlayQueue;
+pub use self::history::{
+    ListenId,
+    Listen,
+};
+pub use self::smart_playlist::{
+    SmartPlaylistId,
+    SmartPlaylist,
+    SmartRules,
+    SmartRule,
+};
+pub use self::artist::{
+    ArtistId,
+    Artist,
+    ArtistSummary,
+};
+pub use self::facet::FacetValue;
+pub use self::duplicate::{
+    DuplicateKind,
+    DuplicateGroup,
+};
+pub use self::recommendation::ScoredSong;
+pub use self::account::{
+    AccountProfile,
+    LinkedAccount,
+    SongStats,
+};
+pub use self::catalog::CatalogChanges;
+pub use self::lyrics::{
+    Lyrics,
+    LyricLine,
+};
+pub use self::tags::{
+    disc_number,
+    track_number,
+    is_replaygain_field,
+    uppercase_replaygain_fields,
+    track_replaygain,
+    album_replaygain,
 };
\ No newline at end of file
@@ -0,0 +1,26 @@
+use uuid::Uuid;
+use super::song::Song;
+
+#[derive(Serialize, Deserialize, PartialEq, Eq, Debug, Clone)]
+pub struct PlaylistId(pub i64);
+
+#[derive(Serialize, Deserialize, PartialEq, Eq, Debug, Clone)]
+pub struct PlaylistEntryId(pub i64);
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct Playlist {
+    pub id: PlaylistId,
+    pub account_id: Uuid,
+    pub name: String,
+    pub description: String,
+    pub entry_count: i64,
+    pub created_at: i64,
+    pub updated_at: i64,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct PlaylistEntry {
+    pub id: PlaylistEntryId,
+    pub position: i32,
+    pub song: Song,
+}
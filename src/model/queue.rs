@@ -0,0 +1,11 @@
+use super::song::SongId;
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct PlayQueue {
+    pub song_ids: Vec<SongId>,
+    pub current_index: i32,
+    pub position_ms: i64,
+    /// Zero until the queue is first written.
+    pub version: i64,
+    pub updated_at: i64,
+}
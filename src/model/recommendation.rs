@@ -0,0 +1,8 @@
+use super::Song;
+
+/// A song suggested by the similarity data, higher scores are better.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct ScoredSong {
+    pub song: Song,
+    pub score: f64,
+}
@@ -0,0 +1,36 @@
+use serde_json::Value;
+use uuid::Uuid;
+
+#[derive(Serialize, Deserialize, PartialEq, Eq, Debug, Clone)]
+pub struct SmartPlaylistId(pub i64);
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct SmartPlaylist {
+    pub id: SmartPlaylistId,
+    pub account_id: Uuid,
+    pub name: String,
+    pub rules: SmartRules,
+    pub created_at: i64,
+    pub updated_at: i64,
+}
+
+/// A song matches if it satisfies every rule.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct SmartRules {
+    #[serde(default)]
+    pub rules: Vec<SmartRule>,
+    // comma separated sort keys as for `GET /songs`, or `random`
+    #[serde(default, skip_serializing_if="Option::is_none")]
+    pub sort: Option<String>,
+    #[serde(default, skip_serializing_if="Option::is_none")]
+    pub limit: Option<u32>,
+}
+
+/// `field` is `rating`, `play_count`, `last_played` or else the name of a
+/// metadata field.  `value` is a number or a string depending on `field`.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct SmartRule {
+    pub field: String,
+    pub op: String,
+    pub value: Value,
+}
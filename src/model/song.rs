@@ -2,6 +2,7 @@ use std::collections::BTreeMap;
 use super::album::{
     Album,
 };
+use super::tags;
 
 #[derive(Serialize, Deserialize, PartialEq, Eq, Debug, Clone)]
 pub struct SongId(pub i64);
@@ -11,8 +12,105 @@ pub struct Song {
     pub id: SongId,
     pub blob: String,
     pub length_ms: i32,
+    #[serde(default="first_disc")]
+    pub disc_no: i16,
     pub track_no: i16,
     pub metadata: BTreeMap<String, String>,
     pub album: Album,
+    // typed copies of what `metadata` says, see `Song::new`
+    #[serde(default)]
+    pub disc_total: Option<i16>,
+    #[serde(default)]
+    pub track_total: Option<i16>,
+    #[serde(default)]
+    pub date: Option<String>,
+    #[serde(default)]
+    pub year: Option<i16>,
+    /// ReplayGain in dB, to be added when playing the song in shuffle
+    #[serde(default)]
+    pub track_gain: Option<f64>,
+    /// the highest sample, 1.0 being full scale
+    #[serde(default)]
+    pub track_peak: Option<f64>,
+    /// every encoding of the song, `blob` among them, highest bitrate first
+    #[serde(default)]
+    pub variants: Vec<SongVariant>,
+    // only present when the songs were requested on behalf of an account
+    #[serde(default, skip_serializing_if="Option::is_none")]
+    pub play_count: Option<i32>,
+    #[serde(default, skip_serializing_if="Option::is_none")]
+    pub rating: Option<i32>,
 }
 
+impl Song {
+    /// Fills in the typed fields from `metadata`, falling back to the
+    /// album's for anything the song doesn't say itself.  Play count and
+    /// rating are left out, as are the gain and the variants.
+    pub fn new(
+        id: SongId,
+        blob: String,
+        length_ms: i32,
+        disc_no: i16,
+        track_no: i16,
+        metadata: BTreeMap<String, String>,
+        album: Album,
+    ) -> Song {
+        let (date, year) = match tags::date(&metadata) {
+            (Some(date), year) => (Some(date), year),
+            (None, _) => (album.date.clone(), album.year),
+        };
+        Song {
+            id: id,
+            blob: blob,
+            length_ms: length_ms,
+            disc_no: disc_no,
+            track_no: track_no,
+            disc_total: tags::disc_total(&metadata).or(album.disc_total),
+            track_total: tags::track_total(&metadata).or(album.track_total),
+            date: date,
+            year: year,
+            metadata: metadata,
+            album: album,
+            track_gain: None,
+            track_peak: None,
+            variants: Vec::new(),
+            play_count: None,
+            rating: None,
+        }
+    }
+
+    /// The song's value for `field`, or else its album's.
+    pub fn effective_metadata(&self, field: &str) -> Option<&String> {
+        self.metadata.get(field).or_else(|| self.album.metadata.get(field))
+    }
+}
+
+/// One encoding of a song's audio, e.g. a lossless master or a lossy copy.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct SongVariant {
+    pub blob: String,
+    pub codec: String,
+    /// in bits per second; the nominal bitrate if the encoder gave one,
+    /// otherwise the average over the file
+    pub bitrate: i32,
+    pub channels: i16,
+    pub sample_rate: i32,
+    /// in bytes
+    pub size: i64,
+}
+
+impl SongVariant {
+    /// The media type the blob is served as.
+    pub fn media_type(&self) -> &'static str {
+        match &self.codec[..] {
+            "vorbis" | "opus" => "audio/ogg",
+            "flac" => "audio/flac",
+            "mp3" => "audio/mpeg",
+            _ => "application/octet-stream",
+        }
+    }
+}
+
+fn first_disc() -> i16 {
+    1
+}
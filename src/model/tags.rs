@@ -0,0 +1,120 @@
+//! Typed values read out of the free-form Vorbis comment metadata.
+
+use std::collections::BTreeMap;
+
+/// The number before a slash, as in `TRACKNUMBER=3/12`.
+pub fn track_number(metadata: &BTreeMap<String, String>) -> Option<i16> {
+    metadata.get("TRACKNUMBER").and_then(|v| number_pair(v).0)
+}
+
+/// The disc a song is on, which may be tagged on the song or, when all of
+/// an album's songs share it, on the album.  Untagged songs are on disc 1.
+pub fn disc_number(song: &BTreeMap<String, String>, album: &BTreeMap<String, String>) -> i16 {
+    song.get("DISCNUMBER")
+        .or_else(|| album.get("DISCNUMBER"))
+        .and_then(|v| number_pair(v).0)
+        .unwrap_or(1)
+}
+
+/// `DISCTOTAL` or `TOTALDISCS`, or the part after the slash in `DISCNUMBER`.
+pub fn disc_total(metadata: &BTreeMap<String, String>) -> Option<i16> {
+    total(metadata, "DISCNUMBER", &["DISCTOTAL", "TOTALDISCS"])
+}
+
+/// `TRACKTOTAL` or `TOTALTRACKS`, or the part after the slash in
+/// `TRACKNUMBER`.
+pub fn track_total(metadata: &BTreeMap<String, String>) -> Option<i16> {
+    total(metadata, "TRACKNUMBER", &["TRACKTOTAL", "TOTALTRACKS"])
+}
+
+/// `DATE`, cut down to the `YYYY`, `YYYY-MM` or `YYYY-MM-DD` it starts
+/// with, and its year.
+pub fn date(metadata: &BTreeMap<String, String>) -> (Option<String>, Option<i16>) {
+    let raw = match metadata.get("DATE") {
+        Some(raw) => raw.trim().as_bytes(),
+        None => return (None, None),
+    };
+    let digits = |from: usize, len: usize| {
+        from + len <= raw.len() && raw[from..from + len].iter().all(|b| b'0' <= *b && *b <= b'9')
+    };
+    if !digits(0, 4) {
+        return (None, None);
+    }
+
+    let mut len = 4;
+    while len < 10 && len < raw.len() && raw[len] == b'-' && digits(len + 1, 2) {
+        len += 3;
+    }
+    let date = String::from_utf8_lossy(&raw[..len]).into_owned();
+    let year = date[..4].parse().ok();
+    (Some(date), year)
+}
+
+/// Whether a field is one of the `REPLAYGAIN_*` tags, which are kept as
+/// typed values on songs and albums rather than as metadata.
+pub fn is_replaygain_field(field: &str) -> bool {
+    field.to_uppercase().starts_with("REPLAYGAIN_")
+}
+
+/// A copy of client-supplied metadata with the `REPLAYGAIN_*` field names
+/// uppercased, as they are in `media::comment_map`, so that the lookups
+/// below find them however the client spelled them.
+pub fn uppercase_replaygain_fields(metadata: &BTreeMap<String, String>) -> BTreeMap<String, String> {
+    metadata.iter()
+        .map(|(key, val)| {
+            let key = if is_replaygain_field(key) { key.to_uppercase() } else { key.clone() };
+            (key, val.clone())
+        })
+        .collect()
+}
+
+/// `REPLAYGAIN_TRACK_GAIN` and `REPLAYGAIN_TRACK_PEAK`.
+pub fn track_replaygain(metadata: &BTreeMap<String, String>) -> (Option<f64>, Option<f64>) {
+    replaygain(metadata, "REPLAYGAIN_TRACK_GAIN", "REPLAYGAIN_TRACK_PEAK")
+}
+
+/// `REPLAYGAIN_ALBUM_GAIN` and `REPLAYGAIN_ALBUM_PEAK`.
+pub fn album_replaygain(metadata: &BTreeMap<String, String>) -> (Option<f64>, Option<f64>) {
+    replaygain(metadata, "REPLAYGAIN_ALBUM_GAIN", "REPLAYGAIN_ALBUM_PEAK")
+}
+
+/// Gains are written like `-6.48 dB`, peaks as a plain fraction of full
+/// scale.
+fn replaygain(metadata: &BTreeMap<String, String>, gain_field: &str, peak_field: &str)
+    -> (Option<f64>, Option<f64>)
+{
+    let gain = metadata.get(gain_field).and_then(|v| {
+        let v = v.trim();
+        let number = if v.to_lowercase().ends_with("db") { &v[..v.len() - 2] } else { v };
+        number.trim().parse::<f64>().ok()
+    });
+    let peak = metadata.get(peak_field).and_then(|v| v.trim().parse::<f64>().ok());
+    (
+        gain.and_then(|g| if g.is_finite() { Some(g) } else { None }),
+        peak.and_then(|p| if p.is_finite() && 0.0 <= p { Some(p) } else { None }),
+    )
+}
+
+fn total(metadata: &BTreeMap<String, String>, pair_field: &str, total_fields: &[&str]) -> Option<i16> {
+    total_fields.iter()
+        .filter_map(|f| metadata.get(*f))
+        .filter_map(|v| v.trim().parse().ok())
+        .filter(|&n: &i16| 0 < n)
+        .nth(0)
+        .or_else(|| metadata.get(pair_field).and_then(|v| number_pair(v).1))
+}
+
+/// `"3/12"` is `(Some(3), Some(12))`, `"3"` is `(Some(3), None)`.
+fn number_pair(value: &str) -> (Option<i16>, Option<i16>) {
+    fn positive(part: Option<&str>) -> Option<i16> {
+        match part.and_then(|p| p.trim().parse().ok()) {
+            Some(n) if 0 < n => Some(n),
+            _ => None,
+        }
+    }
+
+    let mut parts = value.splitn(2, '/');
+    let number = positive(parts.next());
+    let total = positive(parts.next());
+    (number, total)
+}
@@ -0,0 +1,49 @@
+use super::super::model::{
+    AccountProfile,
+    LinkedAccount,
+    Playlist,
+    PlayQueue,
+    SmartPlaylist,
+    SongId,
+    SongStats,
+};
+
+#[derive(Serialize, Debug)]
+pub struct AccountResponse {
+    pub account: AccountProfile,
+    pub linked_accounts: Vec<LinkedAccount>,
+}
+
+#[derive(Deserialize, Debug)]
+pub struct AccountUpdateRequest {
+    #[serde(default)]
+    pub display_name: Option<String>,
+}
+
+/// Everything stored about an account, for `GET /me/export`.
+#[derive(Serialize, Debug)]
+pub struct AccountExport {
+    pub exported_at: i64,
+    pub account: AccountProfile,
+    pub linked_accounts: Vec<LinkedAccount>,
+    pub song_stats: Vec<SongStats>,
+    pub listens: Vec<ExportedListen>,
+    pub playlists: Vec<ExportedPlaylist>,
+    pub smart_playlists: Vec<SmartPlaylist>,
+    pub queue: PlayQueue,
+}
+
+#[derive(Serialize, Debug)]
+pub struct ExportedListen {
+    pub song_id: SongId,
+    pub started_at: i64,
+    pub position_ms: i32,
+    pub duration_ms: i32,
+    pub client_id: Option<String>,
+}
+
+#[derive(Serialize, Debug)]
+pub struct ExportedPlaylist {
+    pub playlist: Playlist,
+    pub song_ids: Vec<SongId>,
+}
@@ -1,16 +1,35 @@
 use std::collections::BTreeMap;
-use super::super::model::Song;
+use super::super::model::{Album, AlbumSummary, Song};
 use super::StagedBlob;
 
+#[derive(Deserialize, Debug)]
 pub struct SongCreate {
     pub blob: StagedBlob,
+    // overrides the Vorbis comments found in the blob
+    #[serde(default)]
     pub metadata: BTreeMap<String, String>,
 }
 
+#[derive(Deserialize, Debug)]
 pub struct AlbumCreateRequest {
     pub songs: Vec<SongCreate>,
+    // fields shared by every song are moved here even when not given
+    #[serde(default)]
+    pub metadata: BTreeMap<String, String>,
 }
 
+#[derive(Serialize, Debug)]
 pub struct AlbumCreateResponse {
     pub songs: Vec<Song>,
 }
+
+#[derive(Serialize, Debug)]
+pub struct AlbumSetResponse {
+    pub results: Vec<AlbumSummary>,
+}
+
+#[derive(Serialize, Debug)]
+pub struct AlbumResponse {
+    pub album: Album,
+    pub songs: Vec<Song>,
+}
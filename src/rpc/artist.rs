@@ -0,0 +1,19 @@
+use super::super::model::{Album, Artist, ArtistId, ArtistSummary, Song};
+
+#[derive(Serialize, Debug)]
+pub struct ArtistSetResponse {
+    pub results: Vec<ArtistSummary>,
+}
+
+#[derive(Serialize, Debug)]
+pub struct ArtistResponse {
+    pub artist: Artist,
+    pub albums: Vec<Album>,
+    pub songs: Vec<Song>,
+}
+
+#[derive(Deserialize, Debug)]
+pub struct ArtistMergeRequest {
+    // artists to fold into the one named in the path
+    pub duplicates: Vec<ArtistId>,
+}
@@ -1,5 +1,7 @@
+#[derive(Serialize, Deserialize, Debug)]
 pub struct BlobUploadResponse {
     pub stage_id: StagedBlob,
 }
 
-pub struct StagedBlob(pub String);
\ No newline at end of file
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct StagedBlob(pub String);
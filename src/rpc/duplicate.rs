@@ -0,0 +1,25 @@
+use super::super::model::{DuplicateGroup, Song, SongId};
+
+#[derive(Serialize, Debug)]
+pub struct DuplicateReportResponse {
+    pub results: Vec<DuplicateGroup>,
+}
+
+#[derive(Deserialize, Debug)]
+pub struct DuplicateMergeRequest {
+    pub keep: SongId,
+    pub duplicates: Vec<SongId>,
+}
+
+#[derive(Serialize, Debug)]
+pub struct DuplicateMergeResponse {
+    pub song: Song,
+}
+
+#[derive(Serialize, Debug)]
+pub struct AudioDigestBackfillResponse {
+    // songs whose digest was recorded by this request
+    pub updated: usize,
+    // songs still without a digest, including any that failed
+    pub remaining: usize,
+}
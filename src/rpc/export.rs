@@ -0,0 +1,15 @@
+use super::super::model::Song;
+
+/// The `json` export format; the other formats are rendered from it.
+#[derive(Serialize, Debug)]
+pub struct ExportResponse {
+    pub title: String,
+    pub tracks: Vec<ExportedTrack>,
+}
+
+#[derive(Serialize, Debug)]
+pub struct ExportedTrack {
+    // a signed URL or a path relative to the blob store
+    pub location: String,
+    pub song: Song,
+}
@@ -0,0 +1,7 @@
+use super::super::model::FacetValue;
+
+#[derive(Serialize, Debug)]
+pub struct FacetResponse {
+    pub field: String,
+    pub results: Vec<FacetValue>,
+}
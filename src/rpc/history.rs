@@ -0,0 +1,9 @@
+use super::super::model::Listen;
+
+#[derive(Serialize, Debug)]
+pub struct HistoryResponse {
+    pub results: Vec<Listen>,
+    // pass back as `cursor` to fetch the next page, absent on the last page
+    #[serde(skip_serializing_if="Option::is_none")]
+    pub next_cursor: Option<String>,
+}
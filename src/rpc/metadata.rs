@@ -0,0 +1,15 @@
+use std::collections::BTreeMap;
+use super::super::model::MetadataChange;
+
+#[derive(Deserialize, Debug)]
+pub struct MetadataPatchRequest {
+    #[serde(default)]
+    pub set: BTreeMap<String, String>,
+    #[serde(default)]
+    pub remove: Vec<String>,
+}
+
+#[derive(Serialize, Debug)]
+pub struct MetadataChangeSetResponse {
+    pub results: Vec<MetadataChange>,
+}
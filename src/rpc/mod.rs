@@ -11,15 +11,113 @@ mod album;
 pub use self::album::{
     AlbumCreateRequest,
     AlbumCreateResponse,
+    AlbumSetResponse,
+    AlbumResponse,
 };
 
 mod song;
 pub use self::song::{
     SongSetResponse,
+    PlayRequest,
+    PlayResponse,
+    RatingRequest,
+    RatingResponse,
+    SongVariantAddRequest,
+    SongVariantSetResponse,
+    LyricsResponse,
 };
 
-struct Error {
-    //
+mod metadata;
+pub use self::metadata::{
+    MetadataPatchRequest,
+    MetadataChangeSetResponse,
+};
+
+mod playlist;
+pub use self::playlist::{
+    PlaylistCreateRequest,
+    PlaylistUpdateRequest,
+    PlaylistEntryAddRequest,
+    PlaylistEntryMoveRequest,
+    PlaylistSetResponse,
+    PlaylistResponse,
+    PlaylistImportResponse,
+    ImportMatch,
+    ImportUnmatched,
+};
+
+mod queue;
+pub use self::queue::{
+    PlayQueueRequest,
+    PlayQueueResponse,
+    PlayQueueConflictResponse,
+};
+
+mod history;
+pub use self::history::HistoryResponse;
+
+mod smart_playlist;
+pub use self::smart_playlist::{
+    SmartPlaylistCreateRequest,
+    SmartPlaylistSetResponse,
+    SmartPlaylistResponse,
+};
+
+mod artist;
+pub use self::artist::{
+    ArtistSetResponse,
+    ArtistResponse,
+    ArtistMergeRequest,
+};
+
+mod facet;
+pub use self::facet::FacetResponse;
+
+mod duplicate;
+pub use self::duplicate::{
+    DuplicateReportResponse,
+    DuplicateMergeRequest,
+    DuplicateMergeResponse,
+    AudioDigestBackfillResponse,
+};
+
+mod recommendation;
+pub use self::recommendation::{
+    SimilarSongsResponse,
+    RecommendationResponse,
+};
+
+mod radio;
+pub use self::radio::RadioResponse;
+
+mod account;
+pub use self::account::{
+    AccountResponse,
+    AccountUpdateRequest,
+    AccountExport,
+    ExportedListen,
+    ExportedPlaylist,
+};
+
+mod export;
+pub use self::export::{
+    ExportResponse,
+    ExportedTrack,
+};
+
+mod sync;
+pub use self::sync::SyncResponse;
+
+#[derive(Serialize, Debug)]
+pub struct ErrorResponse {
+    pub error: Error,
+}
+
+#[derive(Serialize, Debug)]
+pub struct Error {
+    #[serde(rename="type")]
+    pub kind: String,
+    pub message: String,
 }
 
 // rocket enforces the existence atm so this one is harder.
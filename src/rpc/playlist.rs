@@ -0,0 +1,63 @@
+use super::super::import::MatchMethod;
+use super::super::model::{Playlist, PlaylistEntry, SongId};
+
+#[derive(Deserialize, Debug)]
+pub struct PlaylistCreateRequest {
+    pub name: String,
+    #[serde(default)]
+    pub description: String,
+}
+
+#[derive(Deserialize, Debug)]
+pub struct PlaylistUpdateRequest {
+    pub name: Option<String>,
+    pub description: Option<String>,
+}
+
+#[derive(Deserialize, Debug)]
+pub struct PlaylistEntryAddRequest {
+    pub song_id: SongId,
+    // insert before this position instead of appending
+    pub position: Option<i32>,
+}
+
+#[derive(Deserialize, Debug)]
+pub struct PlaylistEntryMoveRequest {
+    pub position: i32,
+}
+
+#[derive(Serialize, Debug)]
+pub struct PlaylistSetResponse {
+    pub results: Vec<Playlist>,
+}
+
+#[derive(Serialize, Debug)]
+pub struct PlaylistResponse {
+    pub playlist: Playlist,
+    pub entries: Vec<PlaylistEntry>,
+}
+
+#[derive(Serialize, Debug)]
+pub struct PlaylistImportResponse {
+    pub playlist: Playlist,
+    pub entries: Vec<PlaylistEntry>,
+    pub matched: Vec<ImportMatch>,
+    pub unmatched: Vec<ImportUnmatched>,
+}
+
+#[derive(Serialize, Debug)]
+pub struct ImportMatch {
+    // position of the entry in the imported file
+    pub index: usize,
+    pub song_id: SongId,
+    pub method: MatchMethod,
+}
+
+#[derive(Serialize, Debug)]
+pub struct ImportUnmatched {
+    pub index: usize,
+    pub location: String,
+    pub title: Option<String>,
+    pub artist: Option<String>,
+    pub duration_ms: Option<i32>,
+}
@@ -0,0 +1,25 @@
+use super::Error;
+use super::super::model::{PlayQueue, SongId};
+
+#[derive(Deserialize, Debug)]
+pub struct PlayQueueRequest {
+    // the version the client's edit is based on, 0 for a fresh queue
+    pub version: i64,
+    pub song_ids: Vec<SongId>,
+    #[serde(default)]
+    pub current_index: i32,
+    #[serde(default)]
+    pub position_ms: i64,
+}
+
+#[derive(Serialize, Debug)]
+pub struct PlayQueueResponse {
+    pub queue: PlayQueue,
+}
+
+/// Sent with a 409 so the client can merge against what won.
+#[derive(Serialize, Debug)]
+pub struct PlayQueueConflictResponse {
+    pub error: Error,
+    pub queue: PlayQueue,
+}
@@ -0,0 +1,12 @@
+use super::super::model::Song;
+
+#[derive(Serialize, Debug)]
+pub struct RadioResponse {
+    pub results: Vec<Song>,
+    // pass back as `session` along with `offset` to continue the sequence,
+    // sessions expire a day after they start
+    pub session: String,
+    // absent once the sequence is exhausted
+    #[serde(skip_serializing_if="Option::is_none")]
+    pub next_offset: Option<u32>,
+}
@@ -0,0 +1,12 @@
+use super::super::model::{Song, ScoredSong};
+
+#[derive(Serialize, Debug)]
+pub struct SimilarSongsResponse {
+    pub song: Song,
+    pub results: Vec<ScoredSong>,
+}
+
+#[derive(Serialize, Debug)]
+pub struct RecommendationResponse {
+    pub results: Vec<ScoredSong>,
+}
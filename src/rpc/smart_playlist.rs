@@ -0,0 +1,20 @@
+use super::super::model::{SmartPlaylist, SmartRules, Song};
+
+#[derive(Deserialize, Debug)]
+pub struct SmartPlaylistCreateRequest {
+    pub name: String,
+    pub rules: SmartRules,
+}
+
+#[derive(Serialize, Debug)]
+pub struct SmartPlaylistSetResponse {
+    pub results: Vec<SmartPlaylist>,
+}
+
+#[derive(Serialize, Debug)]
+pub struct SmartPlaylistResponse {
+    pub playlist: SmartPlaylist,
+    pub songs: Vec<Song>,
+    // pass back as `seed` to get the same shuffle again
+    pub seed: u32,
+}
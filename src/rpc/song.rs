@@ -1,6 +1,56 @@
-use super::super::model::Song;
+use super::super::model::{Song, SongId, SongVariant, LyricLine};
+use super::StagedBlob;
 
 #[derive(Serialize)]
 pub struct SongSetResponse {
     pub results: Vec<Song>,
-}
\ No newline at end of file
+}
+
+#[derive(Deserialize, Debug)]
+pub struct PlayRequest {
+    // how far playback got
+    pub position_ms: i32,
+    // percentage of the song that was listened to, 0 to 100
+    pub completion: f64,
+    // unix time playback started, defaults to now minus `duration_ms`
+    pub started_at: Option<i64>,
+    // time actually spent listening, defaults to `position_ms`
+    pub duration_ms: Option<i32>,
+    // free-form name of the device or app reporting the play
+    pub client_id: Option<String>,
+}
+
+#[derive(Serialize, Debug)]
+pub struct PlayResponse {
+    pub counted: bool,
+    pub play_count: i32,
+}
+
+#[derive(Deserialize, Debug)]
+pub struct RatingRequest {
+    pub rating: i32,
+}
+
+#[derive(Serialize, Debug)]
+pub struct RatingResponse {
+    pub rating: Option<i32>,
+}
+
+#[derive(Deserialize, Debug)]
+pub struct SongVariantAddRequest {
+    pub blob: StagedBlob,
+}
+
+#[derive(Serialize, Debug)]
+pub struct SongVariantSetResponse {
+    pub results: Vec<SongVariant>,
+}
+
+#[derive(Serialize, Debug)]
+pub struct LyricsResponse {
+    pub song_id: SongId,
+    pub synced: bool,
+    pub text: String,
+    // empty unless `synced`
+    pub lines: Vec<LyricLine>,
+}
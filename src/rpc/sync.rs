@@ -0,0 +1,11 @@
+use super::super::model::{Album, AlbumId, Song, SongId};
+
+#[derive(Serialize, Debug)]
+pub struct SyncResponse {
+    // pass back as `since` for the next sync
+    pub version: i64,
+    pub songs: Vec<Song>,
+    pub albums: Vec<Album>,
+    pub deleted_song_ids: Vec<SongId>,
+    pub deleted_album_ids: Vec<AlbumId>,
+}
@@ -18,6 +18,12 @@ impl JsonDocument {
         val
     }
 
+    pub fn serialize<T>(value: &T) -> serde_json::Result<JsonDocument>
+        where T: serde::Serialize
+    {
+        serde_json::to_string(value).map(JsonDocument)
+    }
+
     pub fn deserialize<T>(&self) -> serde_json::Result<T>
         where T: serde::Deserialize
     {
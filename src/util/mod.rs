@@ -1,4 +1,6 @@
 mod hex;
+mod time;
+mod stream;
 pub mod json;
 
 pub use self::hex::{
@@ -6,4 +8,6 @@ pub use self::hex::{
     dehex_fixed_size,
     hex,
     dehex,
-};
\ No newline at end of file
+};
+pub use self::time::unix_now;
+pub use self::stream::{ChunkReader, ChunkWriter, chunk_channel};
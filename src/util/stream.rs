@@ -0,0 +1,125 @@
+//! Response bodies written on another thread.
+
+use std::cmp;
+use std::io::{self, Read, Write};
+use std::sync::mpsc::{self, Receiver, SyncSender, TrySendError};
+use std::thread;
+use std::time::{Duration, Instant};
+
+/// bytes buffered by the writer before they are handed to the reader
+const CHUNK_SIZE: usize = 32 * 1024;
+
+/// how often a writer waiting on a full queue checks again, in ms
+const STALL_POLL_MS: u64 = 10;
+
+/// Creates a pipe whose writer can be moved to another thread.  At most
+/// `bound` chunks are queued, so a slow client holds back the writer
+/// rather than letting the body pile up in memory.  A reader that takes
+/// no chunk for `stall_timeout` fails the writer with `TimedOut`.
+pub fn chunk_channel(bound: usize, stall_timeout: Duration) -> (ChunkWriter, ChunkReader) {
+    let (tx, rx) = mpsc::sync_channel(bound);
+    let writer = ChunkWriter {
+        tx: tx,
+        buf: Vec::with_capacity(CHUNK_SIZE),
+        stall_timeout: stall_timeout,
+        stalled: false,
+    };
+    let reader = ChunkReader {
+        rx: rx,
+        chunk: Vec::new(),
+        pos: 0,
+    };
+    (writer, reader)
+}
+
+pub struct ChunkWriter {
+    tx: SyncSender<io::Result<Vec<u8>>>,
+    buf: Vec<u8>,
+    stall_timeout: Duration,
+    // once the reader has stalled, later sends give up right away
+    stalled: bool,
+}
+
+impl ChunkWriter {
+    /// Ends the body with an error, which the reader returns once it has
+    /// read everything written before.
+    pub fn fail(mut self, e: io::Error) {
+        let _ = self.flush();
+        let _ = self.send(Err(e));
+    }
+
+    fn send_buf(&mut self) -> io::Result<()> {
+        let chunk = ::std::mem::replace(&mut self.buf, Vec::with_capacity(CHUNK_SIZE));
+        self.send(Ok(chunk))
+    }
+
+    fn send(&mut self, mut item: io::Result<Vec<u8>>) -> io::Result<()> {
+        if self.stalled {
+            return Err(io::Error::new(io::ErrorKind::TimedOut, "reader stalled"));
+        }
+        let deadline = Instant::now() + self.stall_timeout;
+        loop {
+            match self.tx.try_send(item) {
+                Ok(()) => return Ok(()),
+                Err(TrySendError::Disconnected(_)) => {
+                    return Err(io::Error::new(io::ErrorKind::BrokenPipe, "reader hung up"));
+                },
+                Err(TrySendError::Full(rejected)) => item = rejected,
+            }
+            if deadline <= Instant::now() {
+                self.stalled = true;
+                return Err(io::Error::new(io::ErrorKind::TimedOut, "reader stalled"));
+            }
+            thread::sleep(Duration::from_millis(STALL_POLL_MS));
+        }
+    }
+}
+
+impl Write for ChunkWriter {
+    fn write(&mut self, data: &[u8]) -> io::Result<usize> {
+        self.buf.extend_from_slice(data);
+        if CHUNK_SIZE <= self.buf.len() {
+            self.send_buf()?;
+        }
+        Ok(data.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        if self.buf.len() > 0 {
+            self.send_buf()?;
+        }
+        Ok(())
+    }
+}
+
+impl Drop for ChunkWriter {
+    fn drop(&mut self) {
+        let _ = self.flush();
+    }
+}
+
+pub struct ChunkReader {
+    rx: Receiver<io::Result<Vec<u8>>>,
+    chunk: Vec<u8>,
+    pos: usize,
+}
+
+impl Read for ChunkReader {
+    fn read(&mut self, out: &mut [u8]) -> io::Result<usize> {
+        while self.pos == self.chunk.len() {
+            match self.rx.recv() {
+                Ok(Ok(chunk)) => {
+                    self.chunk = chunk;
+                    self.pos = 0;
+                },
+                Ok(Err(e)) => return Err(e),
+                // the writer is done
+                Err(_) => return Ok(0),
+            }
+        }
+        let len = cmp::min(out.len(), self.chunk.len() - self.pos);
+        out[..len].copy_from_slice(&self.chunk[self.pos..self.pos + len]);
+        self.pos += len;
+        Ok(len)
+    }
+}
@@ -0,0 +1,8 @@
+use std::time::{SystemTime, UNIX_EPOCH};
+
+/// Seconds since the unix epoch, the representation used for timestamps in
+/// the JSON API.
+pub fn unix_now() -> i64 {
+    let dur = SystemTime::now().duration_since(UNIX_EPOCH).unwrap();
+    dur.as_secs() as i64
+}